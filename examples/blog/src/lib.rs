@@ -0,0 +1,89 @@
+//! A complete blog generator built from Shtola plugins, meant as a starting
+//! point for your own site. Posts are written in Markdown in `site/posts` and
+//! grouped into the `posts` collection by their frontmatter:
+//!
+//! ```markdown
+//! ---
+//! title: Hello, world
+//! date: 2020-01-02
+//! layout: post.html
+//! collection: posts
+//! ---
+//! # Hello!
+//! ```
+//!
+//! The plugins run in this order:
+//!
+//! 1. `shtola-markdown` turns the posts into HTML, keeping their frontmatter.
+//! 2. `shtola-templates` renders all pages with their layouts. The post list
+//!    on the front page and the Atom feed in `feed.xml` are plain layouts
+//!    iterating over `collections.posts`, and use the site settings like
+//!    `site.title`.
+//! 3. `shtola-sitemap` lists the rendered pages in `sitemap.xml`. It runs
+//!    last, so the layouts are already gone from the output.
+
+use shtola::site::SiteConfig;
+use shtola::{Shtola, ShtolaError, IR};
+use std::path::PathBuf;
+
+/// The title of the blog.
+pub const TITLE: &str = "A Shtola blog";
+
+/// The absolute URL the blog is served from, used by the feed and sitemap.
+pub const BASE_URL: &str = "https://blog.example.com";
+
+/// Builds the blog from `source` into `destination`.
+pub fn build<S: Into<PathBuf>, D: Into<PathBuf> + Clone>(
+	source: S,
+	destination: D,
+) -> Result<IR, ShtolaError> {
+	generator(source, destination).build()
+}
+
+/// Sets up the blog generator without building, for example to
+/// [watch](../shtola/struct.Shtola.html#method.watch) the source directory.
+pub fn generator<S: Into<PathBuf>, D: Into<PathBuf> + Clone>(source: S, destination: D) -> Shtola {
+	let mut s = Shtola::new();
+	s.source(source);
+	s.destination(destination);
+	s.clean(true);
+	s.site(SiteConfig {
+		title: Some(TITLE.into()),
+		base_url: Some(BASE_URL.into()),
+		language: Some("en".into()),
+		..Default::default()
+	});
+	s.register(shtola_markdown::plugin());
+	s.register(shtola_templates::plugin());
+	s.register(shtola_sitemap::with_options(Default::default()));
+	s
+}
+
+#[test]
+fn it_works() {
+	use std::path::Path;
+
+	let r = build("site", "_site").unwrap();
+	let read = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	let post = read("posts/hello-world.html");
+	assert!(post.contains("<title>Hello, world - A Shtola blog</title>"));
+	// MiniJinja escapes slashes in HTML and XML layouts, which browsers and
+	// feed readers decode again.
+	assert!(post.contains("<link rel=\"stylesheet\" href=\"..&#x2f;style.css\">"));
+	assert!(post.contains("<h1>Hello!</h1>"));
+	let index = read("index.html");
+	let second = index.find("second-post.html").unwrap();
+	let first = index.find("hello-world.html").unwrap();
+	assert!(second < first, "posts should be listed newest first");
+	let feed = read("feed.xml");
+	assert!(feed.contains("<updated>2020-02-14T00:00:00Z</updated>"));
+	assert!(feed.contains("<title>Hello, world</title>"));
+	let sitemap = read("sitemap.xml");
+	assert!(sitemap.contains("<loc>https://blog.example.com/about.html</loc>"));
+	assert!(!sitemap.contains("_layouts"));
+	assert!(r.files.keys().all(|p| !p.starts_with("_layouts")));
+	assert!(r.files.get(Path::new("posts/hello-world.md")).is_none());
+}
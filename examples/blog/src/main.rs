@@ -0,0 +1,41 @@
+use std::env;
+use std::ops::ControlFlow;
+use std::process;
+
+// Usage: shtola-example-blog [--watch | --serve] [SOURCE] [DESTINATION]
+//
+// With --serve, the blog is previewed at http://127.0.0.1:8000/.
+fn main() {
+	let (flags, args): (Vec<String>, Vec<String>) =
+		env::args().skip(1).partition(|a| a.starts_with("--"));
+	let mut args = args.into_iter();
+	let source = args.next().unwrap_or_else(|| "site".into());
+	let destination = args.next().unwrap_or_else(|| "_site".into());
+	let mut s = shtola_example_blog::generator(source, destination.clone());
+	if flags.iter().any(|f| f == "--serve") {
+		if let Err(e) = s.serve(Default::default()) {
+			eprintln!("{}", e);
+			process::exit(1);
+		}
+	} else if flags.iter().any(|f| f == "--watch") {
+		let watched = s.watch(|result| {
+			match result {
+				Ok(ir) => println!("Built {} files into {}", ir.files.len(), destination),
+				Err(e) => eprintln!("{}", e),
+			}
+			ControlFlow::Continue(())
+		});
+		if let Err(e) = watched {
+			eprintln!("{}", e);
+			process::exit(1);
+		}
+	} else {
+		match s.build() {
+			Ok(ir) => println!("Built {} files into {}", ir.files.len(), destination),
+			Err(e) => {
+				eprintln!("{}", e);
+				process::exit(1);
+			}
+		}
+	}
+}
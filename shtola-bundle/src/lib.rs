@@ -0,0 +1,347 @@
+//! JavaScript and TypeScript bundling for Shtola. The
+//! [`plugin`](fn.plugin.html) bundles the configured entry points with
+//! [esbuild](https://esbuild.github.io), so simple sites don't need a Node
+//! toolchain and npm scripts next to their build:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_bundle::Options;
+//!
+//! let mut s = Shtola::new();
+//! s.register_fallible(shtola_bundle::plugin(Options {
+//!   entry_points: vec!["scripts/main.ts".into()],
+//!   ..Options::default()
+//! }));
+//! ```
+//!
+//! esbuild is a single binary and has to be installed separately. Every entry
+//! point becomes a bundle named after it and the hash of its content, like
+//! `assets/main.3f2a91c0.js`, which can be cached forever. The URLs of the
+//! bundles are stored in the `bundles` metadata key, keyed by entry point, so
+//! templates can link to them.
+//!
+//! Bundles are kept in an on-disk cache, keyed by the bundler command and the
+//! content of all files handed to the bundler, so esbuild only runs if one of
+//! them changed. The build fails if the bundler can't be started at all.
+//! Entry points the bundler fails on are reported as failed files.
+
+use sha2::{Digest, Sha256};
+use shtola::command::{self, temp_dir};
+use shtola::json::{Map, Value};
+use shtola::log::{debug, info};
+use shtola::manifest::tool_version;
+use shtola::{has_extension, PluginError, ShFile, TryPlugin, IR};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The program bundling an entry point.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bundler {
+	/// [esbuild](https://esbuild.github.io), found on the `PATH`.
+	Esbuild,
+	/// A custom command, as the program followed by its arguments. `{input}`
+	/// and `{output}` in the arguments are replaced with the paths of the
+	/// entry point and the bundle. The command runs in the directory holding
+	/// the files handed to the bundler.
+	Command(Vec<String>),
+}
+
+impl Bundler {
+	// The program and arguments to bundle `input` into `output`.
+	fn command(&self, input: &Path, output: &Path, minify: bool) -> Vec<String> {
+		let input = input.to_string_lossy().into_owned();
+		let output = output.to_string_lossy().into_owned();
+		match self {
+			Bundler::Esbuild => {
+				let mut args = vec![
+					"esbuild".into(),
+					input,
+					"--bundle".into(),
+					format!("--outfile={}", output),
+				];
+				if minify {
+					args.push("--minify".into());
+				}
+				args
+			}
+			Bundler::Command(args) => args
+				.iter()
+				.map(|a| a.replace("{input}", &input).replace("{output}", &output))
+				.collect(),
+		}
+	}
+}
+
+/// Options for the bundling plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The paths of the entry points in the IR. Default is none.
+	pub entry_points: Vec<PathBuf>,
+	/// The directory the bundles are placed in. Default is `assets`.
+	pub output_dir: PathBuf,
+	/// The bundler to use. Default is [`Bundler::Esbuild`](enum.Bundler.html).
+	pub bundler: Bundler,
+	/// Whether to minify the bundles. Only has an effect with esbuild. Default
+	/// is `true`.
+	pub minify: bool,
+	/// The extensions of the files handed to the bundler, which entry points
+	/// can import. Default is `js`, `mjs`, `cjs`, `jsx`, `ts`, `tsx` and
+	/// `json`.
+	pub extensions: Vec<String>,
+	/// Whether to remove the files handed to the bundler from the build, so
+	/// the sources of the bundles aren't published. JSON files are kept. All
+	/// files with one of the `extensions` are removed, not only the ones the
+	/// entry points import, so scripts that should be published as they are
+	/// need to be left out of `extensions` if this is on. Default is `false`.
+	pub remove_sources: bool,
+	/// The directory bundles are cached in. Default is
+	/// `.shtola-cache/bundle`.
+	pub cache_dir: PathBuf,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			entry_points: Vec::new(),
+			output_dir: "assets".into(),
+			bundler: Bundler::Esbuild,
+			minify: true,
+			extensions: ["js", "mjs", "cjs", "jsx", "ts", "tsx", "json"]
+				.iter()
+				.map(|e| e.to_string())
+				.collect(),
+			remove_sources: false,
+			cache_dir: ".shtola-cache/bundle".into(),
+		}
+	}
+}
+
+/// Creates the bundling plugin.
+pub fn plugin(options: Options) -> TryPlugin {
+	Box::new(move |ir: IR| {
+		if options.entry_points.is_empty() {
+			return Ok(ir);
+		}
+		for entry in &options.entry_points {
+			if !ir.files.contains_key(entry) {
+				return Err(PluginError::new(&format!(
+					"Entry point {:?} doesn't exist",
+					entry
+				)));
+			}
+		}
+		let mut sources: Vec<&(PathBuf, ShFile)> = ir
+			.files
+			.iter()
+			.filter(|(p, _)| {
+				has_extension(p, &options.extensions) || options.entry_points.contains(p)
+			})
+			.collect();
+		sources.sort_by(|a, b| a.0.cmp(&b.0));
+		info!("Bundling {} entry points", options.entry_points.len());
+		let dir = temp_dir("shtola-bundle-")?;
+		let (bundles, failures) = bundle_all(&options, &sources, dir.path())?;
+		let mut files = ir.files.clone();
+		if options.remove_sources {
+			for (path, _) in &sources {
+				if !has_extension(path, &["json"]) {
+					files.remove(path);
+				}
+			}
+		}
+		let mut metadata = Map::new();
+		for (entry, path, content) in bundles {
+			metadata.insert(url(&entry)[1..].to_string(), Value::String(url(&path)));
+			files.insert(
+				path,
+				ShFile {
+					content,
+					..ShFile::empty()
+				},
+			);
+		}
+		let ir = IR {
+			files,
+			metadata: ir
+				.metadata
+				.update("bundles".into(), Value::Object(metadata)),
+			..ir
+		};
+		let version = match options.bundler {
+			Bundler::Esbuild => tool_version("esbuild"),
+			Bundler::Command(_) => None,
+		};
+		let ir = match version {
+			Some(version) => ir.record_tool("esbuild", &version),
+			None => ir,
+		};
+		Ok(failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message)))
+	})
+}
+
+type Bundled = (Vec<(PathBuf, PathBuf, Vec<u8>)>, Vec<(PathBuf, String)>);
+
+fn bundle_all(
+	options: &Options,
+	sources: &[&(PathBuf, ShFile)],
+	dir: &Path,
+) -> Result<Bundled, PluginError> {
+	let cache_error =
+		|e: std::io::Error| PluginError::new(&format!("Unable to access the bundle cache: {}", e));
+	// Everything that can change the bundles, so the cache is invalidated by
+	// changes to any imported file.
+	let mut hasher = Sha256::new();
+	for (path, file) in sources {
+		hasher.update(path.to_string_lossy().as_bytes());
+		hasher.update([0]);
+		hasher.update((file.content.len() as u64).to_le_bytes());
+		hasher.update(&file.content);
+	}
+	let mut prepared = false;
+	let mut bundles = Vec::new();
+	let mut failures = Vec::new();
+	for entry in &options.entry_points {
+		let ext = match entry.extension().and_then(|e| e.to_str()) {
+			Some("css") => "css",
+			_ => "js",
+		};
+		let template = options
+			.bundler
+			.command(entry, Path::new("{output}"), options.minify);
+		let mut hasher = hasher.clone();
+		hasher.update(template.join("\0").as_bytes());
+		let key = format!("{:x}", hasher.finalize());
+		let cached = options.cache_dir.join(format!("{}.{}", key, ext));
+		let content = if cached.exists() {
+			debug!("Using cached bundle of {:?}", entry);
+			fs::read(&cached).map_err(cache_error)?
+		} else {
+			if !prepared {
+				write_sources(sources, dir)?;
+				prepared = true;
+			}
+			match run(options, entry, ext, dir)? {
+				Ok(content) => {
+					fs::create_dir_all(&options.cache_dir).map_err(cache_error)?;
+					fs::write(&cached, &content).map_err(cache_error)?;
+					content
+				}
+				Err(message) => {
+					failures.push((entry.clone(), format!("Unable to bundle: {}", message)));
+					continue;
+				}
+			}
+		};
+		let hash = format!("{:x}", Sha256::digest(&content));
+		let stem = entry.file_stem().unwrap_or_default().to_string_lossy();
+		let path = options
+			.output_dir
+			.join(format!("{}.{}.{}", stem, &hash[..8], ext));
+		bundles.push((entry.clone(), path, content));
+	}
+	Ok((bundles, failures))
+}
+
+fn write_sources(sources: &[&(PathBuf, ShFile)], dir: &Path) -> Result<(), PluginError> {
+	let io_error = |e: std::io::Error| {
+		PluginError::new(&format!("Unable to prepare sources for bundling: {}", e))
+	};
+	for (path, file) in sources {
+		let target = dir.join(path);
+		if let Some(parent) = target.parent() {
+			fs::create_dir_all(parent).map_err(io_error)?;
+		}
+		fs::write(&target, &file.content).map_err(io_error)?;
+	}
+	Ok(())
+}
+
+// Runs the bundler for one entry point. Failures of the bundler are returned
+// as the inner error, so only that entry point fails.
+fn run(
+	options: &Options,
+	entry: &Path,
+	ext: &str,
+	dir: &Path,
+) -> Result<Result<Vec<u8>, String>, PluginError> {
+	let output = dir.join(".shtola-bundle").join(entry).with_extension(ext);
+	let args = options.bundler.command(entry, &output, options.minify);
+	debug!("Running {:?}", &args);
+	Ok(command::run("Bundler", &args, Some(dir), None)?
+		.and_then(|_| fs::read(&output).map_err(|e| e.to_string())))
+}
+
+fn url(path: &Path) -> String {
+	let parts: Vec<String> = path
+		.components()
+		.map(|c| c.as_os_str().to_string_lossy().into_owned())
+		.collect();
+	format!("/{}", parts.join("/"))
+}
+
+#[cfg(unix)]
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let cache = Path::new("../fixtures/dest_bundle_cache");
+	let _ = fs::remove_dir_all(cache);
+	let runs = std::env::temp_dir().join(format!("shtola-bundle-runs-{}", std::process::id()));
+	let _ = fs::remove_file(&runs);
+	// Stands in for esbuild, showing that imported files are available and
+	// counting its runs.
+	let bundler = Bundler::Command(vec![
+		"sh".into(),
+		"-c".into(),
+		format!(
+			"echo >> {}; grep -q fail {{input}} && exit 1; mkdir -p $(dirname {{output}}); \
+			 cat {{input}} scripts/util.js > {{output}}",
+			runs.display()
+		),
+	]);
+	let build = |entry_points: Vec<PathBuf>, remove_sources: bool| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/bundle");
+		s.destination("../fixtures/dest_bundle");
+		s.clean(true);
+		s.strict(false);
+		s.register_fallible(plugin(Options {
+			entry_points,
+			bundler: bundler.clone(),
+			remove_sources,
+			cache_dir: cache.into(),
+			..Options::default()
+		}));
+		s.build()
+	};
+	let r = build(
+		vec!["scripts/main.ts".into(), "scripts/fail.ts".into()],
+		true,
+	)
+	.unwrap();
+	let content = b"main();\nfunction util() {}\n";
+	let hash = format!("{:x}", Sha256::digest(content));
+	let path = format!("assets/main.{}.js", &hash[..8]);
+	assert_eq!(r.files.get(Path::new(&path)).unwrap().content, content);
+	assert!(r.files.get(Path::new("scripts/main.ts")).is_none());
+	assert!(r.files.get(Path::new("scripts/util.js")).is_none());
+	assert!(r.files.get(Path::new("index.html")).is_some());
+	assert_eq!(
+		r.metadata.get("bundles").unwrap(),
+		&shtola::json::json!({ "scripts/main.ts": format!("/{}", path) })
+	);
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("scripts/fail.ts"));
+
+	// The second build takes the bundle from the cache and keeps the sources.
+	let r = build(vec!["scripts/main.ts".into()], false).unwrap();
+	assert!(r.files.get(Path::new(&path)).is_some());
+	assert!(r.files.get(Path::new("scripts/util.js")).is_some());
+	assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 2);
+	let _ = fs::remove_file(&runs);
+
+	let e = build(vec!["scripts/missing.ts".into()], false).unwrap_err();
+	assert!(e.to_string().contains("doesn't exist"));
+}
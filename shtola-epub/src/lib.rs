@@ -0,0 +1,464 @@
+//! EPUB books for Shtola. The [`plugin`](fn.plugin.html) bundles the HTML
+//! pages of a collection into an EPUB 3 book, so documentation and serialized
+//! fiction can be offered for download from the same source:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_epub::Book;
+//!
+//! let mut s = Shtola::new();
+//! s.register(shtola_epub::plugin(vec![Book::new("novel", "novel.epub")]));
+//! ```
+//!
+//! Every page whose `collection` frontmatter key contains the collection
+//! becomes a chapter, titled by its `title` frontmatter key. Chapters are
+//! sorted by their `order` frontmatter key, and by path if it's missing. The
+//! body of each page is used as the chapter, so the plugin should run after
+//! the pages were rendered. Pages are parsed like browsers do and written as
+//! the XHTML EPUB requires, so they don't have to be well-formed. Images shown
+//! by the chapters are bundled with the book, stylesheets aren't.
+//!
+//! The title, author and language of a book default to the
+//! [site settings](../shtola/site/struct.SiteConfig.html). Books are
+//! reproducible: their modification date is the latest `updated` or `date` of
+//! their chapters.
+//!
+//! With [`Options::catalog`](struct.Options.html#structfield.catalog) set, an
+//! [OPDS](https://opds.io) catalog listing all books is written as well, so
+//! e-readers can browse and download them.
+
+use shtola::context::url;
+use shtola::frontmatter::{get_date, get_str};
+use shtola::log::{debug, info};
+use shtola::{has_extension, mime, HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use shtola_links::resolve;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+mod xhtml;
+
+/// A book made from a collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Book {
+	/// The collection whose pages are the chapters.
+	pub collection: String,
+	/// The path of the EPUB file.
+	pub output: PathBuf,
+	/// The title. Default is the title of the site, or the collection if the
+	/// site has none.
+	pub title: Option<String>,
+	/// The author. Default is the author of the site.
+	pub author: Option<String>,
+	/// The language. Default is the language of the site, or `en`.
+	pub language: Option<String>,
+	/// The unique identifier of the book. Default is the URL of the EPUB file.
+	pub identifier: Option<String>,
+}
+
+impl Book {
+	/// Creates a book with defaults.
+	pub fn new<P: Into<PathBuf>>(collection: &str, output: P) -> Book {
+		Book {
+			collection: collection.to_string(),
+			output: output.into(),
+			title: None,
+			author: None,
+			language: None,
+			identifier: None,
+		}
+	}
+}
+
+/// Options for the EPUB plugin.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+	/// The books.
+	pub books: Vec<Book>,
+	/// The path of an OPDS catalog listing the books. Default is `None`,
+	/// writing no catalog.
+	pub catalog: Option<PathBuf>,
+}
+
+/// Creates the EPUB plugin for the given books.
+pub fn plugin(books: Vec<Book>) -> Plugin {
+	with_options(Options {
+		books,
+		catalog: None,
+	})
+}
+
+/// Creates the EPUB plugin with the given options.
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut entries = Vec::new();
+		for book in &options.books {
+			info!("Building {:?}", &book.output);
+			let meta = Metadata::new(book, &ir);
+			let pages = ir
+				.query()
+				.filter("collection", book.collection.as_str())
+				.filter_by(|p, _| has_extension(p, HTML_EXTENSIONS))
+				.sort_by("order")
+				.run();
+			debug!("{:?} has {} chapters", &book.output, pages.len());
+			let modified = pages
+				.iter()
+				.filter_map(|(_, f)| match get_date(&f.frontmatter, "updated") {
+					Ok(Some(date)) => Some(date),
+					_ => get_date(&f.frontmatter, "date").ok().flatten(),
+				})
+				.max()
+				.map_or("1970-01-01".to_string(), |d| d.to_string());
+			let mut images = Vec::new();
+			let chapters: Vec<Chapter> = pages
+				.into_iter()
+				.map(|(path, file)| Chapter::new(path, file, &ir.files, &mut images))
+				.collect();
+			let modified = format!("{}T00:00:00Z", modified);
+			let content =
+				epub(&meta, &modified, &chapters, &images).expect("Writing to memory failed");
+			update_hash.insert(
+				book.output.clone(),
+				ShFile {
+					content,
+					..ShFile::empty()
+				},
+			);
+			entries.push(opds_entry(
+				&meta,
+				&modified,
+				&ir.site.absolute_url(&url(&book.output)),
+			));
+		}
+		if let Some(catalog) = &options.catalog {
+			let updated = entries
+				.iter()
+				.map(|(_, modified)| modified.as_str())
+				.max()
+				.unwrap_or("1970-01-01T00:00:00Z");
+			let feed = format!(
+				"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+				 <feed xmlns=\"http://www.w3.org/2005/Atom\">\n  \
+				 <id>{}</id>\n  <title>{}</title>\n  <updated>{}</updated>\n  \
+				 <link rel=\"self\" href=\"{}\" \
+				 type=\"application/atom+xml;profile=opds-catalog;kind=acquisition\"/>\n\
+				 {}</feed>\n",
+				escape(&ir.site.absolute_url(&url(catalog))),
+				escape(ir.site.title.as_deref().unwrap_or("Books")),
+				updated,
+				escape(&ir.site.absolute_url(&url(catalog))),
+				entries.iter().map(|(e, _)| e.as_str()).collect::<String>()
+			);
+			update_hash.insert(
+				catalog.clone(),
+				ShFile {
+					content: feed.into_bytes(),
+					..ShFile::empty()
+				},
+			);
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+// The metadata of a book with all defaults applied.
+struct Metadata {
+	identifier: String,
+	title: String,
+	author: Option<String>,
+	language: String,
+}
+
+impl Metadata {
+	fn new(book: &Book, ir: &IR) -> Metadata {
+		Metadata {
+			identifier: book
+				.identifier
+				.clone()
+				.unwrap_or_else(|| ir.site.absolute_url(&url(&book.output))),
+			title: book
+				.title
+				.clone()
+				.or_else(|| ir.site.title.clone())
+				.unwrap_or_else(|| book.collection.clone()),
+			author: book.author.clone().or_else(|| ir.site.author.clone()),
+			language: book
+				.language
+				.clone()
+				.or_else(|| ir.site.language.clone())
+				.unwrap_or_else(|| "en".into()),
+		}
+	}
+}
+
+struct Chapter {
+	title: String,
+	body: String,
+}
+
+// An image shown by a chapter, bundled with the book.
+struct Image<'a> {
+	path: PathBuf,
+	href: String,
+	media_type: &'a str,
+	content: &'a [u8],
+}
+
+impl Chapter {
+	// Images the chapter shows are added to `images`, unless an earlier
+	// chapter showed them already.
+	fn new<'a>(
+		path: &Path,
+		file: &ShFile,
+		files: &'a HashMap<PathBuf, ShFile>,
+		images: &mut Vec<Image<'a>>,
+	) -> Chapter {
+		let title = match get_str(&file.frontmatter, "title") {
+			Ok(Some(title)) => title,
+			_ => path
+				.file_stem()
+				.unwrap_or_default()
+				.to_string_lossy()
+				.into_owned(),
+		};
+		let html = String::from_utf8_lossy(&file.content);
+		let body = xhtml::body(&html, |src| {
+			let target = resolve(path, src, files)?;
+			let image = files.get(&target)?;
+			if let Some(known) = images.iter().find(|i| i.path == target) {
+				return Some(known.href.clone());
+			}
+			let media_type = image
+				.mime
+				.as_deref()
+				.or_else(|| mime::from_extension(&target))
+				.filter(|m| m.starts_with("image/"))?;
+			let extension = target
+				.extension()
+				.map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+			let href = format!("images/image-{}{}", images.len() + 1, extension);
+			debug!("Bundling {:?} as {}", target, &href);
+			images.push(Image {
+				path: target,
+				href: href.clone(),
+				media_type,
+				content: &image.content,
+			});
+			Some(href)
+		});
+		Chapter { title, body }
+	}
+}
+
+fn epub(
+	meta: &Metadata,
+	modified: &str,
+	chapters: &[Chapter],
+	images: &[Image],
+) -> zip::result::ZipResult<Vec<u8>> {
+	let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+	// The mimetype has to come first and mustn't be compressed.
+	let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+	let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+	zip.start_file("mimetype", stored)?;
+	zip.write_all(b"application/epub+zip")?;
+	zip.start_file("META-INF/container.xml", deflated)?;
+	zip.write_all(CONTAINER.as_bytes())?;
+	zip.start_file("OEBPS/content.opf", deflated)?;
+	zip.write_all(package(meta, modified, chapters, images).as_bytes())?;
+	zip.start_file("OEBPS/nav.xhtml", deflated)?;
+	let items: String = chapters
+		.iter()
+		.enumerate()
+		.map(|(i, c)| {
+			format!(
+				"      <li><a href=\"chapter-{}.xhtml\">{}</a></li>\n",
+				i + 1,
+				escape(&c.title)
+			)
+		})
+		.collect();
+	let nav = format!(
+		"<nav epub:type=\"toc\">\n    <ol>\n{}    </ol>\n  </nav>",
+		items
+	);
+	zip.write_all(document(&meta.language, &meta.title, &nav).as_bytes())?;
+	for (i, chapter) in chapters.iter().enumerate() {
+		zip.start_file(format!("OEBPS/chapter-{}.xhtml", i + 1), deflated)?;
+		let doc = document(&meta.language, &chapter.title, &chapter.body);
+		zip.write_all(doc.as_bytes())?;
+	}
+	for image in images {
+		zip.start_file(format!("OEBPS/{}", &image.href), deflated)?;
+		zip.write_all(image.content)?;
+	}
+	Ok(zip.finish()?.into_inner())
+}
+
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+	<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n  \
+	<rootfiles>\n    \
+	<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n  \
+	</rootfiles>\n\
+	</container>\n";
+
+fn package(meta: &Metadata, modified: &str, chapters: &[Chapter], images: &[Image]) -> String {
+	let creator = meta.author.as_ref().map_or(String::new(), |author| {
+		format!("    <dc:creator>{}</dc:creator>\n", escape(author))
+	});
+	let mut manifest: String = (1..=chapters.len())
+		.map(|i| {
+			format!(
+				"    <item id=\"chapter-{0}\" href=\"chapter-{0}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+				i
+			)
+		})
+		.collect();
+	for (i, image) in images.iter().enumerate() {
+		manifest.push_str(&format!(
+			"    <item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+			i + 1,
+			escape(&image.href),
+			image.media_type
+		));
+	}
+	let spine: String = (1..=chapters.len())
+		.map(|i| format!("    <itemref idref=\"chapter-{}\"/>\n", i))
+		.collect();
+	format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		 <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n  \
+		 <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n    \
+		 <dc:identifier id=\"id\">{}</dc:identifier>\n    \
+		 <dc:title>{}</dc:title>\n    \
+		 <dc:language>{}</dc:language>\n\
+		 {}    \
+		 <meta property=\"dcterms:modified\">{}</meta>\n  \
+		 </metadata>\n  \
+		 <manifest>\n    \
+		 <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+		 {}  </manifest>\n  \
+		 <spine>\n{}  </spine>\n\
+		 </package>\n",
+		escape(&meta.identifier),
+		escape(&meta.title),
+		escape(&meta.language),
+		creator,
+		modified,
+		manifest,
+		spine
+	)
+}
+
+fn document(language: &str, title: &str, body: &str) -> String {
+	format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		 <!DOCTYPE html>\n\
+		 <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+		 lang=\"{0}\" xml:lang=\"{0}\">\n\
+		 <head>\n  <title>{1}</title>\n</head>\n\
+		 <body>\n  {2}\n</body>\n\
+		 </html>\n",
+		escape(language),
+		escape(title),
+		body
+	)
+}
+
+fn opds_entry(meta: &Metadata, modified: &str, url: &str) -> (String, String) {
+	let author = meta.author.as_ref().map_or(String::new(), |author| {
+		format!("    <author><name>{}</name></author>\n", escape(author))
+	});
+	let entry = format!(
+		"  <entry>\n    <id>{}</id>\n    <title>{}</title>\n{}    <updated>{}</updated>\n    \
+		 <dc:language xmlns:dc=\"http://purl.org/dc/terms/\">{}</dc:language>\n    \
+		 <link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"application/epub+zip\"/>\n  \
+		 </entry>\n",
+		escape(&meta.identifier),
+		escape(&meta.title),
+		author,
+		modified,
+		escape(&meta.language),
+		escape(url)
+	);
+	(entry, modified.to_string())
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+#[test]
+fn it_works() {
+	use shtola::site::SiteConfig;
+	use shtola::Shtola;
+	use std::io::Read;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/epub");
+	s.destination("../fixtures/dest_epub");
+	s.site(SiteConfig {
+		title: Some("A Serial".into()),
+		base_url: Some("https://example.com".into()),
+		author: Some("marisa".into()),
+		..Default::default()
+	});
+	s.register(with_options(Options {
+		books: vec![Book::new("novel", "novel.epub")],
+		catalog: Some("opds.xml".into()),
+	}));
+	let r = s.build().unwrap();
+	let epub = r.files.get(Path::new("novel.epub")).unwrap();
+	let mut archive = zip::ZipArchive::new(Cursor::new(epub.content.clone())).unwrap();
+	assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+	let mut read = |name: &str| {
+		let mut text = String::new();
+		archive
+			.by_name(name)
+			.unwrap()
+			.read_to_string(&mut text)
+			.unwrap();
+		text
+	};
+	let opf = read("OEBPS/content.opf");
+	assert!(opf.contains("<dc:identifier id=\"id\">https://example.com/novel.epub</dc:identifier>"));
+	assert!(opf.contains("<dc:creator>marisa</dc:creator>"));
+	assert!(opf.contains("<meta property=\"dcterms:modified\">2020-02-01T00:00:00Z</meta>"));
+	let nav = read("OEBPS/nav.xhtml");
+	let first = nav.find("The Beginning").unwrap();
+	let second = nav.find("The Middle").unwrap();
+	assert!(first < second);
+	let chapter = read("OEBPS/chapter-1.xhtml");
+	assert!(chapter.contains("<p>It was a dark<br/>and stormy&#160;night.</p>"));
+	assert!(!chapter.contains("<nav>"));
+	// Markup that isn't well-formed is fixed, and images are bundled.
+	let chapter = read("OEBPS/chapter-2.xhtml");
+	assert!(chapter.contains("<img src=\"images/image-1.png\" alt=\"Map\"/>\n</p>"));
+	assert!(chapter.contains("<ul><li>Café &amp; tea</li><li>Rest</li></ul>"));
+	assert!(chapter.contains("<input type=\"checkbox\" checked=\"\" disabled=\"\"/>"));
+	assert!(
+		opf.contains("<item id=\"image-1\" href=\"images/image-1.png\" media-type=\"image/png\"/>")
+	);
+	let mut image = Vec::new();
+	archive
+		.by_name("OEBPS/images/image-1.png")
+		.unwrap()
+		.read_to_end(&mut image)
+		.unwrap();
+	assert_eq!(image, std::fs::read("../fixtures/epub/map.png").unwrap());
+	assert!(archive.by_name("OEBPS/chapter-3.xhtml").is_err());
+	let catalog = r.files.get(Path::new("opds.xml")).unwrap();
+	let catalog = std::str::from_utf8(&catalog.content).unwrap();
+	assert!(
+		catalog.contains("href=\"https://example.com/novel.epub\" type=\"application/epub+zip\"")
+	);
+}
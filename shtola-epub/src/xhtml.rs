@@ -0,0 +1,331 @@
+//! Turns HTML pages into XHTML chapters. Pages are parsed like browsers do,
+//! so named entities, boolean attributes and elements that HTML closes
+//! implicitly, like `<p>` and `<li>`, are written as well-formed XML.
+
+use html5ever::interface::tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink};
+use html5ever::tendril::{StrTendril, TendrilSink};
+use html5ever::{local_name, ns, parse_document, Attribute, QualName};
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+
+type Handle = usize;
+
+const DOCUMENT: Handle = 0;
+
+enum Data {
+	Document,
+	Text(String),
+	Comment(String),
+	Element {
+		name: QualName,
+		attrs: Vec<Attribute>,
+		template: Option<Handle>,
+	},
+	// Doctypes and processing instructions, which aren't written.
+	Other,
+}
+
+struct Node {
+	parent: Option<Handle>,
+	children: Vec<Handle>,
+	data: Data,
+}
+
+// The parsed document, with its nodes stored by handle.
+struct Dom {
+	nodes: RefCell<Vec<Node>>,
+}
+
+impl Dom {
+	fn new() -> Dom {
+		let dom = Dom {
+			nodes: RefCell::new(Vec::new()),
+		};
+		dom.new_node(Data::Document);
+		dom
+	}
+
+	fn new_node(&self, data: Data) -> Handle {
+		let mut nodes = self.nodes.borrow_mut();
+		nodes.push(Node {
+			parent: None,
+			children: Vec::new(),
+			data,
+		});
+		nodes.len() - 1
+	}
+
+	fn detach(&self, node: Handle) {
+		let mut nodes = self.nodes.borrow_mut();
+		if let Some(parent) = nodes[node].parent.take() {
+			nodes[parent].children.retain(|&c| c != node);
+		}
+	}
+
+	// Inserts a child at a position of the parent, merging adjacent text.
+	fn insert(&self, parent: Handle, index: usize, child: NodeOrText<Handle>) {
+		let node = match child {
+			NodeOrText::AppendText(text) => {
+				let mut nodes = self.nodes.borrow_mut();
+				let previous = index.checked_sub(1).map(|i| nodes[parent].children[i]);
+				if let Some(previous) = previous {
+					if let Data::Text(existing) = &mut nodes[previous].data {
+						existing.push_str(&text);
+						return;
+					}
+				}
+				drop(nodes);
+				self.new_node(Data::Text(text.to_string()))
+			}
+			NodeOrText::AppendNode(node) => node,
+		};
+		self.detach(node);
+		let mut nodes = self.nodes.borrow_mut();
+		let index = index.min(nodes[parent].children.len());
+		nodes[parent].children.insert(index, node);
+		nodes[node].parent = Some(parent);
+	}
+
+	fn position(&self, node: Handle) -> Option<(Handle, usize)> {
+		let nodes = self.nodes.borrow();
+		let parent = nodes[node].parent?;
+		let index = nodes[parent].children.iter().position(|&c| c == node)?;
+		Some((parent, index))
+	}
+}
+
+impl TreeSink for Dom {
+	type Handle = Handle;
+	type Output = Dom;
+	type ElemName<'a> = Ref<'a, QualName>;
+
+	fn finish(self) -> Dom {
+		self
+	}
+
+	fn parse_error(&self, _: Cow<'static, str>) {}
+
+	fn get_document(&self) -> Handle {
+		DOCUMENT
+	}
+
+	fn elem_name<'a>(&'a self, target: &'a Handle) -> Ref<'a, QualName> {
+		Ref::map(self.nodes.borrow(), |nodes| match &nodes[*target].data {
+			Data::Element { name, .. } => name,
+			_ => panic!("Not an element"),
+		})
+	}
+
+	fn create_element(&self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> Handle {
+		let template = if flags.template {
+			Some(self.new_node(Data::Document))
+		} else {
+			None
+		};
+		self.new_node(Data::Element {
+			name,
+			attrs,
+			template,
+		})
+	}
+
+	fn create_comment(&self, text: StrTendril) -> Handle {
+		self.new_node(Data::Comment(text.to_string()))
+	}
+
+	fn create_pi(&self, _: StrTendril, _: StrTendril) -> Handle {
+		self.new_node(Data::Other)
+	}
+
+	fn append(&self, parent: &Handle, child: NodeOrText<Handle>) {
+		let index = self.nodes.borrow()[*parent].children.len();
+		self.insert(*parent, index, child);
+	}
+
+	fn append_based_on_parent_node(
+		&self,
+		element: &Handle,
+		prev_element: &Handle,
+		child: NodeOrText<Handle>,
+	) {
+		if self.nodes.borrow()[*element].parent.is_some() {
+			self.append_before_sibling(element, child)
+		} else {
+			self.append(prev_element, child)
+		}
+	}
+
+	fn append_doctype_to_document(&self, _: StrTendril, _: StrTendril, _: StrTendril) {
+		let doctype = self.new_node(Data::Other);
+		self.append(&DOCUMENT, NodeOrText::AppendNode(doctype));
+	}
+
+	fn get_template_contents(&self, target: &Handle) -> Handle {
+		match &self.nodes.borrow()[*target].data {
+			Data::Element {
+				template: Some(contents),
+				..
+			} => *contents,
+			_ => panic!("Not a template element"),
+		}
+	}
+
+	fn same_node(&self, x: &Handle, y: &Handle) -> bool {
+		x == y
+	}
+
+	fn set_quirks_mode(&self, _: QuirksMode) {}
+
+	fn append_before_sibling(&self, sibling: &Handle, child: NodeOrText<Handle>) {
+		if let Some((parent, index)) = self.position(*sibling) {
+			self.insert(parent, index, child);
+		}
+	}
+
+	fn add_attrs_if_missing(&self, target: &Handle, attrs: Vec<Attribute>) {
+		if let Data::Element {
+			attrs: existing, ..
+		} = &mut self.nodes.borrow_mut()[*target].data
+		{
+			for attr in attrs {
+				if !existing.iter().any(|e| e.name == attr.name) {
+					existing.push(attr);
+				}
+			}
+		}
+	}
+
+	fn remove_from_parent(&self, target: &Handle) {
+		self.detach(*target);
+	}
+
+	fn reparent_children(&self, node: &Handle, new_parent: &Handle) {
+		let children = std::mem::take(&mut self.nodes.borrow_mut()[*node].children);
+		for child in children {
+			self.nodes.borrow_mut()[child].parent = None;
+			self.append(new_parent, NodeOrText::AppendNode(child));
+		}
+	}
+}
+
+/// Parses an HTML page and writes the contents of its `body` as XHTML.
+/// `image` is called with the `src` of every image, and returns the URL it's
+/// replaced with in the chapter, or `None` to leave it as it is.
+pub fn body<F>(html: &str, mut image: F) -> String
+where
+	F: FnMut(&str) -> Option<String>,
+{
+	let dom = parse_document(Dom::new(), Default::default()).one(html);
+	let nodes = dom.nodes.into_inner();
+	let mut out = String::new();
+	if let Some(body) = find(&nodes, DOCUMENT, &local_name!("body")) {
+		for &child in &nodes[body].children {
+			write(&nodes, child, &mut image, &mut out);
+		}
+	}
+	out.trim().to_string()
+}
+
+fn find(nodes: &[Node], node: Handle, local: &html5ever::LocalName) -> Option<Handle> {
+	nodes[node]
+		.children
+		.iter()
+		.find_map(|&child| match &nodes[child].data {
+			Data::Element { name, .. } if name.ns == ns!(html) && &name.local == local => {
+				Some(child)
+			}
+			Data::Element { .. } => find(nodes, child, local),
+			_ => None,
+		})
+}
+
+fn write<F>(nodes: &[Node], node: Handle, image: &mut F, out: &mut String)
+where
+	F: FnMut(&str) -> Option<String>,
+{
+	match &nodes[node].data {
+		Data::Text(text) => out.push_str(&escape(text, false)),
+		Data::Comment(text) => {
+			// Comments can't contain `--` in XML.
+			out.push_str("<!--");
+			out.push_str(&text.replace("--", "- -"));
+			out.push_str("-->");
+		}
+		Data::Element { name, attrs, .. } => {
+			let tag = qualified(name.prefix.as_deref(), &name.local);
+			out.push('<');
+			out.push_str(&tag);
+			// Foreign elements inside HTML need their namespace declared.
+			let parent_ns = nodes[node].parent.and_then(|p| match &nodes[p].data {
+				Data::Element { name, .. } => Some(&name.ns),
+				_ => None,
+			});
+			if name.ns != ns!(html) && parent_ns != Some(&name.ns) {
+				out.push_str(&format!(" xmlns=\"{}\"", escape(&name.ns, true)));
+				if name.ns == ns!(svg) {
+					out.push_str(" xmlns:xlink=\"http://www.w3.org/1999/xlink\"");
+				}
+			}
+			let is_image = name.ns == ns!(html) && name.local == local_name!("img");
+			for attr in attrs {
+				let attr_name = qualified(attr.name.prefix.as_deref(), &attr.name.local);
+				// `xmlns` attributes are written above, only for foreign elements.
+				if attr_name == "xmlns" || attr_name.starts_with("xmlns:") {
+					continue;
+				}
+				let value = if is_image && attr.name.local == local_name!("src") {
+					image(&attr.value).unwrap_or_else(|| attr.value.to_string())
+				} else {
+					attr.value.to_string()
+				};
+				out.push_str(&format!(" {}=\"{}\"", attr_name, escape(&value, true)));
+			}
+			if nodes[node].children.is_empty() && (name.ns != ns!(html) || is_void(&name.local)) {
+				out.push_str("/>");
+				return;
+			}
+			out.push('>');
+			for &child in &nodes[node].children {
+				write(nodes, child, image, out);
+			}
+			out.push_str("</");
+			out.push_str(&tag);
+			out.push('>');
+		}
+		Data::Document | Data::Other => {}
+	}
+}
+
+fn qualified(prefix: Option<&str>, local: &str) -> String {
+	match prefix {
+		Some(prefix) => format!("{}:{}", prefix, local),
+		None => local.to_string(),
+	}
+}
+
+// Elements HTML doesn't allow content in.
+const VOID: &[&str] = &[
+	"area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+	"wbr",
+];
+
+fn is_void(local: &str) -> bool {
+	VOID.contains(&local)
+}
+
+// Escapes text for XML. Non-breaking spaces are written as references, so
+// they stay visible in the source.
+fn escape(text: &str, attribute: bool) -> String {
+	let mut out = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' if attribute => out.push_str("&quot;"),
+			'\u{a0}' => out.push_str("&#160;"),
+			c => out.push(c),
+		}
+	}
+	out
+}
@@ -0,0 +1,493 @@
+//! Strips metadata from JPEG and PNG images, since photos taken with a phone
+//! carry where and when they were taken, and with which device. The
+//! [`plugin`](fn.plugin.html) removes it from every image in the IR:
+//!
+//! ```
+//! use shtola::Shtola;
+//!
+//! let mut s = Shtola::new();
+//! s.register(shtola_exif::plugin());
+//! ```
+//!
+//! EXIF tags on the [`keep`](struct.Options.html#structfield.keep) allowlist
+//! stay, which by default is only the orientation, so photos aren't shown
+//! sideways. Everything else goes: the rest of the EXIF data including GPS
+//! positions and thumbnails, XMP and Photoshop metadata and comments in JPEG
+//! files, and text chunks in PNG files. The image data itself and color
+//! profiles are left untouched.
+//!
+//! Images that can't be read are failed with
+//! [`IR::fail_file`](../shtola/struct.IR.html#method.fail_file) instead of
+//! being published with their metadata.
+
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, IR};
+use std::convert::{TryFrom, TryInto};
+use std::path::PathBuf;
+
+/// Common EXIF tags, for use in [`Options::keep`](struct.Options.html#structfield.keep).
+pub mod tags {
+	/// How the image is rotated.
+	pub const ORIENTATION: u16 = 0x0112;
+	/// The device manufacturer.
+	pub const MAKE: u16 = 0x010f;
+	/// The device model.
+	pub const MODEL: u16 = 0x0110;
+	/// When the file was last changed.
+	pub const DATE_TIME: u16 = 0x0132;
+	/// Who created the image.
+	pub const ARTIST: u16 = 0x013b;
+	/// The copyright notice.
+	pub const COPYRIGHT: u16 = 0x8298;
+	/// When the photo was taken, in the EXIF sub-IFD.
+	pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+	/// Points to the EXIF sub-IFD. Kept when any of its tags are.
+	pub const EXIF_IFD: u16 = 0x8769;
+	/// Points to the GPS sub-IFD. Kept when any of its tags are.
+	pub const GPS_IFD: u16 = 0x8825;
+	/// Points to the interoperability sub-IFD, which is always removed.
+	pub const INTEROP_IFD: u16 = 0xa005;
+}
+
+/// Options for the metadata stripping plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The extensions of the images to strip. Default is `jpg`, `jpeg` and
+	/// `png`.
+	pub extensions: Vec<String>,
+	/// The EXIF tags to keep, from the main IFD or the EXIF and GPS sub-IFDs.
+	/// Default is only [`tags::ORIENTATION`](tags/constant.ORIENTATION.html).
+	pub keep: Vec<u16>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			extensions: vec!["jpg".into(), "jpeg".into(), "png".into()],
+			keep: vec![tags::ORIENTATION],
+		}
+	}
+}
+
+/// Creates the metadata stripping plugin with the default options.
+pub fn plugin() -> Plugin {
+	with_options(Options::default())
+}
+
+/// Creates the metadata stripping plugin.
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Stripping image metadata");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files.iter() {
+			let included = path
+				.extension()
+				.and_then(|e| e.to_str())
+				.map(str::to_lowercase)
+				.is_some_and(|e| options.extensions.contains(&e));
+			if !included {
+				continue;
+			}
+			match strip(&file.content, &options.keep) {
+				Ok(content) if content == file.content => {}
+				Ok(content) => {
+					debug!("Stripped metadata from {:?}", path);
+					update_hash.insert(
+						path.clone(),
+						ShFile {
+							content,
+							..file.clone()
+						},
+					);
+				}
+				Err(e) => failures.push((path.clone(), e)),
+			}
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message))
+	})
+}
+
+/// Strips metadata from a JPEG or PNG image, keeping only the EXIF tags in
+/// `keep`.
+///
+/// ```
+/// let png = [
+///   0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a,
+///   0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82,
+/// ];
+/// assert_eq!(shtola_exif::strip(&png, &[]).unwrap(), png);
+/// assert!(shtola_exif::strip(b"GIF89a", &[]).is_err());
+/// ```
+pub fn strip(content: &[u8], keep: &[u16]) -> Result<Vec<u8>, String> {
+	if content.starts_with(&[0xff, 0xd8]) {
+		jpeg(content, keep).ok_or_else(|| "Invalid JPEG image".into())
+	} else if content.starts_with(PNG) {
+		png(content, keep).ok_or_else(|| "Invalid PNG image".into())
+	} else {
+		Err("Not a JPEG or PNG image".into())
+	}
+}
+
+const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const EXIF: &[u8] = b"Exif\0\0";
+
+fn jpeg(content: &[u8], keep: &[u16]) -> Option<Vec<u8>> {
+	let mut out = content[..2].to_vec();
+	let mut pos = 2;
+	loop {
+		if *content.get(pos)? != 0xff {
+			return None;
+		}
+		let marker = *content.get(pos + 1)?;
+		match marker {
+			// Padding before a marker.
+			0xff => {
+				pos += 1;
+				continue;
+			}
+			// Markers without a length.
+			0x01 | 0xd0..=0xd7 => {
+				out.extend_from_slice(&content[pos..pos + 2]);
+				pos += 2;
+				continue;
+			}
+			// After the start of scan, everything is image data.
+			0xda | 0xd9 => {
+				out.extend_from_slice(&content[pos..]);
+				return Some(out);
+			}
+			_ => {}
+		}
+		let length = u16::from_be_bytes([*content.get(pos + 2)?, *content.get(pos + 3)?]) as usize;
+		let segment = content.get(pos..pos + 2 + length)?;
+		let data = segment.get(4..)?;
+		match marker {
+			0xe1 if data.starts_with(EXIF) => {
+				if let Some(tiff) = tiff(&data[EXIF.len()..], keep)? {
+					let length = 2 + EXIF.len() + tiff.len();
+					out.extend_from_slice(&[0xff, 0xe1]);
+					out.extend_from_slice(&u16::try_from(length).ok()?.to_be_bytes());
+					out.extend_from_slice(EXIF);
+					out.extend_from_slice(&tiff);
+				}
+			}
+			// XMP and other APP1 data, Photoshop data and comments.
+			0xe1 | 0xed | 0xfe => {}
+			_ => out.extend_from_slice(segment),
+		}
+		pos += 2 + length;
+	}
+}
+
+fn png(content: &[u8], keep: &[u16]) -> Option<Vec<u8>> {
+	let mut out = PNG.to_vec();
+	let mut pos = PNG.len();
+	while pos < content.len() {
+		let length = u32::from_be_bytes(content.get(pos..pos + 4)?.try_into().ok()?) as usize;
+		let chunk = content.get(pos..pos + 12 + length)?;
+		let kind = &chunk[4..8];
+		let data = &chunk[8..8 + length];
+		match kind {
+			b"eXIf" => {
+				if let Some(tiff) = tiff(data, keep)? {
+					let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+					chunk.extend_from_slice(b"eXIf");
+					chunk.extend_from_slice(&tiff);
+					let crc = crc32(&chunk[4..]);
+					chunk.extend_from_slice(&crc.to_be_bytes());
+					out.extend_from_slice(&chunk);
+				}
+			}
+			b"tEXt" | b"zTXt" | b"iTXt" => {}
+			_ => out.extend_from_slice(chunk),
+		}
+		pos += 12 + length;
+	}
+	Some(out)
+}
+
+// A TIFF directory entry. Sub-IFDs are read into `sub`, other values are kept
+// as their raw bytes.
+struct Entry {
+	tag: u16,
+	kind: u16,
+	count: u32,
+	value: Vec<u8>,
+	sub: Option<Vec<Entry>>,
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	big: bool,
+}
+
+impl Reader<'_> {
+	fn u16(&self, pos: usize) -> Option<u16> {
+		let bytes = self.data.get(pos..pos + 2)?.try_into().ok()?;
+		Some(if self.big {
+			u16::from_be_bytes(bytes)
+		} else {
+			u16::from_le_bytes(bytes)
+		})
+	}
+
+	fn u32(&self, pos: usize) -> Option<u32> {
+		let bytes = self.data.get(pos..pos + 4)?.try_into().ok()?;
+		Some(if self.big {
+			u32::from_be_bytes(bytes)
+		} else {
+			u32::from_le_bytes(bytes)
+		})
+	}
+
+	// Reads the allowed entries of the IFD at `pos`. The next IFD, which holds
+	// the thumbnail, is never read.
+	fn ifd(&self, pos: usize, keep: &[u16], depth: usize) -> Option<Vec<Entry>> {
+		let count = self.u16(pos)? as usize;
+		let mut entries = Vec::new();
+		for i in 0..count {
+			let at = pos + 2 + i * 12;
+			let tag = self.u16(at)?;
+			let kind = self.u16(at + 2)?;
+			let count = self.u32(at + 4)?;
+			if tag == tags::EXIF_IFD || tag == tags::GPS_IFD {
+				// Loops of sub-IFDs only happen in broken files.
+				if depth > 0 {
+					return None;
+				}
+				let sub = self.ifd(self.u32(at + 8)? as usize, keep, depth + 1)?;
+				if !sub.is_empty() {
+					entries.push(Entry {
+						tag,
+						kind: 4,
+						count: 1,
+						value: Vec::new(),
+						sub: Some(sub),
+					});
+				}
+				continue;
+			}
+			if tag == tags::INTEROP_IFD || !keep.contains(&tag) {
+				continue;
+			}
+			let size = match kind {
+				1 | 2 | 6 | 7 => 1,
+				3 | 8 => 2,
+				4 | 9 | 11 => 4,
+				5 | 10 | 12 => 8,
+				_ => return None,
+			};
+			let length = size * count as usize;
+			let start = if length <= 4 {
+				at + 8
+			} else {
+				self.u32(at + 8)? as usize
+			};
+			entries.push(Entry {
+				tag,
+				kind,
+				count,
+				value: self.data.get(start..start + length)?.to_vec(),
+				sub: None,
+			});
+		}
+		Some(entries)
+	}
+}
+
+// Rewrites TIFF data, like the EXIF data of an image, with only the allowed
+// tags. Returns `None` inside if nothing is left.
+fn tiff(data: &[u8], keep: &[u16]) -> Option<Option<Vec<u8>>> {
+	let big = match data.get(..4)? {
+		b"II*\0" => false,
+		b"MM\0*" => true,
+		_ => return None,
+	};
+	let reader = Reader { data, big };
+	let entries = reader.ifd(reader.u32(4)? as usize, keep, 0)?;
+	if entries.is_empty() {
+		return Some(None);
+	}
+	let mut out = data[..4].to_vec();
+	write_u32(&mut out, 8, big);
+	write_ifd(&mut out, &entries, big);
+	Some(Some(out))
+}
+
+// Appends an IFD and its values, returning where it starts.
+fn write_ifd(out: &mut Vec<u8>, entries: &[Entry], big: bool) -> u32 {
+	let start = out.len();
+	write_u16(out, entries.len() as u16, big);
+	out.resize(start + 2 + entries.len() * 12, 0);
+	// There's no next IFD.
+	write_u32(out, 0, big);
+	for (i, entry) in entries.iter().enumerate() {
+		let value = match &entry.sub {
+			Some(sub) => {
+				let offset = write_ifd(out, sub, big);
+				to_bytes_u32(offset, big).to_vec()
+			}
+			None if entry.value.len() <= 4 => {
+				let mut value = entry.value.clone();
+				value.resize(4, 0);
+				value
+			}
+			None => {
+				let offset = out.len() as u32;
+				out.extend_from_slice(&entry.value);
+				// Values start on word boundaries.
+				if out.len() % 2 == 1 {
+					out.push(0);
+				}
+				to_bytes_u32(offset, big).to_vec()
+			}
+		};
+		let mut slot = Vec::with_capacity(12);
+		write_u16(&mut slot, entry.tag, big);
+		write_u16(&mut slot, entry.kind, big);
+		write_u32(&mut slot, entry.count, big);
+		slot.extend_from_slice(&value);
+		let at = start + 2 + i * 12;
+		out[at..at + 12].copy_from_slice(&slot);
+	}
+	start as u32
+}
+
+fn write_u16(out: &mut Vec<u8>, n: u16, big: bool) {
+	out.extend_from_slice(&if big {
+		n.to_be_bytes()
+	} else {
+		n.to_le_bytes()
+	});
+}
+
+fn write_u32(out: &mut Vec<u8>, n: u32, big: bool) {
+	out.extend_from_slice(&to_bytes_u32(n, big));
+}
+
+fn to_bytes_u32(n: u32, big: bool) -> [u8; 4] {
+	if big {
+		n.to_be_bytes()
+	} else {
+		n.to_le_bytes()
+	}
+}
+
+// The CRC-32 that PNG chunks end with.
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = 0xffff_ffffu32;
+	for byte in data {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ 0xedb8_8320
+			} else {
+				crc >> 1
+			};
+		}
+	}
+	!crc
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+	use std::fs;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/exif");
+	s.destination("../fixtures/dest_exif");
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let content = |path: &str| r.files.get(Path::new(path)).unwrap().content.clone();
+	let original = fs::read("../fixtures/exif/photo.jpg").unwrap();
+	let photo = content("photo.jpg");
+	let contains =
+		|haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+	assert!(contains(&original, b"Phone Maker"));
+	assert!(!contains(&photo, b"Phone Maker"));
+	assert!(!contains(&photo, b"http://ns.adobe.com/xap/1.0/"));
+	assert!(!contains(&photo, b"Shot on a phone"));
+	// Only the orientation is left.
+	let start = photo.windows(EXIF.len()).position(|w| w == EXIF).unwrap() + EXIF.len();
+	let tiff = &photo[start..];
+	let reader = Reader {
+		data: tiff,
+		big: true,
+	};
+	let all: Vec<u16> = (0..=u16::MAX).collect();
+	let entries = reader.ifd(8, &all, 0).unwrap();
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].value, vec![0, 6]);
+	// The image data stays the same.
+	let scan =
+		|image: &[u8]| image[image.windows(2).position(|w| w == [0xff, 0xda]).unwrap()..].to_vec();
+	assert_eq!(scan(&photo), scan(&original));
+	assert!(photo.starts_with(&original[..20]));
+
+	let logo = content("logo.png");
+	assert_eq!(logo, fs::read("../fixtures/binary/logo.png").unwrap());
+	assert!(r.files.get(Path::new("notes.md")).is_some());
+}
+
+#[test]
+fn allowlist_works() {
+	use std::fs;
+
+	let original = fs::read("../fixtures/exif/photo.jpg").unwrap();
+	let start = original
+		.windows(EXIF.len())
+		.position(|w| w == EXIF)
+		.unwrap()
+		+ EXIF.len();
+	let keep = [tags::MAKE, tags::DATE_TIME_ORIGINAL];
+	let photo = strip(&original, &keep).unwrap();
+	let start_stripped = photo.windows(EXIF.len()).position(|w| w == EXIF).unwrap() + EXIF.len();
+	let read = |data: &[u8]| {
+		let reader = Reader { data, big: true };
+		let entries = reader.ifd(8, &keep, 0).unwrap();
+		entries
+			.iter()
+			.map(|e| {
+				let sub = e.sub.as_ref().map(|s| {
+					s.iter()
+						.map(|e| (e.tag, e.value.clone()))
+						.collect::<Vec<_>>()
+				});
+				(e.tag, e.value.clone(), sub)
+			})
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(read(&photo[start_stripped..]), read(&original[start..]));
+	assert_eq!(
+		read(&photo[start_stripped..]),
+		vec![
+			(tags::MAKE, b"Phone Maker\0".to_vec(), None),
+			(
+				tags::EXIF_IFD,
+				vec![],
+				Some(vec![(
+					tags::DATE_TIME_ORIGINAL,
+					b"2020:01:31 10:00:00\0".to_vec()
+				)])
+			),
+		]
+	);
+	assert!(strip(&original[..100], &keep).is_err());
+
+	// PNG files keep the EXIF chunk too, with a valid checksum.
+	let png = strip(&fs::read("../fixtures/exif/screenshot.png").unwrap(), &keep).unwrap();
+	let at = png.windows(4).position(|w| w == b"eXIf").unwrap();
+	let length = u32::from_be_bytes(png[at - 4..at].try_into().unwrap()) as usize;
+	let crc = u32::from_be_bytes(png[at + 4 + length..at + 8 + length].try_into().unwrap());
+	assert_eq!(crc, crc32(&png[at..at + 4 + length]));
+	assert!(!png.windows(4).any(|w| w == b"tEXt"));
+}
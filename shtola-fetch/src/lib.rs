@@ -0,0 +1,295 @@
+//! Fetching remote resources during builds, for plugins importing feeds,
+//! webmentions or remote includes. The [`Client`](struct.Client.html) keeps
+//! every response in an on-disk cache, so builds stay fast and keep working
+//! without network access:
+//!
+//! ```no_run
+//! use shtola_fetch::{Client, Options};
+//!
+//! let client = Client::new(Options::default());
+//! let feed = client.get("https://example.com/feed.xml").unwrap();
+//! println!("{} bytes", feed.body.len());
+//! ```
+//!
+//! Cached responses are revalidated with conditional requests using their
+//! `ETag` and `Last-Modified` headers. If the server can't be reached, the
+//! cached response is used instead. In offline mode, which is also enabled by
+//! setting the `SHTOLA_OFFLINE` environment variable, only the cache is used.
+//!
+//! Clients are cheap to clone and share their connections, so one client can
+//! be created per build and moved into all plugins that need it.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shtola::json;
+use shtola::log::{debug, info, warn};
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Options for the client.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The directory cached responses are stored in. Default is
+	/// `.shtola-cache/fetch`.
+	pub cache_dir: PathBuf,
+	/// Whether to only use cached responses. Default is whether the
+	/// `SHTOLA_OFFLINE` environment variable is set.
+	pub offline: bool,
+	/// How long cached responses are used without revalidating them. Default
+	/// is `None`, revalidating on every request.
+	pub max_age: Option<Duration>,
+	/// The timeout of requests. Default is 30 seconds.
+	pub timeout: Duration,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			cache_dir: ".shtola-cache/fetch".into(),
+			offline: std::env::var_os("SHTOLA_OFFLINE").is_some(),
+			max_age: None,
+			timeout: Duration::from_secs(30),
+		}
+	}
+}
+
+/// A fetched resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+	/// The body of the response.
+	pub body: Vec<u8>,
+	/// The `Content-Type` header of the response.
+	pub content_type: Option<String>,
+	/// Whether the body was taken from the cache.
+	pub from_cache: bool,
+}
+
+impl Response {
+	/// The body as text, replacing invalid UTF-8.
+	pub fn text(&self) -> String {
+		String::from_utf8_lossy(&self.body).into_owned()
+	}
+}
+
+/// An error that occurred while fetching a resource.
+#[derive(Debug)]
+pub enum FetchError {
+	/// The resource isn't cached and the client is offline.
+	Offline(String),
+	/// The request failed and the resource isn't cached.
+	Http(String, Box<ureq::Error>),
+	/// Reading or writing the cache failed.
+	Io(std::io::Error),
+}
+
+impl fmt::Display for FetchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			FetchError::Offline(url) => write!(f, "{} isn't cached and the build is offline", url),
+			FetchError::Http(url, e) => write!(f, "Unable to fetch {}: {}", url, e),
+			FetchError::Io(e) => write!(f, "Unable to access the fetch cache: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<std::io::Error> for FetchError {
+	fn from(e: std::io::Error) -> Self {
+		FetchError::Io(e)
+	}
+}
+
+/// What is stored next to a cached body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+	url: String,
+	etag: Option<String>,
+	last_modified: Option<String>,
+	content_type: Option<String>,
+	/// When the response was last fetched or revalidated, in seconds since
+	/// the Unix epoch.
+	fetched: u64,
+}
+
+/// An HTTP client with an on-disk cache.
+#[derive(Clone)]
+pub struct Client {
+	options: Options,
+	agent: ureq::Agent,
+}
+
+impl Client {
+	/// Creates a new client.
+	pub fn new(options: Options) -> Client {
+		let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+		Client { options, agent }
+	}
+
+	/// Fetches a resource with a `GET` request, using the cache as described
+	/// in the [crate documentation](index.html).
+	pub fn get(&self, url: &str) -> Result<Response, FetchError> {
+		let (entry_path, body_path) = self.cache_paths(url);
+		let cached = read_entry(&entry_path).filter(|_| body_path.exists());
+		let cached = match cached {
+			Some(entry) => Some((entry, fs::read(&body_path)?)),
+			None => None,
+		};
+		if let Some((entry, body)) = &cached {
+			let age = now().saturating_sub(entry.fetched);
+			let fresh = self.options.max_age.is_some_and(|max| age < max.as_secs());
+			if self.options.offline || fresh {
+				debug!("Using cached response for {}", url);
+				return Ok(cached_response(entry, body));
+			}
+		} else if self.options.offline {
+			return Err(FetchError::Offline(url.to_string()));
+		}
+
+		info!("Fetching {}", url);
+		let mut request = self.agent.get(url);
+		if let Some((entry, _)) = &cached {
+			if let Some(etag) = &entry.etag {
+				request = request.set("If-None-Match", etag);
+			}
+			if let Some(last_modified) = &entry.last_modified {
+				request = request.set("If-Modified-Since", last_modified);
+			}
+		}
+		let response = match (request.call(), cached) {
+			(Ok(response), Some((entry, body))) if response.status() == 304 => {
+				debug!("{} is unchanged", url);
+				write_entry(
+					&entry_path,
+					&Entry {
+						fetched: now(),
+						..entry.clone()
+					},
+				)?;
+				return Ok(cached_response(&entry, &body));
+			}
+			(Ok(response), _) => response,
+			(Err(e), Some((entry, body))) => {
+				warn!("Unable to fetch {}, using cached response: {}", url, e);
+				return Ok(cached_response(&entry, &body));
+			}
+			(Err(e), None) => return Err(FetchError::Http(url.to_string(), Box::new(e))),
+		};
+		let entry = Entry {
+			url: url.to_string(),
+			etag: response.header("ETag").map(String::from),
+			last_modified: response.header("Last-Modified").map(String::from),
+			content_type: response.header("Content-Type").map(String::from),
+			fetched: now(),
+		};
+		let mut body = Vec::new();
+		response.into_reader().read_to_end(&mut body)?;
+		fs::create_dir_all(&self.options.cache_dir)?;
+		fs::write(&body_path, &body)?;
+		write_entry(&entry_path, &entry)?;
+		Ok(Response {
+			body,
+			content_type: entry.content_type,
+			from_cache: false,
+		})
+	}
+
+	fn cache_paths(&self, url: &str) -> (PathBuf, PathBuf) {
+		let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+		let dir = &self.options.cache_dir;
+		(
+			dir.join(format!("{}.json", key)),
+			dir.join(format!("{}.body", key)),
+		)
+	}
+}
+
+fn cached_response(entry: &Entry, body: &[u8]) -> Response {
+	Response {
+		body: body.to_vec(),
+		content_type: entry.content_type.clone(),
+		from_cache: true,
+	}
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+	let bytes = fs::read(path).ok()?;
+	json::from_slice(&bytes).ok()
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> Result<(), std::io::Error> {
+	let bytes = json::to_vec(entry).map_err(std::io::Error::other)?;
+	fs::write(path, bytes)
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs())
+}
+
+#[test]
+fn it_works() {
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+	let server = thread::spawn(move || {
+		let mut requests = Vec::new();
+		for (i, stream) in listener.incoming().take(2).enumerate() {
+			let mut stream = stream.unwrap();
+			let mut headers = Vec::new();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+				headers.push(line.trim().to_lowercase());
+			}
+			let response = if i == 0 {
+				"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Type: application/xml\r\n\
+				 Content-Length: 6\r\nConnection: close\r\n\r\n<feed>"
+			} else {
+				"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n"
+			};
+			stream.write_all(response.as_bytes()).unwrap();
+			requests.push(headers);
+		}
+		requests
+	});
+
+	let cache_dir = PathBuf::from("../fixtures/dest_fetch_cache");
+	let _ = fs::remove_dir_all(&cache_dir);
+	let options = Options {
+		cache_dir: cache_dir.clone(),
+		offline: false,
+		..Options::default()
+	};
+	let client = Client::new(options.clone());
+	let first = client.get(&url).unwrap();
+	assert_eq!(first.text(), "<feed>");
+	assert_eq!(first.content_type.as_deref(), Some("application/xml"));
+	assert!(!first.from_cache);
+	let second = client.get(&url).unwrap();
+	assert_eq!(second.text(), "<feed>");
+	assert!(second.from_cache);
+	let requests = server.join().unwrap();
+	assert!(requests[1].contains(&"if-none-match: \"v1\"".to_string()));
+
+	let offline = Client::new(Options {
+		offline: true,
+		..options
+	});
+	assert_eq!(offline.get(&url).unwrap().text(), "<feed>");
+	match offline.get("http://127.0.0.1:1/missing") {
+		Err(FetchError::Offline(missing)) => assert_eq!(missing, "http://127.0.0.1:1/missing"),
+		other => panic!("unexpected result: {:?}", other),
+	}
+}
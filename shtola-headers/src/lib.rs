@@ -0,0 +1,338 @@
+//! HTTP headers for Shtola sites, most importantly caching policies. Headers
+//! are assigned to files by glob and emitted in the formats of the hosts
+//! serving the site:
+//!
+//! ```
+//! use shtola::{Shtola, ShtolaError};
+//! use shtola_headers::{Format, Options, Policy};
+//!
+//! fn configure(s: &mut Shtola) -> Result<(), ShtolaError> {
+//!   s.register(shtola_headers::with_options(Options {
+//!     policies: vec![Policy::immutable("assets/**"), Policy::max_age("**/*.html", 300)],
+//!     formats: vec![Format::Netlify, Format::Nginx("headers.conf".into())],
+//!   })?);
+//!   Ok(())
+//! }
+//! ```
+//!
+//! Sites uploaded to S3 get their headers as object metadata instead, which
+//! [`Format::S3`](enum.Format.html#variant.S3) writes as a JSON file mapping
+//! object keys to the parameters of S3's `PutObject` call. Headers S3 has no
+//! parameter for become user metadata, which S3 serves with an `x-amz-meta-`
+//! prefix:
+//!
+//! ```json
+//! {
+//!   "assets/app.css": {
+//!     "CacheControl": "public, max-age=31536000, immutable",
+//!     "Metadata": { "x-frame-options": "DENY" }
+//!   }
+//! }
+//! ```
+//!
+//! The headers of every file are also stored in the `headers` metadata key, so
+//! other deployment backends that set headers per object can pick them up:
+//!
+//! ```json
+//! { "/assets/app.css": { "Cache-Control": "public, max-age=31536000, immutable" } }
+//! ```
+
+use globset::{Glob, GlobMatcher};
+use shtola::context::url;
+use shtola::json::{Map, Value};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, ShtolaError, IR};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Headers for all files matching a glob.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+	/// The glob files have to match, relative to the destination.
+	pub glob: String,
+	/// The headers, as pairs of name and value.
+	pub headers: Vec<(String, String)>,
+}
+
+impl Policy {
+	/// Creates a policy with the given headers.
+	pub fn new(glob: &str, headers: &[(&str, &str)]) -> Policy {
+		Policy {
+			glob: glob.to_string(),
+			headers: headers
+				.iter()
+				.map(|(name, value)| (name.to_string(), value.to_string()))
+				.collect(),
+		}
+	}
+
+	/// Creates a policy caching files forever, for fingerprinted assets whose
+	/// path changes along with their content.
+	pub fn immutable(glob: &str) -> Policy {
+		Policy::new(
+			glob,
+			&[("Cache-Control", "public, max-age=31536000, immutable")],
+		)
+	}
+
+	/// Creates a policy caching files for the given number of seconds.
+	pub fn max_age(glob: &str, seconds: u64) -> Policy {
+		let value = format!("public, max-age={}", seconds);
+		Policy::new(glob, &[("Cache-Control", &value)])
+	}
+}
+
+/// A host-specific format to emit headers in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+	/// A `_headers` file as read by Netlify and Cloudflare Pages.
+	Netlify,
+	/// An nginx configuration file at the given path, to be included in a
+	/// `server` block.
+	Nginx(PathBuf),
+	/// A JSON file at the given path with the S3 object metadata of every
+	/// file, for deployments to S3 that upload the files with it. Not part of
+	/// the site, so it should be written outside of the uploaded directory or
+	/// left out of the upload.
+	S3(PathBuf),
+}
+
+/// Options for the headers plugin.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+	/// The policies. If several policies set the same header for a file, the
+	/// first one wins.
+	pub policies: Vec<Policy>,
+	/// The formats to emit. The `headers` metadata key is always set.
+	pub formats: Vec<Format>,
+}
+
+/// Creates the headers plugin emitting a Netlify `_headers` file. Fails if
+/// the glob of a policy is invalid.
+pub fn plugin(policies: Vec<Policy>) -> Result<Plugin, ShtolaError> {
+	with_options(Options {
+		policies,
+		formats: vec![Format::Netlify],
+	})
+}
+
+/// Creates the headers plugin with the given options. Fails if the glob of a
+/// policy is invalid.
+pub fn with_options(options: Options) -> Result<Plugin, ShtolaError> {
+	let mut matchers: Vec<(GlobMatcher, Policy)> = Vec::new();
+	for policy in &options.policies {
+		matchers.push((Glob::new(&policy.glob)?.compile_matcher(), policy.clone()));
+	}
+	Ok(Box::new(move |ir: IR| {
+		info!("Assigning headers");
+		let mut headers: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+		for path in ir.files.keys() {
+			let mut file_headers: Vec<(String, String)> = Vec::new();
+			for (_, policy) in matchers.iter().filter(|(m, _)| m.is_match(path)) {
+				for (name, value) in &policy.headers {
+					if !file_headers
+						.iter()
+						.any(|(n, _)| n.eq_ignore_ascii_case(name))
+					{
+						file_headers.push((name.clone(), value.clone()));
+					}
+				}
+			}
+			if !file_headers.is_empty() {
+				debug!("Headers for {:?}: {:?}", path, file_headers);
+				headers.insert(url(path), file_headers);
+			}
+		}
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for format in &options.formats {
+			let (path, content) = match format {
+				Format::Netlify => ("_headers".into(), netlify(&headers)),
+				Format::Nginx(path) => (path.clone(), nginx(&headers)),
+				Format::S3(path) => (path.clone(), s3(&headers)),
+			};
+			update_hash.insert(
+				path,
+				ShFile {
+					content: content.into_bytes(),
+					..ShFile::empty()
+				},
+			);
+		}
+		let metadata: Map<String, Value> = headers
+			.iter()
+			.map(|(url, headers)| {
+				let map = headers
+					.iter()
+					.map(|(name, value)| (name.clone(), value.clone().into()))
+					.collect();
+				(url.clone(), Value::Object(map))
+			})
+			.collect();
+		IR {
+			files: update_hash.union(ir.files),
+			metadata: ir
+				.metadata
+				.update("headers".into(), Value::Object(metadata)),
+			..ir
+		}
+	}))
+}
+
+fn netlify(headers: &BTreeMap<String, Vec<(String, String)>>) -> String {
+	headers
+		.iter()
+		.map(|(url, headers)| {
+			let lines: String = headers
+				.iter()
+				.map(|(name, value)| format!("  {}: {}\n", name, value))
+				.collect();
+			format!("{}\n{}", url, lines)
+		})
+		.collect()
+}
+
+fn nginx(headers: &BTreeMap<String, Vec<(String, String)>>) -> String {
+	headers
+		.iter()
+		.map(|(url, headers)| {
+			let lines: String = headers
+				.iter()
+				.map(|(name, value)| format!("\tadd_header {} {};\n", name, nginx_quote(value)))
+				.collect();
+			format!("location = {} {{\n{}}}\n", nginx_location(url), lines)
+		})
+		.collect()
+}
+
+// Quotes a location, if it contains characters nginx would read as the end of
+// the path or the start of a block or comment.
+fn nginx_location(url: &str) -> String {
+	let special = |c: char| c.is_whitespace() || ";{}\"'#\\".contains(c);
+	if url.contains(special) {
+		nginx_quote(url)
+	} else {
+		url.to_string()
+	}
+}
+
+fn nginx_quote(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The parameters of S3's `PutObject` call for the headers S3 sets itself.
+const S3_PARAMETERS: &[(&str, &str)] = &[
+	("Cache-Control", "CacheControl"),
+	("Content-Disposition", "ContentDisposition"),
+	("Content-Encoding", "ContentEncoding"),
+	("Content-Language", "ContentLanguage"),
+	("Content-Type", "ContentType"),
+	("Expires", "Expires"),
+];
+
+fn s3(headers: &BTreeMap<String, Vec<(String, String)>>) -> String {
+	let objects: Map<String, Value> = headers
+		.iter()
+		.map(|(url, headers)| {
+			let mut object = Map::new();
+			let mut metadata = Map::new();
+			for (name, value) in headers {
+				let parameter = S3_PARAMETERS
+					.iter()
+					.find(|(header, _)| header.eq_ignore_ascii_case(name));
+				match parameter {
+					Some((_, parameter)) => {
+						object.insert(parameter.to_string(), value.clone().into());
+					}
+					None => {
+						metadata.insert(name.to_ascii_lowercase(), value.clone().into());
+					}
+				}
+			}
+			if !metadata.is_empty() {
+				object.insert("Metadata".into(), Value::Object(metadata));
+			}
+			(s3_key(url), Value::Object(object))
+		})
+		.collect();
+	let mut json = shtola::json::to_string_pretty(&Value::Object(objects)).unwrap_or_default();
+	json.push('\n');
+	json
+}
+
+// Object keys have no leading slash, and directories are served from their
+// `index.html` object.
+fn s3_key(url: &str) -> String {
+	let key = url.trim_start_matches('/');
+	if key.is_empty() || key.ends_with('/') {
+		format!("{}index.html", key)
+	} else {
+		key.to_string()
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::json::json;
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/headers");
+	s.destination("../fixtures/dest_headers");
+	s.register(
+		with_options(Options {
+			policies: vec![
+				Policy::immutable("assets/**"),
+				Policy::max_age("**/*.html", 300),
+				Policy::new(
+					"**",
+					&[("X-Frame-Options", "DENY"), ("Cache-Control", "no-cache")],
+				),
+			],
+			formats: vec![
+				Format::Netlify,
+				Format::Nginx("headers.conf".into()),
+				Format::S3("s3.json".into()),
+			],
+		})
+		.unwrap(),
+	);
+	let r = s.build().unwrap();
+	let read = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		read("_headers"),
+		"/\n  Cache-Control: public, max-age=300\n  X-Frame-Options: DENY\n\
+		 /assets/app.3f2a1b.css\n  Cache-Control: public, max-age=31536000, immutable\n  \
+		 X-Frame-Options: DENY\n\
+		 /blog/my post.html\n  Cache-Control: public, max-age=300\n  X-Frame-Options: DENY\n\
+		 /blog/post.html\n  Cache-Control: public, max-age=300\n  X-Frame-Options: DENY\n\
+		 /robots.txt\n  X-Frame-Options: DENY\n  Cache-Control: no-cache\n"
+	);
+	assert!(read("headers.conf").starts_with(
+		"location = / {\n\tadd_header Cache-Control \"public, max-age=300\";\n\
+		 \tadd_header X-Frame-Options \"DENY\";\n}\n"
+	));
+	assert!(read("headers.conf").contains("location = \"/blog/my post.html\" {\n"));
+	let s3: Value = shtola::json::from_str(&read("s3.json")).unwrap();
+	assert_eq!(
+		s3["index.html"],
+		json!({ "CacheControl": "public, max-age=300", "Metadata": { "x-frame-options": "DENY" } })
+	);
+	assert_eq!(
+		s3["robots.txt"],
+		json!({ "Metadata": { "x-frame-options": "DENY" }, "CacheControl": "no-cache" })
+	);
+	assert_eq!(
+		r.metadata.get("headers").unwrap()["/robots.txt"],
+		json!({ "X-Frame-Options": "DENY", "Cache-Control": "no-cache" })
+	);
+}
+
+#[test]
+fn invalid_globs_fail() {
+	let e = plugin(vec![Policy::immutable("assets/[")]).err().unwrap();
+	assert!(matches!(e, ShtolaError::Glob(_)));
+}
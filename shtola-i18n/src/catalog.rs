@@ -0,0 +1,283 @@
+//! Translation catalogs for the strings of a site's interface, like "Read
+//! more" or "Posted on". Catalogs are gettext PO files named after their
+//! language, read from the `_i18n` directory of the source by
+//! [`plugin`](fn.plugin.html):
+//!
+//! ```text
+//! # _i18n/de.po
+//! msgid "Read more"
+//! msgstr "Weiterlesen"
+//!
+//! msgid "Posted by {author}"
+//! msgstr "Veröffentlicht von {author}"
+//! ```
+//!
+//! The plugin stores the catalogs in the `translations` metadata key, where
+//! [`Catalog::from_ir`](struct.Catalog.html#method.from_ir) finds them again
+//! for later plugins. Lookups fall back from a regional language like `de-AT`
+//! to `de`, then to the default language and finally to the key itself, so
+//! missing translations show up in the source language instead of failing the
+//! build.
+
+use shtola::json::{Map, Value};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, IR};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Options for the catalog plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The directory catalogs are read from. Default is `_i18n`.
+	pub dir: PathBuf,
+	/// The language used for pages without a translation. Default is `None`,
+	/// which falls back to the key.
+	pub default_language: Option<String>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			dir: "_i18n".into(),
+			default_language: None,
+		}
+	}
+}
+
+/// Translated strings for any number of languages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+	/// The language to fall back to.
+	pub default_language: Option<String>,
+	/// The translations of each language, keyed by message.
+	pub languages: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Catalog {
+	/// Adds the messages of a PO file for a language. Untranslated and fuzzy
+	/// messages are skipped, so they fall back like missing ones. Of plural
+	/// messages, only the first form is used, and message contexts are
+	/// ignored.
+	///
+	/// ```
+	/// use shtola_i18n::catalog::Catalog;
+	///
+	/// let mut catalog = Catalog::default();
+	/// catalog.add_po("de", "msgid \"Read more\"\nmsgstr \"Weiterlesen\"\n").unwrap();
+	/// assert_eq!(catalog.translate(Some("de"), "Read more", &Default::default()), "Weiterlesen");
+	/// ```
+	pub fn add_po(&mut self, language: &str, text: &str) -> Result<(), String> {
+		let messages = parse_po(text)?;
+		self.languages
+			.entry(language.to_string())
+			.or_default()
+			.extend(messages);
+		Ok(())
+	}
+
+	/// Translates a message into a language, replacing `{name}` placeholders
+	/// with the values in `args`.
+	///
+	/// ```
+	/// use shtola::json::json;
+	/// use shtola_i18n::catalog::Catalog;
+	///
+	/// let mut catalog = Catalog::default();
+	/// catalog.default_language = Some("en".into());
+	/// catalog.add_po("en", "msgid \"greeting\"\nmsgstr \"Hi {name}\"\n").unwrap();
+	/// let args = json!({ "name": "Marisa" });
+	/// let args = args.as_object().unwrap();
+	/// assert_eq!(catalog.translate(Some("fr"), "greeting", args), "Hi Marisa");
+	/// assert_eq!(catalog.translate(None, "missing", args), "missing");
+	/// ```
+	pub fn translate(
+		&self,
+		language: Option<&str>,
+		key: &str,
+		args: &Map<String, Value>,
+	) -> String {
+		let primary = language.and_then(|l| l.split(['-', '_']).next());
+		let message = [language, primary, self.default_language.as_deref()]
+			.iter()
+			.flatten()
+			.find_map(|l| self.languages.get(*l)?.get(key))
+			.map_or(key, |m| m.as_str());
+		let mut result = message.to_string();
+		for (name, value) in args {
+			let value = match value {
+				Value::String(s) => s.clone(),
+				other => other.to_string(),
+			};
+			result = result.replace(&format!("{{{}}}", name), &value);
+		}
+		result
+	}
+
+	/// Reads the catalog the plugin stored in the `translations` metadata key.
+	pub fn from_ir(ir: &IR) -> Option<Catalog> {
+		let translations = ir.metadata.get("translations")?;
+		let default_language = translations
+			.get("default")
+			.and_then(Value::as_str)
+			.map(|l| l.to_string());
+		let languages = translations
+			.get("languages")?
+			.as_object()?
+			.iter()
+			.map(|(language, messages)| {
+				let messages = messages
+					.as_object()
+					.into_iter()
+					.flatten()
+					.filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+					.collect();
+				(language.clone(), messages)
+			})
+			.collect();
+		Some(Catalog {
+			default_language,
+			languages,
+		})
+	}
+
+	fn to_json(&self) -> Value {
+		let languages = self
+			.languages
+			.iter()
+			.map(|(language, messages)| {
+				let messages = messages
+					.iter()
+					.map(|(k, v)| (k.clone(), Value::String(v.clone())))
+					.collect();
+				(language.clone(), Value::Object(messages))
+			})
+			.collect();
+		let mut map = Map::new();
+		if let Some(language) = &self.default_language {
+			map.insert("default".into(), Value::String(language.clone()));
+		}
+		map.insert("languages".into(), Value::Object(languages));
+		Value::Object(map)
+	}
+}
+
+/// Reads the PO files in the catalog directory into the `translations`
+/// metadata key and removes them from the output. Catalogs that can't be
+/// parsed are reported as failed files.
+pub fn plugin(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		let mut catalog = Catalog {
+			default_language: options.default_language.clone(),
+			..Catalog::default()
+		};
+		let mut removal_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		let mut paths: Vec<&PathBuf> = ir
+			.files
+			.keys()
+			.filter(|p| p.starts_with(&options.dir))
+			.collect();
+		paths.sort();
+		for path in paths {
+			removal_hash.insert(path.clone(), ShFile::empty());
+			let language = match path.extension().and_then(|e| e.to_str()) {
+				Some("po") => path.file_stem().unwrap().to_string_lossy(),
+				_ => continue,
+			};
+			debug!("Reading catalog {:?}", path);
+			let text = String::from_utf8_lossy(&ir.files[path].content);
+			if let Err(e) = catalog.add_po(&language, &text) {
+				failures.push((path.clone(), format!("Invalid catalog: {}", e)));
+			}
+		}
+		info!("Read catalogs for {} languages", catalog.languages.len());
+		let ir = IR {
+			files: ir.files.clone().difference(removal_hash),
+			metadata: ir.metadata.update("translations".into(), catalog.to_json()),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message))
+	})
+}
+
+enum Field {
+	Id,
+	Str,
+	Other,
+}
+
+// Parses the messages of a PO file, skipping the header, untranslated and
+// fuzzy messages.
+fn parse_po(text: &str) -> Result<BTreeMap<String, String>, String> {
+	let mut messages = BTreeMap::new();
+	let (mut id, mut translation) = (String::new(), String::new());
+	let mut field = Field::Other;
+	let (mut fuzzy, mut translated) = (false, false);
+	let lines = text.lines().map(str::trim).chain(std::iter::once(""));
+	for (i, line) in lines.enumerate() {
+		let starts_entry = line.is_empty()
+			|| line.starts_with('#')
+			|| line.starts_with("msgid ")
+			|| line.starts_with("msgctxt ");
+		if starts_entry && translated {
+			if !id.is_empty() && !translation.is_empty() && !fuzzy {
+				messages.insert(id.clone(), translation.clone());
+			}
+			id.clear();
+			translation.clear();
+			fuzzy = false;
+			translated = false;
+		}
+		let error = || format!("Unexpected line {}: {:?}", i + 1, line);
+		if let Some(flags) = line.strip_prefix("#,") {
+			fuzzy |= flags.split(',').any(|f| f.trim() == "fuzzy");
+		} else if line.is_empty() || line.starts_with('#') {
+			continue;
+		} else if let Some(rest) = line.strip_prefix("msgid ") {
+			field = Field::Id;
+			id = unquote(rest).ok_or_else(error)?;
+		} else if let Some(rest) = line
+			.strip_prefix("msgstr ")
+			.or_else(|| line.strip_prefix("msgstr[0] "))
+		{
+			field = Field::Str;
+			translated = true;
+			translation = unquote(rest).ok_or_else(error)?;
+		} else if line.starts_with("msgstr[") {
+			field = Field::Other;
+			translated = true;
+		} else if line.starts_with("msgctxt ") || line.starts_with("msgid_plural ") {
+			field = Field::Other;
+		} else if line.starts_with('"') {
+			let part = unquote(line).ok_or_else(error)?;
+			match field {
+				Field::Id => id.push_str(&part),
+				Field::Str => translation.push_str(&part),
+				Field::Other => (),
+			}
+		} else {
+			return Err(error());
+		}
+	}
+	Ok(messages)
+}
+
+fn unquote(quoted: &str) -> Option<String> {
+	let inner = quoted.trim().strip_prefix('"')?.strip_suffix('"')?;
+	let mut result = String::new();
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			result.push(c);
+			continue;
+		}
+		match chars.next()? {
+			'n' => result.push('\n'),
+			't' => result.push('\t'),
+			other => result.push(other),
+		}
+	}
+	Some(result)
+}
@@ -0,0 +1,297 @@
+//! Formatting of dates and numbers for the language of a page, like the one
+//! it is tagged with by [`plugin`](../fn.plugin.html). English, German,
+//! French, Spanish, Italian, Dutch and Portuguese are supported. Regional
+//! languages like `de-AT` are formatted like their base language, and
+//! unknown languages like English.
+//!
+//! ```
+//! use shtola::frontmatter::Date;
+//! use shtola_i18n::format::{format_date, format_number, DateStyle};
+//!
+//! let date = Date::parse("2020-01-31").unwrap();
+//! assert_eq!(format_date(Some("de"), date, DateStyle::Long), "31. Januar 2020");
+//! assert_eq!(format_number(Some("de"), 1234.5, 2), "1.234,50");
+//! ```
+
+use shtola::frontmatter::Date;
+
+/// How a date is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateStyle {
+	/// Only digits, like `1/31/2020` or `31.01.2020`.
+	Short,
+	/// With the name of the month, like `January 31, 2020`.
+	Long,
+	/// Like `Long`, but with an ordinal day where the language uses one, like
+	/// `January 31st, 2020` or `1er janvier 2020`. German dates are always
+	/// written with an ordinal day.
+	Ordinal,
+}
+
+impl DateStyle {
+	/// Parses the name of a style, like `short`, as used in templates.
+	pub fn from_name(name: &str) -> Option<DateStyle> {
+		match name {
+			"short" => Some(DateStyle::Short),
+			"long" => Some(DateStyle::Long),
+			"ordinal" => Some(DateStyle::Ordinal),
+			_ => None,
+		}
+	}
+}
+
+struct Locale {
+	language: &'static str,
+	months: [&'static str; 12],
+	decimal: &'static str,
+	group: &'static str,
+}
+
+const LOCALES: &[Locale] = &[
+	Locale {
+		language: "en",
+		months: [
+			"January",
+			"February",
+			"March",
+			"April",
+			"May",
+			"June",
+			"July",
+			"August",
+			"September",
+			"October",
+			"November",
+			"December",
+		],
+		decimal: ".",
+		group: ",",
+	},
+	Locale {
+		language: "de",
+		months: [
+			"Januar",
+			"Februar",
+			"März",
+			"April",
+			"Mai",
+			"Juni",
+			"Juli",
+			"August",
+			"September",
+			"Oktober",
+			"November",
+			"Dezember",
+		],
+		decimal: ",",
+		group: ".",
+	},
+	Locale {
+		language: "fr",
+		months: [
+			"janvier",
+			"février",
+			"mars",
+			"avril",
+			"mai",
+			"juin",
+			"juillet",
+			"août",
+			"septembre",
+			"octobre",
+			"novembre",
+			"décembre",
+		],
+		decimal: ",",
+		group: "\u{202f}",
+	},
+	Locale {
+		language: "es",
+		months: [
+			"enero",
+			"febrero",
+			"marzo",
+			"abril",
+			"mayo",
+			"junio",
+			"julio",
+			"agosto",
+			"septiembre",
+			"octubre",
+			"noviembre",
+			"diciembre",
+		],
+		decimal: ",",
+		group: ".",
+	},
+	Locale {
+		language: "it",
+		months: [
+			"gennaio",
+			"febbraio",
+			"marzo",
+			"aprile",
+			"maggio",
+			"giugno",
+			"luglio",
+			"agosto",
+			"settembre",
+			"ottobre",
+			"novembre",
+			"dicembre",
+		],
+		decimal: ",",
+		group: ".",
+	},
+	Locale {
+		language: "nl",
+		months: [
+			"januari",
+			"februari",
+			"maart",
+			"april",
+			"mei",
+			"juni",
+			"juli",
+			"augustus",
+			"september",
+			"oktober",
+			"november",
+			"december",
+		],
+		decimal: ",",
+		group: ".",
+	},
+	Locale {
+		language: "pt",
+		months: [
+			"janeiro",
+			"fevereiro",
+			"março",
+			"abril",
+			"maio",
+			"junho",
+			"julho",
+			"agosto",
+			"setembro",
+			"outubro",
+			"novembro",
+			"dezembro",
+		],
+		decimal: ",",
+		group: ".",
+	},
+];
+
+// Finds the locale of a language, falling back to its base language and then
+// to English.
+fn locale(language: Option<&str>) -> &'static Locale {
+	let base = language.and_then(|l| l.split(['-', '_']).next());
+	LOCALES
+		.iter()
+		.find(|l| base.is_some_and(|base| l.language.eq_ignore_ascii_case(base)))
+		.unwrap_or(&LOCALES[0])
+}
+
+/// Formats a date in the given language.
+///
+/// ```
+/// use shtola::frontmatter::Date;
+/// use shtola_i18n::format::{format_date, DateStyle};
+///
+/// let date = Date::parse("2020-03-01").unwrap();
+/// assert_eq!(format_date(None, date, DateStyle::Short), "3/1/2020");
+/// assert_eq!(format_date(Some("en"), date, DateStyle::Ordinal), "March 1st, 2020");
+/// assert_eq!(format_date(Some("fr-CA"), date, DateStyle::Ordinal), "1er mars 2020");
+/// assert_eq!(format_date(Some("es"), date, DateStyle::Long), "1 de marzo de 2020");
+/// ```
+pub fn format_date(language: Option<&str>, date: Date, style: DateStyle) -> String {
+	let locale = locale(language);
+	let (year, month, day) = (date.year, date.month, date.day);
+	if style == DateStyle::Short {
+		return match locale.language {
+			"en" => format!("{}/{}/{}", month, day, year),
+			"de" => format!("{:02}.{:02}.{}", day, month, year),
+			"nl" => format!("{:02}-{:02}-{}", day, month, year),
+			_ => format!("{:02}/{:02}/{}", day, month, year),
+		};
+	}
+	let name = locale.months[(month as usize).clamp(1, 12) - 1];
+	let day = match (style, locale.language, day) {
+		(DateStyle::Ordinal, "en", _) | (DateStyle::Ordinal, "fr" | "es" | "it" | "pt", 1) => {
+			ordinal(Some(locale.language), day as u64)
+		}
+		_ => day.to_string(),
+	};
+	match locale.language {
+		"en" => format!("{} {}, {}", name, day, year),
+		"de" => format!("{}. {} {}", day, name, year),
+		"es" | "pt" => format!("{} de {} de {}", day, name, year),
+		_ => format!("{} {} {}", day, name, year),
+	}
+}
+
+/// Formats a number with a fixed number of decimals and the decimal and
+/// group separators of the given language.
+///
+/// ```
+/// use shtola_i18n::format::format_number;
+///
+/// assert_eq!(format_number(None, 1234567.891, 2), "1,234,567.89");
+/// assert_eq!(format_number(Some("de-CH"), -1234.0, 0), "-1.234");
+/// ```
+pub fn format_number(language: Option<&str>, number: f64, decimals: usize) -> String {
+	if !number.is_finite() {
+		return number.to_string();
+	}
+	let locale = locale(language);
+	let formatted = format!("{:.*}", decimals, number.abs());
+	let (integer, fraction) = match formatted.split_once('.') {
+		Some((integer, fraction)) => (integer, Some(fraction)),
+		None => (formatted.as_str(), None),
+	};
+	let mut result = String::new();
+	if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+		result.push('-');
+	}
+	for (i, digit) in integer.chars().enumerate() {
+		if i > 0 && (integer.len() - i) % 3 == 0 {
+			result.push_str(locale.group);
+		}
+		result.push(digit);
+	}
+	if let Some(fraction) = fraction {
+		result.push_str(locale.decimal);
+		result.push_str(fraction);
+	}
+	result
+}
+
+/// Formats an ordinal number in the given language.
+///
+/// ```
+/// use shtola_i18n::format::ordinal;
+///
+/// assert_eq!(ordinal(Some("en"), 22), "22nd");
+/// assert_eq!(ordinal(Some("en"), 12), "12th");
+/// assert_eq!(ordinal(Some("de"), 3), "3.");
+/// assert_eq!(ordinal(Some("fr"), 2), "2e");
+/// ```
+pub fn ordinal(language: Option<&str>, n: u64) -> String {
+	match locale(language).language {
+		"en" => {
+			let suffix = match (n % 10, n % 100) {
+				(_, 11..=13) => "th",
+				(1, _) => "st",
+				(2, _) => "nd",
+				(3, _) => "rd",
+				_ => "th",
+			};
+			format!("{}{}", n, suffix)
+		}
+		"de" => format!("{}.", n),
+		"fr" if n == 1 => "1er".into(),
+		"fr" | "nl" => format!("{}e", n),
+		_ => format!("{}º", n),
+	}
+}
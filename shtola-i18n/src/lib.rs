@@ -0,0 +1,598 @@
+//! Multilingual content for Shtola. The [`plugin`](fn.plugin.html) tags files
+//! with their language, which the other plugins in this crate build upon.
+//! Strings of the site's interface are translated with the
+//! [`catalog`](catalog/index.html) module, and dates and numbers are written
+//! the way the language does with the [`format`](format/index.html) module.
+//! [`attributes`](fn.attributes.html) marks up HTML pages with their language
+//! and writing direction, so right-to-left languages like Arabic and Hebrew
+//! render correctly with the same layouts, and
+//! [`alternates`](fn.alternates.html) links the translations of a page to each
+//! other for search engines.
+
+pub mod catalog;
+pub mod format;
+
+use regex::Regex;
+use shtola::context::url;
+use shtola::json::{json, Map, Value};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Languages written from right to left.
+pub const RTL_LANGUAGES: &[&str] = &[
+	"ar", "arc", "ckb", "dv", "fa", "he", "iw", "ks", "ps", "sd", "syr", "ug", "ur", "yi",
+];
+
+const RTL_SCRIPTS: &[&str] = &["arab", "hebr", "syrc", "thaa", "nkoo", "adlm", "rohg"];
+
+/// Tags files with their language. The language is either taken from the
+/// `lang` frontmatter key, or from a language suffix in the file name, like
+/// `about.de.md`. Only suffixes in `languages` are recognized. Suffix languages
+/// are written to the `lang` frontmatter key, so later plugins only need to
+/// look there. Files without a language are shared by all languages.
+pub fn plugin(languages: &[&str]) -> Plugin {
+	let languages: Vec<String> = languages.iter().map(|l| l.to_string()).collect();
+	Box::new(move |ir: IR| {
+		info!("Tagging languages");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in &ir.files {
+			if language(file).is_some() {
+				continue;
+			}
+			let suffix = match suffix(path) {
+				Some(suffix) if languages.contains(&suffix) => suffix,
+				_ => continue,
+			};
+			let frontmatter = match &file.frontmatter {
+				Value::Null => json!({ "lang": suffix }),
+				Value::Object(map) => {
+					let mut map = map.clone();
+					map.insert("lang".into(), json!(suffix));
+					Value::Object(map)
+				}
+				_ => continue,
+			};
+			debug!("Tagging {:?} as {:?}", path, suffix);
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					frontmatter,
+					..file.clone()
+				},
+			);
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Moves every file into a subtree named after its language, so `about.de.md`
+/// becomes `de/about.md`. Files in `root_language` stay at the top level, but
+/// lose their language suffix as well. Shared files are left where they are,
+/// so they are only written once.
+///
+/// Files whose language isn't a valid [language tag](fn.is_language_tag.html)
+/// are reported as failed, as are files that would be moved onto another
+/// file's path.
+pub fn split(root_language: Option<&str>) -> Plugin {
+	let root_language = root_language.map(|l| l.to_string());
+	Box::new(move |ir: IR| {
+		relocate(ir, |path, lang| match lang {
+			Some(lang) if Some(lang) == root_language.as_deref() => Some(strip_suffix(path, lang)),
+			Some(lang) => Some(Path::new(lang).join(strip_suffix(path, lang))),
+			None => Some(path.to_path_buf()),
+		})
+	})
+}
+
+/// Keeps only the files of the given language and the shared files, removing
+/// language suffixes from file names. Meant to be used with
+/// [`Shtola::add_destination`](../shtola/struct.Shtola.html#method.add_destination)
+/// to write each language to its own destination. Like with
+/// [`split`](fn.split.html), files with invalid languages or colliding paths
+/// are reported as failed.
+pub fn only(language_to_keep: &str) -> Plugin {
+	let language_to_keep = language_to_keep.to_string();
+	Box::new(move |ir: IR| {
+		relocate(ir, |path, lang| match lang {
+			Some(lang) if lang == language_to_keep => Some(strip_suffix(path, lang)),
+			Some(_) => None,
+			None => Some(path.to_path_buf()),
+		})
+	})
+}
+
+/// Sets the `lang` and `dir` attributes of the `<html>` tag of every page
+/// with a language, replacing the ones the layout may have set. Pages without
+/// an `<html>` tag are left untouched. The plugin should run after the
+/// templates plugin, so it sees the complete pages.
+pub fn attributes() -> Plugin {
+	Box::new(|ir: IR| {
+		info!("Setting language attributes");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let lang = match language(file) {
+				Some(lang) => lang,
+				None => continue,
+			};
+			let html = match std::str::from_utf8(&file.content) {
+				Ok(html) => html,
+				Err(_) => continue,
+			};
+			let updated = set_attributes(html, &lang);
+			if updated != html {
+				debug!("Setting language attributes of {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: updated.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Links the translations of every page to each other. Translations are the
+/// pages with a language that have the same path once the language is
+/// removed from it, like `about.html` and `de/about.html` after
+/// [`split`](fn.split.html). Pages with translated paths can be grouped with
+/// the same `translation_key` frontmatter key instead.
+///
+/// Every page with translations gets `<link rel="alternate" hreflang="…">`
+/// tags for all of them, itself included, at the end of its `<head>`, using
+/// the base URL of the [site settings](../shtola/site/struct.SiteConfig.html).
+/// The pages in `root_language`, if given, are also the `x-default`
+/// alternate. The site-absolute URLs are stored in the `alternates` metadata
+/// of each page, keyed by language, where the sitemap plugin finds them:
+///
+/// ```json
+/// { "en": "/about.html", "de": "/de/about.html", "x-default": "/about.html" }
+/// ```
+///
+/// The plugin should run after the templates plugin, so it sees the complete
+/// pages.
+pub fn alternates(root_language: Option<&str>) -> Plugin {
+	let root_language = root_language.map(|l| l.to_string());
+	Box::new(move |ir: IR| {
+		info!("Linking translations");
+		let mut groups: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let lang = match language(file) {
+				Some(lang) => lang,
+				None => continue,
+			};
+			let group = groups
+				.entry(translation_key(path, file, &lang))
+				.or_default();
+			if Some(&lang) == root_language.as_ref() {
+				group.insert("x-default".into(), url(path));
+			}
+			group.insert(lang, url(path));
+		}
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let group = match language(file) {
+				Some(lang) => &groups[&translation_key(path, file, &lang)],
+				None => continue,
+			};
+			if group.keys().filter(|l| *l != "x-default").count() < 2 {
+				continue;
+			}
+			debug!("Linking translations of {:?}", path);
+			let links: String = group
+				.iter()
+				.map(|(lang, url)| {
+					format!(
+						"<link rel=\"alternate\" hreflang=\"{}\" href=\"{}\">",
+						escape(lang),
+						escape(&ir.site.absolute_url(url))
+					)
+				})
+				.collect();
+			let mut content = file.content.clone();
+			let head = std::str::from_utf8(&content)
+				.ok()
+				.and_then(|html| html.to_ascii_lowercase().find("</head"));
+			if let Some(at) = head {
+				content.splice(at..at, links.into_bytes());
+			}
+			let group: Map<String, Value> = group
+				.iter()
+				.map(|(lang, url)| (lang.clone(), Value::String(url.clone())))
+				.collect();
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					content,
+					metadata: file
+						.metadata
+						.update("alternates".into(), Value::Object(group)),
+					..file.clone()
+				},
+			);
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Returns the writing direction of a language, `rtl` or `ltr`. A script
+/// subtag decides over the language, so `az-Arab` is written from right to
+/// left.
+///
+/// ```
+/// use shtola_i18n::direction;
+///
+/// assert_eq!(direction("he"), "rtl");
+/// assert_eq!(direction("ar-EG"), "rtl");
+/// assert_eq!(direction("az-Arab"), "rtl");
+/// assert_eq!(direction("de"), "ltr");
+/// ```
+pub fn direction(lang: &str) -> &'static str {
+	let mut subtags = lang.split(['-', '_']);
+	let base = subtags.next().unwrap_or_default().to_ascii_lowercase();
+	let script = subtags.find(|s| s.len() == 4 && s.chars().all(|c| c.is_ascii_alphabetic()));
+	let rtl = match script {
+		Some(script) => RTL_SCRIPTS.contains(&script.to_ascii_lowercase().as_str()),
+		None => RTL_LANGUAGES.contains(&base.as_str()),
+	};
+	if rtl {
+		"rtl"
+	} else {
+		"ltr"
+	}
+}
+
+/// Sets the `lang` and `dir` attributes of the `<html>` tag of a page.
+///
+/// ```
+/// use shtola_i18n::set_attributes;
+///
+/// assert_eq!(
+///   set_attributes("<html lang=\"en\"><body></body></html>", "ar"),
+///   "<html lang=\"ar\" dir=\"rtl\"><body></body></html>"
+/// );
+/// ```
+pub fn set_attributes(html: &str, lang: &str) -> String {
+	static TAG: OnceLock<Regex> = OnceLock::new();
+	static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+	let tag = TAG.get_or_init(|| Regex::new(r"(?i)<html\b([^>]*?)(\s*/?)>").unwrap());
+	let attribute = ATTRIBUTE.get_or_init(|| {
+		Regex::new(r#"(?i)\s+(?:lang|dir)\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+)"#).unwrap()
+	});
+	let lang: String = lang
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+		.collect();
+	let captures = match tag.captures(html) {
+		Some(captures) => captures,
+		None => return html.to_string(),
+	};
+	let whole = captures.get(0).unwrap();
+	let rest = attribute.replace_all(&captures[1], "");
+	let replacement = format!(
+		"<{}{} lang=\"{}\" dir=\"{}\"{}>",
+		&whole.as_str()[1..5],
+		rest,
+		lang,
+		direction(&lang),
+		&captures[2]
+	);
+	format!(
+		"{}{}{}",
+		&html[..whole.start()],
+		replacement,
+		&html[whole.end()..]
+	)
+}
+
+/// Whether a language tag is well-formed according to
+/// [BCP 47](https://www.rfc-editor.org/info/bcp47), like `de` or `zh-Hant-TW`.
+/// Only well-formed tags are used in paths and markup, so a tag like `../..`
+/// can't escape the destination directory.
+///
+/// ```
+/// use shtola_i18n::is_language_tag;
+///
+/// assert!(is_language_tag("de"));
+/// assert!(is_language_tag("sr-Latn-RS"));
+/// assert!(is_language_tag("x-default"));
+/// assert!(!is_language_tag("../.."));
+/// assert!(!is_language_tag("/tmp"));
+/// assert!(!is_language_tag("de_AT"));
+/// ```
+pub fn is_language_tag(tag: &str) -> bool {
+	let mut subtags = tag.split('-');
+	let first = subtags.next().unwrap_or_default();
+	let valid_first = match first.len() {
+		1 => first.eq_ignore_ascii_case("x") || first.eq_ignore_ascii_case("i"),
+		2..=8 => first.chars().all(|c| c.is_ascii_alphabetic()),
+		_ => false,
+	};
+	valid_first
+		&& subtags
+			.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Returns the language a file is tagged with.
+pub fn language(file: &ShFile) -> Option<String> {
+	file.frontmatter
+		.get("lang")
+		.and_then(Value::as_str)
+		.map(|l| l.to_string())
+}
+
+// Moves every file to the path `target` returns for it and its language,
+// dropping it if there is none. Files with invalid languages fail, and so do
+// moved files whose new path is taken: by a file staying where it is, or by
+// another moved file.
+fn relocate<F>(ir: IR, target: F) -> IR
+where
+	F: Fn(&Path, Option<&str>) -> Option<PathBuf>,
+{
+	let mut failures: Vec<(PathBuf, String)> = Vec::new();
+	let mut targets: BTreeMap<PathBuf, Vec<&PathBuf>> = BTreeMap::new();
+	for (path, file) in &ir.files {
+		let lang = language(file);
+		match &lang {
+			Some(lang) if !is_language_tag(lang) => {
+				failures.push((path.clone(), format!("Invalid language tag {:?}", lang)));
+				continue;
+			}
+			_ => {}
+		}
+		if let Some(new_path) = target(path, lang.as_deref()) {
+			targets.entry(new_path).or_default().push(path);
+		}
+	}
+	let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+	for (new_path, mut paths) in targets {
+		paths.sort();
+		let winner = match paths.iter().position(|p| **p == new_path) {
+			Some(i) => Some(i),
+			None if paths.len() == 1 => Some(0),
+			None => None,
+		};
+		for (i, path) in paths.into_iter().enumerate() {
+			if Some(i) == winner {
+				moves.push((path.clone(), new_path.clone()));
+			} else {
+				let message = format!("Moving it to {:?} would overwrite another file", &new_path);
+				failures.push((path.clone(), message));
+			}
+		}
+	}
+	let files = moves
+		.into_iter()
+		.map(|(path, new_path)| (new_path, ir.files[&path].clone()))
+		.collect();
+	let ir = failures
+		.into_iter()
+		.fold(ir, |ir, (path, message)| ir.fail_file(path, &message));
+	IR { files, ..ir }
+}
+
+// Escapes text for use in an attribute value.
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&#39;")
+}
+
+// The path of a page without its language, or its `translation_key`.
+fn translation_key(path: &Path, file: &ShFile, lang: &str) -> String {
+	if let Some(Value::String(key)) = file.frontmatter.get("translation_key") {
+		return key.clone();
+	}
+	let path = path.strip_prefix(lang).unwrap_or(path);
+	strip_suffix(path, lang)
+		.to_string_lossy()
+		.replace('\\', "/")
+}
+
+fn suffix(path: &Path) -> Option<String> {
+	let stem = Path::new(path.file_stem()?);
+	stem.extension().map(|e| e.to_string_lossy().into_owned())
+}
+
+fn strip_suffix(path: &Path, lang: &str) -> PathBuf {
+	if suffix(path).as_deref() != Some(lang) {
+		return path.to_path_buf();
+	}
+	let stem = Path::new(path.file_stem().unwrap()).file_stem().unwrap();
+	let mut name = stem.to_os_string();
+	if let Some(ext) = path.extension() {
+		name.push(".");
+		name.push(ext);
+	}
+	path.with_file_name(name)
+}
+
+#[test]
+fn split_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n");
+	s.destination("../fixtures/dest_i18n");
+	s.clean(true);
+	s.register(plugin(&["en", "de"]));
+	s.register(split(Some("en")));
+	let r = s.build().unwrap();
+	let mut paths: Vec<&PathBuf> = r.files.keys().collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		vec![
+			Path::new("de/blog/post.md"),
+			Path::new("de/index.md"),
+			Path::new("index.md"),
+			Path::new("style.css"),
+		]
+	);
+	let file = r.files.get(Path::new("de/index.md")).unwrap();
+	assert_eq!(file.frontmatter, json!({"lang": "de"}));
+}
+
+#[test]
+fn only_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n");
+	s.destination("../fixtures/dest_i18n_only");
+	s.clean(true);
+	s.register(plugin(&["en", "de"]));
+	s.register(only("de"));
+	let r = s.build().unwrap();
+	let mut paths: Vec<&PathBuf> = r.files.keys().collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		vec![
+			Path::new("blog/post.md"),
+			Path::new("index.md"),
+			Path::new("style.css"),
+		]
+	);
+	let file = r.files.get(Path::new("index.md")).unwrap();
+	assert_eq!(file.content, b"Hallo\n");
+}
+
+#[test]
+fn invalid_and_colliding_files_fail() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n_invalid");
+	s.destination("../fixtures/dest_i18n_invalid");
+	s.clean(true);
+	s.strict(false);
+	s.register(plugin(&["en", "de"]));
+	s.register(split(Some("en")));
+	let r = s.build().unwrap();
+	let paths: Vec<&PathBuf> = r.files.keys().collect();
+	assert_eq!(paths, vec![Path::new("de/index.md")]);
+	assert_eq!(r.files[Path::new("de/index.md")].content, b"Shared\n");
+	let mut errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	errors.sort();
+	assert_eq!(
+		errors,
+		vec![
+			Path::new("absolute.md"),
+			Path::new("escape.md"),
+			Path::new("index.de.md"),
+		]
+	);
+}
+
+#[test]
+fn catalog_works() {
+	use catalog::Catalog;
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n_catalog");
+	s.destination("../fixtures/dest_i18n_catalog");
+	s.clean(true);
+	s.register(catalog::plugin(catalog::Options {
+		default_language: Some("en".into()),
+		..catalog::Options::default()
+	}));
+	let r = s.build().unwrap();
+	assert!(r.files.keys().all(|p| !p.starts_with("_i18n")));
+	let catalog = Catalog::from_ir(&r).unwrap();
+	let args = json!({ "author": "Marisa", "count": 2 });
+	let args = args.as_object().unwrap();
+	let t = |lang, key| catalog.translate(lang, key, args);
+	assert_eq!(t(Some("de"), "Read more"), "Weiterlesen");
+	assert_eq!(
+		t(Some("de-AT"), "Posted by {author}"),
+		"Veröffentlicht von Marisa"
+	);
+	assert_eq!(t(Some("de"), "{count} post"), "2 Beitrag");
+	assert_eq!(t(Some("de"), "Archive"), "All posts");
+	assert_eq!(t(None, "Unknown"), "Unknown");
+}
+
+#[test]
+fn attributes_work() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n_rtl");
+	s.destination("../fixtures/dest_i18n_rtl");
+	s.clean(true);
+	s.register(attributes());
+	let r = s.build().unwrap();
+	let content = |path: &str| String::from_utf8(r.files[Path::new(path)].content.clone()).unwrap();
+	assert_eq!(
+		content("ar.html"),
+		"<!DOCTYPE html>\n<html class=\"page\" lang=\"ar\" dir=\"rtl\"><body>مرحبا</body></html>"
+	);
+	assert_eq!(
+		content("de.html"),
+		"<HTML lang=\"de-AT\" dir=\"ltr\"><body>Servus</body></HTML>"
+	);
+	assert_eq!(
+		content("shared.html"),
+		"<html lang=\"en\"><body>Shared</body></html>\n"
+	);
+	assert_eq!(content("fragment.html"), "<p>Fragment</p>");
+}
+
+#[test]
+fn alternates_work() {
+	use shtola::site::SiteConfig;
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n_alternates");
+	s.destination("../fixtures/dest_i18n_alternates");
+	s.clean(true);
+	s.site(SiteConfig {
+		base_url: Some("https://example.com/".into()),
+		..Default::default()
+	});
+	s.register(plugin(&["en", "de"]));
+	s.register(split(Some("en")));
+	s.register(alternates(Some("en")));
+	let r = s.build().unwrap();
+	let about = r.files.get(Path::new("de/about.html")).unwrap();
+	assert_eq!(
+		std::str::from_utf8(&about.content).unwrap(),
+		"<html><head><title>T</title>\
+		 <link rel=\"alternate\" hreflang=\"de\" href=\"https://example.com/de/about.html\">\
+		 <link rel=\"alternate\" hreflang=\"en\" href=\"https://example.com/about.html\">\
+		 <link rel=\"alternate\" hreflang=\"x-default\" href=\"https://example.com/about.html\">\
+		 </head><body>B</body></html>"
+	);
+	assert_eq!(
+		about.metadata["alternates"],
+		json!({ "de": "/de/about.html", "en": "/about.html", "x-default": "/about.html" })
+	);
+	let legal = r.files.get(Path::new("legal.html")).unwrap();
+	assert_eq!(legal.metadata["alternates"]["de"], "/de/impressum.html");
+	let faq = r.files.get(Path::new("q&a.html")).unwrap();
+	assert!(String::from_utf8_lossy(&faq.content).contains(
+		"<link rel=\"alternate\" hreflang=\"de\" href=\"https://example.com/de/q&amp;a.html\">"
+	));
+	let contact = r.files.get(Path::new("contact.html")).unwrap();
+	assert!(contact.metadata.get("alternates").is_none());
+	assert!(!String::from_utf8_lossy(&contact.content).contains("<link"));
+}
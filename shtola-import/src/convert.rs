@@ -0,0 +1,537 @@
+//! Converting Jekyll and Hugo sites into Shtola source trees, to ease moving
+//! an existing site over. The conversion runs once, outside of any build:
+//!
+//! ```no_run
+//! use shtola_import::convert::{convert, Generator};
+//! use std::path::Path;
+//!
+//! let conversion = convert(Generator::Jekyll, Path::new("old-site"), Path::new("site")).unwrap();
+//! for warning in &conversion.warnings {
+//!   eprintln!("{}", warning);
+//! }
+//! ```
+//!
+//! Both generators get their settings written to `site.yml`, which can be
+//! read with [`Shtola::site_config`](../../shtola/struct.Shtola.html#method.site_config),
+//! with the title, base URL, language and author in their usual places and
+//! everything else in `params`. Frontmatter is written as YAML, and posts get
+//! `collection: posts`.
+//!
+//! For Jekyll sites:
+//!
+//! - Posts in `_posts` like `2020-01-31-hello.md` move to `posts/hello.md`,
+//!   getting a `date` from their name unless they have one.
+//! - Drafts in `_drafts` move to `posts` as well, with `draft: true`.
+//! - `published: false` becomes `draft: true`, layouts get an `.html`
+//!   extension, and space-separated `tags` and `categories` become lists.
+//! - `{% highlight %}` blocks become fenced code blocks, `{% post_url %}`
+//!   tags become links, and `{% raw %}` tags are removed.
+//!
+//! For Hugo sites:
+//!
+//! - `content` and `static` are merged into the root of the new tree, with
+//!   content taking precedence. `_index.md` files become `index.md`.
+//! - TOML frontmatter between `+++` lines is converted, and `url` becomes
+//!   `permalink`.
+//! - The `highlight`, `figure`, `youtube`, `ref` and `relref` shortcodes are
+//!   replaced with their Markdown or HTML equivalents.
+//!
+//! Layouts are copied to `_layouts` and data files to `_data`, but since
+//! Liquid and Go templates differ from Shtola's, they have to be ported by
+//! hand. Anything left unconverted, like other Liquid tags or shortcodes, is
+//! listed in the warnings.
+
+use crate::toml;
+use regex::{Captures, Regex};
+use shtola::frontmatter::{has_binary_extension, is_text, lexer, to_json};
+use shtola::json::{json, Map, Value};
+use shtola::log::debug;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The generator a site was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generator {
+	/// [Jekyll](https://jekyllrb.com).
+	Jekyll,
+	/// [Hugo](https://gohugo.io).
+	Hugo,
+}
+
+/// The result of a conversion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Conversion {
+	/// The written files, relative to the new source directory, sorted.
+	pub files: Vec<PathBuf>,
+	/// Things that need to be looked at by hand.
+	pub warnings: Vec<String>,
+}
+
+// What to do with a file of the old site.
+enum Action {
+	Skip,
+	Copy(PathBuf),
+	Page(PathBuf, Page),
+}
+
+#[derive(Default)]
+struct Page {
+	date: Option<String>,
+	draft: bool,
+	post: bool,
+}
+
+/// Converts the site in `from` into a Shtola source tree in `to`. Existing
+/// files in `to` are overwritten.
+pub fn convert(generator: Generator, from: &Path, to: &Path) -> Result<Conversion, io::Error> {
+	let mut outputs: BTreeMap<PathBuf, Vec<u8>> = BTreeMap::new();
+	let mut warnings = Vec::new();
+	let mut paths = Vec::new();
+	walk(from, from, &mut paths)?;
+	// Hugo's content wins over static files at the same path.
+	paths.sort_by_key(|p| {
+		(
+			generator == Generator::Hugo && p.starts_with("static"),
+			p.clone(),
+		)
+	});
+	for path in paths {
+		let (target, content) = match action(generator, &path) {
+			Action::Skip => {
+				debug!("Skipping {:?}", &path);
+				continue;
+			}
+			Action::Copy(target) => (target, fs::read(from.join(&path))?),
+			Action::Page(target, page) => {
+				let bytes = fs::read(from.join(&path))?;
+				if has_binary_extension(&path) || !is_text(&bytes) {
+					(target, bytes)
+				} else {
+					let text = String::from_utf8(bytes).unwrap();
+					match convert_page(generator, &path, &text, &page, &mut warnings) {
+						Some(content) => (target, content.into_bytes()),
+						None => (target, text.into_bytes()),
+					}
+				}
+			}
+		};
+		if outputs.contains_key(&target) {
+			warnings.push(format!("{:?} is produced twice, keeping the first", target));
+			continue;
+		}
+		outputs.insert(target, content);
+	}
+	if outputs
+		.keys()
+		.any(|p| p.starts_with("_layouts") || p.starts_with("_includes"))
+	{
+		warnings.push("Layouts were copied, but need to be ported by hand".into());
+	}
+	if generator == Generator::Hugo && from.join("themes").exists() {
+		warnings.push("Themes aren't converted".into());
+	}
+	if let Some(site) = site_config(generator, from, &mut warnings)? {
+		outputs.insert("site.yml".into(), site.into_bytes());
+	}
+	for (path, content) in &outputs {
+		let target = to.join(path);
+		if let Some(parent) = target.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		fs::write(target, content)?;
+	}
+	Ok(Conversion {
+		files: outputs.into_keys().collect(),
+		warnings,
+	})
+}
+
+fn walk(root: &Path, dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let path = entry.path();
+		if entry.file_type()?.is_dir() {
+			walk(root, &path, paths)?;
+		} else {
+			paths.push(path.strip_prefix(root).unwrap().to_path_buf());
+		}
+	}
+	Ok(())
+}
+
+fn action(generator: Generator, path: &Path) -> Action {
+	let parts: Vec<&str> = path
+		.components()
+		.filter_map(|c| match c {
+			Component::Normal(part) => part.to_str(),
+			_ => None,
+		})
+		.collect();
+	let (first, rest) = match parts.split_first() {
+		Some((first, rest)) => (*first, rest),
+		None => return Action::Skip,
+	};
+	let rest: PathBuf = rest.iter().collect();
+	if parts.iter().any(|p| p.starts_with('.')) {
+		return Action::Skip;
+	}
+	match generator {
+		Generator::Jekyll => match first {
+			"_site" | "vendor" | "node_modules" | "Gemfile" | "Gemfile.lock" | "_config.yml" => {
+				Action::Skip
+			}
+			"_layouts" | "_includes" | "_data" | "_sass" => Action::Copy(path.to_path_buf()),
+			"_posts" | "_drafts" => {
+				let name = rest.file_name().unwrap().to_string_lossy();
+				let (date, name) = match split_date(&name) {
+					Some((date, name)) => (Some(date), name),
+					None => (None, name.to_string()),
+				};
+				Action::Page(
+					Path::new("posts").join(rest.with_file_name(name)),
+					Page {
+						date,
+						draft: first == "_drafts",
+						post: true,
+					},
+				)
+			}
+			_ => Action::Page(path.to_path_buf(), Page::default()),
+		},
+		Generator::Hugo => match first {
+			"content" => {
+				let target = if rest.file_name().is_some_and(|n| n == "_index.md") {
+					rest.with_file_name("index.md")
+				} else {
+					rest.clone()
+				};
+				let post = rest.starts_with("posts") || rest.starts_with("post");
+				Action::Page(
+					target,
+					Page {
+						post,
+						..Default::default()
+					},
+				)
+			}
+			"static" => Action::Copy(rest),
+			"layouts" => Action::Copy(Path::new("_layouts").join(rest)),
+			"data" => Action::Copy(Path::new("_data").join(rest)),
+			_ => Action::Skip,
+		},
+	}
+}
+
+// Splits a `YYYY-MM-DD-` prefix off a file name.
+fn split_date(name: &str) -> Option<(String, String)> {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	let re = RE.get_or_init(|| Regex::new(r"^(\d{4}-\d{2}-\d{2})-(.+)$").unwrap());
+	re.captures(name)
+		.map(|c| (c[1].to_string(), c[2].to_string()))
+}
+
+// Converts a page with frontmatter. Returns `None` for files without
+// frontmatter, which are copied as they are.
+fn convert_page(
+	generator: Generator,
+	path: &Path,
+	text: &str,
+	page: &Page,
+	warnings: &mut Vec<String>,
+) -> Option<String> {
+	let (frontmatter, content) = match split_toml(text) {
+		Some((matter, content)) => match toml::parse(matter) {
+			Ok(value) => (value, content.to_string()),
+			Err(e) => {
+				warnings.push(format!("{:?}: Invalid frontmatter: {}", path, e));
+				return None;
+			}
+		},
+		None => {
+			let (matter, content) = lexer(text)?;
+			// Jekyll only processes files with frontmatter, even empty.
+			if matter.is_empty() && !text.starts_with("---") && !page.post {
+				return None;
+			}
+			match to_json(&matter) {
+				Ok(value) => (value, content),
+				Err(e) => {
+					warnings.push(format!("{:?}: Invalid frontmatter: {}", path, e));
+					return None;
+				}
+			}
+		}
+	};
+	let mut matter = match frontmatter {
+		Value::Object(map) => map,
+		_ => Map::new(),
+	};
+	convert_frontmatter(generator, &mut matter, page);
+	let content = match generator {
+		Generator::Jekyll => convert_liquid(path, &content, warnings),
+		Generator::Hugo => convert_shortcodes(path, &content, warnings),
+	};
+	let yaml = if matter.is_empty() {
+		String::new()
+	} else {
+		let yaml = to_yaml(&Value::Object(matter));
+		format!("{}\n", yaml)
+	};
+	Some(format!("---\n{}---\n{}\n", yaml, content.trim_end()))
+}
+
+pub(crate) fn to_yaml(value: &Value) -> String {
+	let yaml = serde_yaml::to_string(value).unwrap_or_default();
+	yaml.strip_prefix("---\n")
+		.unwrap_or(&yaml)
+		.trim_end()
+		.to_string()
+}
+
+// Splits off TOML frontmatter between `+++` lines.
+fn split_toml(text: &str) -> Option<(&str, &str)> {
+	let rest = text.strip_prefix("+++\n")?;
+	let end = rest.find("\n+++")?;
+	let content = rest[end + 4..].trim_start_matches(['\r', '\n']);
+	Some((&rest[..end], content))
+}
+
+fn convert_frontmatter(generator: Generator, matter: &mut Map<String, Value>, page: &Page) {
+	if let Some(date) = &page.date {
+		matter.entry("date").or_insert_with(|| json!(date));
+	}
+	if page.draft {
+		matter.insert("draft".into(), json!(true));
+	}
+	if page.post {
+		matter.entry("collection").or_insert_with(|| json!("posts"));
+	}
+	if let Some(Value::String(layout)) = matter.get_mut("layout") {
+		if Path::new(layout.as_str()).extension().is_none() {
+			layout.push_str(".html");
+		}
+	}
+	match generator {
+		Generator::Jekyll => {
+			if matter.get("published") == Some(&json!(false)) {
+				matter.remove("published");
+				matter.insert("draft".into(), json!(true));
+			}
+			for key in ["tags", "categories"] {
+				if let Some(Value::String(list)) = matter.get(key) {
+					let items: Vec<Value> = list.split_whitespace().map(|s| json!(s)).collect();
+					matter.insert(key.into(), Value::Array(items));
+				}
+			}
+		}
+		Generator::Hugo => {
+			if let Some(url) = matter.remove("url") {
+				matter.entry("permalink").or_insert(url);
+			}
+		}
+	}
+}
+
+fn convert_liquid(path: &Path, content: &str, warnings: &mut Vec<String>) -> String {
+	static RAW: OnceLock<Regex> = OnceLock::new();
+	static TAG: OnceLock<Regex> = OnceLock::new();
+	let raw = RAW
+		.get_or_init(|| Regex::new(r"(?s)\{%-?\s*raw\s*-?%\}(.*?)\{%-?\s*endraw\s*-?%\}").unwrap());
+	let tag = TAG.get_or_init(|| Regex::new(r"\{%-?\s*(\w+)\s*(.*?)\s*-?%\}").unwrap());
+	let mut unknown = Vec::new();
+	let mut convert = |text: &str| {
+		let converted = tag.replace_all(text, |c: &Captures| match &c[1] {
+			"highlight" => {
+				let lang = c[2].split_whitespace().next().unwrap_or_default();
+				format!("```{}", lang)
+			}
+			"endhighlight" => "```".to_string(),
+			"post_url" => {
+				let name = c[2].rsplit('/').next().unwrap_or_default();
+				let name = split_date(name).map_or(name.to_string(), |(_, n)| n);
+				format!("/posts/{}.html", name)
+			}
+			name => {
+				unknown.push(name.to_string());
+				c[0].to_string()
+			}
+		});
+		if converted.contains("{{") {
+			unknown.push("{{ }}".into());
+		}
+		converted.into_owned()
+	};
+	let mut result = String::new();
+	let mut last = 0;
+	for m in raw.captures_iter(content) {
+		let whole = m.get(0).unwrap();
+		result.push_str(&convert(&content[last..whole.start()]));
+		result.push_str(&m[1]);
+		last = whole.end();
+	}
+	result.push_str(&convert(&content[last..]));
+	unknown.dedup();
+	for name in unknown {
+		warnings.push(format!(
+			"{:?}: Liquid {} needs to be converted by hand",
+			path, name
+		));
+	}
+	result
+}
+
+fn convert_shortcodes(path: &Path, content: &str, warnings: &mut Vec<String>) -> String {
+	static SHORTCODE: OnceLock<Regex> = OnceLock::new();
+	static ARG: OnceLock<Regex> = OnceLock::new();
+	let shortcode =
+		SHORTCODE.get_or_init(|| Regex::new(r"\{\{([<%])\s*(/?)([\w-]+)(.*?)\s*[>%]\}\}").unwrap());
+	let arg =
+		ARG.get_or_init(|| Regex::new(r#"(?:([\w-]+)=)?(?:"([^"]*)"|'([^']*)'|(\S+))"#).unwrap());
+	let mut unknown = Vec::new();
+	let result = shortcode.replace_all(content, |c: &Captures| {
+		let mut named = BTreeMap::new();
+		let mut positional = Vec::new();
+		for a in arg.captures_iter(&c[4]) {
+			let value = a
+				.get(2)
+				.or_else(|| a.get(3))
+				.or_else(|| a.get(4))
+				.map_or("", |m| m.as_str())
+				.to_string();
+			match a.get(1) {
+				Some(key) => {
+					named.insert(key.as_str().to_string(), value);
+				}
+				None => positional.push(value),
+			}
+		}
+		let get = |key: &str, position: usize| {
+			named
+				.get(key)
+				.or_else(|| positional.get(position))
+				.cloned()
+				.unwrap_or_default()
+		};
+		match (&c[2], &c[3]) {
+			("", "highlight") => format!("```{}", get("lang", 0)),
+			("/", "highlight") => "```".to_string(),
+			("", "figure") => {
+				let caption = named.get("caption").or_else(|| named.get("title"));
+				let caption = caption
+					.map(|c| format!("<figcaption>{}</figcaption>", c))
+					.unwrap_or_default();
+				format!(
+					"<figure><img src=\"{}\" alt=\"{}\">{}</figure>",
+					get("src", 0),
+					get("alt", 1),
+					caption
+				)
+			}
+			("", "youtube") => format!(
+				"<iframe src=\"https://www.youtube-nocookie.com/embed/{}\" allowfullscreen></iframe>",
+				get("id", 0)
+			),
+			("", "ref") | ("", "relref") => {
+				let target = get("path", 0);
+				let target = target.trim_start_matches('/');
+				match target.strip_suffix(".md") {
+					Some(page) => format!("/{}.html", page),
+					None => format!("/{}", target),
+				}
+			}
+			(_, name) => {
+				unknown.push(name.to_string());
+				c[0].to_string()
+			}
+		}
+	});
+	unknown.dedup();
+	for name in unknown {
+		warnings.push(format!(
+			"{:?}: Shortcode {} needs to be converted by hand",
+			path, name
+		));
+	}
+	result.into_owned()
+}
+
+fn site_config(
+	generator: Generator,
+	from: &Path,
+	warnings: &mut Vec<String>,
+) -> Result<Option<String>, io::Error> {
+	let names: &[&str] = match generator {
+		Generator::Jekyll => &["_config.yml", "_config.yaml"],
+		Generator::Hugo => &[
+			"hugo.toml",
+			"config.toml",
+			"hugo.yaml",
+			"config.yaml",
+			"hugo.json",
+			"config.json",
+		],
+	};
+	let path = match names.iter().map(|n| from.join(n)).find(|p| p.exists()) {
+		Some(path) => path,
+		None => return Ok(None),
+	};
+	let text = fs::read_to_string(&path)?;
+	let parsed = if path.extension().is_some_and(|e| e == "toml") {
+		toml::parse(&text)
+	} else {
+		to_json(&text).map_err(|e| e.to_string())
+	};
+	let mut settings = match parsed {
+		Ok(Value::Object(map)) => map,
+		Ok(_) => Map::new(),
+		Err(e) => {
+			warnings.push(format!("{:?}: Invalid settings: {}", path, e));
+			return Ok(None);
+		}
+	};
+	let mut site = Map::new();
+	match generator {
+		Generator::Jekyll => {
+			take(&mut site, &mut settings, "title", "title");
+			let base = settings.remove("baseurl");
+			let base = base.as_ref().and_then(Value::as_str).unwrap_or_default();
+			if let Some(Value::String(url)) = settings.remove("url") {
+				site.insert("base_url".into(), json!(format!("{}{}", url, base)));
+			}
+			take(&mut site, &mut settings, "lang", "language");
+		}
+		Generator::Hugo => {
+			take(&mut site, &mut settings, "title", "title");
+			take(&mut site, &mut settings, "baseURL", "base_url");
+			take(&mut site, &mut settings, "languageCode", "language");
+			// Hugo's params are meant for themes, like Shtola's.
+			if let Some(Value::Object(params)) = settings.remove("params") {
+				settings.extend(params);
+			}
+		}
+	}
+	match settings.remove("author") {
+		Some(Value::Object(author)) => {
+			if let Some(name) = author.get("name") {
+				site.insert("author".into(), name.clone());
+			}
+		}
+		Some(author) => {
+			site.insert("author".into(), author);
+		}
+		None => {}
+	}
+	if !settings.is_empty() {
+		site.insert("params".into(), Value::Object(settings));
+	}
+	Ok(Some(format!("{}\n", to_yaml(&Value::Object(site)))))
+}
+
+fn take(site: &mut Map<String, Value>, settings: &mut Map<String, Value>, from: &str, to: &str) {
+	if let Some(value) = settings.remove(from) {
+		site.insert(to.into(), value);
+	}
+}
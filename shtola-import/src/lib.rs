@@ -0,0 +1,619 @@
+//! Importing items from remote feeds and JSON APIs as Shtola pages, for
+//! "elsewhere on the web" lists or archives of posts published somewhere else.
+//! Every item becomes a file whose path, frontmatter and content are rendered
+//! from the item's fields, and then runs through the rest of the pipeline like
+//! any other file:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_import::Source;
+//!
+//! let mut s = Shtola::new();
+//! s.register_fallible(shtola_import::plugin(vec![
+//!   Source::feed("https://example.com/feed.xml", "elsewhere/{title|slug}.html"),
+//! ]));
+//! ```
+//!
+//! Templates insert fields with `{field}`, where nested JSON fields are
+//! written like `{author.name}`. The `slug` filter, as in `{title|slug}`,
+//! turns a field into a lowercase, hyphenated path segment. A frontmatter
+//! template consisting of a single field keeps the field's JSON value, so
+//! numbers and lists stay numbers and lists.
+//!
+//! RSS and Atom items have the fields `id`, `title`, `link`, `date` (like
+//! `2020-01-31`), `author`, `summary` and `content`. JSON items keep their own
+//! fields.
+//!
+//! Responses are fetched with [`shtola_fetch`](../shtola_fetch/index.html), so
+//! they are cached and builds work offline. Files from the source directory
+//! take precedence over imported files with the same path.
+//!
+//! To move a whole Jekyll or Hugo site over instead, see
+//! [`convert`](convert/index.html), and for WordPress sites
+//! [`wordpress`](wordpress/index.html).
+
+use regex::{Captures, Regex};
+use roxmltree::{Document, Node};
+use shtola::frontmatter::get;
+use shtola::json::{self, Map, Value};
+use shtola::log::{debug, info, warn};
+use shtola::{HashMap, PluginError, ShFile, TryPlugin, IR};
+use shtola_fetch::{Client, Options};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub mod convert;
+mod toml;
+pub mod wordpress;
+
+/// The format of a source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+	/// An RSS 2.0 or Atom feed.
+	Feed,
+	/// A JSON document. The items are the array at the given
+	/// [JSON pointer](https://tools.ietf.org/html/rfc6901), like `/items`, or
+	/// the whole document if the pointer is empty.
+	Json(String),
+}
+
+/// A remote source of items, and how to turn them into files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+	/// The URL of the feed or JSON document.
+	pub url: String,
+	/// The format of the source.
+	pub format: Format,
+	/// The template for the path of each file.
+	pub path: String,
+	/// The templates for the frontmatter of each file, as pairs of key and
+	/// template.
+	pub frontmatter: Vec<(String, String)>,
+	/// The template for the content of each file.
+	pub content: String,
+}
+
+impl Source {
+	/// Creates a source for an RSS or Atom feed. The files get the item's
+	/// `title`, `date` and `link` as frontmatter and its `content` as content.
+	pub fn feed(url: &str, path: &str) -> Source {
+		Source {
+			url: url.to_string(),
+			format: Format::Feed,
+			path: path.to_string(),
+			frontmatter: vec![
+				("title".into(), "{title}".into()),
+				("date".into(), "{date}".into()),
+				("link".into(), "{link}".into()),
+			],
+			content: "{content}".into(),
+		}
+	}
+
+	/// Creates a source for the items at a JSON pointer in a JSON document.
+	/// The files get every field of the item as frontmatter and no content.
+	pub fn json(url: &str, items: &str, path: &str) -> Source {
+		Source {
+			url: url.to_string(),
+			format: Format::Json(items.to_string()),
+			path: path.to_string(),
+			frontmatter: Vec::new(),
+			content: String::new(),
+		}
+	}
+}
+
+/// Creates the import plugin with a default [`Client`](../shtola_fetch/struct.Client.html).
+pub fn plugin(sources: Vec<Source>) -> TryPlugin {
+	with_client(Client::new(Options::default()), sources)
+}
+
+/// Creates the import plugin with the given client. The plugin fails if a
+/// source can't be fetched or parsed.
+pub fn with_client(client: Client, sources: Vec<Source>) -> TryPlugin {
+	Box::new(move |ir: IR| {
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for source in &sources {
+			info!("Importing {}", source.url);
+			let response = client
+				.get(&source.url)
+				.map_err(|e| PluginError::new(&e.to_string()))?;
+			let items = items(&response.body, &source.format).map_err(|e| {
+				PluginError::new(&format!("Unable to import {}: {}", source.url, e))
+			})?;
+			for item in items {
+				let (path, file) = file(source, &item);
+				debug!("Imported {:?}", path);
+				if ir.files.contains_key(&path) {
+					warn!("{:?} exists in the source directory, skipping import", path);
+				}
+				update_hash.insert(path, file);
+			}
+		}
+		// Unlike in most plugins, the existing files win.
+		Ok(IR {
+			files: ir.files.clone().union(update_hash),
+			..ir
+		})
+	})
+}
+
+/// Parses the items of a source into JSON objects.
+pub fn items(body: &[u8], format: &Format) -> Result<Vec<Value>, String> {
+	match format {
+		Format::Feed => {
+			let text = std::str::from_utf8(body).map_err(|e| e.to_string())?;
+			let doc = Document::parse(text).map_err(|e| e.to_string())?;
+			Ok(doc
+				.descendants()
+				.filter(|n| n.has_tag_name("item") || n.has_tag_name("entry"))
+				.map(feed_item)
+				.collect())
+		}
+		Format::Json(pointer) => {
+			let doc: Value = json::from_slice(body).map_err(|e| e.to_string())?;
+			match doc.pointer(pointer) {
+				Some(Value::Array(items)) => Ok(items.clone()),
+				_ => Err(format!("{:?} doesn't point to an array", pointer)),
+			}
+		}
+	}
+}
+
+/// Renders a template with the fields of an item.
+pub fn render(template: &str, item: &Value) -> String {
+	placeholders()
+		.replace_all(template, |captures: &Captures| {
+			let text = match get(item, &captures[1]) {
+				Some(Value::String(s)) => s.clone(),
+				None => String::new(),
+				Some(v) => v.to_string(),
+			};
+			match captures.get(2).map(|f| f.as_str()) {
+				Some("slug") => slug(&text),
+				_ => text,
+			}
+		})
+		.into_owned()
+}
+
+fn placeholders() -> &'static Regex {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	RE.get_or_init(|| Regex::new(r"\{([\w.-]+)(?:\|(\w+))?\}").unwrap())
+}
+
+fn file(source: &Source, item: &Value) -> (PathBuf, ShFile) {
+	let frontmatter = if source.format != Format::Feed && source.frontmatter.is_empty() {
+		item.clone()
+	} else {
+		let map: Map<String, Value> = source
+			.frontmatter
+			.iter()
+			.map(|(key, template)| (key.clone(), frontmatter_value(template, item)))
+			.collect();
+		Value::Object(map)
+	};
+	let file = ShFile {
+		frontmatter,
+		content: render(&source.content, item).into_bytes(),
+		..ShFile::empty()
+	};
+	(render(&source.path, item).into(), file)
+}
+
+fn frontmatter_value(template: &str, item: &Value) -> Value {
+	let single = placeholders()
+		.captures(template)
+		.filter(|c| c[0].len() == template.len() && c.get(2).is_none());
+	match single {
+		Some(captures) => get(item, &captures[1]).cloned().unwrap_or(Value::Null),
+		None => Value::String(render(template, item)),
+	}
+}
+
+fn feed_item(node: Node) -> Value {
+	let child = |names: &[&str]| {
+		names.iter().find_map(|name| {
+			node.children()
+				.find(|c| c.tag_name().name() == *name)
+				.and_then(|c| c.text())
+				.map(|t| t.trim().to_string())
+		})
+	};
+	// Atom links are attributes, RSS links are text.
+	let link = node
+		.children()
+		.find(|c| c.has_tag_name("link") && c.attribute("rel").is_none_or(|r| r == "alternate"))
+		.and_then(|c| {
+			c.attribute("href")
+				.map(String::from)
+				.or_else(|| c.text().map(String::from))
+		});
+	let author = node
+		.children()
+		.find(|c| c.tag_name().name() == "author" || c.tag_name().name() == "creator")
+		.map(|c| {
+			c.children()
+				.find(|n| n.has_tag_name("name"))
+				.and_then(|n| n.text())
+				.or_else(|| c.text())
+				.unwrap_or_default()
+				.trim()
+				.to_string()
+		});
+	let summary = child(&["description", "summary"]);
+	let content = child(&["encoded", "content"]).or_else(|| summary.clone());
+	let date = child(&["published", "pubDate", "updated", "date"]).map(|d| date(&d));
+	let mut map = Map::new();
+	let fields = [
+		("id", child(&["guid", "id"]).or_else(|| link.clone())),
+		("title", child(&["title"])),
+		("link", link),
+		("date", date),
+		("author", author),
+		("summary", summary),
+		("content", content),
+	];
+	for (key, value) in fields {
+		map.insert(key.into(), value.map_or(Value::Null, Value::String));
+	}
+	Value::Object(map)
+}
+
+// Turns RFC 2822 dates as used by RSS, like `Fri, 31 Jan 2020 10:00:00 GMT`,
+// and RFC 3339 dates as used by Atom into `2020-01-31`. Other dates are kept.
+fn date(text: &str) -> String {
+	const MONTHS: [&str; 12] = [
+		"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+	];
+	if text.len() >= 10 && text.as_bytes()[4] == b'-' {
+		return text[..10].to_string();
+	}
+	let parts: Vec<&str> = text
+		.split(|c: char| c == ',' || c.is_whitespace())
+		.filter(|p| !p.is_empty())
+		.collect();
+	let start = if parts.first().is_some_and(|p| p.parse::<u32>().is_err()) {
+		1
+	} else {
+		0
+	};
+	match parts.get(start..start + 3) {
+		Some([day, month, year]) => match (
+			day.parse::<u32>(),
+			MONTHS.iter().position(|m| month.starts_with(m)),
+			year.parse::<u32>(),
+		) {
+			(Ok(day), Some(month), Ok(year)) => {
+				format!("{:04}-{:02}-{:02}", year, month + 1, day)
+			}
+			_ => text.to_string(),
+		},
+		_ => text.to_string(),
+	}
+}
+
+fn slug(text: &str) -> String {
+	text.to_lowercase()
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<&str>>()
+		.join("-")
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::TcpListener;
+	use std::path::Path;
+	use std::thread;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let base = format!("http://{}", listener.local_addr().unwrap());
+	thread::spawn(move || {
+		for stream in listener.incoming().take(2) {
+			let mut stream = stream.unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut request = String::new();
+			reader.read_line(&mut request).unwrap();
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+			}
+			let name = if request.contains("feed.xml") {
+				"feed.xml"
+			} else {
+				"toots.json"
+			};
+			let body = std::fs::read(Path::new("../fixtures/import").join(name)).unwrap();
+			write!(
+				stream,
+				"HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+				body.len()
+			)
+			.unwrap();
+			stream.write_all(&body).unwrap();
+		}
+	});
+
+	let cache_dir = PathBuf::from("../fixtures/dest_import_cache");
+	let _ = std::fs::remove_dir_all(&cache_dir);
+	let client = Client::new(Options {
+		cache_dir,
+		offline: false,
+		..Options::default()
+	});
+	let mut toots = Source::json(
+		&format!("{}/toots.json", base),
+		"/orderedItems",
+		"toots/{id}.md",
+	);
+	toots.frontmatter = vec![
+		("title".into(), "Toot by {account.name}".into()),
+		("tags".into(), "{tags}".into()),
+	];
+	toots.content = "{text}".into();
+	let mut s = Shtola::new();
+	s.source("../fixtures/import/site");
+	s.destination("../fixtures/dest_import");
+	s.register_fallible(with_client(
+		client,
+		vec![
+			Source::feed(&format!("{}/feed.xml", base), "elsewhere/{title|slug}.html"),
+			toots,
+		],
+	));
+	let r = s.build().unwrap();
+	let post = r
+		.files
+		.get(Path::new("elsewhere/writing-a-static-site-generator.html"))
+		.unwrap();
+	assert_eq!(
+		post.frontmatter,
+		json::json!({
+			"title": "Writing a static site generator",
+			"date": "2020-01-31",
+			"link": "https://example.com/ssg",
+		})
+	);
+	assert_eq!(post.content, b"<p>It's easier than you think.</p>");
+	let toot = r.files.get(Path::new("toots/42.md")).unwrap();
+	assert_eq!(toot.frontmatter["title"], "Toot by marisa");
+	assert_eq!(toot.frontmatter["tags"], json::json!(["rust", "web"]));
+	assert_eq!(toot.content, b"Hello, fediverse!");
+	let local = r.files.get(Path::new("elsewhere/local.html")).unwrap();
+	assert_eq!(local.content, b"Written here.\n");
+}
+
+#[test]
+fn dates_are_normalized() {
+	assert_eq!(date("Fri, 31 Jan 2020 10:00:00 GMT"), "2020-01-31");
+	assert_eq!(date("2 Feb 2020 10:00 +0100"), "2020-02-02");
+	assert_eq!(date("2020-01-31T10:00:00Z"), "2020-01-31");
+	assert_eq!(date("yesterday"), "yesterday");
+}
+
+#[test]
+fn atom_feeds_work() {
+	let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+		<entry>
+			<id>urn:post:1</id>
+			<title>Atom post</title>
+			<link rel="self" href="https://example.com/post.atom"/>
+			<link href="https://example.com/post"/>
+			<author><name>marisa</name></author>
+			<updated>2020-02-01T12:00:00Z</updated>
+			<summary>Short.</summary>
+		</entry>
+	</feed>"#;
+	let items = items(atom.as_bytes(), &Format::Feed).unwrap();
+	assert_eq!(
+		items,
+		vec![json::json!({
+			"id": "urn:post:1",
+			"title": "Atom post",
+			"link": "https://example.com/post",
+			"date": "2020-02-01",
+			"author": "marisa",
+			"summary": "Short.",
+			"content": "Short.",
+		})]
+	);
+}
+
+#[test]
+fn jekyll_conversion_works() {
+	use convert::{convert, Generator};
+	use std::fs;
+	use std::path::Path;
+
+	let to = Path::new("../fixtures/dest_convert_jekyll");
+	let _ = fs::remove_dir_all(to);
+	let from = Path::new("../fixtures/convert/jekyll");
+	let conversion = convert(Generator::Jekyll, from, to).unwrap();
+	let read = |path: &str| fs::read_to_string(to.join(path)).unwrap();
+	let files: Vec<&str> = conversion
+		.files
+		.iter()
+		.map(|p| p.to_str().unwrap())
+		.collect();
+	assert_eq!(
+		files,
+		vec![
+			"_layouts/post.html",
+			"about.md",
+			"assets/style.css",
+			"posts/hello-world.md",
+			"posts/older.md",
+			"posts/wip.md",
+			"site.yml",
+		]
+	);
+	assert_eq!(
+		read("posts/hello-world.md"),
+		"---\nlayout: post.html\ntitle: Hello\ntags:\n  - rust\n  - web\ndate: 2020-01-31\n\
+		collection: posts\n---\nSee /posts/older.html.\n\n```rust\nfn main() {}\n```\n\n\
+		{{ not liquid }}\n\n{% include note.html %}\n"
+	);
+	assert!(read("posts/older.md").contains("draft: true"));
+	assert!(!read("posts/older.md").contains("published"));
+	assert!(read("posts/wip.md").contains("draft: true"));
+	assert_eq!(
+		read("site.yml"),
+		"title: My Jekyll blog\nbase_url: \"https://example.com/blog\"\nauthor: Jane\n\
+		params:\n  markdown: kramdown\n"
+	);
+	assert_eq!(
+		conversion.warnings,
+		vec![
+			"\"_posts/2020-01-31-hello-world.md\": Liquid include needs to be converted by hand",
+			"Layouts were copied, but need to be ported by hand",
+		]
+	);
+}
+
+#[test]
+fn hugo_conversion_works() {
+	use convert::{convert, Generator};
+	use std::fs;
+	use std::path::Path;
+
+	let to = Path::new("../fixtures/dest_convert_hugo");
+	let _ = fs::remove_dir_all(to);
+	let from = Path::new("../fixtures/convert/hugo");
+	let conversion = convert(Generator::Hugo, from, to).unwrap();
+	let read = |path: &str| fs::read_to_string(to.join(path)).unwrap();
+	let files: Vec<&str> = conversion
+		.files
+		.iter()
+		.map(|p| p.to_str().unwrap())
+		.collect();
+	assert_eq!(
+		files,
+		vec![
+			"_layouts/_default/single.html",
+			"img/cover.png",
+			"index.md",
+			"posts/first.md",
+			"posts/second.md",
+			"robots.txt",
+			"site.yml",
+		]
+	);
+	assert_eq!(
+		read("posts/first.md"),
+		"---\ntitle: \"First \\\"post\\\"\"\ndate: \"2021-03-04T05:06:07Z\"\ntags:\n  - one\n  - two\n\
+		collection: posts\ndraft: false\ncover:\n  image: img/cover.png\npermalink: /first/\n---\n\
+		<figure><img src=\"/img/cover.png\" alt=\"Cover\"><figcaption>A cover</figcaption></figure>\n\n\
+		```go\npackage main\n```\n\n\
+		<iframe src=\"https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ\" allowfullscreen></iframe>\n\n\
+		See [the other](/posts/second.html).\n\n{{< gist user 123 >}}\n"
+	);
+	assert_eq!(read("index.md"), "---\ntitle: Home\n---\nWelcome.\n");
+	assert_eq!(
+		read("site.yml"),
+		"title: My Hugo site\nbase_url: \"https://example.org/\"\nlanguage: en-us\nparams:\n\
+		\x20 description: A site\n  social:\n    - a\n    - b\n"
+	);
+	assert_eq!(
+		conversion.warnings,
+		vec![
+			"\"content/posts/first.md\": Shortcode gist needs to be converted by hand",
+			"Layouts were copied, but need to be ported by hand",
+			"Themes aren't converted",
+		]
+	);
+}
+
+#[test]
+fn wordpress_import_works() {
+	use std::fs;
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::TcpListener;
+	use std::path::Path;
+	use std::thread;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let base = format!("http://{}", listener.local_addr().unwrap());
+	thread::spawn(move || {
+		for stream in listener.incoming().take(2) {
+			let mut stream = stream.unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut request = String::new();
+			reader.read_line(&mut request).unwrap();
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+			}
+			let body = request.split(' ').nth(1).unwrap().to_string();
+			write!(
+				stream,
+				"HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+				body.len(),
+				body
+			)
+			.unwrap();
+		}
+	});
+
+	let to = Path::new("../fixtures/dest_wordpress");
+	let _ = fs::remove_dir_all(to);
+	fs::create_dir_all(to).unwrap();
+	// The media URLs in the export point to the server above.
+	let export = fs::read_to_string("../fixtures/wordpress/export.xml").unwrap();
+	let export_path = to.join("export.xml");
+	fs::write(&export_path, export.replace("{base}", &base)).unwrap();
+	let cache_dir = PathBuf::from("../fixtures/dest_wordpress_cache");
+	let _ = fs::remove_dir_all(&cache_dir);
+	let client = Client::new(Options {
+		cache_dir,
+		offline: false,
+		..Options::default()
+	});
+	let site = to.join("site");
+	let conversion = wordpress::import(&export_path, &site, Some(&client)).unwrap();
+	let read = |path: &str| fs::read_to_string(site.join(path)).unwrap();
+	let files: Vec<&str> = conversion
+		.files
+		.iter()
+		.map(|p| p.to_str().unwrap())
+		.collect();
+	assert_eq!(
+		files,
+		vec![
+			"about.md",
+			"media/2020/01/cat.png",
+			"media/2020/01/report.pdf",
+			"posts/hello-world.md",
+			"posts/unfinished-thoughts.md",
+		]
+	);
+	assert_eq!(
+		read("posts/hello-world.md"),
+		"---\ntitle: Hello World\ndate: 2020-01-31\nauthor: jane\ncategories:\n  - News\n\
+		tags:\n  - rust\n  - web\nsummary: The first post.\ncollection: posts\n---\n\
+		Welcome to **my** [blog](https://example.com).\n\n## Pictures\n\n\
+		<figure class=\"wp-block-image\">![A cat](/media/2020/01/cat.png)</figure>\n"
+	);
+	assert_eq!(
+		read("posts/unfinished-thoughts.md"),
+		"---\ntitle: Unfinished Thoughts\nauthor: jane\ncollection: posts\ndraft: true\n---\n\
+		Not *quite* done.\n"
+	);
+	assert_eq!(
+		read("about.md"),
+		"---\ntitle: About\ndate: 2019-06-01\nauthor: jane\n---\nI write things.  \nSometimes.\n"
+	);
+	assert_eq!(
+		read("media/2020/01/report.pdf"),
+		"/wp-content/uploads/2020/01/report.pdf"
+	);
+	assert!(conversion.warnings.is_empty());
+}
@@ -0,0 +1,361 @@
+// A small TOML reader for Hugo frontmatter and configuration files. It covers
+// what these files use in practice: tables, arrays of tables, dotted keys,
+// strings, numbers, booleans, arrays and inline tables. Dates are kept as
+// strings, like YAML frontmatter has them.
+
+use shtola::json::{Map, Value};
+
+pub fn parse(text: &str) -> Result<Value, String> {
+	let mut parser = Parser {
+		chars: text.chars().collect(),
+		pos: 0,
+		line: 1,
+	};
+	parser
+		.document()
+		.map_err(|e| format!("{} at line {}", e, parser.line))
+}
+
+struct Parser {
+	chars: Vec<char>,
+	pos: usize,
+	line: usize,
+}
+
+impl Parser {
+	fn document(&mut self) -> Result<Value, String> {
+		let mut root = Map::new();
+		let mut table: Vec<String> = Vec::new();
+		loop {
+			self.skip_blank(true);
+			match self.peek() {
+				None => break,
+				Some('[') => {
+					self.pos += 1;
+					let array = self.eat('[');
+					self.skip_blank(false);
+					let path = self.key()?;
+					self.expect(']')?;
+					if array {
+						self.expect(']')?;
+						let (last, parents) = path.split_last().unwrap();
+						let parent = table_mut(&mut root, parents)?;
+						let entry = parent
+							.entry(last.clone())
+							.or_insert_with(|| Value::Array(Vec::new()));
+						match entry {
+							Value::Array(items) => items.push(Value::Object(Map::new())),
+							_ => return Err(format!("{} isn't an array of tables", last)),
+						}
+					} else {
+						table_mut(&mut root, &path)?;
+					}
+					table = path;
+				}
+				Some(_) => {
+					let path = self.key()?;
+					self.expect('=')?;
+					self.skip_blank(false);
+					let value = self.value()?;
+					let (last, parents) = path.split_last().unwrap();
+					let mut full = table.clone();
+					full.extend(parents.iter().cloned());
+					let target = table_mut(&mut root, &full)?;
+					if target.insert(last.clone(), value).is_some() {
+						return Err(format!("Duplicate key {}", last));
+					}
+				}
+			}
+			self.skip_blank(false);
+			match self.peek() {
+				None | Some('\n') => {}
+				Some(c) => return Err(format!("Unexpected {:?}", c)),
+			}
+		}
+		Ok(Value::Object(root))
+	}
+
+	// A dotted key like `a."b c".d`.
+	fn key(&mut self) -> Result<Vec<String>, String> {
+		let mut path = Vec::new();
+		loop {
+			self.skip_blank(false);
+			let part = match self.peek() {
+				Some('"') => {
+					self.pos += 1;
+					self.basic_string()?
+				}
+				Some('\'') => {
+					self.pos += 1;
+					self.literal_string()?
+				}
+				_ => {
+					let start = self.pos;
+					while self
+						.peek()
+						.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+					{
+						self.pos += 1;
+					}
+					if start == self.pos {
+						return Err("Expected a key".into());
+					}
+					self.chars[start..self.pos].iter().collect()
+				}
+			};
+			path.push(part);
+			self.skip_blank(false);
+			if !self.eat('.') {
+				return Ok(path);
+			}
+		}
+	}
+
+	fn value(&mut self) -> Result<Value, String> {
+		match self.peek() {
+			Some('"') => {
+				self.pos += 1;
+				if self.eat_str("\"\"") {
+					self.multiline_string('"')
+				} else {
+					self.basic_string()
+				}
+				.map(Value::String)
+			}
+			Some('\'') => {
+				self.pos += 1;
+				if self.eat_str("''") {
+					self.multiline_string('\'')
+				} else {
+					self.literal_string()
+				}
+				.map(Value::String)
+			}
+			Some('[') => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				loop {
+					self.skip_blank(true);
+					if self.eat(']') {
+						return Ok(Value::Array(items));
+					}
+					items.push(self.value()?);
+					self.skip_blank(true);
+					if !self.eat(',') {
+						self.skip_blank(true);
+						self.expect(']')?;
+						return Ok(Value::Array(items));
+					}
+				}
+			}
+			Some('{') => {
+				self.pos += 1;
+				let mut map = Map::new();
+				loop {
+					self.skip_blank(false);
+					if self.eat('}') {
+						return Ok(Value::Object(map));
+					}
+					let path = self.key()?;
+					self.expect('=')?;
+					self.skip_blank(false);
+					let value = self.value()?;
+					let (last, parents) = path.split_last().unwrap();
+					table_mut(&mut map, parents)?.insert(last.clone(), value);
+					self.skip_blank(false);
+					if !self.eat(',') {
+						self.expect('}')?;
+						return Ok(Value::Object(map));
+					}
+				}
+			}
+			_ => self.scalar(),
+		}
+	}
+
+	// Booleans, numbers and dates.
+	fn scalar(&mut self) -> Result<Value, String> {
+		let token = |c: char| c.is_ascii_alphanumeric() || "+-_:.".contains(c);
+		let start = self.pos;
+		while self.peek().is_some_and(token) {
+			self.pos += 1;
+		}
+		// Date-times may separate the date and time with a space.
+		let is_date = self.pos - start == 10 && self.chars[start + 4] == '-';
+		if is_date
+			&& self.peek() == Some(' ')
+			&& self
+				.chars
+				.get(self.pos + 1)
+				.is_some_and(char::is_ascii_digit)
+		{
+			self.pos += 1;
+			while self.peek().is_some_and(token) {
+				self.pos += 1;
+			}
+		}
+		let text: String = self.chars[start..self.pos].iter().collect();
+		let number = text.replace('_', "");
+		if text == "true" || text == "false" {
+			Ok(Value::Bool(text == "true"))
+		} else if let Ok(n) = number.parse::<i64>() {
+			Ok(Value::from(n))
+		} else if let Ok(n) = number.parse::<f64>() {
+			Ok(Value::from(n))
+		} else if text.len() >= 8 && text.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+			Ok(Value::String(text))
+		} else if text.is_empty() {
+			Err(format!("Unexpected {:?}", self.peek().unwrap_or(' ')))
+		} else {
+			Err(format!("Invalid value {:?}", text))
+		}
+	}
+
+	fn basic_string(&mut self) -> Result<String, String> {
+		let mut result = String::new();
+		loop {
+			match self.next() {
+				None | Some('\n') => return Err("Unterminated string".into()),
+				Some('"') => return Ok(result),
+				Some('\\') => result.push(self.escape()?),
+				Some(c) => result.push(c),
+			}
+		}
+	}
+
+	fn literal_string(&mut self) -> Result<String, String> {
+		let mut result = String::new();
+		loop {
+			match self.next() {
+				None | Some('\n') => return Err("Unterminated string".into()),
+				Some('\'') => return Ok(result),
+				Some(c) => result.push(c),
+			}
+		}
+	}
+
+	fn multiline_string(&mut self, quote: char) -> Result<String, String> {
+		let end = quote.to_string().repeat(3);
+		// A line break right after the opening quotes isn't part of the string.
+		self.eat('\n');
+		let mut result = String::new();
+		loop {
+			if self.eat_str(&end) {
+				return Ok(result);
+			}
+			match self.next() {
+				None => return Err("Unterminated string".into()),
+				Some('\\') if quote == '"' => result.push(self.escape()?),
+				Some(c) => result.push(c),
+			}
+		}
+	}
+
+	fn escape(&mut self) -> Result<char, String> {
+		match self.next() {
+			Some('n') => Ok('\n'),
+			Some('t') => Ok('\t'),
+			Some('r') => Ok('\r'),
+			Some('"') => Ok('"'),
+			Some('\\') => Ok('\\'),
+			Some(u @ ('u' | 'U')) => {
+				let len = if u == 'u' { 4 } else { 8 };
+				let hex: String = (0..len).filter_map(|_| self.next()).collect();
+				u32::from_str_radix(&hex, 16)
+					.ok()
+					.and_then(char::from_u32)
+					.ok_or_else(|| format!("Invalid escape \\{}{}", u, hex))
+			}
+			c => Err(format!("Invalid escape {:?}", c)),
+		}
+	}
+
+	// Skips spaces and comments, and line breaks if asked to.
+	fn skip_blank(&mut self, newlines: bool) {
+		while let Some(c) = self.peek() {
+			match c {
+				' ' | '\t' | '\r' => self.pos += 1,
+				'\n' if newlines => {
+					self.pos += 1;
+					self.line += 1;
+				}
+				'#' => {
+					while self.peek().is_some_and(|c| c != '\n') {
+						self.pos += 1;
+					}
+				}
+				_ => break,
+			}
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).copied()
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let c = self.peek()?;
+		self.pos += 1;
+		if c == '\n' {
+			self.line += 1;
+		}
+		Some(c)
+	}
+
+	fn eat(&mut self, c: char) -> bool {
+		if self.peek() == Some(c) {
+			self.next();
+			true
+		} else {
+			false
+		}
+	}
+
+	fn eat_str(&mut self, s: &str) -> bool {
+		let len = s.chars().count();
+		let matches = self.chars.len() >= self.pos + len
+			&& self.chars[self.pos..self.pos + len]
+				.iter()
+				.copied()
+				.eq(s.chars());
+		if matches {
+			self.pos += len;
+		}
+		matches
+	}
+
+	fn expect(&mut self, c: char) -> Result<(), String> {
+		self.skip_blank(false);
+		if self.eat(c) {
+			Ok(())
+		} else {
+			Err(format!("Expected {:?}", c))
+		}
+	}
+}
+
+// The table at a path, created if needed. Arrays of tables resolve to their
+// last table.
+fn table_mut<'a>(
+	mut map: &'a mut Map<String, Value>,
+	path: &[String],
+) -> Result<&'a mut Map<String, Value>, String> {
+	for key in path {
+		let entry = map
+			.entry(key.clone())
+			.or_insert_with(|| Value::Object(Map::new()));
+		let entry = if entry.is_array() {
+			let items = entry.as_array_mut().unwrap();
+			items
+				.last_mut()
+				.ok_or_else(|| format!("{} is empty", key))?
+		} else {
+			entry
+		};
+		map = match entry {
+			Value::Object(inner) => inner,
+			_ => return Err(format!("{} isn't a table", key)),
+		};
+	}
+	Ok(map)
+}
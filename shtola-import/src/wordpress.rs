@@ -0,0 +1,255 @@
+//! Importing WordPress sites from their WXR export, the XML file WordPress
+//! writes under Tools → Export. Like [`convert`](../convert/index.html), the
+//! import runs once and writes a Shtola source tree:
+//!
+//! ```no_run
+//! use shtola_fetch::{Client, Options};
+//! use shtola_import::wordpress::import;
+//! use std::path::Path;
+//!
+//! let client = Client::new(Options::default());
+//! let conversion = import(Path::new("export.xml"), Path::new("site"), Some(&client)).unwrap();
+//! for warning in &conversion.warnings {
+//!   eprintln!("{}", warning);
+//! }
+//! ```
+//!
+//! Posts are written to `posts/{slug}.md` and pages to `{slug}.md`, with
+//! their `title`, `date`, `author`, `categories`, `tags` and excerpt as
+//! `summary` in the frontmatter. Posts get `collection: posts`, and anything
+//! that isn't published gets `draft: true`. The content is turned into
+//! Markdown where that's simple, like for paragraphs, headings, emphasis,
+//! links and images, and kept as HTML otherwise, which Markdown allows.
+//!
+//! Media from `wp-content/uploads`, both attachments and files referenced in
+//! the content, are downloaded into `media` with the given client, and the
+//! content is changed to refer to them there. Without a client, media are
+//! left where they are.
+
+use crate::convert::{to_yaml, Conversion};
+use crate::{date, slug};
+use regex::{Captures, Regex};
+use roxmltree::{Document, Node};
+use shtola::json::{json, Map, Value};
+use shtola::log::{debug, info};
+use shtola_fetch::Client;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Imports the WXR export at `export` into a Shtola source tree in `to`,
+/// downloading media with `client` if given. Existing files in `to` are
+/// overwritten.
+pub fn import(export: &Path, to: &Path, client: Option<&Client>) -> Result<Conversion, io::Error> {
+	let text = fs::read_to_string(export)?;
+	let doc = Document::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	let mut outputs: BTreeMap<PathBuf, Vec<u8>> = BTreeMap::new();
+	let mut media: BTreeSet<String> = BTreeSet::new();
+	let mut warnings = Vec::new();
+	for item in doc.descendants().filter(|n| n.has_tag_name("item")) {
+		let kind = child(item, "post_type").unwrap_or_default();
+		match kind.as_str() {
+			"attachment" => {
+				if let Some(url) = child(item, "attachment_url") {
+					if upload_path(&url).is_some() {
+						media.insert(url);
+					}
+				}
+			}
+			"post" | "page" => {
+				let (path, content) = page(item, kind == "post", &mut media);
+				let path = if outputs.contains_key(&path) {
+					let id = child(item, "post_id").unwrap_or_default();
+					warnings.push(format!("{:?} exists twice, adding the ID {}", path, id));
+					path.with_file_name(format!(
+						"{}-{}.md",
+						path.file_stem().unwrap().to_string_lossy(),
+						id
+					))
+				} else {
+					path
+				};
+				debug!("Imported {:?}", &path);
+				outputs.insert(path, content.into_bytes());
+			}
+			_ => {}
+		}
+	}
+	info!("Imported {} posts and pages", outputs.len());
+	match client {
+		Some(client) => {
+			for url in &media {
+				match client.get(url) {
+					Ok(response) => {
+						let path = Path::new("media").join(upload_path(url).unwrap());
+						outputs.insert(path, response.body);
+					}
+					Err(e) => warnings.push(e.to_string()),
+				}
+			}
+		}
+		None if !media.is_empty() => {
+			warnings.push(format!("{} media files weren't downloaded", media.len()));
+		}
+		None => {}
+	}
+	for (path, content) in &outputs {
+		let target = to.join(path);
+		if let Some(parent) = target.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		fs::write(target, content)?;
+	}
+	Ok(Conversion {
+		files: outputs.into_keys().collect(),
+		warnings,
+	})
+}
+
+// Turns a post or page into a file, collecting the media it refers to.
+fn page(item: Node, post: bool, media: &mut BTreeSet<String>) -> (PathBuf, String) {
+	let title = child(item, "title").unwrap_or_default();
+	let name = child(item, "post_name")
+		.filter(|n| !n.is_empty())
+		.unwrap_or_else(|| slug(&title));
+	let name = if name.is_empty() {
+		format!("post-{}", child(item, "post_id").unwrap_or_default())
+	} else {
+		name
+	};
+	let mut matter = Map::new();
+	matter.insert("title".into(), json!(title));
+	// Drafts that were never scheduled have no date.
+	if let Some(posted) = child(item, "post_date").filter(|d| !d.starts_with("0000")) {
+		matter.insert("date".into(), json!(date(&posted)));
+	}
+	if let Some(author) = child(item, "creator") {
+		matter.insert("author".into(), json!(author));
+	}
+	for (domain, key) in [("category", "categories"), ("post_tag", "tags")] {
+		let terms: Vec<Value> = item
+			.children()
+			.filter(|c| c.has_tag_name("category") && c.attribute("domain") == Some(domain))
+			.filter_map(|c| c.text())
+			.map(|t| json!(t.trim()))
+			.collect();
+		if !terms.is_empty() {
+			matter.insert(key.into(), Value::Array(terms));
+		}
+	}
+	if let Some(excerpt) = encoded(item, "excerpt").filter(|e| !e.trim().is_empty()) {
+		matter.insert("summary".into(), json!(excerpt.trim()));
+	}
+	if post {
+		matter.insert("collection".into(), json!("posts"));
+	}
+	if child(item, "status").is_some_and(|s| s != "publish") {
+		matter.insert("draft".into(), json!(true));
+	}
+	let html = encoded(item, "content").unwrap_or_default();
+	let content = localize_media(&markdown(&html), media);
+	let path = if post {
+		Path::new("posts").join(format!("{}.md", name))
+	} else {
+		PathBuf::from(format!("{}.md", name))
+	};
+	let yaml = to_yaml(&Value::Object(matter));
+	(path, format!("---\n{}\n---\n{}\n", yaml, content))
+}
+
+// The text of the first child with a local name, like `post_type` for
+// `wp:post_type`.
+fn child(node: Node, name: &str) -> Option<String> {
+	node.children()
+		.find(|c| c.tag_name().name() == name)
+		.and_then(|c| c.text())
+		.map(|t| t.trim().to_string())
+}
+
+// The text of `content:encoded` or `excerpt:encoded`, told apart by their
+// namespaces.
+fn encoded(node: Node, namespace: &str) -> Option<String> {
+	node.children()
+		.find(|c| {
+			c.tag_name().name() == "encoded"
+				&& c.tag_name()
+					.namespace()
+					.is_some_and(|n| n.contains(namespace))
+		})
+		.and_then(|c| c.text())
+		.map(String::from)
+}
+
+// The path of an uploaded file below `wp-content/uploads`.
+fn upload_path(url: &str) -> Option<&str> {
+	let (_, path) = url.split_once("/wp-content/uploads/")?;
+	let path = path.split(['?', '#']).next().unwrap_or_default();
+	if path.is_empty() || path.split('/').any(|p| p == "..") {
+		None
+	} else {
+		Some(path)
+	}
+}
+
+// Points URLs of uploaded files to `/media`, collecting them.
+fn localize_media(content: &str, media: &mut BTreeSet<String>) -> String {
+	static URL: OnceLock<Regex> = OnceLock::new();
+	let url = URL.get_or_init(|| {
+		Regex::new(r#"https?://[^\s"'()<>]+/wp-content/uploads/[^\s"'()<>?#]+"#).unwrap()
+	});
+	url.replace_all(content, |c: &Captures| match upload_path(&c[0]) {
+		Some(path) => {
+			media.insert(c[0].to_string());
+			format!("/media/{}", path)
+		}
+		None => c[0].to_string(),
+	})
+	.into_owned()
+}
+
+// Turns the simple parts of WordPress HTML into Markdown.
+fn markdown(html: &str) -> String {
+	static RULES: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+	static IMG: OnceLock<Regex> = OnceLock::new();
+	static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+	let rules = RULES.get_or_init(|| {
+		[
+			// Block editor comments.
+			(r"<!-- /?wp:[^>]*-->", ""),
+			(r"(?s)<h1[^>]*>(.*?)</h1>", "\n# $1\n"),
+			(r"(?s)<h2[^>]*>(.*?)</h2>", "\n## $1\n"),
+			(r"(?s)<h3[^>]*>(.*?)</h3>", "\n### $1\n"),
+			(r"(?s)<h4[^>]*>(.*?)</h4>", "\n#### $1\n"),
+			(r"(?s)<(?:strong|b)>(.*?)</(?:strong|b)>", "**$1**"),
+			(r"(?s)<(?:em|i)>(.*?)</(?:em|i)>", "*$1*"),
+			(r#"(?s)<a href="([^"]*)"[^>]*>(.*?)</a>"#, "[$2]($1)"),
+			(r"<br\s*/?>", "  \n"),
+			(r"<p[^>]*>", "\n"),
+			(r"</p>", "\n"),
+			(r"\n{3,}", "\n\n"),
+		]
+		.iter()
+		.map(|(re, replacement)| (Regex::new(re).unwrap(), *replacement))
+		.collect()
+	});
+	let img = IMG.get_or_init(|| Regex::new(r"<img\s[^>]*>").unwrap());
+	let attribute = ATTRIBUTE.get_or_init(|| Regex::new(r#"\b(src|alt)\s*=\s*"([^"]*)""#).unwrap());
+	let text = img.replace_all(html, |c: &Captures| {
+		let mut src = "";
+		let mut alt = "";
+		for a in attribute.captures_iter(&c[0]) {
+			match &a[1] {
+				"src" => src = a.get(2).unwrap().as_str(),
+				_ => alt = a.get(2).unwrap().as_str(),
+			}
+		}
+		format!("![{}]({})", alt, src)
+	});
+	let mut text = text.into_owned();
+	for (re, replacement) in rules {
+		text = re.replace_all(&text, *replacement).into_owned();
+	}
+	text.trim().to_string()
+}
@@ -0,0 +1,213 @@
+//! Infers frontmatter that content imported from elsewhere often lacks, so it
+//! still gets sensible URLs and shows up in listings. The
+//! [`plugin`](fn.plugin.html) fills in these keys when they're missing:
+//!
+//! - `title` from the first heading of the content, like `# Hello` or
+//!   `<h1>Hello</h1>`, or else from the file name
+//! - `slug` from the file name, so `posts/2020-01-31-hello-world.md` gets
+//!   `hello-world`
+//! - `date` from a `YYYY-MM-DD-` prefix of the file name, like `2020-01-31`
+//!
+//! ```
+//! use shtola::Shtola;
+//!
+//! let mut s = Shtola::new();
+//! s.register(shtola_infer::plugin());
+//! ```
+//!
+//! Keys set in the frontmatter are never overwritten. The plugin should run
+//! before plugins reading these keys, like templates or permalinks.
+
+use regex::Regex;
+use shtola::json::{Map, Value};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, IR};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Options for the inference plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The extensions of the files to infer frontmatter for. Default is `md`,
+	/// `markdown`, `html` and `htm`.
+	pub extensions: Vec<String>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			extensions: vec!["md".into(), "markdown".into(), "html".into(), "htm".into()],
+		}
+	}
+}
+
+/// Creates the inference plugin with the default options.
+pub fn plugin() -> Plugin {
+	with_options(Options::default())
+}
+
+/// Creates the inference plugin.
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Inferring missing frontmatter");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in ir.files.iter() {
+			let included = path
+				.extension()
+				.and_then(|e| e.to_str())
+				.is_some_and(|e| options.extensions.iter().any(|x| x == e));
+			if !included {
+				continue;
+			}
+			let mut map = match &file.frontmatter {
+				Value::Object(map) => map.clone(),
+				Value::Null => Map::new(),
+				_ => continue,
+			};
+			let inferred = infer(path, &file.content);
+			let mut changed = false;
+			for (key, value) in inferred {
+				if map.get(key).is_none_or(Value::is_null) {
+					debug!("Inferred {} {:?} for {:?}", key, value, path);
+					map.insert(key.to_string(), Value::String(value));
+					changed = true;
+				}
+			}
+			if changed {
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						frontmatter: Value::Object(map),
+						..file.clone()
+					},
+				);
+			}
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Infers the title, slug and date of a file from its path and content.
+/// Returns the keys that could be inferred along with their values.
+///
+/// ```
+/// use std::path::Path;
+///
+/// let inferred = shtola_infer::infer(Path::new("posts/2020-01-31-hi.md"), b"# Hi there");
+/// assert_eq!(
+///   inferred,
+///   vec![("title", "Hi there".into()), ("slug", "hi".into()), ("date", "2020-01-31".into())]
+/// );
+/// ```
+pub fn infer(path: &Path, content: &[u8]) -> Vec<(&'static str, String)> {
+	let stem = path
+		.file_stem()
+		.map(|s| s.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	let (date, name) = split_date(&stem);
+	let text = String::from_utf8_lossy(content);
+	let title = heading(&text).unwrap_or_else(|| title_from_name(name));
+	let mut inferred = vec![("title", title), ("slug", slug(name))];
+	if let Some(date) = date {
+		inferred.push(("date", date));
+	}
+	inferred
+		.into_iter()
+		.filter(|(_, value)| !value.is_empty())
+		.collect()
+}
+
+// Splits a valid `YYYY-MM-DD-` prefix off a file name.
+fn split_date(stem: &str) -> (Option<String>, &str) {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	let re = RE.get_or_init(|| Regex::new(r"^(\d{4})-(\d{2})-(\d{2})-(.+)$").unwrap());
+	if let Some(captures) = re.captures(stem) {
+		let month: u32 = captures[2].parse().unwrap();
+		let day: u32 = captures[3].parse().unwrap();
+		if (1..=12).contains(&month) && (1..=31).contains(&day) {
+			let date = format!("{}-{}-{}", &captures[1], &captures[2], &captures[3]);
+			return (Some(date), captures.get(4).unwrap().as_str());
+		}
+	}
+	(None, stem)
+}
+
+// Finds the first top-level heading in Markdown or HTML.
+fn heading(text: &str) -> Option<String> {
+	static ATX: OnceLock<Regex> = OnceLock::new();
+	static SETEXT: OnceLock<Regex> = OnceLock::new();
+	static H1: OnceLock<Regex> = OnceLock::new();
+	static TAG: OnceLock<Regex> = OnceLock::new();
+	let atx =
+		ATX.get_or_init(|| Regex::new(r"(?m)^ {0,3}#[ \t]+(.+?)(?:[ \t]+#+)?[ \t]*$").unwrap());
+	let setext =
+		SETEXT.get_or_init(|| Regex::new(r"(?m)^ {0,3}(\S.*?)[ \t]*\n {0,3}=+[ \t]*$").unwrap());
+	let h1 = H1.get_or_init(|| Regex::new(r"(?is)<h1(?:\s[^>]*)?>(.*?)</h1>").unwrap());
+	let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+	[atx, setext, h1]
+		.iter()
+		.filter_map(|re| re.captures(text))
+		.map(|c| c.get(1).unwrap())
+		.min_by_key(|m| m.start())
+		.map(|m| tag.replace_all(m.as_str(), "").trim().to_string())
+		.filter(|title| !title.is_empty())
+}
+
+// Turns a file name like `hello-world` into `Hello world`.
+fn title_from_name(name: &str) -> String {
+	let words = name
+		.split(['-', '_', ' '])
+		.filter(|w| !w.is_empty())
+		.collect::<Vec<&str>>()
+		.join(" ");
+	let mut chars = words.chars();
+	match chars.next() {
+		Some(first) => first.to_uppercase().chain(chars).collect(),
+		None => String::new(),
+	}
+}
+
+fn slug(text: &str) -> String {
+	text.to_lowercase()
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<&str>>()
+		.join("-")
+}
+
+#[test]
+fn it_works() {
+	use shtola::json::json;
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/infer");
+	s.destination("../fixtures/dest_infer");
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let matter = |path: &str| r.files.get(Path::new(path)).unwrap().frontmatter.clone();
+	assert_eq!(
+		matter("posts/2020-01-31-hello-world.md"),
+		json!({ "title": "Hello, World!", "slug": "hello-world", "date": "2020-01-31" })
+	);
+	assert_eq!(
+		matter("posts/2020-02-01-explicit.md"),
+		json!({ "title": "Kept", "slug": "custom", "date": "2019-05-05" })
+	);
+	assert_eq!(
+		matter("posts/setext.md"),
+		json!({ "title": "Setext Title", "slug": "setext" })
+	);
+	assert_eq!(
+		matter("posts/2020-13-01-not_a-date.html"),
+		json!({ "title": "2020 13 01 not a date", "slug": "2020-13-01-not-a-date" })
+	);
+	assert_eq!(
+		matter("page.html"),
+		json!({ "title": "Page One", "slug": "page" })
+	);
+	assert!(matter("notes.txt").is_null());
+}
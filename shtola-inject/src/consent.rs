@@ -0,0 +1,167 @@
+//! A consent banner, and scripts that wait for consent. The
+//! [`plugin`](fn.plugin.html) inserts a banner partial at the end of every
+//! page and disables matching scripts until a consent manager enables them:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_inject::consent;
+//!
+//! let mut s = Shtola::new();
+//! s.register_fallible(consent::with_options(consent::Options {
+//!   scripts: vec!["googletagmanager.com".into(), "gtag(".into()],
+//!   ..consent::Options::default()
+//! }));
+//! ```
+//!
+//! Scripts containing one of the patterns, in their attributes or their
+//! code, get `type="text/plain"` so browsers don't run them, and a
+//! `data-consent` attribute naming their category:
+//!
+//! ```html
+//! <script type="text/plain" data-consent="analytics" src="https://www.googletagmanager.com/gtag/js"></script>
+//! ```
+//!
+//! A previous `type`, like `module`, is kept in `data-type`. The banner's
+//! script is expected to turn these back into running scripts once the
+//! visitor agrees. Register the plugin after the
+//! [injection plugin](../fn.plugin.html), so injected snippets are covered
+//! too. Pages opt out with `consent: false` in their frontmatter.
+
+use crate::{inject, Snippet};
+use regex::{Captures, Regex};
+use shtola::frontmatter::get_bool;
+use shtola::log::{debug, info};
+use shtola::{HashMap, PluginError, ShFile, TryPlugin, HTML_EXTENSIONS, IR};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Options for the consent plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The path of the banner partial in the source directory. It isn't
+	/// written to the destination. Default is `_partials/consent.html`.
+	pub banner: PathBuf,
+	/// Patterns of the scripts to disable until consent is given. Default is
+	/// none.
+	pub scripts: Vec<String>,
+	/// The category written to `data-consent`. Default is `analytics`.
+	pub category: String,
+	/// The frontmatter key pages opt out with by setting it to `false`. Default
+	/// is `consent`.
+	pub key: String,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			banner: "_partials/consent.html".into(),
+			scripts: Vec::new(),
+			category: "analytics".into(),
+			key: "consent".into(),
+		}
+	}
+}
+
+/// Creates the consent plugin with the default options, which only inserts
+/// the banner.
+pub fn plugin() -> TryPlugin {
+	with_options(Options::default())
+}
+
+/// Creates the consent plugin. The plugin fails if the banner partial is
+/// missing.
+pub fn with_options(options: Options) -> TryPlugin {
+	Box::new(move |ir: IR| {
+		info!("Adding consent banner");
+		let banner = ir.files.get(&options.banner).ok_or_else(|| {
+			PluginError::new(&format!("No consent banner at {:?}", &options.banner))
+		})?;
+		let banner = Snippet::body(String::from_utf8_lossy(&banner.content).trim_end());
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			match get_bool(&file.frontmatter, &options.key) {
+				Ok(Some(false)) => continue,
+				Ok(_) => {}
+				Err(e) => {
+					failures.push((path.clone(), e.to_string()));
+					continue;
+				}
+			}
+			let html = match std::str::from_utf8(&file.content) {
+				Ok(html) => html,
+				Err(_) => continue,
+			};
+			let deferred = defer(html, &options.scripts, &options.category);
+			let html = inject(&deferred, std::slice::from_ref(&banner));
+			debug!("Added consent banner to {:?}", path);
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					content: html.into_bytes(),
+					..file.clone()
+				},
+			);
+		}
+		let mut files = update_hash.union(ir.files);
+		files.remove(&options.banner);
+		let ir = IR { files, ..ir };
+		Ok(failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message)))
+	})
+}
+
+/// Disables the scripts in a page that contain one of the patterns until
+/// consent for `category` is given.
+///
+/// ```
+/// use shtola_inject::consent::defer;
+///
+/// assert_eq!(
+///   defer(r#"<script src="/stats.js"></script><script>hi()</script>"#, &["stats".into()], "analytics"),
+///   r#"<script type="text/plain" data-consent="analytics" src="/stats.js"></script><script>hi()</script>"#
+/// );
+/// ```
+pub fn defer(html: &str, patterns: &[String], category: &str) -> String {
+	static SCRIPT: OnceLock<Regex> = OnceLock::new();
+	static TYPE: OnceLock<Regex> = OnceLock::new();
+	let script =
+		SCRIPT.get_or_init(|| Regex::new(r"(?is)<script\b([^>]*)>(.*?)</script\s*>").unwrap());
+	let kind = TYPE.get_or_init(|| {
+		Regex::new(r#"(?i)\s+type\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap()
+	});
+	if patterns.is_empty() {
+		return html.to_string();
+	}
+	script
+		.replace_all(html, |c: &Captures| {
+			let element = &c[0];
+			if !patterns.iter().any(|p| element.contains(p.as_str())) {
+				return element.to_string();
+			}
+			let attributes = &c[1];
+			let previous = kind.captures(attributes).map(|t| {
+				t.get(1)
+					.or_else(|| t.get(2))
+					.or_else(|| t.get(3))
+					.unwrap()
+					.as_str()
+					.to_string()
+			});
+			// Already waiting for consent.
+			if previous.as_deref() == Some("text/plain") {
+				return element.to_string();
+			}
+			let attributes = kind.replace(attributes, "");
+			let data_type = match previous {
+				Some(previous) => format!(" data-type=\"{}\"", previous),
+				None => String::new(),
+			};
+			format!(
+				"<script type=\"text/plain\" data-consent=\"{}\"{}{}>{}</script>",
+				category, data_type, attributes, &c[2]
+			)
+		})
+		.into_owned()
+}
@@ -0,0 +1,264 @@
+//! Injects analytics and other script snippets into HTML pages, but only in
+//! production builds, so tracking stays out of templates and development
+//! builds don't count as visits:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_inject::Snippet;
+//!
+//! let mut s = Shtola::new();
+//! s.register(shtola_inject::plugin(vec![Snippet::head(
+//!   r#"<script defer data-domain="example.com" src="https://plausible.io/js/script.js"></script>"#,
+//! )]));
+//! ```
+//!
+//! A build counts as a production build if the `SHTOLA_ENV` environment
+//! variable is `production`, unless
+//! [`Options::production`](struct.Options.html#structfield.production) says
+//! otherwise. Pages opt out with `inject: false` in their frontmatter.
+//!
+//! The plugin should run after the templates plugin, so it sees the complete
+//! pages. To ask visitors before running the snippets, see
+//! [`consent`](consent/index.html).
+
+use shtola::frontmatter::get_bool;
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::path::PathBuf;
+
+pub mod consent;
+
+/// Where in a page a snippet goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+	/// At the end of `<head>`. Pages without a `</head>` don't get the
+	/// snippet.
+	Head,
+	/// At the end of `<body>`, or of the page if it has no `</body>`.
+	Body,
+}
+
+/// An HTML snippet to inject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+	/// The HTML to inject.
+	pub html: String,
+	/// Where to inject it.
+	pub position: Position,
+}
+
+impl Snippet {
+	/// Creates a snippet going at the end of `<head>`.
+	pub fn head(html: &str) -> Snippet {
+		Snippet {
+			html: html.to_string(),
+			position: Position::Head,
+		}
+	}
+
+	/// Creates a snippet going at the end of `<body>`.
+	pub fn body(html: &str) -> Snippet {
+		Snippet {
+			html: html.to_string(),
+			position: Position::Body,
+		}
+	}
+}
+
+/// Options for the injection plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The snippets to inject, in order.
+	pub snippets: Vec<Snippet>,
+	/// Whether this is a production build. Default is whether the `SHTOLA_ENV`
+	/// environment variable is `production`.
+	pub production: bool,
+	/// The frontmatter key pages opt out with by setting it to `false`. Default
+	/// is `inject`.
+	pub key: String,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			snippets: Vec::new(),
+			production: std::env::var("SHTOLA_ENV").is_ok_and(|env| env == "production"),
+			key: "inject".into(),
+		}
+	}
+}
+
+/// Creates the injection plugin with the given snippets and the default
+/// options.
+pub fn plugin(snippets: Vec<Snippet>) -> Plugin {
+	with_options(Options {
+		snippets,
+		..Options::default()
+	})
+}
+
+/// Creates the injection plugin. Pages with an invalid opt-out value are
+/// failed with [`IR::fail_file`](../shtola/struct.IR.html#method.fail_file).
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		if !options.production {
+			info!("Not a production build, not injecting snippets");
+			return ir;
+		}
+		info!("Injecting snippets");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			match get_bool(&file.frontmatter, &options.key) {
+				Ok(Some(false)) => continue,
+				Ok(_) => {}
+				Err(e) => {
+					failures.push((path.clone(), e.to_string()));
+					continue;
+				}
+			}
+			let html = match std::str::from_utf8(&file.content) {
+				Ok(html) => html,
+				Err(_) => continue,
+			};
+			let injected = inject(html, &options.snippets);
+			if injected != html {
+				debug!("Injected snippets into {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: injected.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message))
+	})
+}
+
+/// Injects snippets into a page.
+///
+/// ```
+/// use shtola_inject::{inject, Snippet};
+///
+/// let html = "<head><title>Hi</title></head><body>Hi</body>";
+/// assert_eq!(
+///   inject(html, &[Snippet::head("<script></script>"), Snippet::body("<noscript></noscript>")]),
+///   "<head><title>Hi</title><script></script></head><body>Hi<noscript></noscript></body>"
+/// );
+/// ```
+pub fn inject(html: &str, snippets: &[Snippet]) -> String {
+	let mut html = html.to_string();
+	for snippet in snippets {
+		let at = match snippet.position {
+			Position::Head => match find_tag(&html, "</head", false) {
+				Some(at) => at,
+				None => continue,
+			},
+			Position::Body => find_tag(&html, "</body", true).unwrap_or(html.len()),
+		};
+		html.insert_str(at, &snippet.html);
+	}
+	html
+}
+
+// Finds a closing tag, ignoring case.
+fn find_tag(html: &str, tag: &str, last: bool) -> Option<usize> {
+	let lower = html.to_ascii_lowercase();
+	if last {
+		lower.rfind(tag)
+	} else {
+		lower.find(tag)
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let snippets = vec![
+		Snippet::head("<script src=\"/stats.js\"></script>"),
+		Snippet::body("<noscript><img src=\"/pixel.gif\"></noscript>"),
+	];
+	let mut s = Shtola::new();
+	s.source("../fixtures/inject");
+	s.destination("../fixtures/dest_inject");
+	s.strict(false);
+	s.register(with_options(Options {
+		snippets: snippets.clone(),
+		production: true,
+		..Options::default()
+	}));
+	let r = s.build().unwrap();
+	let content = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		content("index.html"),
+		"<html><HEAD><title>Home</title><script src=\"/stats.js\"></script></HEAD>\n\
+		<body>Home<noscript><img src=\"/pixel.gif\"></noscript></body></html>\n"
+	);
+	assert_eq!(content("private.html"), "<head></head><body>Private</body>");
+	assert_eq!(
+		content("fragment.html"),
+		"<p>Fragment</p>\n<noscript><img src=\"/pixel.gif\"></noscript>"
+	);
+	assert_eq!(content("style.css"), "body {}\n");
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("broken.html"));
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/inject");
+	s.destination("../fixtures/dest_inject_development");
+	s.ignores(&mut vec!["broken.html".into()]);
+	s.register(with_options(Options {
+		snippets,
+		production: false,
+		..Options::default()
+	}));
+	let r = s.build().unwrap();
+	let index = r.files.get(Path::new("index.html")).unwrap();
+	assert!(!String::from_utf8_lossy(&index.content).contains("stats.js"));
+}
+
+#[test]
+fn consent_works() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/consent");
+	s.destination("../fixtures/dest_consent");
+	s.register(with_options(Options {
+		snippets: vec![Snippet::head("<script src=\"/stats.js\"></script>")],
+		production: true,
+		..Options::default()
+	}));
+	s.register_fallible(consent::with_options(consent::Options {
+		scripts: vec!["stats.js".into(), "track(".into()],
+		..consent::Options::default()
+	}));
+	let r = s.build().unwrap();
+	let content = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		content("index.html"),
+		"<head><script type=\"text/plain\" data-consent=\"analytics\" src=\"/stats.js\"></script></head>\n\
+		<body><script type=\"text/plain\" data-consent=\"analytics\" data-type=\"module\">track(\"home\")</script>\n\
+		<script src=\"/app.js\"></script>\n\
+		<div id=\"consent\"><button>OK</button></div></body>\n"
+	);
+	assert_eq!(content("imprint.html"), "<body>No tracking here.</body>");
+	assert!(!r.files.contains_key(Path::new("_partials/consent.html")));
+}
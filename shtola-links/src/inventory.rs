@@ -0,0 +1,124 @@
+//! URL inventories protect against accidentally breaking permalinks. An
+//! inventory is a JSON file, meant to be committed next to the site, that maps
+//! every page URL the site ever had to the hash of the page content:
+//!
+//! ```json
+//! { "/": "5e0c…", "/blog/hello.html": "9a41…" }
+//! ```
+//!
+//! The [`stable_urls`](fn.stable_urls.html) checker compares each build against
+//! the inventory and reports URLs that disappeared without a redirect. Pages
+//! declare redirects with the `redirect_from` frontmatter key, holding a URL or
+//! a list of URLs.
+
+use shtola::context::url;
+use shtola::json::Value;
+use shtola::log::{info, warn};
+use shtola::manifest::Manifest;
+use shtola::{has_extension, Checker, HashMap, ShFile, HTML_EXTENSIONS, IR};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Page URLs along with the hashes of their content.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inventory {
+	/// The content hash of every URL.
+	pub urls: BTreeMap<String, String>,
+}
+
+impl Inventory {
+	/// Creates the inventory of all HTML pages of a set of files.
+	pub fn new(files: &HashMap<PathBuf, ShFile>) -> Inventory {
+		let urls = Manifest::from_files(files)
+			.files
+			.into_iter()
+			.filter(|(path, _)| has_extension(path, HTML_EXTENSIONS))
+			.map(|(path, entry)| (url(&path), entry.hash))
+			.collect();
+		Inventory { urls }
+	}
+
+	/// Reads an inventory from a JSON file. A missing file results in an empty
+	/// inventory.
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<Inventory, std::io::Error> {
+		let path = path.as_ref();
+		if !path.exists() {
+			return Ok(Inventory::default());
+		}
+		let urls = shtola::json::from_slice(&fs::read(path)?).map_err(std::io::Error::other)?;
+		Ok(Inventory { urls })
+	}
+
+	/// Writes the inventory as JSON to the given path.
+	pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+		let json = shtola::json::to_vec_pretty(&self.urls).map_err(std::io::Error::other)?;
+		fs::write(path, json)
+	}
+}
+
+/// Checks that no URL of the inventory at `path` disappeared without a
+/// redirect. If a page with the same content exists under a new URL, the
+/// report suggests it as the redirect target. With `fail` set, missing URLs
+/// fail the build, otherwise they are only logged as warnings.
+///
+/// Once the check passes, new URLs are added to the inventory, so it grows
+/// with the site. URLs are never removed from it, since their redirects have
+/// to stay as well.
+pub fn stable_urls<P: Into<PathBuf>>(path: P, fail: bool) -> Checker {
+	let path = path.into();
+	Box::new(move |ir: &IR| {
+		info!("Checking URLs against {:?}", &path);
+		let previous = Inventory::read(&path).map_err(|e| vec![e.to_string()])?;
+		let current = Inventory::new(&ir.files);
+		let redirects = redirects(ir);
+		let mut problems = Vec::new();
+		for (old_url, hash) in &previous.urls {
+			if current.urls.contains_key(old_url) || redirects.contains(old_url) {
+				continue;
+			}
+			let moved_to = current
+				.urls
+				.iter()
+				.find(|(url, h)| *h == hash && !previous.urls.contains_key(*url));
+			problems.push(match moved_to {
+				Some((new_url, _)) => format!(
+					"URL {} disappeared without a redirect, it may have moved to {}",
+					old_url, new_url
+				),
+				None => format!("URL {} disappeared without a redirect", old_url),
+			});
+		}
+		if fail && !problems.is_empty() {
+			return Err(problems);
+		}
+		for problem in &problems {
+			warn!("{}", problem);
+		}
+		let mut merged = previous.clone();
+		for (url, hash) in current.urls {
+			merged.urls.insert(url, hash);
+		}
+		if merged != previous {
+			info!("Updating URL inventory at {:?}", &path);
+			merged.write(&path).map_err(|e| vec![e.to_string()])?;
+		}
+		Ok(())
+	})
+}
+
+fn redirects(ir: &IR) -> BTreeSet<String> {
+	let mut redirects = BTreeSet::new();
+	for (_, file) in ir.files.iter() {
+		match file.frontmatter.get("redirect_from") {
+			Some(Value::String(url)) => {
+				redirects.insert(url.clone());
+			}
+			Some(Value::Array(urls)) => {
+				redirects.extend(urls.iter().filter_map(Value::as_str).map(String::from));
+			}
+			_ => (),
+		}
+	}
+	redirects
+}
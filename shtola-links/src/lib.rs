@@ -0,0 +1,450 @@
+//! Internal link handling for Shtola. The [`plugin`](fn.plugin.html) parses
+//! the links of all HTML pages and publishes the resulting link graph into the
+//! `links` metadata key:
+//!
+//! ```json
+//! {
+//!   "index.html": { "links": ["about.html"], "backlinks": ["about.html"] },
+//!   "about.html": { "links": ["index.html"], "backlinks": ["index.html"] }
+//! }
+//! ```
+//!
+//! The [`check`](fn.check.html) checker fails the build on links to files
+//! and fragments that don't exist. The [`weight`](weight/index.html) module
+//! reports how much each page loads.
+
+pub mod inventory;
+pub mod weight;
+
+use regex::Regex;
+use shtola::decode;
+use shtola::json::{json, Value};
+use shtola::log::{debug, info, warn};
+use shtola::{has_extension, Checker, HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The links between all files of a build.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkGraph {
+	/// For every HTML page, the files it links to.
+	pub links: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+	/// For every linked file, the HTML pages linking to it.
+	pub backlinks: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl LinkGraph {
+	/// Builds the link graph of a set of files.
+	pub fn new(files: &HashMap<PathBuf, ShFile>) -> LinkGraph {
+		let mut graph = LinkGraph::default();
+		for (path, file) in files
+			.iter()
+			.filter(|(p, _)| has_extension(p, HTML_EXTENSIONS))
+		{
+			let html = String::from_utf8_lossy(&file.content);
+			let targets: BTreeSet<PathBuf> = links(&html)
+				.iter()
+				.filter_map(|link| resolve(path, link, files))
+				.filter(|target| target != path)
+				.collect();
+			for target in &targets {
+				graph
+					.backlinks
+					.entry(target.clone())
+					.or_default()
+					.insert(path.clone());
+			}
+			graph.links.insert(path.clone(), targets);
+		}
+		graph
+	}
+
+	/// Converts the graph into the JSON structure stored in the metadata.
+	pub fn to_json(&self) -> Value {
+		let mut paths: BTreeSet<&PathBuf> = self.links.keys().collect();
+		paths.extend(self.backlinks.keys());
+		let to_list = |set: Option<&BTreeSet<PathBuf>>| -> Vec<String> {
+			set.into_iter()
+				.flatten()
+				.map(|p| p.to_string_lossy().into_owned())
+				.collect()
+		};
+		let map = paths
+			.into_iter()
+			.map(|path| {
+				let entry = json!({
+					"links": to_list(self.links.get(path)),
+					"backlinks": to_list(self.backlinks.get(path)),
+				});
+				(path.to_string_lossy().into_owned(), entry)
+			})
+			.collect();
+		Value::Object(map)
+	}
+}
+
+/// Publishes the link graph of all HTML pages into the `links` metadata key.
+pub fn plugin() -> Plugin {
+	Box::new(|ir: IR| {
+		info!("Building link graph");
+		let graph = LinkGraph::new(&ir.files);
+		debug!("Found {} pages with links", graph.links.len());
+		IR {
+			metadata: ir.metadata.update("links".into(), graph.to_json()),
+			..ir
+		}
+	})
+}
+
+/// Reports HTML pages that can't be reached by following links from the given
+/// entry pages, and other files that aren't linked from any page. Note that
+/// only `href` and `src` attributes count as links, so files only referenced
+/// from CSS or JavaScript will show up as unreferenced. The report is stored in
+/// the `orphans` metadata key:
+///
+/// ```json
+/// { "pages": ["old/draft.html"], "assets": ["img/unused.png"] }
+/// ```
+///
+/// If `prune` is set, unreferenced assets are removed from the IR so they
+/// don't end up in the destination.
+pub fn orphans(entries: &[&str], prune: bool) -> Plugin {
+	let entries: Vec<PathBuf> = entries.iter().map(PathBuf::from).collect();
+	Box::new(move |ir: IR| {
+		info!("Looking for orphans");
+		let graph = LinkGraph::new(&ir.files);
+		let mut reachable: BTreeSet<PathBuf> = BTreeSet::new();
+		let mut queue: Vec<PathBuf> = entries.clone();
+		while let Some(path) = queue.pop() {
+			if !reachable.insert(path.clone()) {
+				continue;
+			}
+			if let Some(targets) = graph.links.get(&path) {
+				queue.extend(targets.iter().cloned());
+			}
+		}
+		let mut pages = Vec::new();
+		let mut assets = Vec::new();
+		for path in ir.files.keys() {
+			if has_extension(path, HTML_EXTENSIONS) {
+				if !reachable.contains(path) {
+					pages.push(path.clone());
+				}
+			} else if !graph.backlinks.contains_key(path) {
+				assets.push(path.clone());
+			}
+		}
+		pages.sort();
+		assets.sort();
+		for path in pages.iter().chain(assets.iter()) {
+			warn!("Orphaned file: {:?}", path);
+		}
+		let report = json!({ "pages": pages, "assets": assets });
+		let mut files = ir.files;
+		if prune {
+			for path in &assets {
+				debug!("Pruning {:?}", path);
+				files.remove(path);
+			}
+		}
+		IR {
+			files,
+			metadata: ir.metadata.update("orphans".into(), report),
+			..ir
+		}
+	})
+}
+
+/// Reports internal links to files that don't exist, and links to fragments
+/// like `guide.html#install` whose page has no element with that `id`, or an
+/// `<a>` element with that `name`. This includes fragment links within the
+/// same page. The empty fragment and `#top` always point to the top of the
+/// page.
+pub fn check() -> Checker {
+	Box::new(|ir: &IR| {
+		info!("Checking links");
+		let mut pages: Vec<&PathBuf> = ir
+			.files
+			.keys()
+			.filter(|p| has_extension(p, HTML_EXTENSIONS))
+			.collect();
+		pages.sort();
+		let mut anchors: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+		let mut problems = Vec::new();
+		for page in pages {
+			let html = String::from_utf8_lossy(&ir.files[page].content);
+			for link in links(&html) {
+				let target = if link.starts_with('#') {
+					page.clone()
+				} else {
+					match resolve(page, &link, &ir.files) {
+						Some(target) => target,
+						None => continue,
+					}
+				};
+				let file = match ir.files.get(&target) {
+					Some(file) => file,
+					None => {
+						problems.push(format!(
+							"Broken link on {:?}: {} ({:?} doesn't exist)",
+							page, link, target
+						));
+						continue;
+					}
+				};
+				let fragment = match link.split_once('#') {
+					Some((_, fragment)) if !fragment.is_empty() && fragment != "top" => {
+						decode::percent(fragment)
+					}
+					_ => continue,
+				};
+				if !has_extension(&target, HTML_EXTENSIONS) {
+					continue;
+				}
+				let ids = anchors
+					.entry(target.clone())
+					.or_insert_with(|| ids(&String::from_utf8_lossy(&file.content)));
+				if !ids.contains(&fragment) {
+					problems.push(format!(
+						"Broken link on {:?}: {} ({:?} has no element with id {:?})",
+						page, link, target, fragment
+					));
+				}
+			}
+		}
+		if problems.is_empty() {
+			Ok(())
+		} else {
+			Err(problems)
+		}
+	})
+}
+
+/// Extracts the values of all `id` attributes and the `name` attributes of
+/// `<a>` elements from HTML, which is what fragments can point to. Character
+/// references in the values are decoded.
+///
+/// ```
+/// use shtola_links::ids;
+///
+/// let ids = ids("<h2 id=\"caf&eacute;\" data-id=\"other\">Café</h2>");
+/// assert_eq!(ids.into_iter().collect::<Vec<_>>(), vec!["café"]);
+/// ```
+pub fn ids(html: &str) -> BTreeSet<String> {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	let re = RE.get_or_init(|| {
+		Regex::new(r#"(?i)(?:\sid|<a\s(?:[^>]*\s)?name)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap()
+	});
+	re.captures_iter(html)
+		.filter_map(|c| c.get(1).or_else(|| c.get(2)))
+		.map(|m| decode::entities(m.as_str()))
+		.collect()
+}
+
+/// Extracts the targets of all `href` and `src` attributes from HTML.
+/// Character references in the values are decoded, so they are the URLs a
+/// browser would follow.
+///
+/// ```
+/// use shtola_links::links;
+///
+/// assert_eq!(links("<link href=\"..&#x2f;style.css\">"), vec!["../style.css"]);
+/// ```
+pub fn links(html: &str) -> Vec<String> {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	let re = RE
+		.get_or_init(|| Regex::new(r#"(?i)\b(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+	re.captures_iter(html)
+		.filter_map(|c| c.get(1).or_else(|| c.get(2)))
+		.map(|m| decode::entities(m.as_str()))
+		.collect()
+}
+
+/// Resolves a link found on `page` to the path of a file in the build. Query
+/// strings and fragments are ignored, and percent-encoded characters are
+/// decoded. Directory links resolve to their
+/// `index.html`, extensionless links to the `.html` file if it exists.
+/// Returns `None` for external links and links to fragments of the same page.
+/// Links to files that don't exist resolve to the path they point to.
+pub fn resolve(page: &Path, link: &str, files: &HashMap<PathBuf, ShFile>) -> Option<PathBuf> {
+	let link = link.split(['#', '?']).next().unwrap_or("");
+	if link.is_empty() || link.starts_with("//") || link.contains(':') {
+		return None;
+	}
+	let link = decode::percent(link);
+	let link = link.as_str();
+	let joined = match link.strip_prefix('/') {
+		Some(absolute) => PathBuf::from(absolute),
+		None => page.parent().unwrap_or_else(|| Path::new("")).join(link),
+	};
+	let path = normalize(&joined);
+	let candidates = [
+		path.clone(),
+		path.join("index.html"),
+		path.with_extension("html"),
+	];
+	let found = candidates.iter().find(|c| files.contains_key(*c)).cloned();
+	Some(
+		found.unwrap_or(if link.ends_with('/') || path.as_os_str().is_empty() {
+			path.join("index.html")
+		} else {
+			path
+		}),
+	)
+}
+
+fn normalize(path: &Path) -> PathBuf {
+	let mut result = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::ParentDir => {
+				result.pop();
+			}
+			Component::Normal(c) => result.push(c),
+			_ => (),
+		}
+	}
+	result
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/links");
+	s.destination("../fixtures/dest_links");
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let graph = r.metadata.get("links").unwrap();
+	assert_eq!(
+		graph["index.html"],
+		json!({
+			"links": ["about.html", "blog/index.html", "img/cat.png"],
+			"backlinks": ["about.html", "blog/post.html", "orphan.html"],
+		})
+	);
+	assert_eq!(
+		graph["blog/index.html"]["backlinks"],
+		json!(["blog/post.html", "index.html"])
+	);
+	assert_eq!(graph["img/cat.png"]["links"], json!([]));
+}
+
+#[test]
+fn check_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/fragments");
+	s.destination("../fixtures/dest_fragments");
+	s.check(check());
+	let error = s.build().unwrap_err().to_string();
+	assert_eq!(
+		error,
+		"Verification failed:\n\
+		 - Broken link on \"index.html\": #nowhere (\"index.html\" has no element with id \"nowhere\")\n\
+		 - Broken link on \"index.html\": docs/index.html#uninstall \
+		 (\"docs/index.html\" has no element with id \"uninstall\")\n\
+		 - Broken link on \"index.html\": docs/#Setup (\"docs/index.html\" has no element with id \"Setup\")\n\
+		 - Broken link on \"index.html\": docs/old.html (\"docs/old.html\" doesn't exist)\n\
+		 - Broken link on \"index.html\": #ghost (\"index.html\" has no element with id \"ghost\")"
+	);
+}
+
+#[test]
+fn orphans_work() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/links");
+	s.destination("../fixtures/dest_orphans");
+	s.register(orphans(&["index.html"], true));
+	let r = s.build().unwrap();
+	assert_eq!(
+		r.metadata.get("orphans").unwrap(),
+		&json!({ "pages": ["orphan.html"], "assets": ["img/never.png"] })
+	);
+	assert!(!r.files.contains_key(Path::new("img/never.png")));
+	assert!(r.files.contains_key(Path::new("img/unused.png")));
+	assert!(r.files.contains_key(Path::new("img/logo.png")));
+}
+
+#[test]
+fn stable_urls_work() {
+	use shtola::manifest::Manifest;
+	use shtola::Shtola;
+
+	let file = ShFile {
+		content: b"<p>Moved page</p>\n".to_vec(),
+		..ShFile::empty()
+	};
+	let moved_hash = Manifest::from_files(&HashMap::unit("moved.html".into(), file)).files
+		[Path::new("moved.html")]
+	.hash
+	.clone();
+	let previous = inventory::Inventory {
+		urls: vec![
+			("/moved.html".to_string(), moved_hash),
+			("/gone.html".to_string(), "0".to_string()),
+			("/deleted.html".to_string(), "0".to_string()),
+		]
+		.into_iter()
+		.collect(),
+	};
+	let path = Path::new("../fixtures/inventory.json");
+	previous.write(path).unwrap();
+	let build = |fail: bool| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/inventory");
+		s.destination("../fixtures/dest_inventory");
+		s.check(inventory::stable_urls(path, fail));
+		s.build()
+	};
+	let error = build(true).unwrap_err().to_string();
+	assert_eq!(
+		error,
+		"Verification failed:\n\
+		 - URL /deleted.html disappeared without a redirect\n\
+		 - URL /moved.html disappeared without a redirect, it may have moved to /renamed.html"
+	);
+	build(false).unwrap();
+	let updated = inventory::Inventory::read(path).unwrap();
+	let urls: Vec<&String> = updated.urls.keys().collect();
+	assert_eq!(
+		urls,
+		vec![
+			"/",
+			"/deleted.html",
+			"/gone.html",
+			"/kept.html",
+			"/moved.html",
+			"/renamed.html"
+		]
+	);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn weights_work() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/weights");
+	s.destination("../fixtures/dest_weights");
+	s.register(weight::plugin(weight::Options {
+		page_budget: Some(1000),
+		..Default::default()
+	}));
+	let r = s.build().unwrap();
+	let weights = r.metadata.get("weights").unwrap();
+	let index = &weights["index.html"];
+	assert_eq!(
+		index["assets"],
+		json!({ "hero.svg": 1201, "hero@2x.svg": 2401, "style.css": 21 })
+	);
+	let size = r.files.get(Path::new("index.html")).unwrap().content.len();
+	assert_eq!(index["total"], json!(size + 3623));
+	assert_eq!(weights["about.html"]["assets"], json!({}));
+}
@@ -0,0 +1,181 @@
+//! Page weight reports, so regressions like a post pulling in a 9 MB image
+//! show up in the build output instead of in production. The
+//! [`plugin`](fn.plugin.html) adds up the size of every HTML page and of the
+//! assets it loads, that is files referenced by `src` and `srcset` attributes
+//! and by `<link>` elements like stylesheets and icons. Plain links to other
+//! pages or downloads don't count, and neither do files only referenced from
+//! CSS or JavaScript.
+//!
+//! The heaviest pages are logged, pages and assets over budget are logged as
+//! warnings, and the full report is stored in the `weights` metadata key:
+//!
+//! ```json
+//! { "index.html": { "size": 2048, "total": 9439232, "assets": { "img/hero.png": 9437184 } } }
+//! ```
+
+use regex::Regex;
+use shtola::json::{json, Map, Value};
+use shtola::log::{info, warn};
+use shtola::{has_extension, HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::resolve;
+
+/// Options for the page weight plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The total size in bytes a page and its assets should stay below.
+	/// Default is `None`, warning about no page.
+	pub page_budget: Option<u64>,
+	/// The size in bytes a single asset should stay below. Default is `None`,
+	/// warning about no asset.
+	pub asset_budget: Option<u64>,
+	/// How many of the heaviest pages to log. Default is 5.
+	pub top: usize,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			page_budget: None,
+			asset_budget: None,
+			top: 5,
+		}
+	}
+}
+
+/// The weight of a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageWeight {
+	/// The size of the page itself in bytes.
+	pub size: u64,
+	/// The assets the page loads, along with their sizes in bytes.
+	pub assets: BTreeMap<PathBuf, u64>,
+}
+
+impl PageWeight {
+	/// Measures a page in a set of files. Assets that don't exist are left
+	/// out.
+	pub fn new(path: &Path, file: &ShFile, files: &HashMap<PathBuf, ShFile>) -> PageWeight {
+		let html = String::from_utf8_lossy(&file.content);
+		let assets = assets(&html)
+			.iter()
+			.filter_map(|link| resolve(path, link, files))
+			.filter_map(|target| {
+				let size = files.get(&target)?.content.len() as u64;
+				Some((target, size))
+			})
+			.collect();
+		PageWeight {
+			size: file.content.len() as u64,
+			assets,
+		}
+	}
+
+	/// The size of the page and all its assets in bytes.
+	pub fn total(&self) -> u64 {
+		self.size + self.assets.values().sum::<u64>()
+	}
+}
+
+/// Creates the page weight plugin.
+pub fn plugin(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Weighing pages");
+		let mut weights: Vec<(PathBuf, PageWeight)> = ir
+			.files
+			.iter()
+			.filter(|(p, _)| has_extension(p, HTML_EXTENSIONS))
+			.map(|(path, file)| (path.clone(), PageWeight::new(path, file, &ir.files)))
+			.collect();
+		weights.sort_by(|(a_path, a), (b_path, b)| {
+			b.total().cmp(&a.total()).then_with(|| a_path.cmp(b_path))
+		});
+		for (path, weight) in weights.iter().take(options.top) {
+			info!(
+				"{:?}: {} bytes in total, {} assets",
+				path,
+				weight.total(),
+				weight.assets.len()
+			);
+		}
+		for (path, weight) in &weights {
+			if options.page_budget.is_some_and(|b| weight.total() > b) {
+				warn!("{:?} is over budget with {} bytes", path, weight.total());
+			}
+			for (asset, size) in &weight.assets {
+				if options.asset_budget.is_some_and(|b| *size > b) {
+					warn!(
+						"{:?} loads {:?}, which is over budget with {} bytes",
+						path, asset, size
+					);
+				}
+			}
+		}
+		let report: Map<String, Value> = weights
+			.iter()
+			.map(|(path, weight)| {
+				let assets: Map<String, Value> = weight
+					.assets
+					.iter()
+					.map(|(p, size)| (p.to_string_lossy().into_owned(), json!(size)))
+					.collect();
+				let entry = json!({
+					"size": weight.size,
+					"total": weight.total(),
+					"assets": assets,
+				});
+				(path.to_string_lossy().into_owned(), entry)
+			})
+			.collect();
+		IR {
+			metadata: ir.metadata.update("weights".into(), Value::Object(report)),
+			..ir
+		}
+	})
+}
+
+// Extracts the URLs of everything a page loads.
+fn assets(html: &str) -> Vec<String> {
+	static SRC: OnceLock<Regex> = OnceLock::new();
+	static LINK: OnceLock<Regex> = OnceLock::new();
+	static NAVIGATION: OnceLock<Regex> = OnceLock::new();
+	let src = SRC
+		.get_or_init(|| Regex::new(r#"(?i)\b(src|srcset)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+	let link = LINK.get_or_init(|| Regex::new(r"(?i)<link\s[^>]*>").unwrap());
+	let mut urls = Vec::new();
+	for captures in src.captures_iter(html) {
+		let value = captures
+			.get(2)
+			.or_else(|| captures.get(3))
+			.unwrap()
+			.as_str();
+		if captures[1].eq_ignore_ascii_case("srcset") {
+			// Candidates like `small.png 1x, large.png 2x`.
+			urls.extend(
+				value
+					.split(',')
+					.filter_map(|c| c.split_whitespace().next())
+					.map(String::from),
+			);
+		} else {
+			urls.push(value.to_string());
+		}
+	}
+	// Links like `<link rel="canonical">` refer to pages instead of loading
+	// anything.
+	let navigation = NAVIGATION.get_or_init(|| {
+		Regex::new(
+			r#"(?i)\brel\s*=\s*["']?(alternate|author|canonical|help|license|me|next|prev|search)\b"#,
+		)
+		.unwrap()
+	});
+	for tag in link.find_iter(html).map(|m| m.as_str()) {
+		if !navigation.is_match(tag) {
+			urls.extend(crate::links(tag));
+		}
+	}
+	urls
+}
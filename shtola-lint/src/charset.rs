@@ -0,0 +1,177 @@
+//! Charset enforcement for HTML output. Every page should be valid UTF-8 and
+//! say so with `<meta charset="utf-8">`, or browsers guess, and guess wrong
+//! for content mixing encodings. The [`plugin`](fn.plugin.html) fixes pages
+//! that don't, or flags them:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_lint::charset;
+//!
+//! let mut s = Shtola::new();
+//! s.register(charset::plugin(charset::Mode::Fix));
+//! ```
+//!
+//! When fixing, bytes that aren't valid UTF-8 are read as Windows-1252, which
+//! is what mixed-encoding content usually contains, charset declarations
+//! other than UTF-8 are replaced, and missing ones are added at the start of
+//! `<head>`. Every fix is logged as a warning. When flagging, such pages are
+//! failed with [`IR::fail_file_at`](../../shtola/struct.IR.html#method.fail_file_at)
+//! instead.
+//!
+//! Unlike the lint plugin, this plugin should run last, so it sees the pages
+//! as they are written.
+
+use regex::Regex;
+use shtola::log::{info, warn};
+use shtola::{HashMap, Location, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// What to do with pages breaking the rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+	/// Fix the page and log a warning.
+	Fix,
+	/// Fail the page.
+	Flag,
+}
+
+/// Creates the charset plugin.
+pub fn plugin(mode: Mode) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Checking charsets");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let (html, invalid_line) = match std::str::from_utf8(&file.content) {
+				Ok(html) => (html.to_string(), None),
+				Err(e) => {
+					let line = file.content[..e.valid_up_to()]
+						.iter()
+						.filter(|b| **b == b'\n')
+						.count() + 1;
+					(decode(&file.content), Some(line))
+				}
+			};
+			let declared = declare_utf8(&html);
+			let mut problems = Vec::new();
+			if let Some(line) = invalid_line {
+				problems.push(("Invalid UTF-8".to_string(), Some(line)));
+			}
+			if let Some(problem) = &declared.1 {
+				problems.push((problem.clone(), None));
+			}
+			if problems.is_empty() {
+				continue;
+			}
+			match mode {
+				Mode::Fix => {
+					for (problem, _) in &problems {
+						warn!("{:?}: {}, fixing it", path, problem);
+					}
+					update_hash.insert(
+						path.clone(),
+						ShFile {
+							content: declared.0.into_bytes(),
+							..file.clone()
+						},
+					);
+				}
+				Mode::Flag => failures.extend(problems.into_iter().map(|p| (path.clone(), p))),
+			}
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, (message, line))| match line {
+				Some(line) => ir.fail_file_at(
+					&path,
+					&message,
+					Location {
+						path: path.clone(),
+						line,
+						column: None,
+					},
+				),
+				None => ir.fail_file(&path, &message),
+			})
+	})
+}
+
+// Makes a page declare UTF-8, returning the fixed page and what was wrong.
+fn declare_utf8(html: &str) -> (String, Option<String>) {
+	static META: OnceLock<Regex> = OnceLock::new();
+	static OPENINGS: OnceLock<Vec<Regex>> = OnceLock::new();
+	let meta = META.get_or_init(|| {
+		Regex::new(
+			r#"(?i)<meta\s[^>]*?(?:\bcharset\s*=\s*["']?|content\s*=\s*["'][^"']*;\s*charset=)([\w-]+)[^>]*>"#,
+		)
+		.unwrap()
+	});
+	let openings = OPENINGS.get_or_init(|| {
+		[
+			r"(?i)<head(?:\s[^>]*)?>",
+			r"(?i)<html(?:\s[^>]*)?>",
+			r"(?i)<!doctype[^>]*>",
+		]
+		.iter()
+		.map(|re| Regex::new(re).unwrap())
+		.collect()
+	});
+	const DECLARATION: &str = "<meta charset=\"utf-8\">";
+	if let Some(declared) = meta.captures(html) {
+		let charset = &declared[1];
+		if charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("utf8") {
+			return (html.to_string(), None);
+		}
+		let tag = declared.get(0).unwrap();
+		let fixed = format!(
+			"{}{}{}",
+			&html[..tag.start()],
+			DECLARATION,
+			&html[tag.end()..]
+		);
+		return (fixed, Some(format!("Declares the charset {}", charset)));
+	}
+	// Right after `<head>`, or else where it would start.
+	let at = openings
+		.iter()
+		.find_map(|re| re.find(html))
+		.map_or(0, |m| m.end());
+	let fixed = format!("{}{}{}", &html[..at], DECLARATION, &html[at..]);
+	(fixed, Some("Missing <meta charset=\"utf-8\">".into()))
+}
+
+// Decodes text that is mostly UTF-8, reading the invalid bytes as
+// Windows-1252.
+fn decode(bytes: &[u8]) -> String {
+	// Windows-1252 differs from Latin-1 in these bytes, starting at 0x80.
+	const HIGH: [char; 32] = [
+		'€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+		'\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+		'ž', 'Ÿ',
+	];
+	let mut result = String::with_capacity(bytes.len());
+	let mut rest = bytes;
+	loop {
+		match std::str::from_utf8(rest) {
+			Ok(valid) => {
+				result.push_str(valid);
+				return result;
+			}
+			Err(e) => {
+				let (valid, invalid) = rest.split_at(e.valid_up_to());
+				result.push_str(std::str::from_utf8(valid).unwrap());
+				let byte = invalid[0];
+				result.push(match byte {
+					0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+					_ => byte as char,
+				});
+				rest = &invalid[1..];
+			}
+		}
+	}
+}
@@ -0,0 +1,551 @@
+//! Content linting for Shtola. Rules declare what the content and frontmatter
+//! of files may or must contain, and the [`plugin`](fn.plugin.html) checks
+//! them all at once:
+//!
+//! ```
+//! use shtola::{Shtola, ShtolaError};
+//! use shtola_lint::Rule;
+//!
+//! fn configure(s: &mut Shtola) -> Result<(), ShtolaError> {
+//!   s.register(shtola_lint::plugin(vec![
+//!     Rule::forbid("no-todo", r"\bTODO\b", "Unfinished TODO marker")
+//!       .only("posts/**")?
+//!       .unless("draft", true),
+//!     Rule::forbid("image-alt", r"!\[\]\(", "Image without alt text").warning(),
+//!     Rule::require("title", "title").only("**/*.md")?,
+//!   ]));
+//!   Ok(())
+//! }
+//! ```
+//!
+//! Rules with the error severity report the files breaking them with
+//! [`IR::fail_file_at`](../shtola/struct.IR.html#method.fail_file_at), so
+//! they fail the build in strict mode and are shown with the offending line.
+//! Warnings are logged. All findings are also stored in the `lint` metadata
+//! key:
+//!
+//! ```json
+//! [{ "rule": "no-todo", "severity": "error", "path": "posts/hello.md", "line": 5, "column": 1, "message": "Unfinished TODO marker" }]
+//! ```
+//!
+//! The plugin should run before the content is transformed, so lines point
+//! into the source files. The output is checked by the
+//! [`charset`](charset/index.html) plugin instead. Pages that haven't been updated
+//! for a long time are reported by the [`stale`](stale/index.html) plugin.
+
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use shtola::frontmatter::get;
+use shtola::json::{json, Value};
+use shtola::log::{info, warn};
+use shtola::{HashMap, Location, Plugin, ShFile, ShtolaError, IR};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub mod charset;
+pub mod stale;
+
+/// How bad breaking a rule is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	/// The finding is logged.
+	Warning,
+	/// The file fails to be processed.
+	Error,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Severity::Warning => write!(f, "warning"),
+			Severity::Error => write!(f, "error"),
+		}
+	}
+}
+
+/// A problem found by a custom rule, see [`Rule::custom`](struct.Rule.html#method.custom).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+	/// What's wrong.
+	pub message: String,
+	/// The line in the content, starting at 1, if known.
+	pub line: Option<usize>,
+	/// The column, starting at 1, if known.
+	pub column: Option<usize>,
+}
+
+impl Problem {
+	/// Creates a problem concerning the whole file.
+	pub fn new(message: &str) -> Problem {
+		Problem {
+			message: message.to_string(),
+			line: None,
+			column: None,
+		}
+	}
+}
+
+/// A callback checking a single file.
+pub type CustomRule = Box<dyn Fn(&Path, &ShFile) -> Vec<Problem>>;
+
+enum Check {
+	Forbid(Regex, String),
+	Require(String),
+	Custom(CustomRule),
+}
+
+/// A lint rule. Rules are errors by default and apply to all files.
+pub struct Rule {
+	name: String,
+	severity: Severity,
+	glob: Option<GlobMatcher>,
+	conditions: Vec<(String, Value, bool)>,
+	check: Check,
+}
+
+impl Rule {
+	/// Creates a rule forbidding a regular expression in the content. Every
+	/// match is reported.
+	///
+	/// # Panics
+	///
+	/// Panics if the regular expression is invalid.
+	pub fn forbid(name: &str, pattern: &str, message: &str) -> Rule {
+		Rule::new(
+			name,
+			Check::Forbid(Regex::new(pattern).unwrap(), message.to_string()),
+		)
+	}
+
+	/// Creates a rule requiring a frontmatter key. Nested keys are written
+	/// with dots, like `author.name`.
+	pub fn require(name: &str, key: &str) -> Rule {
+		Rule::new(name, Check::Require(key.to_string()))
+	}
+
+	/// Creates a rule checking files with a callback, which returns every
+	/// problem it found.
+	pub fn custom(name: &str, check: CustomRule) -> Rule {
+		Rule::new(name, Check::Custom(check))
+	}
+
+	fn new(name: &str, check: Check) -> Rule {
+		Rule {
+			name: name.to_string(),
+			severity: Severity::Error,
+			glob: None,
+			conditions: Vec::new(),
+			check,
+		}
+	}
+
+	/// Lowers the severity of the rule to a warning.
+	pub fn warning(mut self) -> Rule {
+		self.severity = Severity::Warning;
+		self
+	}
+
+	/// Only applies the rule to files matching a glob. Fails if the glob is
+	/// invalid.
+	pub fn only(mut self, glob: &str) -> Result<Rule, ShtolaError> {
+		self.glob = Some(Glob::new(glob)?.compile_matcher());
+		Ok(self)
+	}
+
+	/// Only applies the rule to files whose frontmatter key equals the value,
+	/// or contains it if the key holds a list.
+	pub fn when<V: Into<Value>>(mut self, key: &str, value: V) -> Rule {
+		self.conditions.push((key.to_string(), value.into(), true));
+		self
+	}
+
+	/// Only applies the rule to files whose frontmatter key doesn't equal or
+	/// contain the value.
+	pub fn unless<V: Into<Value>>(mut self, key: &str, value: V) -> Rule {
+		self.conditions.push((key.to_string(), value.into(), false));
+		self
+	}
+
+	fn applies(&self, path: &Path, file: &ShFile) -> bool {
+		self.glob.as_ref().is_none_or(|g| g.is_match(path))
+			&& self.conditions.iter().all(|(key, value, expected)| {
+				let matches = match get(&file.frontmatter, key) {
+					Some(Value::Array(items)) => items.contains(value),
+					Some(v) => v == value,
+					None => false,
+				};
+				matches == *expected
+			})
+	}
+
+	fn check(&self, path: &Path, file: &ShFile) -> Vec<Problem> {
+		match &self.check {
+			Check::Forbid(re, message) => {
+				let text = String::from_utf8_lossy(&file.content);
+				re.find_iter(&text)
+					.map(|m| {
+						let before = &text[..m.start()];
+						let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+						Problem {
+							message: message.clone(),
+							line: Some(before.matches('\n').count() + 1),
+							column: Some(before[line_start..].chars().count() + 1),
+						}
+					})
+					.collect()
+			}
+			Check::Require(key) => match get(&file.frontmatter, key) {
+				Some(_) => Vec::new(),
+				None => vec![Problem::new(&format!(
+					"Frontmatter key {:?} is missing",
+					key
+				))],
+			},
+			Check::Custom(check) => check(path, file),
+		}
+	}
+}
+
+/// A broken rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+	/// The name of the rule.
+	pub rule: String,
+	/// The severity of the rule.
+	pub severity: Severity,
+	/// The path of the file.
+	pub path: PathBuf,
+	/// The line in the source file, starting at 1, if known. Lines of the
+	/// frontmatter are counted.
+	pub line: Option<usize>,
+	/// The column, starting at 1, if known.
+	pub column: Option<usize>,
+	/// What's wrong.
+	pub message: String,
+}
+
+impl fmt::Display for Finding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}[{}]: {}", self.severity, self.rule, self.message)
+	}
+}
+
+/// Checks all files against the rules. Findings are sorted by path and line.
+pub fn lint(rules: &[Rule], files: &HashMap<PathBuf, ShFile>) -> Vec<Finding> {
+	let mut findings = Vec::new();
+	for (path, file) in files.iter() {
+		// Lines of the content start after the frontmatter and its fences.
+		let offset = match file.raw_frontmatter.as_str() {
+			"" => 0,
+			matter => matter.lines().count() + 2,
+		};
+		for rule in rules.iter().filter(|r| r.applies(path, file)) {
+			findings.extend(rule.check(path, file).into_iter().map(|p| Finding {
+				rule: rule.name.clone(),
+				severity: rule.severity,
+				path: path.clone(),
+				line: p.line.map(|l| l + offset),
+				column: p.column,
+				message: p.message,
+			}));
+		}
+	}
+	findings.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+	findings
+}
+
+/// Creates the lint plugin.
+pub fn plugin(rules: Vec<Rule>) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Linting {} files", ir.files.len());
+		let findings = lint(&rules, &ir.files);
+		let report: Vec<Value> = findings
+			.iter()
+			.map(|f| {
+				json!({
+					"rule": f.rule,
+					"severity": f.severity.to_string(),
+					"path": f.path,
+					"line": f.line,
+					"column": f.column,
+					"message": f.message,
+				})
+			})
+			.collect();
+		let mut ir = IR {
+			metadata: ir.metadata.update("lint".into(), Value::Array(report)),
+			..ir
+		};
+		for finding in findings {
+			if finding.severity == Severity::Warning {
+				match finding.line {
+					Some(line) => warn!("{:?}:{}: {}", finding.path, line, finding),
+					None => warn!("{:?}: {}", finding.path, finding),
+				}
+				continue;
+			}
+			ir = match finding.line {
+				Some(line) => ir.fail_file_at(
+					&finding.path,
+					&finding.to_string(),
+					Location {
+						path: finding.path.clone(),
+						line,
+						column: finding.column,
+					},
+				),
+				None => ir.fail_file(&finding.path, &finding.to_string()),
+			};
+		}
+		ir
+	})
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let build = |strict: bool| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/lint");
+		s.destination("../fixtures/dest_lint");
+		s.strict(strict);
+		s.register(plugin(vec![
+			Rule::forbid("no-todo", r"\bTODO\b", "Unfinished TODO marker")
+				.only("posts/**")
+				.unwrap()
+				.unless("draft", true),
+			Rule::forbid("image-alt", r"!\[\]\(", "Image without alt text").warning(),
+			Rule::require("title", "title"),
+			Rule::custom(
+				"short",
+				Box::new(|_, file: &ShFile| {
+					if file.content.len() > 100 {
+						vec![Problem::new("Too long")]
+					} else {
+						Vec::new()
+					}
+				}),
+			),
+		]));
+		s.build()
+	};
+	let r = build(false).unwrap();
+	let lint = r.metadata.get("lint").unwrap();
+	assert_eq!(
+		lint,
+		&json!([
+			{
+				"rule": "image-alt",
+				"severity": "warning",
+				"path": "posts/published.md",
+				"line": 5,
+				"column": 1,
+				"message": "Image without alt text",
+			},
+			{
+				"rule": "no-todo",
+				"severity": "error",
+				"path": "posts/published.md",
+				"line": 6,
+				"column": 7,
+				"message": "Unfinished TODO marker",
+			},
+		])
+	);
+	assert!(r.files.get(Path::new("posts/draft.md")).is_some());
+	assert!(r.files.get(Path::new("posts/published.md")).is_none());
+
+	match build(true) {
+		Err(ShtolaError::Verification { files, .. }) => {
+			assert_eq!(files.len(), 1);
+			assert_eq!(files[0].message, "error[no-todo]: Unfinished TODO marker");
+			assert_eq!(files[0].location.as_ref().unwrap().line, 6);
+		}
+		other => panic!("unexpected result: {:?}", other.map(|_| ())),
+	}
+}
+
+#[test]
+fn charset_works() {
+	use shtola::Shtola;
+
+	let build = |mode: charset::Mode, destination: &str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/charset");
+		s.destination(destination);
+		s.strict(false);
+		s.register(charset::plugin(mode));
+		s.build().unwrap()
+	};
+	let r = build(charset::Mode::Fix, "../fixtures/dest_charset");
+	let content = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		content("good.html"),
+		"<html><head><meta charset=\"UTF-8\"></head><body>Café</body></html>\n"
+	);
+	assert_eq!(
+		content("missing.html"),
+		"<!DOCTYPE html>\n<html><HEAD lang=\"en\"><meta charset=\"utf-8\"><title>Hi</title></HEAD></html>\n"
+	);
+	assert_eq!(
+		content("latin.html"),
+		"<html><head><meta charset=\"utf-8\"></head>\n<body>Café “crème” — é</body></html>\n"
+	);
+	assert_eq!(
+		content("fragment.html"),
+		"<meta charset=\"utf-8\"><p>Hi</p>\n"
+	);
+	assert_eq!(content("style.css"), "body {}\n");
+	assert!(r.report.errors.is_empty());
+
+	let r = build(charset::Mode::Flag, "../fixtures/dest_charset_flag");
+	let mut errors: Vec<(String, String, Option<usize>)> = r
+		.report
+		.errors
+		.iter()
+		.map(|e| {
+			(
+				e.path.to_string_lossy().into_owned(),
+				e.message.clone(),
+				e.location.as_ref().map(|l| l.line),
+			)
+		})
+		.collect();
+	errors.sort();
+	assert_eq!(
+		errors,
+		vec![
+			(
+				"fragment.html".into(),
+				"Missing <meta charset=\"utf-8\">".into(),
+				None
+			),
+			(
+				"latin.html".into(),
+				"Declares the charset iso-8859-1".into(),
+				None
+			),
+			("latin.html".into(), "Invalid UTF-8".into(), Some(2)),
+			(
+				"missing.html".into(),
+				"Missing <meta charset=\"utf-8\">".into(),
+				None
+			),
+		]
+	);
+	assert!(r.files.contains_key(Path::new("good.html")));
+}
+
+#[test]
+fn invalid_globs_fail() {
+	let e = Rule::require("title", "title")
+		.only("posts/[")
+		.err()
+		.unwrap();
+	assert!(matches!(e, ShtolaError::Glob(_)));
+	let e = stale::plugin(stale::Options {
+		only: Some("docs/[".into()),
+		..stale::Options::default()
+	})
+	.err()
+	.unwrap();
+	assert!(matches!(e, ShtolaError::Glob(_)));
+}
+
+#[test]
+fn stale_works() {
+	use shtola::frontmatter::Date;
+	use shtola::Shtola;
+
+	let build = |severity: Severity, destination: &str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/stale");
+		s.destination(destination);
+		s.strict(false);
+		s.register(
+			stale::plugin(stale::Options {
+				severity,
+				today: Date::parse("2020-07-01"),
+				..stale::Options::default()
+			})
+			.unwrap(),
+		);
+		s.build().unwrap()
+	};
+	let r = build(Severity::Warning, "../fixtures/dest_stale");
+	assert_eq!(
+		r.metadata["stale"],
+		json!([
+			{ "path": "guides/setup.md", "updated": "2018-05-01", "age_days": 792 },
+			{ "path": "intro.md", "updated": "2019-03-01", "age_days": 488 },
+		])
+	);
+	let errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	assert_eq!(errors, vec![Path::new("broken.md")]);
+	assert!(r.files.contains_key(Path::new("intro.md")));
+
+	let r = build(Severity::Error, "../fixtures/dest_stale_error");
+	let mut errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	errors.sort();
+	assert_eq!(
+		errors,
+		vec![
+			Path::new("broken.md"),
+			Path::new("guides/setup.md"),
+			Path::new("intro.md"),
+		]
+	);
+}
+
+#[test]
+fn stale_git_dates_work() {
+	use shtola::frontmatter::Date;
+	use shtola::Shtola;
+	use std::process::Command;
+
+	let source = Path::new("../fixtures/dest_stale_git_source");
+	let _ = std::fs::remove_dir_all(source);
+	std::fs::create_dir_all(source).unwrap();
+	std::fs::write(source.join("café.md"), "Café").unwrap();
+	std::fs::write(source.join("two words.md"), "Two words").unwrap();
+	let git = |args: &[&str]| {
+		let status = Command::new("git")
+			.args(args)
+			.current_dir(source)
+			.env("GIT_AUTHOR_NAME", "Shtola")
+			.env("GIT_AUTHOR_EMAIL", "shtola@example.com")
+			.env("GIT_COMMITTER_NAME", "Shtola")
+			.env("GIT_COMMITTER_EMAIL", "shtola@example.com")
+			.env("GIT_COMMITTER_DATE", "2018-05-01T12:00:00Z")
+			.status()
+			.unwrap();
+		assert!(status.success());
+	};
+	git(&["init", "-q"]);
+	git(&["add", "."]);
+	git(&["commit", "-q", "-m", "Add pages"]);
+	let mut s = Shtola::new();
+	s.source(source);
+	s.destination("../fixtures/dest_stale_git");
+	s.ignores(&mut vec![".git".into()]);
+	s.register(
+		stale::plugin(stale::Options {
+			git: true,
+			today: Date::parse("2020-07-01"),
+			..stale::Options::default()
+		})
+		.unwrap(),
+	);
+	let r = s.build().unwrap();
+	assert_eq!(
+		r.metadata["stale"],
+		json!([
+			{ "path": "café.md", "updated": "2018-05-01", "age_days": 792 },
+			{ "path": "two words.md", "updated": "2018-05-01", "age_days": 792 },
+		])
+	);
+}
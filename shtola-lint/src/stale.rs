@@ -0,0 +1,224 @@
+//! Staleness reports for documentation. The [`plugin`](fn.plugin.html) flags
+//! pages that haven't been updated for longer than a threshold, so outdated
+//! pages can be triaged as part of the build:
+//!
+//! ```
+//! use shtola::{Shtola, ShtolaError};
+//! use shtola_lint::stale;
+//!
+//! fn configure(s: &mut Shtola) -> Result<(), ShtolaError> {
+//!   s.register(stale::plugin(stale::Options {
+//!     max_age_days: 180,
+//!     git: true,
+//!     ..stale::Options::default()
+//!   })?);
+//!   Ok(())
+//! }
+//! ```
+//!
+//! A page was last updated on the date in its `updated` frontmatter key. If
+//! it has none, the date of the last commit touching it is used when
+//! [`Options::git`](struct.Options.html#structfield.git) is set, and the
+//! modification time of the source file otherwise. Pages without any of these
+//! are skipped.
+//!
+//! Stale pages are logged as warnings, or failed if the severity is
+//! [`Severity::Error`](../enum.Severity.html#variant.Error), and listed in
+//! the `stale` metadata key, oldest first:
+//!
+//! ```json
+//! [{ "path": "guides/setup.md", "updated": "2019-03-01", "age_days": 412 }]
+//! ```
+//!
+//! The plugin should run before pages are renamed, so the paths match the
+//! source files.
+
+use crate::Severity;
+use globset::{Glob, GlobMatcher};
+use shtola::frontmatter::{get_date, Date};
+use shtola::json::{json, Value};
+use shtola::log::{debug, info, warn};
+use shtola::{HashMap, Plugin, ShFile, ShtolaError, IR};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Options for the staleness plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// How many days a page may go without an update. Default is 365.
+	pub max_age_days: i64,
+	/// The frontmatter key holding the date of the last update. Default is
+	/// `updated`.
+	pub key: String,
+	/// Whether to ask git for the date of the last commit touching a page.
+	/// Default is `false`.
+	pub git: bool,
+	/// Only checks files matching the glob. Default is `None`, checking
+	/// Markdown and HTML files.
+	pub only: Option<String>,
+	/// Whether stale pages are logged or failed. Default is
+	/// [`Severity::Warning`](../enum.Severity.html#variant.Warning).
+	pub severity: Severity,
+	/// The date ages are computed from. Default is `None`, using the
+	/// [build date](../../shtola/struct.IR.html#method.build_date).
+	pub today: Option<Date>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			max_age_days: 365,
+			key: "updated".into(),
+			git: false,
+			only: None,
+			severity: Severity::Warning,
+			today: None,
+		}
+	}
+}
+
+/// A page that hasn't been updated for too long.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StalePage {
+	/// The path of the page.
+	pub path: PathBuf,
+	/// When the page was last updated.
+	pub updated: Date,
+	/// How many days ago that was.
+	pub age_days: i64,
+}
+
+/// Creates the staleness plugin. Fails if the glob in
+/// [`Options::only`](struct.Options.html#structfield.only) is invalid.
+pub fn plugin(options: Options) -> Result<Plugin, ShtolaError> {
+	let glob: Option<GlobMatcher> = match &options.only {
+		Some(g) => Some(Glob::new(g)?.compile_matcher()),
+		None => None,
+	};
+	Ok(Box::new(move |ir: IR| {
+		info!("Looking for stale pages");
+		let today = options
+			.today
+			.unwrap_or_else(|| ir.build_date())
+			.days_since_epoch();
+		let commits = if options.git {
+			git_dates(&ir.config.source)
+		} else {
+			HashMap::new()
+		};
+		let mut stale = Vec::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files.iter() {
+			let checked = match &glob {
+				Some(glob) => glob.is_match(path),
+				None => is_page(path),
+			};
+			if !checked {
+				continue;
+			}
+			let updated = match last_update(path, file, &options.key, &commits) {
+				Ok(Some(updated)) => updated,
+				Ok(None) => continue,
+				Err(e) => {
+					failures.push((path.clone(), e));
+					continue;
+				}
+			};
+			let age_days = today - updated.days_since_epoch();
+			if age_days > options.max_age_days {
+				stale.push(StalePage {
+					path: path.clone(),
+					updated,
+					age_days,
+				});
+			}
+		}
+		stale.sort_by(|a, b| (b.age_days, &a.path).cmp(&(a.age_days, &b.path)));
+		debug!("Found {} stale pages", stale.len());
+		let report: Vec<Value> = stale
+			.iter()
+			.map(|page| {
+				json!({
+					"path": page.path,
+					"updated": page.updated.to_string(),
+					"age_days": page.age_days,
+				})
+			})
+			.collect();
+		let mut ir = IR {
+			metadata: ir.metadata.update("stale".into(), Value::Array(report)),
+			..ir
+		};
+		for page in stale {
+			let message = format!(
+				"Last updated on {}, {} days ago",
+				page.updated, page.age_days
+			);
+			match options.severity {
+				Severity::Warning => warn!("{:?}: {}", page.path, message),
+				Severity::Error => failures.push((page.path, message)),
+			}
+		}
+		for (path, message) in failures {
+			ir = ir.fail_file(path, &message);
+		}
+		ir
+	}))
+}
+
+fn last_update(
+	path: &Path,
+	file: &ShFile,
+	key: &str,
+	commits: &HashMap<PathBuf, Date>,
+) -> Result<Option<Date>, String> {
+	if let Some(date) = get_date(&file.frontmatter, key).map_err(|e| e.to_string())? {
+		return Ok(Some(date));
+	}
+	if let Some(date) = commits.get(path) {
+		return Ok(Some(*date));
+	}
+	let modified = file.stat.as_ref().and_then(|s| s.modified);
+	Ok(modified.map(Date::from_system_time))
+}
+
+// Reads the date of the last commit touching each file in the source
+// directory, relative to it. Files outside of a repository have none.
+fn git_dates(source: &Path) -> HashMap<PathBuf, Date> {
+	let mut dates = HashMap::new();
+	// With `-z`, names aren't quoted or escaped, and each commit starts with
+	// an empty field followed by its date.
+	let output = Command::new("git")
+		.args(["-c", "core.quotePath=false", "log", "-z"])
+		.args(["--format=%x00%cs", "--name-only", "--relative"])
+		.current_dir(source)
+		.output();
+	let output = match output {
+		Ok(output) if output.status.success() => output.stdout,
+		_ => return dates,
+	};
+	let mut current = None;
+	let mut date_next = false;
+	for field in output.split(|&b| b == 0) {
+		if date_next {
+			current = Date::parse(&String::from_utf8_lossy(field));
+			date_next = false;
+		} else if field.is_empty() {
+			date_next = true;
+		} else if let Some(date) = current {
+			// The names of a commit start on a new line.
+			let name = field.strip_prefix(b"\n").unwrap_or(field);
+			let name = String::from_utf8_lossy(name).into_owned();
+			// The log starts with the latest commit.
+			dates.entry(PathBuf::from(name)).or_insert(date);
+		}
+	}
+	dates
+}
+
+fn is_page(path: &Path) -> bool {
+	match path.extension().and_then(|e| e.to_str()) {
+		Some(extension) => ["md", "html", "htm"].contains(&extension),
+		None => false,
+	}
+}
@@ -6,34 +6,42 @@ use std::path::PathBuf;
 pub fn plugin() -> Plugin {
 	Box::new(|ir: IR| {
 		info!("Starting Markdown processing");
-		let markdown_files = ir
-			.files
-			.iter()
-			.filter(|(p, _)| p.extension().unwrap() == "md");
 		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
-		let mut removal_hash: HashMap<PathBuf, ShFile> = HashMap::new();
-		for (path, file) in markdown_files {
+		let mut invalid = Vec::new();
+		for (path, file) in ir.files_with_extension("md") {
 			debug!("Processing {:?}", &path);
-			let mut p = path.clone();
-			p.set_extension("html");
-			removal_hash.insert(path.to_path_buf(), ShFile::empty());
+			let markdown = match std::str::from_utf8(&file.content) {
+				Ok(markdown) => markdown,
+				Err(_) => {
+					invalid.push(path.clone());
+					continue;
+				}
+			};
 			update_hash.insert(
-				p,
+				path.clone(),
 				ShFile {
-					content: markdown_to_html(
-						std::str::from_utf8(&file.content).unwrap(),
-						&ComrakOptions::default(),
-					)
-					.into(),
-					frontmatter: file.frontmatter.clone(),
+					content: markdown_to_html(markdown, &ComrakOptions::default()).into(),
+					mime: Some("text/html".into()),
+					..file.clone()
 				},
 			);
 		}
 		info!("Finished Markdown processing");
-		IR {
-			files: update_hash.union(ir.files).difference(removal_hash),
+		let paths: Vec<PathBuf> = update_hash.keys().cloned().collect();
+		let ir = IR {
+			files: update_hash.union(ir.files),
 			..ir
-		}
+		};
+		let ir = invalid.into_iter().fold(ir, |ir, path| {
+			ir.fail_file(
+				path,
+				"Unable to render Markdown, the file is not valid UTF-8",
+			)
+		});
+		paths.into_iter().fold(ir, |ir, path| {
+			let html = path.with_extension("html");
+			ir.rename(path, html)
+		})
 	})
 }
 
@@ -53,3 +61,19 @@ fn it_works() {
 		"<h1>Hello!</h1>\n<p>What's going <em>on</em>?</p>\n"
 	)
 }
+
+#[test]
+fn invalid_utf8_fails() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/markdown_invalid");
+	s.destination("../fixtures/dest_markdown_invalid");
+	s.strict(false);
+	s.register(plugin());
+	let r = s.build().unwrap();
+	assert!(r.files.get(&PathBuf::from("latin1.md")).is_none());
+	assert!(r.files.get(&PathBuf::from("latin1.html")).is_none());
+	let errors: Vec<&PathBuf> = r.report.errors.iter().map(|e| &e.path).collect();
+	assert_eq!(errors, vec![&PathBuf::from("latin1.md")]);
+}
@@ -0,0 +1,198 @@
+//! PDF export for Shtola. The [`plugin`](fn.plugin.html) renders every HTML
+//! page with `pdf: true` in its frontmatter into a PDF next to it, so
+//! `guide/setup.html` gets a `guide/setup.pdf`:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_pdf::Renderer;
+//!
+//! let mut s = Shtola::new();
+//! s.register_fallible(shtola_pdf::plugin(Renderer::WeasyPrint));
+//! ```
+//!
+//! Rendering HTML well is a big job, so it's left to an external headless
+//! renderer, which has to be installed separately. Pages are rendered from a
+//! temporary copy of the build output, so stylesheets and images they link to
+//! relatively are picked up. The plugin should run after the pages were
+//! rendered.
+//!
+//! The build fails if the renderer can't be started at all. PDFs the renderer
+//! fails on are reported as failed files, while their pages stay in the build.
+
+use shtola::command::{run, temp_dir};
+use shtola::frontmatter::get_bool;
+use shtola::log::{debug, info};
+use shtola::manifest::tool_version;
+use shtola::{has_extension, HashMap, PluginError, ShFile, TryPlugin, HTML_EXTENSIONS, IR};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A headless renderer turning HTML into PDF.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Renderer {
+	/// [WeasyPrint](https://weasyprint.org), which supports CSS paged media.
+	WeasyPrint,
+	/// Chromium in headless mode.
+	Chromium,
+	/// [wkhtmltopdf](https://wkhtmltopdf.org).
+	WkHtmlToPdf,
+	/// A custom command, as the program followed by its arguments. `{input}`
+	/// and `{output}` in the arguments are replaced with the paths of the HTML
+	/// and PDF files. The command runs in the directory of the HTML file.
+	Command(Vec<String>),
+}
+
+impl Renderer {
+	// The program and arguments to render `input` into `output`.
+	fn command(&self, input: &Path, output: &Path) -> Vec<String> {
+		let input = input.to_string_lossy().into_owned();
+		let output = output.to_string_lossy().into_owned();
+		match self {
+			Renderer::WeasyPrint => vec!["weasyprint".into(), input, output],
+			Renderer::Chromium => vec![
+				"chromium".into(),
+				"--headless".into(),
+				"--disable-gpu".into(),
+				"--no-pdf-header-footer".into(),
+				format!("--print-to-pdf={}", output),
+				format!("file://{}", input),
+			],
+			Renderer::WkHtmlToPdf => vec![
+				"wkhtmltopdf".into(),
+				"--enable-local-file-access".into(),
+				input,
+				output,
+			],
+			Renderer::Command(args) => args
+				.iter()
+				.map(|a| a.replace("{input}", &input).replace("{output}", &output))
+				.collect(),
+		}
+	}
+
+	// The program of a built-in renderer, whose version is recorded in the
+	// build manifest.
+	fn program(&self) -> Option<&'static str> {
+		match self {
+			Renderer::WeasyPrint => Some("weasyprint"),
+			Renderer::Chromium => Some("chromium"),
+			Renderer::WkHtmlToPdf => Some("wkhtmltopdf"),
+			Renderer::Command(_) => None,
+		}
+	}
+}
+
+/// Creates the PDF plugin using the given renderer.
+pub fn plugin(renderer: Renderer) -> TryPlugin {
+	Box::new(move |ir: IR| {
+		let pages: Vec<PathBuf> = ir
+			.files
+			.iter()
+			.filter(|(p, f)| {
+				has_extension(p, HTML_EXTENSIONS)
+					&& get_bool(&f.frontmatter, "pdf") == Ok(Some(true))
+			})
+			.map(|(p, _)| p.clone())
+			.collect();
+		if pages.is_empty() {
+			return Ok(ir);
+		}
+		info!("Rendering {} pages to PDF", pages.len());
+		let dir = temp_dir("shtola-pdf-")?;
+		let (update_hash, failures) = render_all(&renderer, &ir, &pages, dir.path())?;
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		let ir = match renderer.program() {
+			Some(program) => match tool_version(program) {
+				Some(version) => ir.record_tool(program, &version),
+				None => ir,
+			},
+			None => ir,
+		};
+		Ok(failures
+			.into_iter()
+			.fold(ir, |ir, (path, message)| ir.fail_file(path, &message)))
+	})
+}
+
+type Rendered = (HashMap<PathBuf, ShFile>, Vec<(PathBuf, String)>);
+
+fn render_all(
+	renderer: &Renderer,
+	ir: &IR,
+	pages: &[PathBuf],
+	dir: &Path,
+) -> Result<Rendered, PluginError> {
+	let io_error = |e: std::io::Error| {
+		PluginError::new(&format!("Unable to prepare pages for rendering: {}", e))
+	};
+	for (path, file) in ir.files.iter() {
+		let target = dir.join(path);
+		if let Some(parent) = target.parent() {
+			fs::create_dir_all(parent).map_err(io_error)?;
+		}
+		fs::write(&target, &file.content).map_err(io_error)?;
+	}
+	let mut update_hash = HashMap::new();
+	let mut failures = Vec::new();
+	for path in pages {
+		let input = dir.join(path);
+		let output = input.with_extension("pdf");
+		let args = renderer.command(&input, &output);
+		debug!("Running {:?}", &args);
+		let result = run("PDF renderer", &args, input.parent(), None)?
+			.and_then(|_| fs::read(&output).map_err(|e| e.to_string()));
+		match result {
+			Ok(content) => {
+				update_hash.insert(
+					path.with_extension("pdf"),
+					ShFile {
+						content,
+						..ShFile::empty()
+					},
+				);
+			}
+			Err(message) => failures.push((
+				path.with_extension("pdf"),
+				format!("Unable to render PDF: {}", message),
+			)),
+		}
+	}
+	Ok((update_hash, failures))
+}
+
+#[cfg(unix)]
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/pdf");
+	s.destination("../fixtures/dest_pdf");
+	s.strict(false);
+	// Stands in for a real renderer, showing that linked files are available.
+	s.register_fallible(plugin(Renderer::Command(vec![
+		"sh".into(),
+		"-c".into(),
+		"grep -q fail {input} && exit 1; cat {input} style.css > {output}".into(),
+	])));
+	let r = s.build().unwrap();
+	let pdf = r.files.get(Path::new("guide/setup.pdf")).unwrap();
+	assert_eq!(pdf.content, b"<h1>Setup</h1>h1 { color: red; }\n");
+	assert!(r.files.get(Path::new("index.pdf")).is_none());
+	assert!(r.files.get(Path::new("guide/fail.pdf")).is_none());
+	assert!(r.files.get(Path::new("guide/fail.html")).is_some());
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("guide/fail.pdf"));
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/pdf");
+	s.destination("../fixtures/dest_pdf");
+	s.register_fallible(plugin(Renderer::Command(vec![
+		"shtola-missing-renderer".into()
+	])));
+	let e = s.build().unwrap_err();
+	assert!(e.to_string().contains("isn't installed"));
+}
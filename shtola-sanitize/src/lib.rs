@@ -0,0 +1,128 @@
+//! HTML sanitization for Shtola, for sites where part of the content comes
+//! from untrusted sources. The plugins remove everything from matching files
+//! that isn't on an allowlist, like scripts, iframes and event handler
+//! attributes. Register them after the plugins that produce HTML, like the
+//! Markdown plugin, so the final output is what gets sanitized:
+//!
+//! ```
+//! use shtola::{Shtola, ShtolaError};
+//!
+//! fn configure(s: &mut Shtola) -> Result<(), ShtolaError> {
+//!   s.register(shtola_sanitize::plugin(&["submissions/**/*.html"])?);
+//!   Ok(())
+//! }
+//! ```
+//!
+//! Matching files that aren't valid UTF-8 can't be sanitized, so they are
+//! reported as failed and left out of the build instead of being published
+//! as they are.
+//!
+//! The allowlist is an [`ammonia::Builder`](https://docs.rs/ammonia), so it can
+//! be configured in all the ways ammonia supports.
+
+pub use ammonia;
+
+use ammonia::Builder;
+use globset::{Glob, GlobSetBuilder};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, ShtolaError, IR};
+use std::path::PathBuf;
+
+/// Sanitizes files matching any of the globs with ammonia's default
+/// allowlist, which keeps common formatting tags and removes scripts, styles,
+/// iframes and forms. Fails if one of the globs is invalid.
+pub fn plugin(globs: &[&str]) -> Result<Plugin, ShtolaError> {
+	with_builder(globs, Builder::default())
+}
+
+/// Sanitizes files matching any of the globs with the given allowlist.
+/// Fails if one of the globs is invalid.
+pub fn with_builder(globs: &[&str], builder: Builder<'static>) -> Result<Plugin, ShtolaError> {
+	let mut set = GlobSetBuilder::new();
+	for glob in globs {
+		set.add(Glob::new(glob)?);
+	}
+	let set = set.build()?;
+	Ok(Box::new(move |ir: IR| {
+		info!("Sanitizing HTML");
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files.iter().filter(|(p, _)| set.is_match(p)) {
+			let html = match std::str::from_utf8(&file.content) {
+				Ok(html) => html,
+				Err(_) => {
+					failures.push(path.clone());
+					continue;
+				}
+			};
+			debug!("Sanitizing {:?}", path);
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					content: builder.clean(html).to_string().into_bytes(),
+					..file.clone()
+				},
+			);
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures.into_iter().fold(ir, |ir, path| {
+			ir.fail_file(path, "Unable to sanitize, the file is not valid UTF-8")
+		})
+	}))
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/sanitize");
+	s.destination("../fixtures/dest_sanitize");
+	s.strict(false);
+	s.register(plugin(&["submissions/**"]).unwrap());
+	let r = s.build().unwrap();
+	let content = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		content("submissions/guest.html"),
+		"<p>Hello <a href=\"https://example.com\" rel=\"noopener noreferrer\">there</a></p>\n"
+	);
+	assert_eq!(
+		content("index.html"),
+		"<p>Welcome</p><script src=\"/site.js\"></script>\n"
+	);
+	assert!(!r.files.contains_key(Path::new("submissions/latin1.html")));
+	let errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	assert_eq!(errors, vec![Path::new("submissions/latin1.html")]);
+}
+
+#[test]
+fn custom_allowlist_works() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut builder = Builder::default();
+	builder.rm_tags(&["a"]);
+	let mut s = Shtola::new();
+	s.source("../fixtures/sanitize");
+	s.destination("../fixtures/dest_sanitize_custom");
+	s.strict(false);
+	s.register(with_builder(&["submissions/*.html"], builder).unwrap());
+	let r = s.build().unwrap();
+	let file = r.files.get(Path::new("submissions/guest.html")).unwrap();
+	assert_eq!(file.content, b"<p>Hello there</p>\n");
+}
+
+#[test]
+fn invalid_globs_fail() {
+	match plugin(&["submissions/[*.html"]) {
+		Err(ShtolaError::Glob(_)) => {}
+		_ => panic!("The glob should be invalid"),
+	}
+}
@@ -0,0 +1,336 @@
+//! Dark mode variants of SVG assets for Shtola. The
+//! [`plugin`](fn.plugin.html) swaps the colors of matching SVG files according
+//! to a token map, writes the result next to the original, and wires it into
+//! the HTML pages showing the original, so browsers pick the variant in dark
+//! mode:
+//!
+//! ```
+//! use shtola::{Shtola, ShtolaError};
+//!
+//! fn configure(s: &mut Shtola) -> Result<(), ShtolaError> {
+//!   s.register_fallible(shtola_schemes::plugin()?);
+//!   Ok(())
+//! }
+//! ```
+//!
+//! The token map is a YAML or JSON file in the source directory, by default
+//! `data/colors.yml`, mapping colors of the light version to colors of the
+//! dark version:
+//!
+//! ```yaml
+//! "#ffffff": "#1e1e1e"
+//! "#222": "#eeeeee"
+//! black: white
+//! ```
+//!
+//! Colors are matched case-insensitively and only as a whole, so `#222` is
+//! left alone in `#222222`. The variant of `logo.svg` is `logo-dark.svg`,
+//! unless the source directory already has one, which is then used as is.
+//! Images showing an SVG with a variant are wrapped in a `<picture>`:
+//!
+//! ```html
+//! <picture><source srcset="/logo-dark.svg" media="(prefers-color-scheme: dark)"><img src="/logo.svg" alt="Logo"></picture>
+//! ```
+//!
+//! Images already inside a `<picture>` are left as they are. The plugin should
+//! run after the plugins that produce HTML, like the Markdown plugin.
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::{Captures, Regex};
+use shtola::frontmatter::to_json;
+use shtola::json::Value;
+use shtola::log::{debug, info};
+use shtola::{HashMap, PluginError, ShFile, ShtolaError, TryPlugin, HTML_EXTENSIONS, IR};
+use shtola_links::resolve;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Options for the color scheme plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// Globs of the SVG files to create dark variants of. Default is all SVG
+	/// files.
+	pub assets: Vec<String>,
+	/// The path of the token map in the source directory. It isn't written to
+	/// the destination. Default is `data/colors.yml`.
+	pub tokens: PathBuf,
+	/// What to add to the file name of a variant. Default is `-dark`.
+	pub suffix: String,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			assets: vec!["**/*.svg".into()],
+			tokens: PathBuf::from("data/colors.yml"),
+			suffix: "-dark".into(),
+		}
+	}
+}
+
+/// Creates the color scheme plugin with the default options.
+pub fn plugin() -> Result<TryPlugin, ShtolaError> {
+	with_options(Options::default())
+}
+
+/// Creates the color scheme plugin. The plugin fails if the token map is
+/// missing or isn't a map of colors. Fails if one of the asset globs is
+/// invalid.
+pub fn with_options(options: Options) -> Result<TryPlugin, ShtolaError> {
+	let assets = glob_set(&options.assets)?;
+	Ok(Box::new(move |ir: IR| {
+		info!("Creating dark mode variants");
+		let file = ir.files.get(&options.tokens).ok_or_else(|| {
+			PluginError::new(&format!("No color tokens at {:?}", &options.tokens))
+		})?;
+		let tokens =
+			tokens(file).map_err(|e| PluginError::new(&format!("{:?}: {}", &options.tokens, e)))?;
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		// The variant of every asset that has one.
+		let mut variants: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+		for (path, file) in ir.files.iter() {
+			if !assets.is_match(path) || is_variant(path, &options.suffix) {
+				continue;
+			}
+			let variant = variant_path(path, &options.suffix);
+			if ir.files.contains_key(&variant) {
+				variants.insert(path.clone(), variant);
+				continue;
+			}
+			let svg = String::from_utf8_lossy(&file.content);
+			let dark = substitute(&svg, &tokens);
+			if dark == svg {
+				continue;
+			}
+			debug!("Created {:?}", &variant);
+			update_hash.insert(
+				variant.clone(),
+				ShFile {
+					content: dark.into_bytes(),
+					..file.clone()
+				},
+			);
+			variants.insert(path.clone(), variant);
+		}
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let html = match std::str::from_utf8(&file.content) {
+				Ok(html) => html,
+				Err(_) => continue,
+			};
+			let wired = wire(path, html, &ir.files, &variants, &options.suffix);
+			if wired != html {
+				debug!("Added dark mode variants to {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: wired.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		let mut files = update_hash.union(ir.files);
+		files.remove(&options.tokens);
+		Ok(IR { files, ..ir })
+	}))
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, ShtolaError> {
+	let mut set = GlobSetBuilder::new();
+	for glob in globs {
+		set.add(Glob::new(glob)?);
+	}
+	Ok(set.build()?)
+}
+
+// Reads the token map, lowercasing the light colors.
+fn tokens(file: &ShFile) -> Result<Vec<(String, String)>, String> {
+	let text = std::str::from_utf8(&file.content).map_err(|e| e.to_string())?;
+	let map = match to_json(text.trim()).map_err(|e| e.to_string())? {
+		Value::Object(map) => map,
+		_ => return Err("The color tokens need to be a map".into()),
+	};
+	let mut tokens = Vec::new();
+	for (light, dark) in map {
+		match dark {
+			Value::String(dark) => tokens.push((light.to_lowercase(), dark)),
+			_ => return Err(format!("The dark color for {:?} isn't a string", light)),
+		}
+	}
+	Ok(tokens)
+}
+
+/// Replaces the light colors in `svg` with their dark counterparts from
+/// `tokens`. Colors are matched case-insensitively and only as a whole.
+///
+/// ```
+/// let tokens = vec![("#fff".to_string(), "#000".to_string())];
+/// assert_eq!(
+///   shtola_schemes::substitute(r##"<rect fill="#FFF" stroke="#ffffff"/>"##, &tokens),
+///   r##"<rect fill="#000" stroke="#ffffff"/>"##
+/// );
+/// ```
+pub fn substitute(svg: &str, tokens: &[(String, String)]) -> String {
+	if tokens.is_empty() {
+		return svg.to_string();
+	}
+	let mut lights: Vec<&str> = tokens.iter().map(|(light, _)| light.as_str()).collect();
+	// Longer colors first, so `#fff` doesn't win over `#ffffff`.
+	lights.sort_by_key(|light| std::cmp::Reverse(light.len()));
+	let alternatives: Vec<String> = lights.iter().map(|l| regex::escape(l)).collect();
+	let re = Regex::new(&format!(
+		r"(?i)(^|[^\w#-])({})([\w-]?)",
+		alternatives.join("|")
+	))
+	.unwrap();
+	re.replace_all(svg, |c: &Captures| {
+		// Part of a longer color or name.
+		if !c[3].is_empty() {
+			return c[0].to_string();
+		}
+		let light = c[2].to_lowercase();
+		let dark = &tokens
+			.iter()
+			.find(|(l, _)| l.to_lowercase() == light)
+			.unwrap()
+			.1;
+		format!("{}{}", &c[1], dark)
+	})
+	.into_owned()
+}
+
+// Where the variant of an asset goes, like `logo-dark.svg` for `logo.svg`.
+fn variant_path(path: &Path, suffix: &str) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match path.extension() {
+		Some(extension) => format!("{}{}.{}", stem, suffix, extension.to_string_lossy()),
+		None => format!("{}{}", stem, suffix),
+	};
+	path.with_file_name(name)
+}
+
+fn is_variant(path: &Path, suffix: &str) -> bool {
+	path.file_stem()
+		.is_some_and(|stem| stem.to_string_lossy().ends_with(suffix))
+}
+
+// Wraps images showing an asset with a variant in a `<picture>`.
+fn wire(
+	page: &Path,
+	html: &str,
+	files: &HashMap<PathBuf, ShFile>,
+	variants: &BTreeMap<PathBuf, PathBuf>,
+	suffix: &str,
+) -> String {
+	static ELEMENT: OnceLock<Regex> = OnceLock::new();
+	static SRC: OnceLock<Regex> = OnceLock::new();
+	let element =
+		ELEMENT.get_or_init(|| Regex::new(r"(?is)(<picture\b.*?</picture>)|<img\s[^>]*>").unwrap());
+	let src = SRC.get_or_init(|| Regex::new(r#"(?i)\bsrc\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+	element
+		.replace_all(html, |c: &Captures| {
+			let img = &c[0];
+			let link = match src.captures(img) {
+				Some(link) if c.get(1).is_none() => {
+					link.get(1).or_else(|| link.get(2)).unwrap().as_str()
+				}
+				_ => return img.to_string(),
+			};
+			let has_variant = resolve(page, link, files).is_some_and(|p| variants.contains_key(&p));
+			if !has_variant {
+				return img.to_string();
+			}
+			format!(
+				r#"<picture><source srcset="{}" media="(prefers-color-scheme: dark)">{}</picture>"#,
+				variant_link(link, suffix),
+				img
+			)
+		})
+		.into_owned()
+}
+
+// Turns a link to an asset into a link to its variant, keeping query strings
+// and fragments.
+fn variant_link(link: &str, suffix: &str) -> String {
+	let end = link.find(['?', '#']).unwrap_or(link.len());
+	let (path, rest) = link.split_at(end);
+	let name_start = path.rfind('/').map_or(0, |i| i + 1);
+	match path[name_start..].rfind('.') {
+		Some(dot) => {
+			let dot = name_start + dot;
+			format!("{}{}{}{}", &path[..dot], suffix, &path[dot..], rest)
+		}
+		None => format!("{}{}{}", path, suffix, rest),
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/schemes");
+	s.destination("../fixtures/dest_schemes");
+	s.register_fallible(plugin().unwrap());
+	let r = s.build().unwrap();
+	let content = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		content("img/logo-dark.svg"),
+		"<svg xmlns=\"http://www.w3.org/2000/svg\"><rect fill=\"#1e1e1e\"/>\
+		<path stroke=\"#eeeeee\" fill=\"#2222ff\"/><text fill=\"white\">Hi</text></svg>\n"
+	);
+	// Icons with their own variant keep it, and icons without known colors
+	// get none.
+	assert_eq!(
+		content("img/icon-dark.svg"),
+		"<svg><!-- drawn by hand --></svg>\n"
+	);
+	assert!(!r.files.contains_key(Path::new("img/plain-dark.svg")));
+	assert!(!r.files.contains_key(Path::new("data/colors.yml")));
+	assert_eq!(
+		content("index.html"),
+		"<picture><source srcset=\"/img/logo-dark.svg?v=2\" media=\"(prefers-color-scheme: dark)\">\
+		<img src=\"/img/logo.svg?v=2\" alt=\"Logo\"></picture>\n\
+		<picture><source srcset=\"img/icon-dark.svg\" media=\"(prefers-color-scheme: dark)\">\
+		<img src='img/icon.svg'></picture>\n\
+		<img src=\"img/plain.svg\">\n\
+		<picture><img src=\"img/logo.svg\"></picture>\n"
+	);
+}
+
+#[test]
+fn invalid_globs_fail() {
+	let e = with_options(Options {
+		assets: vec!["img/[.svg".into()],
+		..Options::default()
+	})
+	.err()
+	.unwrap();
+	assert!(matches!(e, ShtolaError::Glob(_)));
+}
+
+#[test]
+fn missing_tokens_fail() {
+	use shtola::{Shtola, ShtolaError};
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/schemes");
+	s.destination("../fixtures/dest_schemes_missing");
+	s.register_fallible(
+		with_options(Options {
+			tokens: PathBuf::from("data/missing.yml"),
+			..Options::default()
+		})
+		.unwrap(),
+	);
+	match s.build() {
+		Err(ShtolaError::Plugin(e)) => {
+			assert_eq!(e.message, "No color tokens at \"data/missing.yml\"")
+		}
+		r => panic!("Expected a plugin error, got {:?}", r.map(|_| ())),
+	}
+}
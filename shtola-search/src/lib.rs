@@ -0,0 +1,246 @@
+//! Offline full-text search for Shtola sites. The [`plugin`](fn.plugin.html)
+//! indexes the text of all HTML pages in the final IR and emits the index
+//! together with a small JavaScript loader, so searching works without any
+//! server-side service:
+//!
+//! ```html
+//! <script src="/search/search.js"></script>
+//! <script>
+//!   shtolaSearch("static sites").then(results => console.log(results));
+//! </script>
+//! ```
+//!
+//! The index is split into fragments by the first two letters of each word, so
+//! a search only downloads the fragments for the words it looks for. On large
+//! sites, the page list is split into shards as well, of which a search only
+//! downloads the ones holding its results.
+//!
+//! Pages asking search engines not to index them, see
+//! [`robots`](../shtola/robots/index.html), are left out of the index.
+
+use regex::Regex;
+use shtola::context::url;
+use shtola::decode;
+use shtola::json::{json, Map, Value};
+use shtola::log::{debug, info};
+use shtola::robots::noindex;
+use shtola::{has_extension, HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const LOADER: &str = include_str!("search.js");
+const EXCERPT_LENGTH: usize = 160;
+
+/// Options for the search plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The directory the index and loader are written to. Default is `search`.
+	pub output_dir: PathBuf,
+	/// The URL prefix of all pages. Default is `/`.
+	pub base_url: String,
+	/// How many pages `pages.json` may list before the page list is split into
+	/// `pages/0.json`, `pages/1.json` and so on. Default is 10,000.
+	pub pages_per_shard: usize,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			output_dir: "search".into(),
+			base_url: "/".into(),
+			pages_per_shard: 10_000,
+		}
+	}
+}
+
+/// A page as it is stored in the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+	/// The URL of the page.
+	pub url: String,
+	/// The page title, taken from the `<title>` or the first `<h1>` element.
+	pub title: String,
+	/// The beginning of the page text.
+	pub excerpt: String,
+}
+
+/// Creates the search plugin with default options.
+pub fn plugin() -> Plugin {
+	with_options(Options::default())
+}
+
+/// Creates the search plugin with the given options.
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Building search index");
+		let mut pages: Vec<(&PathBuf, &ShFile)> = ir
+			.files
+			.iter()
+			// Invalid `noindex` values are reported by the plugins writing
+			// the robots tags, so they don't fail the index as well.
+			.filter(|(p, f)| has_extension(p, HTML_EXTENSIONS) && !noindex(f).unwrap_or(false))
+			.map(|(p, f)| (p, f))
+			.collect();
+		pages.sort_by_key(|(p, _)| *p);
+		let mut entries = Vec::new();
+		let mut words: BTreeMap<String, BTreeMap<usize, usize>> = BTreeMap::new();
+		for (id, (path, file)) in pages.into_iter().enumerate() {
+			debug!("Indexing {:?}", path);
+			let html = String::from_utf8_lossy(&file.content);
+			let text = text(&html);
+			for word in tokenize(&text) {
+				*words.entry(word).or_default().entry(id).or_default() += 1;
+			}
+			entries.push(Page {
+				url: format!("{}{}", options.base_url.trim_end_matches('/'), url(path)),
+				title: title(&html).unwrap_or_default(),
+				excerpt: text.chars().take(EXCERPT_LENGTH).collect(),
+			});
+		}
+		let mut fragments: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+		for (word, hits) in words {
+			let hits: Vec<Value> = hits.into_iter().map(|(id, n)| json!([id, n])).collect();
+			fragments
+				.entry(prefix(&word))
+				.or_default()
+				.insert(word, Value::Array(hits));
+		}
+		let pages_json: Vec<Value> = entries
+			.iter()
+			.map(|p| json!({ "url": p.url, "title": p.title, "excerpt": p.excerpt }))
+			.collect();
+		let dir = &options.output_dir;
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		update_hash.insert(dir.join("search.js"), file(LOADER.into()));
+		if pages_json.len() <= options.pages_per_shard {
+			update_hash.insert(
+				dir.join("pages.json"),
+				file(Value::Array(pages_json).to_string().into_bytes()),
+			);
+		} else {
+			// The loader recognizes a sharded page list by `pages.json` being
+			// an object instead of a list.
+			let shard_size = options.pages_per_shard.max(1);
+			debug!("Splitting page list into shards of {}", shard_size);
+			let meta = json!({ "shard_size": shard_size, "count": pages_json.len() });
+			update_hash.insert(dir.join("pages.json"), file(meta.to_string().into_bytes()));
+			for (i, shard) in pages_json.chunks(shard_size).enumerate() {
+				let path = dir.join("pages").join(format!("{}.json", i));
+				update_hash.insert(
+					path,
+					file(Value::Array(shard.to_vec()).to_string().into_bytes()),
+				);
+			}
+		}
+		for (prefix, words) in fragments {
+			let path = dir.join("index").join(format!("{}.json", prefix));
+			update_hash.insert(path, file(Value::Object(words).to_string().into_bytes()));
+		}
+		debug!("Indexed {} pages", entries.len());
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Extracts the visible text of an HTML page, without scripts and styles.
+pub fn text(html: &str) -> String {
+	static INVISIBLE: OnceLock<Regex> = OnceLock::new();
+	static TAG: OnceLock<Regex> = OnceLock::new();
+	let invisible = INVISIBLE.get_or_init(|| {
+		Regex::new(r"(?is)<(script|style|head)\b.*?</(script|style|head)\s*>").unwrap()
+	});
+	let tag = TAG.get_or_init(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+	let without_invisible = invisible.replace_all(html, " ");
+	let without_tags = tag.replace_all(&without_invisible, " ");
+	decode::entities(&without_tags)
+		.split_whitespace()
+		.collect::<Vec<&str>>()
+		.join(" ")
+}
+
+/// Splits text into lowercase words of at least two characters. The loader
+/// script tokenizes search queries the same way.
+pub fn tokenize(text: &str) -> Vec<String> {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| w.chars().count() > 1)
+		.map(|w| w.to_lowercase())
+		.collect()
+}
+
+fn title(html: &str) -> Option<String> {
+	static TITLE: OnceLock<Regex> = OnceLock::new();
+	let re = TITLE
+		.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>|<h1[^>]*>(.*?)</h1>").unwrap());
+	let captures = re.captures(html)?;
+	let title = captures.get(1).or_else(|| captures.get(2))?.as_str();
+	Some(text(title))
+}
+
+fn prefix(word: &str) -> String {
+	word.chars().take(2).collect()
+}
+
+fn file(content: Vec<u8>) -> ShFile {
+	ShFile {
+		content,
+		..ShFile::empty()
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/search");
+	s.destination("../fixtures/dest_search");
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let read = |path: &str| -> Value {
+		let file = r.files.get(Path::new(path)).unwrap();
+		shtola::json::from_slice(&file.content).unwrap()
+	};
+	let pages = read("search/pages.json");
+	assert_eq!(pages.as_array().unwrap().len(), 2);
+	assert_eq!(pages[0]["url"], "/docs/plugins.html");
+	assert_eq!(pages[1]["url"], "/");
+	assert_eq!(pages[1]["title"], "Welcome & hello");
+	assert_eq!(
+		pages[1]["excerpt"],
+		"Welcome Shtola builds static sites. Static sites are fast."
+	);
+	let fragment = read("search/index/st.json");
+	assert_eq!(fragment["static"], json!([[0, 1], [1, 2]]));
+	assert_eq!(fragment["shtola"], Value::Null);
+	assert!(r.files.get(Path::new("search/index/se.json")).is_none());
+	assert!(r.files.contains_key(Path::new("search/search.js")));
+}
+
+#[test]
+fn page_list_is_sharded() {
+	use shtola::Shtola;
+	use std::path::Path;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/search");
+	s.destination("../fixtures/dest_search_sharded");
+	s.register(with_options(Options {
+		pages_per_shard: 1,
+		..Options::default()
+	}));
+	let r = s.build().unwrap();
+	let read = |path: &str| -> Value {
+		let file = r.files.get(Path::new(path)).unwrap();
+		shtola::json::from_slice(&file.content).unwrap()
+	};
+	assert_eq!(
+		read("search/pages.json"),
+		json!({ "shard_size": 1, "count": 2 })
+	);
+	assert_eq!(read("search/pages/0.json")[0]["url"], "/docs/plugins.html");
+	assert_eq!(read("search/pages/1.json")[0]["url"], "/");
+}
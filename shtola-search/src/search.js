@@ -0,0 +1,109 @@
+// Loader for the search index generated by shtola-search. Include it with a
+// script tag and call `shtolaSearch("query")`, which resolves to a list of
+// `{ url, title, excerpt, score }` objects, best matches first.
+(function (global) {
+	var script = document.currentScript;
+	var base = script ? script.src.replace(/[^/]*$/, "") : "/search/";
+	var cache = {};
+
+	function load(path) {
+		if (!cache[path]) {
+			cache[path] = fetch(base + path).then(function (response) {
+				return response.ok ? response.json() : {};
+			});
+		}
+		return cache[path];
+	}
+
+	function tokenize(text) {
+		return text
+			.toLowerCase()
+			.split(/[^\p{L}\p{N}]+/u)
+			.filter(function (word) {
+				return Array.from(word).length > 1;
+			});
+	}
+
+	function prefix(word) {
+		return Array.from(word).slice(0, 2).join("");
+	}
+
+	// Large sites split their page list into shards, described by an object
+	// in pages.json. Only the shards holding the given pages are loaded.
+	function pageList(pages, ids) {
+		if (Array.isArray(pages)) {
+			return Promise.resolve(pages);
+		}
+		var shards = {};
+		ids.forEach(function (id) {
+			shards[Math.floor(id / pages.shard_size)] = true;
+		});
+		return Promise.all(
+			Object.keys(shards).map(function (shard) {
+				return load("pages/" + shard + ".json").then(function (list) {
+					var start = shard * pages.shard_size;
+					var result = {};
+					list.forEach(function (page, i) {
+						result[start + i] = page;
+					});
+					return result;
+				});
+			})
+		).then(function (parts) {
+			return Object.assign.apply(Object, [{}].concat(parts));
+		});
+	}
+
+	global.shtolaSearch = function (query) {
+		var terms = tokenize(query);
+		if (terms.length === 0) {
+			return Promise.resolve([]);
+		}
+		var fragments = terms.map(function (term) {
+			return load("index/" + encodeURIComponent(prefix(term)) + ".json");
+		});
+		return Promise.all([load("pages.json")].concat(fragments)).then(function (loaded) {
+			var pages = loaded[0];
+			var scores = null;
+			terms.forEach(function (term, i) {
+				var fragment = loaded[i + 1];
+				var termScores = {};
+				Object.keys(fragment).forEach(function (word) {
+					if (word.indexOf(term) !== 0) {
+						return;
+					}
+					fragment[word].forEach(function (hit) {
+						termScores[hit[0]] = (termScores[hit[0]] || 0) + hit[1];
+					});
+				});
+				if (scores === null) {
+					scores = termScores;
+					return;
+				}
+				Object.keys(scores).forEach(function (id) {
+					if (termScores[id] === undefined) {
+						delete scores[id];
+					} else {
+						scores[id] += termScores[id];
+					}
+				});
+			});
+			var ids = Object.keys(scores);
+			return pageList(pages, ids).then(function (list) {
+				return ids
+					.map(function (id) {
+						var page = list[id];
+						return {
+							url: page.url,
+							title: page.title,
+							excerpt: page.excerpt,
+							score: scores[id],
+						};
+					})
+					.sort(function (a, b) {
+						return b.score - a.score;
+					});
+			});
+		});
+	};
+})(window);
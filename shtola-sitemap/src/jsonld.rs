@@ -0,0 +1,250 @@
+//! Writes [schema.org](https://schema.org) structured data into pages, as a
+//! JSON-LD `<script>` at the end of their `<head>`:
+//!
+//! - The home page describes the site as a `WebSite`, from the
+//!   [site settings](../../shtola/site/struct.SiteConfig.html).
+//! - Pages in a collection, set with the `collection` frontmatter key, are
+//!   an `Article`. The `title`, `description`, `image`, `author`, `date` and
+//!   `updated` frontmatter keys fill in its details. The author defaults to
+//!   the author of the site.
+//! - Pages below the home page get a `BreadcrumbList` leading to them through
+//!   the `index.html` pages of their directories. Each step is named by the
+//!   `breadcrumb` or `title` frontmatter key of its page.
+//!
+//! Pages can be left out by setting the `jsonld` frontmatter key to `false`.
+//! Pages without a `</head>` and pages whose layout already writes JSON-LD
+//! are left alone. Pages with invalid values for these keys are reported as
+//! failed. The plugin should run after the templates plugin, so it sees the
+//! complete pages.
+
+use regex::Regex;
+use shtola::context::url;
+use shtola::frontmatter::{get_bool, get_date, get_str, FieldError};
+use shtola::json::{self, json, Map, Value};
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Creates the JSON-LD plugin for the site at the given URL. If it's empty,
+/// the base URL of the [site settings](../../shtola/site/struct.SiteConfig.html)
+/// is used.
+pub fn plugin(base_url: &str) -> Plugin {
+	let base_url = base_url.trim_end_matches('/').to_string();
+	Box::new(move |ir: IR| {
+		info!("Adding structured data");
+		let base_url = match base_url.as_str() {
+			"" => ir.site.base_url().to_string(),
+			base_url => base_url.to_string(),
+		};
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let graph = match graph(&ir, path, file, &base_url) {
+				Ok(Some(graph)) => graph,
+				Ok(None) => continue,
+				Err(e) => {
+					failures.push((path.clone(), e));
+					continue;
+				}
+			};
+			let html = String::from_utf8_lossy(&file.content);
+			if let Some(html) = add_script(&html, &graph) {
+				debug!("Adding structured data to {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: html.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, e)| ir.fail_file(path, &e.to_string()))
+	})
+}
+
+/// Adds the JSON-LD to the end of the `<head>` of a page. Returns `None` if
+/// the page has no `</head>` or already has JSON-LD.
+///
+/// ```
+/// use shtola::json::json;
+/// use shtola_sitemap::jsonld::add_script;
+///
+/// let data = json!({ "@type": "WebSite", "name": "</script>" });
+/// assert_eq!(
+///   add_script("<head></head>", &data).unwrap(),
+///   "<head><script type=\"application/ld+json\">\
+///    {\"@type\":\"WebSite\",\"name\":\"<\\/script>\"}</script></head>"
+/// );
+/// assert_eq!(add_script("<p>Hi</p>", &data), None);
+/// ```
+pub fn add_script(html: &str, data: &Value) -> Option<String> {
+	static SCRIPT: OnceLock<Regex> = OnceLock::new();
+	let script = SCRIPT.get_or_init(|| {
+		Regex::new(r#"(?i)<script\b[^>]*\btype\s*=\s*["']?application/ld\+json\b"#).unwrap()
+	});
+	let end = html.to_ascii_lowercase().find("</head>")?;
+	if script.is_match(html) {
+		return None;
+	}
+	// `</` would end the script early.
+	let data = json::to_string(data).ok()?.replace("</", "<\\/");
+	Some(format!(
+		"{}<script type=\"application/ld+json\">{}</script>{}",
+		&html[..end],
+		data,
+		&html[end..]
+	))
+}
+
+fn graph(ir: &IR, path: &Path, file: &ShFile, base_url: &str) -> Result<Option<Value>, FieldError> {
+	if get_bool(&file.frontmatter, "jsonld")? == Some(false) {
+		return Ok(None);
+	}
+	let page_url = format!("{}{}", base_url, url(path));
+	let mut graph = Vec::new();
+	if path == Path::new("index.html") {
+		let mut site = object(json!({
+			"@type": "WebSite",
+			"url": page_url,
+		}));
+		insert(&mut site, "name", ir.site.title.clone());
+		insert(&mut site, "inLanguage", ir.site.language.clone());
+		graph.push(Value::Object(site));
+	}
+	if get_str(&file.frontmatter, "collection")?.is_some() {
+		graph.push(article(ir, file, &page_url, base_url)?);
+	}
+	if let Some(breadcrumbs) = breadcrumbs(ir, path, base_url)? {
+		graph.push(breadcrumbs);
+	}
+	if graph.is_empty() {
+		return Ok(None);
+	}
+	Ok(Some(json!({
+		"@context": "https://schema.org",
+		"@graph": graph,
+	})))
+}
+
+fn article(ir: &IR, file: &ShFile, page_url: &str, base_url: &str) -> Result<Value, FieldError> {
+	let frontmatter = &file.frontmatter;
+	let mut article = object(json!({
+		"@type": "Article",
+		"url": page_url,
+		"mainEntityOfPage": page_url,
+	}));
+	insert(&mut article, "headline", get_str(frontmatter, "title")?);
+	insert(
+		&mut article,
+		"description",
+		get_str(frontmatter, "description")?,
+	);
+	insert(
+		&mut article,
+		"image",
+		get_str(frontmatter, "image")?.map(|image| absolute(base_url, &image)),
+	);
+	let date = get_date(frontmatter, "date")?;
+	let updated = get_date(frontmatter, "updated")?;
+	insert(&mut article, "datePublished", date.map(|d| d.to_string()));
+	insert(
+		&mut article,
+		"dateModified",
+		updated.or(date).map(|d| d.to_string()),
+	);
+	let author = match get_str(frontmatter, "author")? {
+		Some(author) => Some(author),
+		None => ir.site.author.clone(),
+	};
+	if let Some(author) = author {
+		article.insert(
+			"author".into(),
+			json!({ "@type": "Person", "name": author }),
+		);
+	}
+	Ok(Value::Object(article))
+}
+
+/// The trail from the home page to the page, through the `index.html` pages
+/// of its directories. `None` for the home page itself and for pages with
+/// nothing to lead through.
+fn breadcrumbs(ir: &IR, path: &Path, base_url: &str) -> Result<Option<Value>, FieldError> {
+	let mut steps = Vec::new();
+	let mut dir = path.parent();
+	if path.file_name() == Some("index.html".as_ref()) {
+		dir = dir.and_then(Path::parent);
+	}
+	while let Some(d) = dir {
+		let index = d.join("index.html");
+		if let Some(file) = ir.files.get(&index) {
+			steps.push((index, file));
+		}
+		dir = d.parent();
+	}
+	if steps.is_empty() {
+		return Ok(None);
+	}
+	steps.reverse();
+	steps.push((path.to_path_buf(), &ir.files[path]));
+	let mut items = Vec::new();
+	for (i, (path, file)) in steps.into_iter().enumerate() {
+		let name = match get_str(&file.frontmatter, "breadcrumb")? {
+			Some(name) => Some(name),
+			None => get_str(&file.frontmatter, "title")?,
+		};
+		let name = match (name, path == Path::new("index.html")) {
+			(Some(name), _) => name,
+			(None, true) => ir.site.title.clone().unwrap_or_else(|| "Home".into()),
+			(None, false) => stem(&path),
+		};
+		items.push(json!({
+			"@type": "ListItem",
+			"position": i + 1,
+			"name": name,
+			"item": format!("{}{}", base_url, url(&path)),
+		}));
+	}
+	Ok(Some(json!({
+		"@type": "BreadcrumbList",
+		"itemListElement": items,
+	})))
+}
+
+// The name of the directory for `index.html` pages, the file name otherwise.
+fn stem(path: &Path) -> String {
+	let path = match path.file_name() {
+		Some(name) if name == "index.html" => path.parent().unwrap_or(path),
+		_ => path,
+	};
+	path.file_stem()
+		.map(|s| s.to_string_lossy().into_owned())
+		.unwrap_or_default()
+}
+
+fn absolute(base_url: &str, url: &str) -> String {
+	match url.strip_prefix('/') {
+		Some(path) if !path.starts_with('/') => format!("{}/{}", base_url, path),
+		_ => url.to_string(),
+	}
+}
+
+fn object(value: Value) -> Map<String, Value> {
+	match value {
+		Value::Object(map) => map,
+		_ => Map::new(),
+	}
+}
+
+fn insert(map: &mut Map<String, Value>, key: &str, value: Option<String>) {
+	if let Some(value) = value {
+		map.insert(key.into(), Value::String(value));
+	}
+}
@@ -0,0 +1,409 @@
+//! Sitemaps for Shtola. The [`plugin`](fn.plugin.html) lists all HTML pages in
+//! `sitemap.xml`. Pages can be left out by setting the `sitemap` frontmatter
+//! key to `false`. Pages asking search engines not to index them and pages
+//! whose canonical URL is another one are left out as well, see
+//! [`robots`](../shtola/robots/index.html). The `updated` or `date`
+//! frontmatter key is used as the last modification date. Pages with invalid
+//! values for these keys are reported as failed. Translations of a page
+//! linked by the `alternates` plugin of
+//! [shtola-i18n](../shtola_i18n/fn.alternates.html) are listed as alternates
+//! of its URL.
+//!
+//! Crawlers only accept up to 50,000 URLs per sitemap. Larger sites are split
+//! into `sitemap-1.xml`, `sitemap-2.xml` and so on, and `sitemap.xml` becomes
+//! a sitemap index listing them.
+//!
+//! The [`robots`](robots/index.html) plugin writes the canonical URL and
+//! robots tags into the pages themselves, and the [`jsonld`](jsonld/index.html)
+//! plugin their schema.org structured data.
+
+use shtola::context::url;
+use shtola::frontmatter::{get_bool, get_date, FieldError};
+use shtola::json::Value;
+use shtola::log::{debug, info};
+use shtola::robots::{canonical, noindex};
+use shtola::{has_extension, HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::path::{Path, PathBuf};
+
+pub mod jsonld;
+pub mod robots;
+
+/// The maximum number of URLs in a sitemap allowed by the sitemap protocol.
+pub const MAX_URLS: usize = 50_000;
+
+/// Options for the sitemap plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The absolute URL of the site, like `https://example.com`. If empty, the
+	/// base URL of the [site settings](../shtola/site/struct.SiteConfig.html)
+	/// is used.
+	pub base_url: String,
+	/// The path of the sitemap. Default is `sitemap.xml`.
+	pub output: PathBuf,
+	/// How many URLs a sitemap may hold before it is split. Default is
+	/// [`MAX_URLS`](constant.MAX_URLS.html).
+	pub max_urls: usize,
+}
+
+impl Options {
+	/// Creates options with defaults for the site at the given URL.
+	pub fn new(base_url: &str) -> Options {
+		Options {
+			base_url: base_url.trim_end_matches('/').to_string(),
+			output: "sitemap.xml".into(),
+			max_urls: MAX_URLS,
+		}
+	}
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options::new("")
+	}
+}
+
+/// Creates the sitemap plugin for the site at the given URL.
+pub fn plugin(base_url: &str) -> Plugin {
+	with_options(Options::new(base_url))
+}
+
+/// Creates the sitemap plugin with the given options.
+pub fn with_options(options: Options) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Building sitemap");
+		let base_url = match options.base_url.as_str() {
+			"" => ir.site.base_url().to_string(),
+			base_url => base_url.to_string(),
+		};
+		let mut pages: Vec<(&PathBuf, &ShFile)> = ir
+			.files
+			.iter()
+			.filter(|(p, _)| has_extension(p, HTML_EXTENSIONS))
+			.map(|(p, f)| (p, f))
+			.collect();
+		pages.sort_by_key(|(p, _)| *p);
+		let mut entries: Vec<String> = Vec::new();
+		let mut failures: Vec<(PathBuf, FieldError)> = Vec::new();
+		for (path, file) in pages {
+			match entry(&base_url, path, file) {
+				Ok(Some(entry)) => entries.push(entry),
+				Ok(None) => (),
+				Err(e) => failures.push((path.clone(), e)),
+			}
+		}
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		if entries.len() <= options.max_urls {
+			update_hash.insert(options.output.clone(), file(urlset(&entries)));
+		} else {
+			let chunks: Vec<&[String]> = entries.chunks(options.max_urls.max(1)).collect();
+			debug!("Splitting sitemap into {} parts", chunks.len());
+			let mut sitemaps = Vec::new();
+			for (i, chunk) in chunks.into_iter().enumerate() {
+				let path = part_path(&options.output, i + 1);
+				sitemaps.push(format!(
+					"  <sitemap><loc>{}/{}</loc></sitemap>\n",
+					base_url,
+					escape(&path.to_string_lossy().replace('\\', "/"))
+				));
+				update_hash.insert(path, file(urlset(chunk)));
+			}
+			let index = format!(
+				"{}<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}</sitemapindex>\n",
+				HEADER,
+				sitemaps.concat()
+			);
+			update_hash.insert(options.output.clone(), file(index));
+		}
+		debug!("Added {} pages to the sitemap", entries.len());
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, e)| ir.fail_file(path, &e.to_string()))
+	})
+}
+
+const HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+fn urlset(entries: &[String]) -> String {
+	let xhtml = if entries.iter().any(|e| e.contains("<xhtml:link")) {
+		" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\""
+	} else {
+		""
+	};
+	format!(
+		"{}<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\"{}>\n{}</urlset>\n",
+		HEADER,
+		xhtml,
+		entries.concat()
+	)
+}
+
+// Returns `None` for pages left out of the sitemap.
+fn entry(base_url: &str, path: &Path, file: &ShFile) -> Result<Option<String>, FieldError> {
+	if get_bool(&file.frontmatter, "sitemap")? == Some(false) || noindex(file)? {
+		return Ok(None);
+	}
+	let loc = format!("{}{}", base_url, url(path));
+	if canonical(file, base_url)?.is_some_and(|c| c != loc) {
+		return Ok(None);
+	}
+	let lastmod = match get_date(&file.frontmatter, "updated")? {
+		Some(date) => Some(date),
+		None => get_date(&file.frontmatter, "date")?,
+	};
+	let lastmod = match lastmod {
+		Some(date) => format!("<lastmod>{}</lastmod>", date),
+		None => String::new(),
+	};
+	let alternates: String = match file.metadata.get("alternates") {
+		Some(Value::Object(alternates)) => alternates
+			.iter()
+			.filter_map(|(lang, href)| {
+				Some(format!(
+					"<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}{}\"/>",
+					escape(lang),
+					base_url,
+					escape(href.as_str()?)
+				))
+			})
+			.collect(),
+		_ => String::new(),
+	};
+	Ok(Some(format!(
+		"  <url><loc>{}</loc>{}{}</url>\n",
+		escape(&loc),
+		lastmod,
+		alternates
+	)))
+}
+
+fn part_path(output: &Path, n: usize) -> PathBuf {
+	let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match output.extension() {
+		Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+		None => format!("{}-{}", stem, n),
+	};
+	output.with_file_name(name)
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
+}
+
+fn file(content: String) -> ShFile {
+	ShFile {
+		content: content.into_bytes(),
+		..ShFile::empty()
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/sitemap");
+	s.destination("../fixtures/dest_sitemap");
+	s.register(plugin("https://example.com/"));
+	let r = s.build().unwrap();
+	let sitemap = r.files.get(Path::new("sitemap.xml")).unwrap();
+	assert_eq!(
+		std::str::from_utf8(&sitemap.content).unwrap(),
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		 <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+		 <url><loc>https://example.com/about.html</loc></url>\n  \
+		 <url><loc>https://example.com/blog/post.html</loc><lastmod>2020-01-02</lastmod></url>\n  \
+		 <url><loc>https://example.com/</loc></url>\n\
+		 </urlset>\n"
+	);
+}
+
+#[test]
+fn large_sitemaps_are_split() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/sitemap");
+	s.destination("../fixtures/dest_sitemap_split");
+	s.register(with_options(Options {
+		max_urls: 2,
+		..Options::new("https://example.com")
+	}));
+	let r = s.build().unwrap();
+	let read = |path: &str| {
+		let file = r.files.get(Path::new(path)).unwrap();
+		String::from_utf8(file.content.clone()).unwrap()
+	};
+	assert_eq!(
+		read("sitemap.xml"),
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		 <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+		 <sitemap><loc>https://example.com/sitemap-1.xml</loc></sitemap>\n  \
+		 <sitemap><loc>https://example.com/sitemap-2.xml</loc></sitemap>\n\
+		 </sitemapindex>\n"
+	);
+	assert!(read("sitemap-1.xml").contains("/blog/post.html"));
+	assert!(read("sitemap-2.xml").contains("<loc>https://example.com/</loc>"));
+	assert!(r.files.get(Path::new("sitemap-3.xml")).is_none());
+}
+
+#[test]
+fn alternates_are_listed() {
+	use shtola::json::json;
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/sitemap");
+	s.destination("../fixtures/dest_sitemap_alternates");
+	s.register(Box::new(|ir: IR| {
+		let about = &ir.files[Path::new("about.html")];
+		let about = ShFile {
+			metadata: about.metadata.update(
+				"alternates".into(),
+				json!({ "de": "/de/about.html", "en": "/about.html" }),
+			),
+			..about.clone()
+		};
+		IR {
+			files: ir.files.update("about.html".into(), about),
+			..ir
+		}
+	}));
+	s.register(plugin("https://example.com"));
+	let r = s.build().unwrap();
+	let sitemap = r.files.get(Path::new("sitemap.xml")).unwrap();
+	let sitemap = std::str::from_utf8(&sitemap.content).unwrap();
+	assert!(sitemap.contains(
+		"<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" \
+		 xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">"
+	));
+	assert!(sitemap.contains(
+		"<url><loc>https://example.com/about.html</loc>\
+		 <xhtml:link rel=\"alternate\" hreflang=\"de\" href=\"https://example.com/de/about.html\"/>\
+		 <xhtml:link rel=\"alternate\" hreflang=\"en\" href=\"https://example.com/about.html\"/>\
+		 </url>"
+	));
+}
+
+#[test]
+fn robots_work() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/robots");
+	s.destination("../fixtures/dest_robots");
+	s.strict(false);
+	s.register(robots::plugin("https://example.com/"));
+	s.register(plugin("https://example.com/"));
+	let r = s.build().unwrap();
+	let content = |path: &str| String::from_utf8(r.files[Path::new(path)].content.clone()).unwrap();
+	assert_eq!(
+		content("copy.html"),
+		"<html><head><title>Copy</title>\
+		 <link rel=\"canonical\" href=\"https://example.com/\"></head><body>Home</body></html>"
+	);
+	assert_eq!(
+		content("private.html"),
+		"<html><HEAD><title>Private</title>\
+		 <meta name=\"robots\" content=\"noindex\"></HEAD><body>Private</body></html>"
+	);
+	assert_eq!(
+		content("self.html"),
+		"<html><head><link rel=\"canonical\" href=\"https://example.com/self.html\"></head>\
+		 <body>Self</body></html>"
+	);
+	assert_eq!(content("fragment.html"), "<p>Fragment</p>");
+	assert_eq!(
+		content("sitemap.xml"),
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		 <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+		 <url><loc>https://example.com/</loc></url>\n  \
+		 <url><loc>https://example.com/self.html</loc></url>\n\
+		 </urlset>\n"
+	);
+	let errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	assert_eq!(errors, vec![Path::new("broken.html")]);
+}
+
+#[test]
+fn jsonld_works() {
+	use shtola::json::{self, json, Value};
+	use shtola::site::SiteConfig;
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/jsonld");
+	s.destination("../fixtures/dest_jsonld");
+	s.strict(false);
+	s.site(SiteConfig {
+		title: Some("Example".into()),
+		author: Some("Marisa".into()),
+		..Default::default()
+	});
+	s.register(jsonld::plugin("https://example.com/"));
+	let r = s.build().unwrap();
+	let content = |path: &str| String::from_utf8(r.files[Path::new(path)].content.clone()).unwrap();
+	let data = |path: &str| -> Value {
+		let html = content(path);
+		let start = html.find("<script type=\"application/ld+json\">").unwrap() + 35;
+		let end = html.rfind("</script>").unwrap();
+		json::from_str(&html[start..end]).unwrap()
+	};
+	assert_eq!(
+		data("index.html"),
+		json!({
+			"@context": "https://schema.org",
+			"@graph": [{ "@type": "WebSite", "url": "https://example.com/", "name": "Example" }],
+		})
+	);
+	let crumb = |position: usize, name: &str, item: &str| json!({ "@type": "ListItem", "position": position, "name": name, "item": item });
+	assert_eq!(
+		data("blog/hello.html"),
+		json!({
+			"@context": "https://schema.org",
+			"@graph": [
+				{
+					"@type": "Article",
+					"url": "https://example.com/blog/hello.html",
+					"mainEntityOfPage": "https://example.com/blog/hello.html",
+					"headline": "Hello </world>",
+					"description": "The first post.",
+					"image": "https://example.com/images/hello.png",
+					"datePublished": "2020-02-03",
+					"dateModified": "2020-02-05",
+					"author": { "@type": "Person", "name": "Marisa" },
+				},
+				{
+					"@type": "BreadcrumbList",
+					"itemListElement": [
+						crumb(1, "Home", "https://example.com/"),
+						crumb(2, "Blog", "https://example.com/blog/"),
+						crumb(3, "Hello </world>", "https://example.com/blog/hello.html"),
+					],
+				},
+			],
+		})
+	);
+	assert!(content("blog/hello.html").contains("Hello <\\/world>"));
+	assert_eq!(
+		data("about.html")["@graph"][0]["itemListElement"][1],
+		crumb(2, "about", "https://example.com/about.html")
+	);
+	assert_eq!(
+		content("blog/hidden.html"),
+		"<html><head><title>Hidden</title></head><body>Hidden</body></html>"
+	);
+	assert_eq!(
+		content("blog/custom.html"),
+		"<html><head><script type=\"application/ld+json\">{}</script></head><body>Custom</body></html>"
+	);
+	let errors: Vec<&Path> = r.report.errors.iter().map(|e| e.path.as_path()).collect();
+	assert_eq!(errors, vec![Path::new("blog/broken.html")]);
+}
@@ -0,0 +1,104 @@
+//! Writes the [search engine controls](../../shtola/robots/index.html) of
+//! pages into their HTML. Pages with a `canonical` frontmatter key get a
+//! `<link rel="canonical">` and pages with `noindex: true` a robots
+//! `<meta>` tag, at the end of their `<head>`:
+//!
+//! ```html
+//! <link rel="canonical" href="https://example.com/guides/setup.html">
+//! <meta name="robots" content="noindex">
+//! ```
+//!
+//! Pages without a `</head>` and tags the layout already writes are left
+//! alone. The plugin should run after the templates plugin, so it sees the
+//! complete pages.
+
+use regex::Regex;
+use shtola::log::{debug, info};
+use shtola::robots::{canonical, noindex};
+use shtola::{HashMap, Plugin, ShFile, HTML_EXTENSIONS, IR};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Creates the robots plugin for the site at the given URL. URLs starting
+/// with `/` are resolved against it. If it's empty, the base URL of the
+/// [site settings](../../shtola/site/struct.SiteConfig.html) is used.
+pub fn plugin(base_url: &str) -> Plugin {
+	let base_url = base_url.trim_end_matches('/').to_string();
+	Box::new(move |ir: IR| {
+		info!("Adding search engine controls");
+		let base_url = match base_url.as_str() {
+			"" => ir.site.base_url().to_string(),
+			base_url => base_url.to_string(),
+		};
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		for (path, file) in ir.files_with_extensions(HTML_EXTENSIONS) {
+			let controls = canonical(file, &base_url).and_then(|c| Ok((c, noindex(file)?)));
+			let (canonical, noindex) = match controls {
+				Ok(controls) => controls,
+				Err(e) => {
+					failures.push((path.clone(), e));
+					continue;
+				}
+			};
+			if canonical.is_none() && !noindex {
+				continue;
+			}
+			let html = String::from_utf8_lossy(&file.content);
+			if let Some(html) = add_tags(&html, canonical.as_deref(), noindex) {
+				debug!("Adding search engine controls to {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: html.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		let ir = IR {
+			files: update_hash.union(ir.files),
+			..ir
+		};
+		failures
+			.into_iter()
+			.fold(ir, |ir, (path, e)| ir.fail_file(path, &e.to_string()))
+	})
+}
+
+/// Adds the tags to the end of the `<head>` of a page. Returns `None` if the
+/// page has no `</head>` or already has the tags.
+///
+/// ```
+/// use shtola_sitemap::robots::add_tags;
+///
+/// assert_eq!(
+///   add_tags("<head><title>Hi</title></head>", Some("https://example.com/"), true).unwrap(),
+///   "<head><title>Hi</title><link rel=\"canonical\" href=\"https://example.com/\">\
+///    <meta name=\"robots\" content=\"noindex\"></head>"
+/// );
+/// assert_eq!(add_tags("<p>Hi</p>", None, true), None);
+/// ```
+pub fn add_tags(html: &str, canonical: Option<&str>, noindex: bool) -> Option<String> {
+	static LINK: OnceLock<Regex> = OnceLock::new();
+	static META: OnceLock<Regex> = OnceLock::new();
+	let link =
+		LINK.get_or_init(|| Regex::new(r#"(?i)<link\b[^>]*\brel\s*=\s*["']?canonical\b"#).unwrap());
+	let meta =
+		META.get_or_init(|| Regex::new(r#"(?i)<meta\b[^>]*\bname\s*=\s*["']?robots\b"#).unwrap());
+	let end = html.to_ascii_lowercase().find("</head>")?;
+	let mut tags = String::new();
+	if let (Some(url), false) = (canonical, link.is_match(html)) {
+		tags.push_str(&format!(
+			"<link rel=\"canonical\" href=\"{}\">",
+			super::escape(url)
+		));
+	}
+	if noindex && !meta.is_match(html) {
+		tags.push_str("<meta name=\"robots\" content=\"noindex\">");
+	}
+	if tags.is_empty() {
+		return None;
+	}
+	Some(format!("{}{}{}", &html[..end], tags, &html[end..]))
+}
@@ -0,0 +1,756 @@
+//! Layouts for Shtola, rendered with [MiniJinja](https://docs.rs/minijinja).
+//! Layouts are read from the `_layouts` directory of the source and removed
+//! from the output. Pages choose their layout with the `layout` frontmatter
+//! key and are rendered with their
+//! [`PageContext`](../shtola/context/struct.PageContext.html):
+//!
+//! ```html
+//! {% extends "base.html" %}
+//! {% block body %}
+//!   <h1>{{ page.title }}</h1>
+//!   {{ page.content | safe }}
+//! {% endblock %}
+//! ```
+//!
+//! Layouts ending in `.html` escape values by default, so the page content has
+//! to be marked as `safe`. Undefined values are errors, so typos don't silently
+//! render as empty strings.
+//!
+//! The layouts every page depends on, including the ones it extends, includes
+//! or imports, are recorded in the `dependencies` metadata key, which maps
+//! pages to the source files they were built from:
+//!
+//! ```json
+//! { "hello.html": ["_layouts/base.html", "_layouts/post.html"] }
+//! ```
+//!
+//! These are the layouts actually loaded while rendering the page, so
+//! computed names are covered, and tags in comments or branches that weren't
+//! taken aren't. Use [`dependents`](fn.dependents.html) to find the pages
+//! affected by a changed layout. When the build reports
+//! [source changes](../shtola/struct.BuildReport.html#structfield.source_changes),
+//! like rebuilds while [watching](../shtola/struct.Shtola.html#method.watch),
+//! the plugin keeps the pages it rendered and only renders those again
+//! whose layouts or data changed.
+//!
+//! Strings of the site's interface are translated with the `t` function,
+//! which looks them up in the
+//! [catalogs](../shtola_i18n/catalog/index.html) in the language of the page.
+//! Keyword arguments fill in placeholders:
+//!
+//! ```html
+//! <a href="{{ page.url }}">{{ t("Read more") }}</a>
+//! <p>{{ t("Posted by {author}", author=page.author) }}</p>
+//! ```
+//!
+//! Dates and numbers are written the way the language of the page does with
+//! the `date`, `number` and `ordinal` filters, see
+//! [`format`](../shtola_i18n/format/index.html). `date` takes the style,
+//! `short`, `long` (the default) or `ordinal`, and `number` the number of
+//! decimals, which defaults to 0:
+//!
+//! ```html
+//! <time>{{ page.date | date("ordinal") }}</time>
+//! <p>{{ page.words | number }} words, {{ page.rank | ordinal }} place</p>
+//! ```
+//!
+//! Content is picked and shuffled deterministically with the `pick` and
+//! `shuffle` filters, see [`rotate`](../shtola/rotate/index.html). Both take
+//! a seed, so different parts of a site make different choices, and
+//! optionally the `period` after which the choice changes, counted from the
+//! [build date](../shtola/struct.IR.html#method.build_date):
+//!
+//! ```html
+//! {% set featured = collections.posts | pick("featured", period="week") %}
+//! {% for quote in site.params.quotes | shuffle("quotes") %}...{% endfor %}
+//! ```
+//!
+//! Files are looked up with the `query` function, which takes the keys of a
+//! [query](../shtola/query/index.html) as keyword arguments and returns the
+//! matching files like collection entries, without their content:
+//!
+//! ```html
+//! {% for post in query(where={"collection": "posts"}, sort="-date", limit=5) %}
+//!   <a href="{{ post.url }}">{{ post.title }}</a>
+//! {% endfor %}
+//! ```
+//!
+//! Pages that fail to render are reported through
+//! [`IR::fail_file_at`](../shtola/struct.IR.html#method.fail_file_at) with
+//! the position of the error in the layout, so all broken pages are listed
+//! with a snippet of the offending code at once when the build fails.
+
+use minijinja::value::Kwargs;
+use minijinja::{Environment, State, UndefinedBehavior};
+use shtola::context::{entry, PageContext};
+use shtola::frontmatter::Date;
+use shtola::json::{Map, Value};
+use shtola::log::{debug, info};
+use shtola::rotate::{Period, Selector};
+use shtola::{HashMap, Location, Plugin, ShFile, IR};
+use shtola_i18n::catalog::Catalog;
+use shtola_i18n::format::{self, DateStyle};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Options for the template plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The directory layouts are read from. Default is `_layouts`.
+	pub layouts_dir: PathBuf,
+	/// The layout for pages without a `layout` frontmatter key. Default is
+	/// `None`, leaving those pages untouched.
+	pub default_layout: Option<String>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			layouts_dir: "_layouts".into(),
+			default_layout: None,
+		}
+	}
+}
+
+/// An error that occurred while rendering a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+	/// The page that was rendered.
+	pub page: PathBuf,
+	/// The layout the error occurred in. This can be a layout included or
+	/// extended by the layout of the page.
+	pub layout: Option<String>,
+	/// The line of the error, starting at 1.
+	pub line: Option<usize>,
+	/// The column of the error, starting at 1.
+	pub column: Option<usize>,
+	/// What went wrong.
+	pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Error rendering {:?}", self.page)?;
+		if let Some(layout) = &self.layout {
+			write!(f, " in layout {:?}", layout)?;
+		}
+		match (self.line, self.column) {
+			(Some(line), Some(column)) => write!(f, " at line {}, column {}", line, column)?,
+			(Some(line), None) => write!(f, " at line {}", line)?,
+			_ => (),
+		}
+		write!(f, ": {}", self.message)
+	}
+}
+
+/// Creates the template plugin with default options.
+pub fn plugin() -> Plugin {
+	with_options(Options::default())
+}
+
+// A page rendered by a previous build of the same plugin.
+struct Rendered {
+	// The fingerprint of the data the page was rendered with.
+	key: u64,
+	// The layouts the page was rendered with and their fingerprints.
+	layouts: Vec<(String, u64)>,
+	content: Vec<u8>,
+}
+
+/// Creates the template plugin with the given options.
+pub fn with_options(options: Options) -> Plugin {
+	let cache: RefCell<BTreeMap<PathBuf, Rendered>> = RefCell::default();
+	Box::new(move |ir: IR| {
+		info!("Rendering layouts");
+		let mut env = Environment::new();
+		env.set_undefined_behavior(UndefinedBehavior::Strict);
+		// Every layout loaded by name while rendering passes through here,
+		// which records the ones a page uses.
+		let loaded: Arc<Mutex<BTreeSet<String>>> = Arc::default();
+		let recorder = loaded.clone();
+		env.set_path_join_callback(move |name, _parent| {
+			recorder.lock().unwrap().insert(name.to_string());
+			Cow::Borrowed(name)
+		});
+		let catalog = Catalog::from_ir(&ir).unwrap_or_default();
+		env.add_function("t", move |state: &State, key: &str, kwargs: Kwargs| {
+			translate(&catalog, state, key, kwargs)
+		});
+		env.add_filter("date", format_date);
+		env.add_filter("number", format_number);
+		env.add_filter("ordinal", format_ordinal);
+		let build_date = ir.build_date();
+		env.add_filter(
+			"pick",
+			move |items: Vec<minijinja::Value>, seed: &str, kwargs: Kwargs| {
+				let selector = selector(build_date, seed, kwargs)?;
+				Ok::<_, minijinja::Error>(selector.pick(&items).cloned().unwrap_or_default())
+			},
+		);
+		env.add_filter(
+			"shuffle",
+			move |mut items: Vec<minijinja::Value>, seed: &str, kwargs: Kwargs| {
+				selector(build_date, seed, kwargs)?.shuffle(&mut items);
+				Ok::<_, minijinja::Error>(items)
+			},
+		);
+		let files = ir.clone();
+		env.add_function("query", move |kwargs: Kwargs| query(&files, kwargs));
+		let mut sources: HashMap<String, String> = HashMap::new();
+		let mut layouts: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in &ir.files {
+			if let Ok(name) = path.strip_prefix(&options.layouts_dir) {
+				let name = name.to_string_lossy().replace('\\', "/");
+				let source = String::from_utf8_lossy(&file.content).into_owned();
+				sources.insert(name, source);
+				layouts.insert(path.clone(), file.clone());
+			}
+		}
+		let mut errors = Vec::new();
+		for (name, source) in &sources {
+			debug!("Loading layout {:?}", name);
+			if let Err(e) = env.add_template_owned(name.clone(), source.clone()) {
+				errors.push(e);
+			}
+		}
+		let contexts = PageContext::all(&ir);
+		// Converted once and shared between all pages, as copying them for
+		// every page makes large sites slow.
+		let (site, collections) = match contexts.values().next() {
+			Some(context) => (
+				minijinja::Value::from_serialize(&*context.site),
+				minijinja::Value::from_serialize(&*context.collections),
+			),
+			None => Default::default(),
+		};
+		let reuse = ir.report.source_changes.is_some();
+		let mut cache = cache.borrow_mut();
+		if !reuse {
+			cache.clear();
+		}
+		let fingerprints: HashMap<String, u64> = sources
+			.iter()
+			.map(|(name, source)| (name.clone(), fingerprint(&[source])))
+			.collect();
+		let shared = if reuse {
+			shared_fingerprint(&ir, &contexts)
+		} else {
+			0
+		};
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		let mut failures = Vec::new();
+		let mut dependencies = match ir.metadata.get("dependencies") {
+			Some(Value::Object(map)) => map.clone(),
+			_ => Map::new(),
+		};
+		for (path, file) in ir.files.iter().filter(|(p, _)| !layouts.contains_key(p)) {
+			// Binary files have no context and are never rendered.
+			let context = match contexts.get(path) {
+				Some(context) => context,
+				None => continue,
+			};
+			let layout = match file.frontmatter.get("layout") {
+				Some(Value::String(layout)) => layout.clone(),
+				_ => match &options.default_layout {
+					Some(layout) => layout.clone(),
+					None => continue,
+				},
+			};
+			let key = if reuse {
+				fingerprint(&[&shared.to_string(), &layout, &context.page.to_string()])
+			} else {
+				0
+			};
+			let unchanged = cache.get(path).filter(|rendered| {
+				rendered.key == key
+					&& rendered
+						.layouts
+						.iter()
+						.all(|(name, f)| fingerprints.get(name) == Some(f))
+			});
+			let (used, rendered) = match unchanged {
+				Some(rendered) => {
+					debug!("Keeping {:?}, nothing it was rendered from changed", path);
+					let used = rendered
+						.layouts
+						.iter()
+						.map(|(name, _)| name.clone())
+						.collect();
+					(used, Ok(rendered.content.clone()))
+				}
+				None => {
+					debug!("Rendering {:?} with layout {:?}", path, layout);
+					loaded.lock().unwrap().clear();
+					let context = minijinja::context! {
+						page => minijinja::Value::from_serialize(&context.page),
+						site => site.clone(),
+						collections => collections.clone(),
+					};
+					let rendered = env
+						.get_template(&layout)
+						.and_then(|template| template.render(context))
+						.map(String::into_bytes);
+					let mut used = loaded.lock().unwrap().clone();
+					used.insert(layout.clone());
+					used.retain(|name| sources.contains_key(name));
+					(used, rendered)
+				}
+			};
+			let used_paths: Vec<Value> = used
+				.iter()
+				.map(|name| options.layouts_dir.join(name).to_string_lossy().into())
+				.collect();
+			dependencies.insert(path.to_string_lossy().into(), Value::Array(used_paths));
+			match rendered {
+				Ok(content) => {
+					if reuse {
+						let layouts = used
+							.iter()
+							.map(|name| (name.clone(), fingerprints[name]))
+							.collect();
+						let rendered = Rendered {
+							key,
+							layouts,
+							content: content.clone(),
+						};
+						cache.insert(path.clone(), rendered);
+					}
+					update_hash.insert(
+						path.clone(),
+						ShFile {
+							content,
+							..file.clone()
+						},
+					);
+				}
+				Err(e) => {
+					cache.remove(path);
+					failures.push(describe(path, &layout, &e, &sources));
+				}
+			}
+		}
+		// A layout that can't be parsed isn't known to the environment, so
+		// pages using it fail with a "template not found" error. Report the
+		// syntax error instead.
+		for failure in failures.iter_mut() {
+			let syntax_error = errors
+				.iter()
+				.find(|e| e.name() == failure.layout.as_deref());
+			if let Some(e) = syntax_error {
+				*failure = describe(&failure.page, e.name().unwrap(), e, &sources);
+			}
+		}
+		let mut ir = IR {
+			files: update_hash.union(ir.files).difference(layouts),
+			metadata: ir
+				.metadata
+				.update("dependencies".into(), Value::Object(dependencies)),
+			..ir
+		};
+		for failure in failures {
+			let location = match (&failure.layout, failure.line) {
+				(Some(layout), Some(line)) => Some(Location {
+					path: options.layouts_dir.join(layout),
+					line,
+					column: failure.column,
+				}),
+				_ => None,
+			};
+			ir = match location {
+				Some(location) => ir.fail_file_at(&failure.page, &failure.to_string(), location),
+				None => ir.fail_file(&failure.page, &failure.to_string()),
+			};
+		}
+		ir
+	})
+}
+
+/// Returns the pages that depend on the given source file according to the
+/// `dependencies` metadata of a finished build, sorted by path. The same as
+/// [`IR::dependents`](../shtola/struct.IR.html#method.dependents).
+pub fn dependents(ir: &IR, changed: &Path) -> Vec<PathBuf> {
+	ir.dependents(changed)
+}
+
+// Translates a message into the language of the page being rendered.
+fn translate(
+	catalog: &Catalog,
+	state: &State,
+	key: &str,
+	kwargs: Kwargs,
+) -> Result<String, minijinja::Error> {
+	let language = page_language(state);
+	let mut args = Map::new();
+	for name in kwargs.args() {
+		let value: minijinja::Value = kwargs.get(name)?;
+		let value = shtola::json::to_value(&value).map_err(|e| {
+			minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string())
+		})?;
+		args.insert(name.to_string(), value);
+	}
+	kwargs.assert_all_used()?;
+	Ok(catalog.translate(language.as_deref(), key, &args))
+}
+
+// Runs a query over the files of the build, described by the keyword
+// arguments.
+fn query(ir: &IR, kwargs: Kwargs) -> Result<minijinja::Value, minijinja::Error> {
+	let invalid =
+		|message: String| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, message);
+	let mut spec = Map::new();
+	for name in kwargs.args() {
+		let value: minijinja::Value = kwargs.get(name)?;
+		let value = shtola::json::to_value(&value).map_err(|e| invalid(e.to_string()))?;
+		spec.insert(name.to_string(), value);
+	}
+	kwargs.assert_all_used()?;
+	let files = ir
+		.query()
+		.apply(&Value::Object(spec))
+		.map_err(invalid)?
+		.run();
+	let entries: Vec<Value> = files.into_iter().map(|(p, f)| entry(p, f)).collect();
+	Ok(minijinja::Value::from_serialize(entries))
+}
+
+// The formatting filters only output digits, month names and separators, so
+// they're marked as safe to keep `/` in dates from being escaped.
+fn format_date(
+	state: &State,
+	value: &str,
+	style: Option<&str>,
+) -> Result<minijinja::Value, minijinja::Error> {
+	let invalid =
+		|message: String| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, message);
+	let date = Date::parse(value).ok_or_else(|| invalid(format!("{:?} isn't a date", value)))?;
+	let style = match style {
+		Some(name) => DateStyle::from_name(name)
+			.ok_or_else(|| invalid(format!("{:?} isn't a date style", name)))?,
+		None => DateStyle::Long,
+	};
+	let language = page_language(state);
+	let formatted = format::format_date(language.as_deref(), date, style);
+	Ok(minijinja::Value::from_safe_string(formatted))
+}
+
+fn format_number(state: &State, value: f64, decimals: Option<usize>) -> minijinja::Value {
+	let language = page_language(state);
+	let formatted = format::format_number(language.as_deref(), value, decimals.unwrap_or(0));
+	minijinja::Value::from_safe_string(formatted)
+}
+
+fn format_ordinal(state: &State, value: u64) -> minijinja::Value {
+	let formatted = format::ordinal(page_language(state).as_deref(), value);
+	minijinja::Value::from_safe_string(formatted)
+}
+
+// Creates the selector for the `pick` and `shuffle` filters.
+fn selector(build_date: Date, seed: &str, kwargs: Kwargs) -> Result<Selector, minijinja::Error> {
+	let period: Option<&str> = kwargs.get("period")?;
+	kwargs.assert_all_used()?;
+	match period {
+		Some(name) => {
+			let period = Period::from_name(name).ok_or_else(|| {
+				minijinja::Error::new(
+					minijinja::ErrorKind::InvalidOperation,
+					format!("{:?} isn't a period", name),
+				)
+			})?;
+			Ok(Selector::for_period(seed, build_date, period))
+		}
+		None => Ok(Selector::new(seed)),
+	}
+}
+
+// The language of the page being rendered, from its `lang` frontmatter key.
+fn page_language(state: &State) -> Option<String> {
+	state
+		.lookup("page")
+		.and_then(|page| page.get_attr("lang").ok())
+		.and_then(|lang| lang.as_str().map(|l| l.to_string()))
+}
+
+// The fingerprint of the data every page sees: the site, the collections,
+// the files the `query` function searches and the build date.
+fn shared_fingerprint(ir: &IR, contexts: &BTreeMap<PathBuf, PageContext>) -> u64 {
+	let entries: BTreeMap<&PathBuf, Value> =
+		ir.files.iter().map(|(p, f)| (p, entry(p, f))).collect();
+	let entries: Vec<&Value> = entries.values().collect();
+	let mut parts = vec![
+		Value::from(entries.len()).to_string(),
+		ir.build_date().to_string(),
+	];
+	parts.extend(entries.iter().map(|e| e.to_string()));
+	if let Some(context) = contexts.values().next() {
+		parts.push(context.site.to_string());
+		parts.push(context.collections.to_string());
+	}
+	let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+	fingerprint(&parts)
+}
+
+fn fingerprint(parts: &[&str]) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	parts.hash(&mut hasher);
+	hasher.finish()
+}
+
+fn describe(
+	page: &Path,
+	layout: &str,
+	error: &minijinja::Error,
+	sources: &HashMap<String, String>,
+) -> TemplateError {
+	let message = match error.detail() {
+		Some(detail) => format!("{}: {}", error.kind(), detail),
+		None => error.kind().to_string(),
+	};
+	let name = error.name().unwrap_or(layout).to_string();
+	let source = sources.get(&name);
+	let column = match (source, error.range()) {
+		(Some(source), Some(range)) => {
+			let line_start = source[..range.start].rfind('\n').map_or(0, |i| i + 1);
+			Some(source[line_start..range.start].chars().count() + 1)
+		}
+		_ => None,
+	};
+	TemplateError {
+		page: page.to_path_buf(),
+		layout: Some(name),
+		line: error.line(),
+		column,
+		message,
+	}
+}
+
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/templates");
+	s.destination("../fixtures/dest_templates");
+	s.clean(true);
+	s.register(Box::new(|ir: IR| IR {
+		metadata: ir.metadata.update("title".into(), "My site".into()),
+		..ir
+	}));
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let file = r.files.get(Path::new("hello.html")).unwrap();
+	assert_eq!(
+		std::str::from_utf8(&file.content).unwrap(),
+		"<title>My site - Hello</title>\n<article><p>Hi</p></article>"
+	);
+	assert!(r.files.get(Path::new("_layouts/post.html")).is_none());
+	assert_eq!(
+		r.metadata.get("dependencies").unwrap(),
+		&shtola::json::json!({ "hello.html": ["_layouts/base.html", "_layouts/post.html"] })
+	);
+	assert_eq!(
+		dependents(&r, Path::new("_layouts/base.html")),
+		vec![PathBuf::from("hello.html")]
+	);
+	assert!(dependents(&r, Path::new("style.css")).is_empty());
+	assert!(r.files.get(Path::new("style.css")).is_some());
+}
+
+#[test]
+fn partials_work() {
+	use shtola::manifest::Summary;
+	use shtola::{BuildReport, Shtola};
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/templates_partials");
+	s.destination("../fixtures/dest_templates_partials");
+	s.dry_run(true);
+	let read = s.build().unwrap();
+	let read = IR {
+		report: BuildReport {
+			source_changes: Some(Summary::default()),
+			..read.report
+		},
+		..read
+	};
+	let render = plugin();
+	let content =
+		|ir: &IR, path: &str| String::from_utf8(ir.files[Path::new(path)].content.clone()).unwrap();
+	let r = render(read.clone());
+	assert_eq!(content(&r, "a.html"), "<h1>A</h1>|a");
+	assert_eq!(content(&r, "b.html"), "<footer>Bye</footer>|b");
+	// Computed names are followed, commented out tags aren't.
+	assert_eq!(
+		r.metadata.get("dependencies").unwrap(),
+		&shtola::json::json!({
+			"a.html": ["_layouts/header.html", "_layouts/page.html"],
+			"b.html": ["_layouts/footer.html", "_layouts/page.html"],
+		})
+	);
+	let footer = Path::new("_layouts/footer.html");
+	assert_eq!(dependents(&r, footer), vec![PathBuf::from("b.html")]);
+	let changed = ShFile {
+		content: b"<footer>Ciao</footer>".to_vec(),
+		..read.files[footer].clone()
+	};
+	let r = render(IR {
+		files: read.files.update(footer.into(), changed),
+		..read
+	});
+	assert_eq!(content(&r, "a.html"), "<h1>A</h1>|a");
+	assert_eq!(content(&r, "b.html"), "<footer>Ciao</footer>|b");
+}
+
+#[test]
+fn query_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/templates_query");
+	s.destination("../fixtures/dest_templates_query");
+	s.clean(true);
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let file = r.files.get(Path::new("index.html")).unwrap();
+	assert_eq!(
+		std::str::from_utf8(&file.content).unwrap(),
+		"b (2020-03-01) c (2020-02-01) "
+	);
+}
+
+#[test]
+fn translations_work() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/i18n_catalog");
+	s.destination("../fixtures/dest_templates_i18n");
+	s.clean(true);
+	s.register(shtola_i18n::catalog::plugin(
+		shtola_i18n::catalog::Options {
+			default_language: Some("en".into()),
+			..Default::default()
+		},
+	));
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let content = |path: &str| String::from_utf8(r.files[Path::new(path)].content.clone()).unwrap();
+	assert_eq!(
+		content("index.html"),
+		"<p>Weiterlesen | Veröffentlicht von Marisa | All posts</p>"
+	);
+	assert_eq!(
+		content("about.html"),
+		"<p>Read more | Posted by Marisa | All posts</p>"
+	);
+}
+
+#[test]
+fn formatting_works() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/templates_format");
+	s.destination("../fixtures/dest_templates_format");
+	s.clean(true);
+	s.strict(false);
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let content = |path: &str| String::from_utf8(r.files[Path::new(path)].content.clone()).unwrap();
+	assert_eq!(
+		content("de.html"),
+		"1. März 2020 | 01.03.2020 | 1.234,5 | 2."
+	);
+	assert_eq!(
+		content("en.html"),
+		"March 1, 2020 | 3/1/2020 | 1,234.5 | 2nd"
+	);
+	assert_eq!(r.report.errors.len(), 1);
+	assert!(r.report.errors[0]
+		.message
+		.ends_with("\"yesterday\" isn't a date"));
+}
+
+#[test]
+fn errors_are_reported() {
+	use shtola::Shtola;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/templates_broken");
+	s.destination("../fixtures/dest_templates_broken");
+	s.strict(false);
+	s.register(plugin());
+	let r = s.build().unwrap();
+	let mut errors: Vec<String> = r
+		.report
+		.errors
+		.iter()
+		.map(|e| e.render(&r.config.source))
+		.collect();
+	errors.sort();
+	assert_eq!(errors.len(), 3);
+	assert_eq!(
+		errors[0],
+		"\"one.html\": Error rendering \"one.html\" in layout \"page.html\" at line 3, \
+		 column 11: undefined value\n \
+		 --> _layouts/page.html:3:11\n  \
+		 |\n\
+		 2 | <h1>{{ page.title }}</h1>\n\
+		 3 | <p>{{ page.author.name }}</p>\n  \
+		 |           ^\n\
+		 4 | </html>"
+	);
+	assert!(errors[1].starts_with(
+		"\"three.html\": Error rendering \"three.html\" in layout \"missing.html\": \
+		 template not found"
+	));
+	assert!(errors[2].starts_with(
+		"\"two.html\": Error rendering \"two.html\" in layout \"syntax.html\" at line 2, \
+		 column 20: syntax error: unexpected end of input, expected end of block\n \
+		 --> _layouts/syntax.html:2:20\n"
+	));
+}
+
+#[test]
+fn rotation_works() {
+	use shtola::Shtola;
+
+	let build = |date: &str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/templates_rotate");
+		s.destination("../fixtures/dest_templates_rotate");
+		s.strict(false);
+		s.dry_run(true);
+		s.build_date(Date::parse(date).unwrap());
+		s.register(plugin());
+		s.build().unwrap()
+	};
+	let quotes = ["a", "b", "c", "d", "e", "f", "g", "h"];
+	let date = Date::parse("2020-02-05").unwrap();
+	let mut shuffled = quotes;
+	Selector::new("order").shuffle(&mut shuffled);
+	let expected = format!(
+		"{}|{}|{}",
+		Selector::new("quote").pick(&quotes).unwrap(),
+		Selector::for_period("featured", date, Period::Week)
+			.pick(&quotes)
+			.unwrap(),
+		shuffled.join(",")
+	);
+	let r = build("2020-02-05");
+	let content = String::from_utf8(r.files[Path::new("index.html")].content.clone()).unwrap();
+	assert_eq!(content, expected);
+	let r = build("2020-02-09");
+	let same_week = String::from_utf8(r.files[Path::new("index.html")].content.clone()).unwrap();
+	assert_eq!(same_week, expected);
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("broken.html"));
+	assert!(r.report.errors[0]
+		.message
+		.contains("\"fortnight\" isn't a period"));
+}
@@ -0,0 +1,150 @@
+//! Build-time token replacement for Shtola. The [`plugin`](fn.plugin.html)
+//! replaces tokens like `{{BUILD_DATE}}` in all text files, which is handy for
+//! cache-busting query strings and footer stamps without a template engine:
+//!
+//! ```html
+//! <link rel="stylesheet" href="/style.css?v={{GIT_SHA}}">
+//! ```
+//!
+//! Token names consist of uppercase letters, digits and underscores, so
+//! template expressions like `{{ page.title }}` are left alone. A token is
+//! looked up in this order:
+//!
+//! 1. The tokens passed to [`with_tokens`](fn.with_tokens.html).
+//! 2. The `tokens` metadata key, an object other plugins can add tokens to.
+//! 3. The environment variable of the same name, for tokens starting with
+//!    `SHTOLA_`, like `{{SHTOLA_DEPLOY_ENV}}`. Other environment variables
+//!    are never read, so content can't publish secrets like
+//!    `{{GITHUB_TOKEN}}` of the build machine.
+//! 4. The built-in tokens `BUILD_DATE` (the
+//!    [build date](../shtola/struct.IR.html#method.build_date), like
+//!    `2020-01-31`), `BUILD_TIMESTAMP` (seconds since the Unix epoch) and
+//!    `GIT_SHA` (the commit checked out in the source directory).
+//!
+//! Unknown tokens are left as they are.
+
+use regex::{Captures, Regex};
+use shtola::frontmatter::is_text;
+use shtola::json::Value;
+use shtola::log::{debug, info};
+use shtola::{HashMap, Plugin, ShFile, IR};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Creates the token plugin.
+pub fn plugin() -> Plugin {
+	with_tokens(BTreeMap::new())
+}
+
+/// Creates the token plugin with additional tokens, which take precedence
+/// over all other sources.
+pub fn with_tokens(tokens: BTreeMap<String, String>) -> Plugin {
+	Box::new(move |ir: IR| {
+		info!("Replacing tokens");
+		let mut known = built_ins(&ir);
+		known.extend(std::env::vars().filter(|(name, _)| is_env_token(name)));
+		if let Some(Value::Object(map)) = ir.metadata.get("tokens") {
+			for (name, value) in map {
+				let value = match value {
+					Value::String(s) => s.clone(),
+					v => v.to_string(),
+				};
+				known.insert(name.clone(), value);
+			}
+		}
+		known.extend(tokens.clone());
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in ir.files.iter().filter(|(_, f)| is_text(&f.content)) {
+			let text = std::str::from_utf8(&file.content).unwrap();
+			let replaced = replace(text, &known);
+			if replaced != text {
+				debug!("Replaced tokens in {:?}", path);
+				update_hash.insert(
+					path.clone(),
+					ShFile {
+						content: replaced.into_bytes(),
+						..file.clone()
+					},
+				);
+			}
+		}
+		IR {
+			files: update_hash.union(ir.files),
+			..ir
+		}
+	})
+}
+
+/// Replaces all known tokens in a text.
+pub fn replace(text: &str, tokens: &BTreeMap<String, String>) -> String {
+	static RE: OnceLock<Regex> = OnceLock::new();
+	let re = RE.get_or_init(|| Regex::new(r"\{\{([A-Z][A-Z0-9_]*)\}\}").unwrap());
+	re.replace_all(text, |captures: &Captures| match tokens.get(&captures[1]) {
+		Some(value) => value.clone(),
+		None => captures[0].to_string(),
+	})
+	.into_owned()
+}
+
+/// The prefix of environment variables that can be used as tokens.
+pub const ENV_PREFIX: &str = "SHTOLA_";
+
+fn is_env_token(name: &str) -> bool {
+	name.starts_with(ENV_PREFIX)
+		&& name
+			.chars()
+			.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn built_ins(ir: &IR) -> BTreeMap<String, String> {
+	let mut tokens = BTreeMap::new();
+	let seconds = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs());
+	tokens.insert("BUILD_TIMESTAMP".into(), seconds.to_string());
+	tokens.insert("BUILD_DATE".into(), ir.build_date().to_string());
+	let git = Command::new("git")
+		.args(["rev-parse", "HEAD"])
+		.current_dir(&ir.config.source)
+		.output();
+	if let Ok(output) = git {
+		if output.status.success() {
+			let sha = String::from_utf8_lossy(&output.stdout).trim().to_string();
+			tokens.insert("GIT_SHA".into(), sha);
+		}
+	}
+	tokens
+}
+
+#[test]
+fn it_works() {
+	use shtola::json::json;
+	use shtola::Shtola;
+	use std::path::Path;
+
+	std::env::set_var("SHTOLA_TEST_BUILDER", "ci");
+	std::env::set_var("TOKENS_TEST_SECRET", "hunter2");
+	let mut s = Shtola::new();
+	s.source("../fixtures/tokens");
+	s.destination("../fixtures/dest_tokens");
+	s.register(Box::new(|ir: IR| IR {
+		metadata: ir
+			.metadata
+			.update("tokens".into(), json!({ "SITE_VERSION": "1.0" })),
+		..ir
+	}));
+	let mut tokens = BTreeMap::new();
+	tokens.insert("BUILD_DATE".to_string(), "2020-01-31".to_string());
+	s.register(with_tokens(tokens));
+	let r = s.build().unwrap();
+	let index = r.files.get(Path::new("index.html")).unwrap();
+	assert_eq!(
+		std::str::from_utf8(&index.content).unwrap(),
+		"<footer>v1.0, built 2020-01-31 by ci {{TOKENS_TEST_SECRET}} {{UNKNOWN}} {{ page.title }}</footer>\n"
+	);
+	let blob = r.files.get(Path::new("blob.bin")).unwrap();
+	assert_eq!(blob.content, b"\0{{BUILD_DATE}}");
+}
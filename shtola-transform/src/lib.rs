@@ -0,0 +1,322 @@
+//! Transforming files with external commands, for toolchains Shtola will
+//! never embed, like Tailwind CSS or PostCSS. The [`plugin`](fn.plugin.html)
+//! runs the configured command once per matching file and replaces the file
+//! with what the command produced:
+//!
+//! ```
+//! use shtola::Shtola;
+//! use shtola_transform::Options;
+//!
+//! let mut s = Shtola::new();
+//! s.register_fallible(shtola_transform::plugin(Options {
+//!   command: vec!["npx".into(), "postcss".into(), "--stdin-filename".into(), "{path}".into()],
+//!   extensions: vec!["css".into()],
+//!   ..Options::default()
+//! }));
+//! ```
+//!
+//! Commands run in the current directory, so they find their configuration
+//! files. Their results are kept in an on-disk cache, keyed by the command and
+//! the path and content of the file, so unchanged files don't run the command
+//! again. The build fails if the command can't be started at all. Files the
+//! command fails on are reported as failed files.
+
+use sha2::{Digest, Sha256};
+use shtola::command::{run, temp_dir};
+use shtola::log::{debug, info};
+use shtola::{has_extension, PluginError, ShFile, TryPlugin, IR};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// How files are passed to the command and read back from it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Io {
+	/// The content is written to the standard input of the command, and its
+	/// standard output becomes the new content.
+	Stdio,
+	/// The content is written to a temporary file, and the new content is
+	/// read from another one. `{input}` and `{output}` in the arguments are
+	/// replaced with their paths.
+	Files,
+}
+
+/// Options for the transforming plugin.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The program followed by its arguments. `{path}` in the arguments is
+	/// replaced with the path of the file relative to the source directory.
+	/// Default is empty, which leaves all files alone.
+	pub command: Vec<String>,
+	/// The extensions of the files to transform. Default is none.
+	pub extensions: Vec<String>,
+	/// How the files are passed to the command. Default is
+	/// [`Io::Stdio`](enum.Io.html).
+	pub io: Io,
+	/// The extension to give the transformed files, like `css` for a Sass
+	/// compiler. Default is `None`, keeping the extension.
+	pub output_extension: Option<String>,
+	/// How many commands may run at once. Default is the number of CPUs.
+	pub jobs: usize,
+	/// The directory results are cached in, or `None` to turn caching off.
+	/// Default is `.shtola-cache/transform`.
+	pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			command: Vec::new(),
+			extensions: Vec::new(),
+			io: Io::Stdio,
+			output_extension: None,
+			jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+			cache_dir: Some(".shtola-cache/transform".into()),
+		}
+	}
+}
+
+/// Creates the transforming plugin.
+pub fn plugin(options: Options) -> TryPlugin {
+	Box::new(move |ir: IR| {
+		let mut paths: Vec<PathBuf> = ir
+			.files
+			.iter()
+			.filter(|(p, _)| has_extension(p, &options.extensions))
+			.map(|(p, _)| p.clone())
+			.collect();
+		if options.command.is_empty() || paths.is_empty() {
+			return Ok(ir);
+		}
+		paths.sort();
+		info!(
+			"Transforming {} files with {:?}",
+			paths.len(),
+			options.command[0]
+		);
+		let results = transform_all(&options, &ir, &paths)?;
+		let mut ir = ir;
+		for (path, result) in results {
+			ir = match result {
+				Ok(content) => {
+					let file = ShFile {
+						content,
+						..ir.files.get(&path).unwrap().clone()
+					};
+					let files = match &options.output_extension {
+						Some(ext) => {
+							let target = path.with_extension(ext);
+							let file = file.detect_type(&target);
+							ir.files.without(&path).update(target, file)
+						}
+						None => ir.files.update(path, file),
+					};
+					IR { files, ..ir }
+				}
+				Err(message) => ir.fail_file(path, &format!("Unable to transform: {}", message)),
+			};
+		}
+		Ok(ir)
+	})
+}
+
+type Transformed = Vec<(PathBuf, Result<Vec<u8>, String>)>;
+
+// Transforms the files on up to `jobs` threads, each taking the next file
+// that's left.
+fn transform_all(
+	options: &Options,
+	ir: &IR,
+	paths: &[PathBuf],
+) -> Result<Transformed, PluginError> {
+	let next = AtomicUsize::new(0);
+	let results = Mutex::new(Vec::new());
+	let jobs = options.jobs.clamp(1, paths.len());
+	thread::scope(|scope| {
+		let workers: Vec<_> = (0..jobs)
+			.map(|_| {
+				scope.spawn(|| -> Result<(), PluginError> {
+					loop {
+						let i = next.fetch_add(1, Ordering::SeqCst);
+						let path = match paths.get(i) {
+							Some(path) => path,
+							None => return Ok(()),
+						};
+						let file = ir.files.get(path).unwrap();
+						let result = transform(options, path, &file.content)?;
+						results.lock().unwrap().push((path.clone(), result));
+					}
+				})
+			})
+			.collect();
+		workers
+			.into_iter()
+			.try_for_each(|worker| worker.join().unwrap())
+	})?;
+	Ok(results.into_inner().unwrap())
+}
+
+// Transforms one file, using the cache if possible. Failures of the command
+// are returned as the inner error, so only that file fails.
+fn transform(
+	options: &Options,
+	path: &Path,
+	content: &[u8],
+) -> Result<Result<Vec<u8>, String>, PluginError> {
+	let cache_error = |e: std::io::Error| {
+		PluginError::new(&format!("Unable to access the transform cache: {}", e))
+	};
+	let cached = options.cache_dir.as_ref().map(|dir| {
+		let mut hasher = Sha256::new();
+		hasher.update(options.command.join("\0").as_bytes());
+		hasher.update([0]);
+		hasher.update(format!("{:?}", options.io).as_bytes());
+		hasher.update([0]);
+		hasher.update(path.to_string_lossy().as_bytes());
+		hasher.update([0]);
+		hasher.update(content);
+		dir.join(format!("{:x}", hasher.finalize()))
+	});
+	if let Some(cached) = cached.as_ref().filter(|c| c.exists()) {
+		debug!("Using cached result for {:?}", path);
+		return fs::read(cached).map(Ok).map_err(cache_error);
+	}
+	let result = match options.io {
+		Io::Stdio => run_stdio(options, path, content)?,
+		Io::Files => run_files(options, path, content)?,
+	};
+	if let (Ok(content), Some(cached)) = (&result, &cached) {
+		if let Some(dir) = cached.parent() {
+			fs::create_dir_all(dir).map_err(cache_error)?;
+		}
+		fs::write(cached, content).map_err(cache_error)?;
+	}
+	Ok(result)
+}
+
+fn run_stdio(
+	options: &Options,
+	path: &Path,
+	content: &[u8],
+) -> Result<Result<Vec<u8>, String>, PluginError> {
+	let args = arguments(options, path, None);
+	debug!("Running {:?}", &args);
+	run("Command", &args, None, Some(content))
+}
+
+fn run_files(
+	options: &Options,
+	path: &Path,
+	content: &[u8],
+) -> Result<Result<Vec<u8>, String>, PluginError> {
+	let dir = temp_dir("shtola-transform-")?;
+	let dir = dir.path();
+	let io_error = |e: std::io::Error| {
+		PluginError::new(&format!("Unable to prepare files for transforming: {}", e))
+	};
+	let name = path.file_name().unwrap_or_default();
+	let input = dir.join("input").join(name);
+	let output = match &options.output_extension {
+		Some(ext) => dir.join("output").join(name).with_extension(ext),
+		None => dir.join("output").join(name),
+	};
+	fs::create_dir_all(input.parent().unwrap()).map_err(io_error)?;
+	fs::create_dir_all(output.parent().unwrap()).map_err(io_error)?;
+	fs::write(&input, content).map_err(io_error)?;
+	let args = arguments(options, path, Some((&input, &output)));
+	debug!("Running {:?}", &args);
+	Ok(run("Command", &args, None, None)?
+		.and_then(|_| fs::read(&output).map_err(|e| e.to_string())))
+}
+
+// The program and arguments for transforming the file at `path`.
+fn arguments(options: &Options, path: &Path, files: Option<(&Path, &Path)>) -> Vec<String> {
+	let path = path.to_string_lossy();
+	options
+		.command
+		.iter()
+		.map(|a| {
+			let a = a.replace("{path}", &path);
+			match files {
+				Some((input, output)) => a
+					.replace("{input}", &input.to_string_lossy())
+					.replace("{output}", &output.to_string_lossy()),
+				None => a,
+			}
+		})
+		.collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn it_works() {
+	use shtola::Shtola;
+
+	let cache = PathBuf::from("../fixtures/dest_transform_cache");
+	let _ = fs::remove_dir_all(&cache);
+	let runs = std::env::temp_dir().join(format!("shtola-transform-runs-{}", std::process::id()));
+	let _ = fs::remove_file(&runs);
+	let build = |io: Io, script: &str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/transform");
+		s.destination("../fixtures/dest_transform");
+		s.clean(true);
+		s.strict(false);
+		s.register_fallible(plugin(Options {
+			// Stands in for a CSS toolchain, counting its runs.
+			command: vec![
+				"sh".into(),
+				"-c".into(),
+				format!("echo >> {}; {}", runs.display(), script),
+			],
+			extensions: vec!["css".into()],
+			io,
+			jobs: 2,
+			cache_dir: Some(cache.clone()),
+			..Options::default()
+		}));
+		s.build()
+	};
+	let stdio = "c=$(cat); case $c in *fail*) exit 1;; esac; echo \"$c\" | tr a-z A-Z";
+	let r = build(Io::Stdio, stdio).unwrap();
+	assert_eq!(
+		r.files.get(Path::new("style.css")).unwrap().content,
+		b".A { COLOR: RED; }\n"
+	);
+	assert_eq!(
+		r.files.get(Path::new("theme.css")).unwrap().content,
+		b".B { COLOR: BLUE; }\n"
+	);
+	assert_eq!(
+		r.files.get(Path::new("index.html")).unwrap().content,
+		b"<p>keep me</p>\n"
+	);
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("broken.css"));
+	assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 3);
+
+	// Successful results are cached, failures aren't.
+	build(Io::Stdio, stdio).unwrap();
+	assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 4);
+
+	let r = build(Io::Files, "echo {path} > {output}; cat {input} >> {output}").unwrap();
+	assert_eq!(
+		r.files.get(Path::new("style.css")).unwrap().content,
+		b"style.css\n.a { color: red; }\n"
+	);
+	let _ = fs::remove_file(&runs);
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/transform");
+	s.destination("../fixtures/dest_transform");
+	s.register_fallible(plugin(Options {
+		command: vec!["shtola-missing-command".into()],
+		extensions: vec!["css".into()],
+		cache_dir: None,
+		..Options::default()
+	}));
+	let e = s.build().unwrap_err();
+	assert!(e.to_string().contains("isn't installed"));
+}
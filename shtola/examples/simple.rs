@@ -13,8 +13,8 @@ fn main() {
 			update_hash.insert(
 				k.into(),
 				ShFile {
-					frontmatter: v.frontmatter.clone(),
 					content: "hello".into(),
+					..v.clone()
 				},
 			);
 		}
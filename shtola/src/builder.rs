@@ -0,0 +1,316 @@
+use crate::frontmatter::Date;
+use crate::site::SiteConfig;
+use crate::{
+	absolute, glob_set, resolve, Checker, FileMapPlugin, InvalidFrontmatter, LineEndings, Plugin,
+	PluginInfo, PreservePermissions, Shtola, ShtolaError, SourceVerification, TryPlugin,
+	UnclosedFrontmatter,
+};
+use std::path::{Path, PathBuf};
+
+/// A builder for [`Shtola`](struct.Shtola.html), created with
+/// [`Shtola::builder`](struct.Shtola.html#method.builder). Its methods can be
+/// chained, and the configuration is validated once when the pipeline is
+/// built instead of failing halfway into the first build:
+///
+/// ```
+/// use shtola::{Shtola, IR};
+///
+/// let mut s = Shtola::builder()
+///   .source("../fixtures/simple")
+///   .destination("../fixtures/dest_builder")
+///   .clean(true)
+///   .plugin(Box::new(|ir: IR| ir))
+///   .build_pipeline()
+///   .unwrap();
+/// s.build().unwrap();
+/// ```
+///
+/// The methods do the same as the setters of `Shtola` with the same name,
+/// except for those registering plugins, which are named after what they
+/// register.
+pub struct ShtolaBuilder {
+	shtola: Shtola,
+}
+
+impl ShtolaBuilder {
+	pub(crate) fn new() -> ShtolaBuilder {
+		ShtolaBuilder {
+			shtola: Shtola::new(),
+		}
+	}
+
+	/// See [`Shtola::source`](struct.Shtola.html#method.source).
+	pub fn source<T: Into<PathBuf>>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.source(path);
+		self
+	}
+
+	/// See [`Shtola::add_source`](struct.Shtola.html#method.add_source).
+	pub fn add_source<T: Into<PathBuf>>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.add_source(path);
+		self
+	}
+
+	/// See [`Shtola::destination`](struct.Shtola.html#method.destination).
+	pub fn destination<T: Into<PathBuf> + Clone>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.destination(path);
+		self
+	}
+
+	/// See [`Shtola::add_destination`](struct.Shtola.html#method.add_destination).
+	pub fn add_destination<T: Into<PathBuf> + Clone>(
+		mut self,
+		path: T,
+		plugins: Vec<Plugin>,
+	) -> ShtolaBuilder {
+		self.shtola.add_destination(path, plugins);
+		self
+	}
+
+	/// See [`Shtola::ignores`](struct.Shtola.html#method.ignores).
+	pub fn ignores(mut self, mut vec: Vec<String>) -> ShtolaBuilder {
+		self.shtola.ignores(&mut vec);
+		self
+	}
+
+	/// See [`Shtola::includes`](struct.Shtola.html#method.includes).
+	pub fn includes(mut self, mut vec: Vec<String>) -> ShtolaBuilder {
+		self.shtola.includes(&mut vec);
+		self
+	}
+
+	/// See [`Shtola::required`](struct.Shtola.html#method.required).
+	pub fn required(mut self, mut vec: Vec<String>) -> ShtolaBuilder {
+		self.shtola.required(&mut vec);
+		self
+	}
+
+	/// See [`Shtola::clean`](struct.Shtola.html#method.clean).
+	pub fn clean(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.clean(b);
+		self
+	}
+
+	/// See [`Shtola::manifest`](struct.Shtola.html#method.manifest).
+	pub fn manifest<T: Into<PathBuf>>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.manifest(path);
+		self
+	}
+
+	/// See [`Shtola::site`](struct.Shtola.html#method.site).
+	pub fn site(mut self, site: SiteConfig) -> ShtolaBuilder {
+		self.shtola.site(site);
+		self
+	}
+
+	/// See [`Shtola::site_config`](struct.Shtola.html#method.site_config).
+	pub fn site_config<T: Into<PathBuf>>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.site_config(path);
+		self
+	}
+
+	/// See [`Shtola::data_dir`](struct.Shtola.html#method.data_dir).
+	pub fn data_dir<T: Into<PathBuf>>(mut self, path: T) -> ShtolaBuilder {
+		self.shtola.data_dir(path);
+		self
+	}
+
+	/// See [`Shtola::incremental`](struct.Shtola.html#method.incremental).
+	pub fn incremental(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.incremental(b);
+		self
+	}
+
+	/// See [`Shtola::dry_run`](struct.Shtola.html#method.dry_run).
+	pub fn dry_run(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.dry_run(b);
+		self
+	}
+
+	/// See [`Shtola::threads`](struct.Shtola.html#method.threads).
+	pub fn threads(mut self, n: usize) -> ShtolaBuilder {
+		self.shtola.threads(n);
+		self
+	}
+
+	/// See [`Shtola::io_limit`](struct.Shtola.html#method.io_limit).
+	pub fn io_limit(mut self, n: usize) -> ShtolaBuilder {
+		self.shtola.io_limit(n);
+		self
+	}
+
+	/// See [`Shtola::verify_sources`](struct.Shtola.html#method.verify_sources).
+	pub fn verify_sources(mut self, mode: SourceVerification) -> ShtolaBuilder {
+		self.shtola.verify_sources(mode);
+		self
+	}
+
+	/// See [`Shtola::drafts`](struct.Shtola.html#method.drafts).
+	pub fn drafts(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.drafts(b);
+		self
+	}
+
+	/// See [`Shtola::strict`](struct.Shtola.html#method.strict).
+	pub fn strict(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.strict(b);
+		self
+	}
+
+	/// See [`Shtola::unclosed_frontmatter`](struct.Shtola.html#method.unclosed_frontmatter).
+	pub fn unclosed_frontmatter(mut self, mode: UnclosedFrontmatter) -> ShtolaBuilder {
+		self.shtola.unclosed_frontmatter(mode);
+		self
+	}
+
+	/// See [`Shtola::invalid_frontmatter`](struct.Shtola.html#method.invalid_frontmatter).
+	pub fn invalid_frontmatter(mut self, mode: InvalidFrontmatter) -> ShtolaBuilder {
+		self.shtola.invalid_frontmatter(mode);
+		self
+	}
+
+	/// See [`Shtola::multi_document_frontmatter`](struct.Shtola.html#method.multi_document_frontmatter).
+	pub fn multi_document_frontmatter(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.multi_document_frontmatter(b);
+		self
+	}
+
+	/// See [`Shtola::defaults_file`](struct.Shtola.html#method.defaults_file).
+	pub fn defaults_file(mut self, name: Option<&str>) -> ShtolaBuilder {
+		self.shtola.defaults_file(name);
+		self
+	}
+
+	/// See [`Shtola::preserve_permissions`](struct.Shtola.html#method.preserve_permissions).
+	pub fn preserve_permissions(mut self, mode: PreservePermissions) -> ShtolaBuilder {
+		self.shtola.preserve_permissions(mode);
+		self
+	}
+
+	/// See [`Shtola::preserve_metadata`](struct.Shtola.html#method.preserve_metadata).
+	pub fn preserve_metadata(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.preserve_metadata(b);
+		self
+	}
+
+	/// See [`Shtola::line_endings`](struct.Shtola.html#method.line_endings).
+	pub fn line_endings(mut self, mode: LineEndings) -> ShtolaBuilder {
+		self.shtola.line_endings(mode);
+		self
+	}
+
+	/// See [`Shtola::keep_frontmatter`](struct.Shtola.html#method.keep_frontmatter).
+	pub fn keep_frontmatter(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.keep_frontmatter(b);
+		self
+	}
+
+	/// See [`Shtola::build_date`](struct.Shtola.html#method.build_date).
+	pub fn build_date(mut self, date: Date) -> ShtolaBuilder {
+		self.shtola.build_date(date);
+		self
+	}
+
+	/// See [`Shtola::atomic`](struct.Shtola.html#method.atomic).
+	pub fn atomic(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.atomic(b);
+		self
+	}
+
+	/// See [`Shtola::frontmatter`](struct.Shtola.html#method.frontmatter).
+	pub fn frontmatter(mut self, b: bool) -> ShtolaBuilder {
+		self.shtola.frontmatter(b);
+		self
+	}
+
+	/// Registers a plugin, see [`Shtola::register`](struct.Shtola.html#method.register).
+	pub fn plugin(mut self, func: Plugin) -> ShtolaBuilder {
+		self.shtola.register(func);
+		self
+	}
+
+	/// Registers a plugin along with a description, see
+	/// [`Shtola::register_with`](struct.Shtola.html#method.register_with).
+	pub fn plugin_with(mut self, info: PluginInfo, func: Plugin) -> ShtolaBuilder {
+		self.shtola.register_with(info, func);
+		self
+	}
+
+	/// Registers a plugin that can fail, see
+	/// [`Shtola::register_fallible`](struct.Shtola.html#method.register_fallible).
+	pub fn fallible_plugin(mut self, func: TryPlugin) -> ShtolaBuilder {
+		self.shtola.register_fallible(func);
+		self
+	}
+
+	/// Registers a plugin that can fail along with a description, see
+	/// [`Shtola::register_fallible_with`](struct.Shtola.html#method.register_fallible_with).
+	pub fn fallible_plugin_with(mut self, info: PluginInfo, func: TryPlugin) -> ShtolaBuilder {
+		self.shtola.register_fallible_with(info, func);
+		self
+	}
+
+	/// Registers a file-local transform, see
+	/// [`Shtola::register_file`](struct.Shtola.html#method.register_file).
+	pub fn file_plugin(mut self, func: FileMapPlugin) -> ShtolaBuilder {
+		self.shtola.register_file(func);
+		self
+	}
+
+	/// Registers a checker, see [`Shtola::check`](struct.Shtola.html#method.check).
+	pub fn checker(mut self, checker: Checker) -> ShtolaBuilder {
+		self.shtola.check(checker);
+		self
+	}
+
+	/// Validates the configuration and returns the configured `Shtola`. Fails
+	/// if a source directory doesn't exist, if a glob is invalid, or
+	/// if a destination directory is inside a source directory without being
+	/// ignored, which would make each build read the output of the previous
+	/// one.
+	pub fn build_pipeline(self) -> Result<Shtola, ShtolaError> {
+		let config = &self.shtola.ir.config;
+		let ignores = glob_set(&config.ignores)?;
+		glob_set(&config.includes)?;
+		for unresolved in config.all_sources() {
+			let source = resolve(unresolved, false)?;
+			let destinations = std::iter::once(&config.destination)
+				.chain(self.shtola.targets.iter().map(|t| &t.path));
+			for destination in destinations {
+				let inside = match normalize(destination)?.strip_prefix(&source) {
+					Ok(relative) => !relative.ancestors().any(|a| ignores.is_match(a)),
+					Err(_) => false,
+				};
+				if inside {
+					return Err(ShtolaError::Config(format!(
+						"The destination directory {:?} is inside the source directory {:?}",
+						destination, unresolved
+					)));
+				}
+			}
+		}
+		Ok(self.shtola)
+	}
+}
+
+// Makes a path absolute like `absolute`, also resolving symlinks and `..`
+// for paths that don't exist yet through their closest existing ancestor.
+fn normalize(path: &Path) -> Result<PathBuf, ShtolaError> {
+	let path = absolute(path)?;
+	let mut existing = path.as_path();
+	let mut rest = Vec::new();
+	while !existing.exists() {
+		match (existing.parent(), existing.file_name()) {
+			(Some(parent), Some(name)) => {
+				rest.push(name);
+				existing = parent;
+			}
+			_ => break,
+		}
+	}
+	let mut result = absolute(existing)?;
+	for name in rest.into_iter().rev() {
+		result.push(name);
+	}
+	Ok(result)
+}
@@ -0,0 +1,89 @@
+//! Running external programs, for plugins that hand files to toolchains
+//! Shtola doesn't embed, like PDF renderers and bundlers:
+//!
+//! ```no_run
+//! use shtola::command::{run, temp_dir};
+//! use std::fs;
+//!
+//! let dir = temp_dir("shtola-pdf-").unwrap();
+//! fs::write(dir.path().join("index.html"), "<h1>Hi</h1>").unwrap();
+//! let args = vec!["weasyprint".into(), "index.html".into(), "index.pdf".into()];
+//! match run("PDF renderer", &args, Some(dir.path()), None).unwrap() {
+//!   Ok(_) => println!("{:?}", fs::read(dir.path().join("index.pdf"))),
+//!   Err(message) => eprintln!("Unable to render PDF: {}", message),
+//! }
+//! ```
+
+use crate::PluginError;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+pub use tempfile::TempDir;
+
+/// Creates a temporary directory for the files handed to a program, with a
+/// random name starting with `prefix` that other users can't guess or read.
+/// It's removed with everything in it when dropped.
+pub fn temp_dir(prefix: &str) -> Result<TempDir, PluginError> {
+	tempfile::Builder::new()
+		.prefix(prefix)
+		.tempdir()
+		.map_err(|e| PluginError::new(&format!("Unable to create a temporary directory: {}", e)))
+}
+
+/// Runs a program, given as the program followed by its arguments, in `dir`
+/// or the current directory. `input` is written to its standard input.
+/// Returns what the program wrote to its standard output.
+///
+/// Fails with an error naming the program as `what` if it isn't installed,
+/// which should fail the build. If it can't be run otherwise or exits with an
+/// error, the inner error holds the reason, usually its standard error, which
+/// should only fail the file it was run for.
+pub fn run(
+	what: &str,
+	args: &[String],
+	dir: Option<&Path>,
+	input: Option<&[u8]>,
+) -> Result<Result<Vec<u8>, String>, PluginError> {
+	let not_installed = || PluginError::new(&format!("{} {:?} isn't installed", what, args[0]));
+	let mut command = Command::new(&args[0]);
+	command
+		.args(&args[1..])
+		.stdin(match input {
+			Some(_) => Stdio::piped(),
+			None => Stdio::null(),
+		})
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped());
+	if let Some(dir) = dir {
+		command.current_dir(dir);
+	}
+	let mut child = match command.spawn() {
+		Err(e) if e.kind() == ErrorKind::NotFound => return Err(not_installed()),
+		Err(e) => return Ok(Err(e.to_string())),
+		Ok(child) => child,
+	};
+	// Writing on another thread, so a program writing a lot of output before
+	// reading all its input doesn't block.
+	let writer = match (child.stdin.take(), input) {
+		(Some(mut stdin), Some(input)) => {
+			let input = input.to_vec();
+			Some(thread::spawn(move || stdin.write_all(&input)))
+		}
+		_ => None,
+	};
+	let output = child.wait_with_output();
+	let written = writer.map(|writer| writer.join().unwrap());
+	Ok(match output {
+		Err(e) => Err(e.to_string()),
+		Ok(out) if !out.status.success() => {
+			Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+		}
+		// A program exiting without reading everything is fine if it succeeded.
+		Ok(out) => match written {
+			Some(Err(e)) if e.kind() != ErrorKind::BrokenPipe => Err(e.to_string()),
+			_ => Ok(out.stdout),
+		},
+	})
+}
@@ -0,0 +1,226 @@
+//! The data rendering plugins pass to templates and shortcodes. Official
+//! plugins build a [`PageContext`](struct.PageContext.html) for every page they
+//! render, so templates see the same data no matter which plugin renders them:
+//!
+//! ```json
+//! {
+//!   "page": {
+//!     "title": "Hello",
+//!     "path": "blog/hello.html",
+//!     "url": "/blog/hello.html",
+//!     "root": "../",
+//!     "content": "<p>Hello!</p>"
+//!   },
+//!   "site": { "title": "My site" },
+//!   "collections": {
+//!     "blog": [{ "title": "Hello", "path": "blog/hello.html", "url": "/blog/hello.html", "root": "../" }]
+//!   }
+//! }
+//! ```
+//!
+//! `page` holds the frontmatter of the page along with its path, URL, the
+//! relative path to the site root and its content. If plugins attached
+//! [metadata](../struct.ShFile.html#structfield.metadata) to the page, it's
+//! under `page.metadata`. `site` holds the
+//! [site settings](../site/struct.SiteConfig.html) along with the global
+//! metadata, which takes precedence. `collections` groups pages by their
+//! `collection` frontmatter key, which may be a string or a list of strings.
+//! Collection entries hold everything `page` does except the content and are
+//! sorted by path.
+//!
+//! Only text files have a context. `site` and `collections` are built once
+//! per build and shared between all contexts, so renderers should pass them
+//! to their templates once as well, instead of copying them into every
+//! page's data.
+
+use crate::json::{Map, Value};
+use crate::mime::Kind;
+use crate::{ShFile, IR};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The render context of a single page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageContext {
+	/// The path of the page.
+	pub path: PathBuf,
+	/// The frontmatter of the page, along with `path`, `url`, `root` and
+	/// `content`.
+	pub page: Value,
+	/// The site settings and global metadata. Shared between all pages of a
+	/// build.
+	pub site: Arc<Value>,
+	/// The collections. Shared between all pages of a build.
+	pub collections: Arc<Value>,
+}
+
+impl PageContext {
+	/// Creates the context of the file at the given path, or `None` if there is
+	/// no such file. Use [`PageContext::all`](#method.all) when rendering more
+	/// than one page, since it only builds the shared parts once.
+	pub fn new(ir: &IR, path: &Path) -> Option<PageContext> {
+		let file = ir.files.get(path)?;
+		Some(PageContext::with_shared(
+			path,
+			file,
+			Arc::new(site(ir)),
+			Arc::new(collections(ir)),
+		))
+	}
+
+	/// Creates the contexts of all text files. Binary files like images are
+	/// left out, as templates can't render them.
+	pub fn all(ir: &IR) -> BTreeMap<PathBuf, PageContext> {
+		let site = Arc::new(site(ir));
+		let collections = Arc::new(collections(ir));
+		ir.files
+			.iter()
+			.filter(|(_, file)| file.kind == Kind::Text)
+			.map(|(path, file)| {
+				let context =
+					PageContext::with_shared(path, file, site.clone(), collections.clone());
+				(path.clone(), context)
+			})
+			.collect()
+	}
+
+	fn with_shared(
+		path: &Path,
+		file: &ShFile,
+		site: Arc<Value>,
+		collections: Arc<Value>,
+	) -> PageContext {
+		let mut page = summary(path, file);
+		page.insert(
+			"content".into(),
+			String::from_utf8_lossy(&file.content).into(),
+		);
+		PageContext {
+			path: path.to_path_buf(),
+			page: Value::Object(page),
+			site,
+			collections,
+		}
+	}
+
+	/// The URL of the page. `index.html` files get the URL of their directory.
+	pub fn url(&self) -> String {
+		url(&self.path)
+	}
+
+	/// Turns a site-absolute URL like `/css/style.css` into one relative to
+	/// the page, so sites also work when they aren't served from the root of a
+	/// domain. Other URLs are returned unchanged.
+	pub fn relative_url(&self, target: &str) -> String {
+		match target.strip_prefix('/') {
+			Some(target) if !target.starts_with('/') => format!("{}{}", root(&self.path), target),
+			_ => target.to_string(),
+		}
+	}
+
+	/// Converts the context into the JSON object handed to templates. This
+	/// copies `site` and `collections`, which is costly for large sites when
+	/// done for every page. Serializing the context writes them without
+	/// copying.
+	pub fn to_json(&self) -> Value {
+		let mut map = Map::new();
+		map.insert("page".into(), self.page.clone());
+		map.insert("site".into(), (*self.site).clone());
+		map.insert("collections".into(), (*self.collections).clone());
+		Value::Object(map)
+	}
+}
+
+impl Serialize for PageContext {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut map = serializer.serialize_map(Some(3))?;
+		map.serialize_entry("page", &self.page)?;
+		map.serialize_entry("site", &*self.site)?;
+		map.serialize_entry("collections", &*self.collections)?;
+		map.end()
+	}
+}
+
+fn site(ir: &IR) -> Value {
+	let mut map = match serde_json::to_value(&ir.site) {
+		Ok(Value::Object(map)) => map,
+		_ => Map::new(),
+	};
+	let mut keys: Vec<&String> = ir.metadata.keys().collect();
+	keys.sort();
+	for key in keys {
+		map.insert(key.clone(), ir.metadata[key].clone());
+	}
+	Value::Object(map)
+}
+
+fn collections(ir: &IR) -> Value {
+	let mut collections: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+	for (path, file) in ir.query().has("collection").run() {
+		let names = match &file.frontmatter["collection"] {
+			Value::String(name) => vec![name.as_str()],
+			Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+			_ => continue,
+		};
+		for name in names {
+			collections
+				.entry(name.to_string())
+				.or_default()
+				.push(Value::Object(summary(path, file)));
+		}
+	}
+	Value::Object(
+		collections
+			.into_iter()
+			.map(|(name, pages)| (name, Value::Array(pages)))
+			.collect(),
+	)
+}
+
+/// Returns the entry of a page as it appears in collections, which holds
+/// everything the `page` of its context does except the content. Plugins
+/// listing pages, like the `query` function of templates, use it so lists
+/// look the same everywhere.
+pub fn entry(path: &Path, file: &ShFile) -> Value {
+	Value::Object(summary(path, file))
+}
+
+fn summary(path: &Path, file: &ShFile) -> Map<String, Value> {
+	let mut map = match &file.frontmatter {
+		Value::Object(map) => map.clone(),
+		_ => Map::new(),
+	};
+	map.insert(
+		"path".into(),
+		path.to_string_lossy().replace('\\', "/").into(),
+	);
+	map.insert("url".into(), url(path).into());
+	map.insert("root".into(), root(path).into());
+	if !file.metadata.is_empty() {
+		let mut keys: Vec<&String> = file.metadata.keys().collect();
+		keys.sort();
+		let metadata = keys
+			.into_iter()
+			.map(|key| (key.clone(), file.metadata[key].clone()))
+			.collect();
+		map.insert("metadata".into(), Value::Object(metadata));
+	}
+	map
+}
+
+/// Returns the URL of the file at the given path, as it appears in the
+/// context. `index.html` files get the URL of their directory.
+pub fn url(path: &Path) -> String {
+	let url = path.to_string_lossy().replace('\\', "/");
+	match url.strip_suffix("index.html") {
+		Some(dir) if path.file_name() == Some("index.html".as_ref()) => format!("/{}", dir),
+		_ => format!("/{}", url),
+	}
+}
+
+fn root(path: &Path) -> String {
+	let depth = path.components().count().saturating_sub(1);
+	"../".repeat(depth)
+}
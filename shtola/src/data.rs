@@ -0,0 +1,64 @@
+//! Data files, like navigation menus and author lists, loaded into
+//! [`IR::metadata`](../struct.IR.html#structfield.metadata) before any plugin
+//! runs. Set the data directory with
+//! [`Shtola::data_dir`](../struct.Shtola.html#method.data_dir), and every
+//! YAML, JSON or TOML file in it becomes a metadata key named after the file.
+//! Files in subdirectories are nested under the name of their directory, so
+//! `_data/authors/marisa.yml` ends up in `authors.marisa`.
+
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Reads all data files in a directory into a map keyed by their names
+/// without extension. Files with other extensions are skipped. A directory
+/// that doesn't exist results in an empty map.
+///
+/// ```
+/// use shtola::data;
+///
+/// let data = data::read("../fixtures/data/_data").unwrap();
+/// assert_eq!(data["menu"][0]["title"], "Home");
+/// ```
+pub fn read<P: AsRef<Path>>(dir: P) -> Result<Map<String, Value>, Error> {
+	let dir = dir.as_ref();
+	let mut result = Map::new();
+	if !dir.is_dir() {
+		return Ok(result);
+	}
+	let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+	entries.sort_by_key(|e| e.file_name());
+	for entry in entries {
+		let path = entry.path();
+		if path.is_dir() {
+			let name = entry.file_name().to_string_lossy().into_owned();
+			result.insert(name, Value::Object(read(&path)?));
+			continue;
+		}
+		let name = match path.file_stem() {
+			Some(name) => name.to_string_lossy().into_owned(),
+			None => continue,
+		};
+		let ext = path
+			.extension()
+			.and_then(|e| e.to_str())
+			.unwrap_or_default();
+		let text = match ext {
+			"yml" | "yaml" | "json" | "toml" => fs::read_to_string(&path)?,
+			_ => continue,
+		};
+		let value = match ext {
+			"toml" => toml::from_str(&text).map_err(|e| e.to_string()),
+			_ => serde_yaml::from_str(&text).map_err(|e| e.to_string()),
+		};
+		let value = value.map_err(|e| {
+			Error::new(
+				ErrorKind::InvalidData,
+				format!("Unable to read data file {:?}: {}", path, e),
+			)
+		})?;
+		result.insert(name, value);
+	}
+	Ok(result)
+}
@@ -0,0 +1,33 @@
+//! Decoding of text escaped in URLs and HTML, shared by the server and the
+//! plugins reading links and text from pages.
+
+/// Decodes percent-encoded bytes, like `%20`, leaving invalid sequences as
+/// they are. Bytes that don't form UTF-8 are replaced.
+pub fn percent(text: &str) -> String {
+	let bytes = text.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = bytes
+			.get(i + 1..i + 3)
+			.and_then(|h| std::str::from_utf8(h).ok())
+			.and_then(|h| u8::from_str_radix(h, 16).ok());
+		match (bytes[i], hex) {
+			(b'%', Some(byte)) => {
+				decoded.push(byte);
+				i += 3;
+			}
+			(byte, _) => {
+				decoded.push(byte);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decodes the character references of HTML text or attribute values, like
+/// `&amp;`, `&nbsp;` or `&#x2f;`.
+pub fn entities(text: &str) -> String {
+	html_escape::decode_html_entities(text).into_owned()
+}
@@ -1,25 +1,601 @@
-use serde_json::{json, Value};
+//! Frontmatter lexing and parsing. Besides what Shtola uses internally, this
+//! module provides [`split`](fn.split.html) for plugins that need to take
+//! documents apart themselves, for example to extract excerpts, and getters
+//! like [`get_bool`](fn.get_bool.html) that read frontmatter values leniently,
+//! so `draft: "true"` and `draft: true` mean the same in every plugin.
+
+use crate::has_extension;
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Deserializer, Value};
 use serde_yaml::from_str;
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The markers [`split`](fn.split.html) uses to find the parts of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Separators {
+	/// The line opening and closing YAML frontmatter. Default is `---`.
+	pub frontmatter: String,
+	/// The marker separating the excerpt from the rest of the content. Default
+	/// is `<!-- more -->`.
+	pub excerpt: String,
+}
+
+impl Default for Separators {
+	fn default() -> Self {
+		Separators {
+			frontmatter: "---".into(),
+			excerpt: "<!-- more -->".into(),
+		}
+	}
+}
+
+/// A document split into its parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+	/// The raw, unparsed frontmatter. Empty if there is none.
+	pub frontmatter: String,
+	/// The content before the excerpt separator, if the separator was found.
+	pub excerpt: Option<String>,
+	/// The complete content without frontmatter and excerpt separator.
+	pub body: String,
+}
+
+/// Splits a document into frontmatter, excerpt and body using the given
+/// separators. Returns `None` if the frontmatter is never closed.
+///
+/// ```
+/// use shtola::frontmatter::{split, Separators};
+///
+/// let doc = split("---\ntitle: Hi\n---\nShort.\n<!-- more -->\nLong.", &Separators::default());
+/// let doc = doc.unwrap();
+/// assert_eq!(doc.frontmatter, "title: Hi");
+/// assert_eq!(doc.excerpt.unwrap(), "Short.");
+/// assert_eq!(doc.body, "Short.\n\nLong.");
+/// ```
+pub fn split(text: &str, separators: &Separators) -> Option<Document> {
+	let (frontmatter, content) = split_frontmatter(text, &separators.frontmatter, false)?;
+	let marker = &separators.excerpt;
+	let (excerpt, body) = match content.find(marker.as_str()) {
+		Some(i) if !marker.is_empty() => (
+			Some(content[..i].trim().to_string()),
+			format!("{}{}", &content[..i], &content[i + marker.len()..]),
+		),
+		_ => (None, content),
+	};
+	Some(Document {
+		frontmatter,
+		excerpt,
+		body,
+	})
+}
+
+/// How many bytes from the start of a file [`is_text`](fn.is_text.html)
+/// looks at.
+pub const TEXT_SNIFF_LENGTH: usize = 8192;
+
+/// Guesses whether file content is text that may contain frontmatter. Content
+/// is considered binary if it contains a NUL byte within the first
+/// [`TEXT_SNIFF_LENGTH`](constant.TEXT_SNIFF_LENGTH.html) bytes or isn't valid
+/// UTF-8, so files like PDFs that happen to start with dashes are never
+/// lexed.
+///
+/// ```
+/// use shtola::frontmatter::is_text;
+///
+/// assert!(is_text(b"---\ntitle: Hello\n---\n"));
+/// assert!(!is_text(b"---\n\0\x01binary"));
+/// ```
+pub fn is_text(content: &[u8]) -> bool {
+	let sniffed = &content[..content.len().min(TEXT_SNIFF_LENGTH)];
+	!sniffed.contains(&0) && std::str::from_utf8(content).is_ok()
+}
 
-pub fn lexer(text: &str) -> (String, String) {
-	if text.starts_with("---\n") {
-		let slice_after_marker = &text[4..];
-		let marker_end = slice_after_marker.find("---\n").unwrap();
-		let yaml_slice = &text[4..marker_end + 4];
-		let content_slice = &text[marker_end + 2 * 4..];
-		(
-			yaml_slice.trim().to_string(),
-			content_slice.trim().to_string(),
-		)
-	} else {
-		(String::new(), text.to_string())
+/// Extensions of files that are never lexed for frontmatter, whatever their
+/// content looks like.
+pub const BINARY_EXTENSIONS: &[&str] = &[
+	"png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "bmp", "tif", "tiff", "woff", "woff2",
+	"ttf", "otf", "eot", "pdf", "zip", "gz", "mp3", "mp4", "webm", "ogg", "wav", "wasm",
+];
+
+/// Whether a path has one of the [`BINARY_EXTENSIONS`](constant.BINARY_EXTENSIONS.html),
+/// ignoring case. Checking the extension first spares
+/// [`is_text`](fn.is_text.html) from validating large assets.
+///
+/// ```
+/// use shtola::frontmatter::has_binary_extension;
+/// use std::path::Path;
+///
+/// assert!(has_binary_extension(Path::new("img/Logo.PNG")));
+/// assert!(!has_binary_extension(Path::new("index.md")));
+/// ```
+pub fn has_binary_extension(path: &Path) -> bool {
+	has_extension(path, BINARY_EXTENSIONS)
+}
+
+/// Extensions of data files, which may start with a JSON object that isn't
+/// frontmatter, like the first record of a JSON Lines file. Unfenced JSON
+/// frontmatter isn't split off them, only frontmatter between `---` or
+/// `---json` and `---` lines is.
+pub const DATA_EXTENSIONS: &[&str] = &["json", "jsonl", "ndjson", "geojson", "webmanifest"];
+
+/// Whether a path has one of the [`DATA_EXTENSIONS`](constant.DATA_EXTENSIONS.html),
+/// ignoring case.
+///
+/// ```
+/// use shtola::frontmatter::has_data_extension;
+/// use std::path::Path;
+///
+/// assert!(has_data_extension(Path::new("events.JSONL")));
+/// assert!(!has_data_extension(Path::new("index.md")));
+/// ```
+pub fn has_data_extension(path: &Path) -> bool {
+	has_extension(path, DATA_EXTENSIONS)
+}
+
+/// How the frontmatter of a document is written, so it can be written back
+/// the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+	/// YAML between two `---` lines.
+	#[default]
+	Yaml,
+	/// A JSON object at the very start of the document.
+	Json,
+	/// JSON between a `---json` and a `---` line.
+	FencedJson,
+}
+
+impl Format {
+	/// The format of a document's frontmatter, given the document and the
+	/// frontmatter [`lexer`](fn.lexer.html) split off.
+	pub fn of(text: &str, matter: &str) -> Format {
+		if text.starts_with("---json\n") || text.starts_with("---json\r\n") {
+			Format::FencedJson
+		} else if matter.starts_with('{') {
+			Format::Json
+		} else {
+			Format::Yaml
+		}
 	}
 }
 
-pub fn to_json(matter: &str) -> Value {
+/// Splits a document into its frontmatter and its content. Frontmatter is
+/// either YAML between two `---` lines, JSON between a `---json` and a `---`
+/// line, or a JSON object at the very start of the document. Documents that
+/// start with none of these have no frontmatter. Use
+/// [`has_data_extension`](fn.has_data_extension.html) to leave JSON data
+/// files alone, which start with an object that isn't frontmatter. Returns `None` if the
+/// document starts with a `---` or `---json` line, but the frontmatter is
+/// never closed.
+///
+/// ```
+/// use shtola::frontmatter::lexer;
+///
+/// let (matter, content) = lexer("---json\n{\"id\": 9007199254740993}\n---\nHi").unwrap();
+/// assert_eq!(matter, "{\"id\": 9007199254740993}");
+/// assert_eq!(content, "Hi");
+/// ```
+pub fn lexer(text: &str) -> Option<(String, String)> {
+	split_frontmatter(text, "---", false)
+}
+
+/// Like [`lexer`](fn.lexer.html), but also consumes further YAML documents
+/// directly following the first one, as long as each of them is a mapping
+/// closed by another `---` line. The documents are kept separated by `---`
+/// lines, which [`to_json`](fn.to_json.html) merges into one object.
+///
+/// Note that a paragraph such as `Note: this` followed by a horizontal rule
+/// is a valid YAML mapping, so this should only be used for content that is
+/// known to use multiple frontmatter documents.
+pub fn multi_document_lexer(text: &str) -> Option<(String, String)> {
+	split_frontmatter(text, "---", true)
+}
+
+// Whether the YAML frontmatter of a document is directly followed by more
+// documents the multi-document lexer would split off, which the single
+// document lexer leaves at the start of the content. Content separated from
+// the frontmatter by a blank line isn't taken for another document.
+pub(crate) fn is_multi_document(text: &str) -> bool {
+	match (locate(text, "---", false), locate(text, "---", true)) {
+		(Some((_, single)), Some((_, multi))) => {
+			single > 0 && multi > single && !text[single..].starts_with(['\n', '\r'])
+		}
+		_ => false,
+	}
+}
+
+fn split_frontmatter(text: &str, marker: &str, multi: bool) -> Option<(String, String)> {
+	let (matter, offset) = locate(text, marker, multi)?;
+	if offset == 0 {
+		return Some((matter, text.to_string()));
+	}
+	Some((matter, text[offset..].trim().to_string()))
+}
+
+// Like `lexer`, but returns the byte offset the content starts at instead of
+// the trimmed content, so the content can be kept exactly as it is when
+// rewriting the frontmatter. The offset is 0 for documents without
+// frontmatter.
+pub(crate) fn locate_frontmatter(text: &str, multi: bool) -> Option<(String, usize)> {
+	locate(text, "---", multi)
+}
+
+fn locate(text: &str, marker: &str, multi: bool) -> Option<(String, usize)> {
+	if text.starts_with('{') {
+		return Some(json_lexer(text));
+	}
+	// JSON frontmatter fenced like YAML frontmatter, for documents where the
+	// content itself starts with a `{`.
+	let json_fence = format!("{}json", marker);
+	if let Some(rest) = text
+		.strip_prefix(json_fence.as_str())
+		.and_then(strip_line_break)
+	{
+		let (start, end) = find_marker(rest, marker)?;
+		return Some((
+			rest[..start].trim().to_string(),
+			text.len() - rest.len() + end,
+		));
+	}
+	let rest = match text.strip_prefix(marker).and_then(strip_line_break) {
+		Some(rest) => rest,
+		None => return Some((String::new(), 0)),
+	};
+	let (start, mut end) = find_marker(rest, marker)?;
+	let mut documents = vec![rest[..start].trim()];
+	if multi {
+		while let Some((next_start, next_end)) = find_marker(&rest[end..], marker) {
+			let next = &rest[end..end + next_start];
+			if !is_mapping(next) {
+				break;
+			}
+			documents.push(next.trim());
+			end += next_end;
+		}
+	}
+	let separator = format!("\n{}\n", marker);
+	Some((documents.join(&separator), text.len() - rest.len() + end))
+}
+
+// Strips the line break ending an opening marker, which is `\r\n` in files
+// written on Windows.
+fn strip_line_break(text: &str) -> Option<&str> {
+	text.strip_prefix("\r\n")
+		.or_else(|| text.strip_prefix('\n'))
+}
+
+// Returns the start and end offsets of the first line consisting of the marker.
+fn find_marker(text: &str, marker: &str) -> Option<(usize, usize)> {
+	let mut offset = 0;
+	for line in text.split_inclusive('\n') {
+		if line.trim_end() == marker {
+			return Some((offset, offset + line.len()));
+		}
+		offset += line.len();
+	}
+	None
+}
+
+fn is_mapping(yaml: &str) -> bool {
+	!yaml.trim().is_empty() && from_str::<serde_yaml::Mapping>(yaml).is_ok()
+}
+
+// A document that consists of nothing but a JSON object is a data file, so
+// JSON frontmatter is only split off if there's content following it.
+fn json_lexer(text: &str) -> (String, usize) {
+	let mut stream = Deserializer::from_str(text).into_iter::<Value>();
+	if let Some(Ok(Value::Object(_))) = stream.next() {
+		let offset = stream.byte_offset();
+		if !text[offset..].trim().is_empty() {
+			return (text[..offset].to_string(), offset);
+		}
+	}
+	(String::new(), 0)
+}
+
+/// Parses lexed frontmatter into a JSON value. Empty frontmatter results in
+/// `null`. YAML frontmatter consisting of multiple documents separated by
+/// `---` lines is merged into one object, with later documents overriding
+/// keys of earlier ones.
+#[allow(clippy::len_zero)]
+pub fn to_json(matter: &str) -> Result<Value, FrontmatterError> {
 	if matter.len() == 0 {
-		return json!(null);
+		return Ok(json!(null));
+	}
+	if matter.starts_with('{') {
+		if let Ok(json) = serde_json::from_str(matter) {
+			return Ok(json);
+		}
+	}
+	let documents: Vec<&str> = matter.split("\n---\n").collect();
+	if documents.len() == 1 {
+		return from_str(matter).map_err(|e| yaml_error(&e, 0));
+	}
+	let mut merged = serde_json::Map::new();
+	let mut offset = 0;
+	for (i, document) in documents.iter().enumerate() {
+		match from_str(document).map_err(|e| yaml_error(&e, offset))? {
+			Value::Object(map) => merged.extend(map),
+			_ => {
+				return Err(FrontmatterError {
+					message: format!(
+						"Frontmatter document {} of {} is not a mapping",
+						i + 1,
+						documents.len()
+					),
+					line: Some(offset + 1),
+					column: None,
+				})
+			}
+		}
+		// Skip the document and the separator following it.
+		offset += document.lines().count() + 1;
+	}
+	Ok(Value::Object(merged))
+}
+
+/// Frontmatter that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontmatterError {
+	/// What went wrong.
+	pub message: String,
+	/// The line of the error within the frontmatter, starting at 1.
+	pub line: Option<usize>,
+	/// The column of the error, starting at 1.
+	pub column: Option<usize>,
+}
+
+impl fmt::Display for FrontmatterError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+fn yaml_error(error: &serde_yaml::Error, line_offset: usize) -> FrontmatterError {
+	let location = error.location();
+	FrontmatterError {
+		message: error.to_string(),
+		line: location.as_ref().map(|l| l.line() + line_offset),
+		column: location.as_ref().map(|l| l.column()),
+	}
+}
+
+/// A frontmatter value that has the wrong type for what it is used for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+	/// The key of the value, with nested keys separated by dots.
+	pub key: String,
+	/// What was expected instead.
+	pub message: String,
+}
+
+impl fmt::Display for FieldError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Frontmatter key {:?} {}", self.key, self.message)
+	}
+}
+
+/// A calendar date, as returned by [`get_date`](fn.get_date.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+	pub year: i32,
+	pub month: u32,
+	pub day: u32,
+}
+
+impl Date {
+	/// Parses a string starting with a date like `2020-01-31`, the way
+	/// [`get_date`](fn.get_date.html) does.
+	///
+	/// ```
+	/// use shtola::frontmatter::Date;
+	///
+	/// let date = Date::parse("2020-01-31T12:00:00Z").unwrap();
+	/// assert_eq!((date.year, date.month, date.day), (2020, 1, 31));
+	/// assert!(Date::parse("2020-02-30").is_none());
+	/// ```
+	pub fn parse(text: &str) -> Option<Date> {
+		parse_date(text)
+	}
+
+	/// The number of days since the Unix epoch, 1970-01-01. Earlier dates are
+	/// negative.
+	///
+	/// ```
+	/// use shtola::frontmatter::Date;
+	///
+	/// let date = Date::parse("2020-01-31").unwrap();
+	/// assert_eq!(date.days_since_epoch(), 18292);
+	/// assert_eq!(Date::from_days_since_epoch(18292), date);
+	/// ```
+	pub fn days_since_epoch(self) -> i64 {
+		// Howard Hinnant's algorithm for converting civil dates to days.
+		let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+		let era = year.div_euclid(400);
+		let year_of_era = year.rem_euclid(400);
+		let month = self.month as i64;
+		let day_of_year =
+			(153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+		let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+		era * 146_097 + day_of_era - 719_468
+	}
+
+	/// The date a number of days after the Unix epoch, see
+	/// [`Date::days_since_epoch`](#method.days_since_epoch).
+	pub fn from_days_since_epoch(days: i64) -> Date {
+		let days = days + 719_468;
+		let era = days.div_euclid(146_097);
+		let day_of_era = days.rem_euclid(146_097);
+		let year_of_era =
+			(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let mp = (5 * day_of_year + 2) / 153;
+		let day = day_of_year - (153 * mp + 2) / 5 + 1;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 };
+		let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+		Date {
+			year: year as i32,
+			month: month as u32,
+			day: day as u32,
+		}
+	}
+
+	/// The date of a point in time, in UTC.
+	pub fn from_system_time(time: SystemTime) -> Date {
+		let days = match time.duration_since(UNIX_EPOCH) {
+			Ok(d) => (d.as_secs() / 86400) as i64,
+			Err(e) => -(e.duration().as_secs().div_ceil(86400) as i64),
+		};
+		Date::from_days_since_epoch(days)
+	}
+}
+
+impl Serialize for Date {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for Date {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Date, D::Error> {
+		let text = String::deserialize(deserializer)?;
+		Date::parse(&text).ok_or_else(|| D::Error::custom(format!("{:?} isn't a date", text)))
+	}
+}
+
+impl fmt::Display for Date {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+	}
+}
+
+/// Looks up a value. Nested keys are separated by dots, like `author.name`,
+/// unless the frontmatter has a key containing the dot itself. `null` values
+/// count as missing.
+pub fn get<'a>(frontmatter: &'a Value, key: &str) -> Option<&'a Value> {
+	frontmatter
+		.get(key)
+		.or_else(|| {
+			key.split('.')
+				.try_fold(frontmatter, |value, part| value.get(part))
+		})
+		.filter(|value| !value.is_null())
+}
+
+/// Gets a value as a string. Numbers and booleans are converted into strings.
+/// Missing and `null` values result in `None`.
+///
+/// ```
+/// use shtola::frontmatter::get_str;
+/// use shtola::json::json;
+///
+/// let matter = json!({ "version": 2, "author": { "name": "Marisa" } });
+/// assert_eq!(get_str(&matter, "version").unwrap().unwrap(), "2");
+/// assert_eq!(get_str(&matter, "author.name").unwrap().unwrap(), "Marisa");
+/// assert_eq!(
+///     get_str(&matter, "author").unwrap_err().to_string(),
+///     "Frontmatter key \"author\" should be a string, but is an object"
+/// );
+/// ```
+pub fn get_str(frontmatter: &Value, key: &str) -> Result<Option<String>, FieldError> {
+	match get(frontmatter, key) {
+		None => Ok(None),
+		Some(Value::String(s)) => Ok(Some(s.clone())),
+		Some(Value::Number(n)) => Ok(Some(n.to_string())),
+		Some(Value::Bool(b)) => Ok(Some(b.to_string())),
+		Some(value) => Err(type_error(key, "a string", value)),
+	}
+}
+
+/// Gets a value as a boolean. The strings `true`, `yes`, `on` and `1` and the
+/// number 1 are true, `false`, `no`, `off`, `0` and the number 0 are false,
+/// ignoring case. Missing and `null` values result in `None`.
+pub fn get_bool(frontmatter: &Value, key: &str) -> Result<Option<bool>, FieldError> {
+	let value = match get(frontmatter, key) {
+		None => return Ok(None),
+		Some(value) => value,
+	};
+	let parsed = match value {
+		Value::Bool(b) => Some(*b),
+		Value::Number(n) => match n.as_u64() {
+			Some(0) => Some(false),
+			Some(1) => Some(true),
+			_ => None,
+		},
+		Value::String(s) => match s.trim().to_lowercase().as_str() {
+			"true" | "yes" | "on" | "1" => Some(true),
+			"false" | "no" | "off" | "0" => Some(false),
+			_ => None,
+		},
+		_ => None,
+	};
+	parsed
+		.map(Some)
+		.ok_or_else(|| type_error(key, "a boolean", value))
+}
+
+/// Gets a value as a date. The value has to be a string starting with a date
+/// like `2020-01-31`. A time following the date, like in
+/// `2020-01-31T12:00:00Z`, is ignored. Missing and `null` values result in
+/// `None`.
+pub fn get_date(frontmatter: &Value, key: &str) -> Result<Option<Date>, FieldError> {
+	let value = match get(frontmatter, key) {
+		None => return Ok(None),
+		Some(value) => value,
+	};
+	value
+		.as_str()
+		.and_then(parse_date)
+		.map(Some)
+		.ok_or_else(|| type_error(key, "a date like 2020-01-31", value))
+}
+
+/// Gets a value as a list. A single value that isn't a list is turned into a
+/// list holding just that value, so `tags: rust` works like `tags: [rust]`.
+/// Missing and `null` values result in an empty list.
+pub fn get_vec(frontmatter: &Value, key: &str) -> Result<Vec<Value>, FieldError> {
+	match get(frontmatter, key) {
+		None => Ok(Vec::new()),
+		Some(Value::Array(values)) => Ok(values.clone()),
+		Some(value @ Value::Object(_)) => Err(type_error(key, "a list", value)),
+		Some(value) => Ok(vec![value.clone()]),
+	}
+}
+
+fn parse_date(text: &str) -> Option<Date> {
+	let date = text.trim().split(['T', ' ']).next()?;
+	let mut parts = date.splitn(3, '-');
+	let year: i32 = parts.next()?.parse().ok()?;
+	let month: u32 = parts.next()?.parse().ok()?;
+	let day: u32 = parts.next()?.parse().ok()?;
+	let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+	let days = match month {
+		1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+		4 | 6 | 9 | 11 => 30,
+		2 if leap => 29,
+		2 => 28,
+		_ => return None,
+	};
+	if day == 0 || day > days {
+		return None;
+	}
+	Some(Date { year, month, day })
+}
+
+fn type_error(key: &str, expected: &str, value: &Value) -> FieldError {
+	let found = match value {
+		Value::Null => "null",
+		Value::Bool(_) => "a boolean",
+		Value::Number(_) => "a number",
+		Value::String(_) => "a string",
+		Value::Array(_) => "a list",
+		Value::Object(_) => "an object",
+	};
+	FieldError {
+		key: key.to_string(),
+		message: format!("should be {}, but is {}", expected, found),
 	}
-	let yaml: Value = from_str(matter).unwrap();
-	yaml
 }
@@ -0,0 +1,176 @@
+//! State kept between incremental builds, see
+//! [`Shtola::incremental`](../struct.Shtola.html#method.incremental). The
+//! state records the hashes of all source files and of all files written to
+//! the destination, so the next build knows which sources changed and which
+//! outputs have to be written again. It also caches the source files as they
+//! were read and the results of
+//! [file-local plugins](../struct.Shtola.html#method.register_file), so
+//! unchanged sources aren't read again and those plugins don't run again on
+//! files they already transformed.
+
+use crate::manifest::Manifest;
+use crate::{checksum, with_threads, FileMapPlugin, HashMap, ShFile, IR};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The name of the state file, which is stored in the destination directory.
+pub const STATE_FILE: &str = ".shtola-state.json";
+
+/// What an incremental build needs to know about the previous one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+	/// The source files, hashed along with their frontmatter.
+	pub sources: Manifest,
+	/// The files written to the destination.
+	pub outputs: Manifest,
+	/// Identifies the build program and its configuration. The caches are
+	/// only used by builds with the same fingerprint.
+	#[serde(default)]
+	pub fingerprint: String,
+	/// The source files as they were read.
+	#[serde(default)]
+	pub reads: BTreeMap<PathBuf, CachedSource>,
+	/// The results of file-local plugins, by the position of the plugin.
+	#[serde(default)]
+	pub transforms: BTreeMap<usize, BTreeMap<PathBuf, CachedTransform>>,
+}
+
+/// A source file as a previous build read it. It's used instead of reading
+/// the file again as long as its size and modification time stay the same.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedSource {
+	/// The size of the file in bytes.
+	pub size: u64,
+	/// When the file was last modified.
+	pub modified: SystemTime,
+	/// The SHA-256 checksum of the file contents.
+	pub checksum: String,
+	/// The file, before frontmatter defaults were applied.
+	pub file: ShFile,
+}
+
+/// The result of a file-local plugin for a single file. It's used instead of
+/// running the plugin again as long as the plugin gets the same file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTransform {
+	/// The SHA-256 checksum of the path and file the plugin got.
+	pub input: String,
+	/// The file the plugin returned.
+	pub output: ShFile,
+}
+
+impl State {
+	/// Reads the state from a JSON file. A missing file results in an empty
+	/// state, so the first incremental build is a full one.
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<State, std::io::Error> {
+		let path = path.as_ref();
+		if !path.exists() {
+			return Ok(State::default());
+		}
+		let json = fs::read(path)?;
+		serde_json::from_slice(&json).map_err(std::io::Error::other)
+	}
+
+	/// Writes the state as JSON to the given path.
+	pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+		let json = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+		fs::write(path, json)
+	}
+}
+
+/// Creates a manifest of source files as read by
+/// [`read_dir`](../fn.read_dir.html). Unlike
+/// [`Manifest::from_files`](../manifest/struct.Manifest.html#method.from_files),
+/// changes to the frontmatter count as changes of the file, including
+/// changes of the frontmatter defaults that apply to it.
+pub fn source_manifest(files: &HashMap<PathBuf, ShFile>) -> Manifest {
+	let sources: HashMap<PathBuf, ShFile> = files
+		.iter()
+		.map(|(path, file)| {
+			let mut content = file.raw_frontmatter.as_bytes().to_vec();
+			if !file.raw_frontmatter.is_empty() {
+				content.extend_from_slice(file.frontmatter.to_string().as_bytes());
+			}
+			content.extend_from_slice(&file.content);
+			(
+				path.clone(),
+				ShFile {
+					content,
+					..ShFile::empty()
+				},
+			)
+		})
+		.collect();
+	Manifest::from_files(&sources)
+}
+
+/// Identifies the running build program and the given configuration, see
+/// [`State::fingerprint`](struct.State.html#structfield.fingerprint).
+/// Rebuilding the program changes it, so cached plugin results are never
+/// mixed with the results of changed plugins.
+pub fn fingerprint<T: std::fmt::Debug>(config: &T) -> String {
+	let program = std::env::current_exe()
+		.and_then(fs::metadata)
+		.map(|m| (m.len(), m.modified().ok()))
+		.ok();
+	checksum(format!("{:?} {:?}", program, config).as_bytes())
+}
+
+// The results of file-local plugins in the current build, next to those of
+// the previous one.
+#[derive(Default)]
+pub(crate) struct Transforms {
+	pub(crate) enabled: bool,
+	pub(crate) previous: BTreeMap<usize, BTreeMap<PathBuf, CachedTransform>>,
+	pub(crate) current: BTreeMap<usize, BTreeMap<PathBuf, CachedTransform>>,
+	pub(crate) reused: usize,
+}
+
+impl Transforms {
+	// Runs the file-local plugin at the given position on all files it didn't
+	// transform in the previous build, and takes the previous results for the
+	// others.
+	pub(crate) fn apply(&mut self, position: usize, ir: IR, func: &FileMapPlugin) -> IR {
+		let previous = self.previous.remove(&position).unwrap_or_default();
+		let mut current = BTreeMap::new();
+		let mut files = ir.files.clone();
+		let mut pending = Vec::new();
+		for (path, file) in ir.files.iter() {
+			let input = input_checksum(path, file);
+			match previous.get(path).filter(|cached| cached.input == input) {
+				Some(cached) => {
+					files.insert(path.clone(), cached.output.clone());
+					current.insert(path.clone(), cached.clone());
+					self.reused += 1;
+				}
+				None => pending.push((path, file, input)),
+			}
+		}
+		let results: Vec<(PathBuf, CachedTransform)> = with_threads(ir.config.threads, || {
+			pending
+				.into_par_iter()
+				.map(|(path, file, input)| {
+					let output = func(path, file.clone());
+					(path.clone(), CachedTransform { input, output })
+				})
+				.collect()
+		});
+		for (path, transform) in results {
+			files.insert(path.clone(), transform.output.clone());
+			current.insert(path, transform);
+		}
+		self.current.insert(position, current);
+		IR { files, ..ir }
+	}
+}
+
+fn input_checksum(path: &Path, file: &ShFile) -> String {
+	let mut bytes = path.to_string_lossy().as_bytes().to_vec();
+	bytes.push(0);
+	bytes.extend(serde_json::to_vec(file).unwrap_or_default());
+	checksum(&bytes)
+}
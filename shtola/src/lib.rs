@@ -28,29 +28,83 @@
 //! ```
 
 use globset::{Glob, GlobSet, GlobSetBuilder};
-use log::{debug, info, trace};
+use incremental::{CachedSource, State, Transforms};
+use log::{debug, error, info, trace, warn};
+use manifest::{Manifest, Summary, Versions};
 use pathdiff::diff_paths;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use site::SiteConfig;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::default::Default;
+use std::fmt;
 use std::fs;
-use std::time::Instant;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
+pub use builder::ShtolaBuilder;
 pub use im::HashMap;
 pub use log;
 pub use serde_json as json;
-pub use ware::Ware;
+pub use ware::{TryWare, Ware};
 
-mod frontmatter;
+mod builder;
+pub mod command;
+pub mod context;
+pub mod data;
+pub mod decode;
+pub mod frontmatter;
+pub mod incremental;
+pub mod manifest;
+mod migrate;
+pub mod mime;
+pub mod query;
+pub mod robots;
+pub mod rotate;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod site;
+mod snapshot;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "watch")]
+mod watch;
 
 /// The main library struct.
 pub struct Shtola {
-	ware: Ware<IR>,
+	ware: TryWare<IR, PluginError>,
+	plugins: Vec<PluginInfo>,
+	plugin_timings: Rc<RefCell<Vec<Duration>>>,
+	transforms: Rc<RefCell<Transforms>>,
+	// The thread pools for the configured numbers of threads, built once and
+	// reused by all builds.
+	pools: Vec<Arc<ThreadPool>>,
+	checkers: Vec<Checker>,
+	targets: Vec<Target>,
 	ir: IR,
+	// The changes that triggered a rebuild in watch mode.
+	pending_changes: Option<Summary>,
+}
+
+// An additional destination with its own plugins.
+struct Target {
+	path: PathBuf,
+	ware: Ware<IR>,
+}
+
+impl Default for Shtola {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Shtola {
@@ -61,13 +115,28 @@ impl Shtola {
 			files: HashMap::new(),
 			config,
 			metadata: HashMap::new(),
+			site: SiteConfig::default(),
+			report: BuildReport::default(),
 		};
 		Shtola {
-			ware: Ware::new(),
+			ware: TryWare::new(),
+			plugins: Vec::new(),
+			plugin_timings: Rc::new(RefCell::new(Vec::new())),
+			transforms: Rc::new(RefCell::new(Transforms::default())),
+			pools: Vec::new(),
+			checkers: Vec::new(),
+			targets: Vec::new(),
 			ir,
+			pending_changes: None,
 		}
 	}
 
+	/// Creates a [`ShtolaBuilder`](struct.ShtolaBuilder.html), which sets up a
+	/// Shtola struct with chained calls and validates the configuration.
+	pub fn builder() -> ShtolaBuilder {
+		ShtolaBuilder::new()
+	}
+
 	/// Appends glob-matched paths to the ignore list. If a glob path matches, the
 	/// file is excluded from the IR.
 	/// ```
@@ -81,16 +150,92 @@ impl Shtola {
 		self.ir.config.ignores.dedup();
 	}
 
-	/// Sets the source directory to read from. Should be relative.
+	/// Appends glob-matched paths to the include list. If the list isn't empty,
+	/// only files matching one of its globs are read, and all others are
+	/// treated like ignored files. Ignores take precedence: a file matching
+	/// both lists is left out, as is everything in an ignored directory.
+	/// Directories themselves don't have to match.
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.includes(&mut vec!["**/*.md".into(), "**/*.toml".into()])
+	/// ```
+	pub fn includes(&mut self, vec: &mut Vec<String>) {
+		self.ir.config.includes.append(vec);
+		self.ir.config.includes.dedup();
+	}
+
+	/// Appends paths to the list of required outputs. If any of these paths is
+	/// missing from the IR after all plugins have run, the build fails in the
+	/// verification phase.
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.required(&mut vec!["index.html".into(), "feed.xml".into()])
+	/// ```
+	pub fn required(&mut self, vec: &mut Vec<String>) {
+		self.ir.config.required.append(vec);
+		self.ir.config.required.dedup();
+	}
+
+	/// Sets the source directory to read from. Should be relative. The path is
+	/// resolved when building, failing the build if it doesn't exist.
 	pub fn source<T: Into<PathBuf>>(&mut self, path: T) {
-		self.ir.config.source = fs::canonicalize(path.into()).unwrap();
+		self.ir.config.source = path.into();
+	}
+
+	/// Adds another source directory to read from, for example for theme
+	/// assets shared between sites. All source directories are merged into
+	/// one set of files. If several of them contain the same path, the file is
+	/// read from the directory set with [`Shtola::source`](#method.source),
+	/// or else from the one added first, so a site can override files of its
+	/// theme:
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.source("site");
+	/// m.add_source("static");
+	/// m.add_source("theme");
+	/// ```
+	///
+	/// Like the source directory, the paths are resolved when building. The
+	/// ignore globs apply to all source directories.
+	pub fn add_source<T: Into<PathBuf>>(&mut self, path: T) {
+		self.ir.config.sources.push(path.into());
 	}
 
-	/// Sets the destination path to write to. This directory will be created on
-	/// calling this function if it doesn't exist.
+	/// Sets the destination path to write to. This directory will be created
+	/// when building if it doesn't exist.
 	pub fn destination<T: Into<PathBuf> + Clone>(&mut self, path: T) {
-		fs::create_dir_all(path.clone().into()).expect("Unable to create destination directory!");
-		self.ir.config.destination = fs::canonicalize(path.into()).unwrap();
+		self.ir.config.destination = path.into();
+	}
+
+	/// Adds another destination path to write to. The final IR is passed through
+	/// the given plugins before being written there, which allows for small
+	/// per-destination changes without rebuilding everything. Like
+	/// [`Shtola::destination`](#method.destination), the directory is created
+	/// when building if it doesn't exist.
+	///
+	/// ```
+	/// use shtola::{Shtola, IR};
+	///
+	/// let mut m = Shtola::new();
+	/// m.destination("../fixtures/dest_public");
+	/// m.add_destination("../fixtures/dest_preview", vec![Box::new(|ir: IR| ir)]);
+	/// ```
+	pub fn add_destination<T: Into<PathBuf> + Clone>(&mut self, path: T, plugins: Vec<Plugin>) {
+		let mut ware = Ware::new();
+		for plugin in plugins {
+			ware.wrap(plugin);
+		}
+		self.targets.push(Target {
+			path: path.into(),
+			ware,
+		});
 	}
 
 	/// Sets whether the destination directory should be removed before building.
@@ -100,7 +245,262 @@ impl Shtola {
 		self.ir.config.clean = b;
 	}
 
-	/// Sets whether frontmatter should be parsed. Default is `true`.
+	/// Sets the path of the build manifest. If set, each build compares its
+	/// output against the manifest left by the previous build, stores the
+	/// resulting [`Summary`](manifest/struct.Summary.html) in the report of the
+	/// returned IR and then replaces the manifest with a new one.
+	pub fn manifest<T: Into<PathBuf>>(&mut self, path: T) {
+		self.ir.config.manifest = Some(path.into());
+	}
+
+	/// Sets the settings of the site, see [`site`](site/index.html).
+	pub fn site(&mut self, site: SiteConfig) {
+		self.ir.site = site;
+	}
+
+	/// Sets the path of a YAML or JSON file to read the settings of the site
+	/// from when building. The file replaces settings set with
+	/// [`Shtola::site`](#method.site).
+	pub fn site_config<T: Into<PathBuf>>(&mut self, path: T) {
+		self.ir.config.site_config = Some(path.into());
+	}
+
+	/// Sets the directory to read [data files](data/index.html) from,
+	/// relative to each source directory. The files are loaded into
+	/// [`IR::metadata`](struct.IR.html#structfield.metadata) before any plugin
+	/// runs and aren't part of the build themselves. Where several source
+	/// directories have the same data file, the one with the highest precedence
+	/// is used.
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.data_dir("_data");
+	/// ```
+	pub fn data_dir<T: Into<PathBuf>>(&mut self, path: T) {
+		self.ir.config.data_dir = Some(path.into());
+	}
+
+	/// Sets whether builds are incremental. Incremental builds keep a
+	/// [state file](incremental/index.html) in the destination directory and
+	/// skip the work the previous build already did:
+	/// - Sources whose size and modification time didn't change aren't read
+	///   again. The files the previous build read are used instead.
+	/// - Plugins registered with [`Shtola::register_file`](#method.register_file)
+	///   only run on the files they didn't transform in the previous build.
+	/// - Outputs that didn't change aren't written, and outputs that are no
+	///   longer produced are removed, which keeps the modification times of
+	///   unchanged files intact. Additional destinations are always written
+	///   in full.
+	///
+	/// The cached files and plugin results are discarded when the build
+	/// program or its configuration changes, and in
+	/// [clean builds](#method.clean). Default is `false`.
+	///
+	/// Other plugins still run on all files. They see the whole site, so a
+	/// changed page can change others, like the listings and feeds that
+	/// include it, and Shtola can't tell which outputs a source affects.
+	/// Instead,
+	/// [`BuildReport::source_changes`](struct.BuildReport.html#structfield.source_changes)
+	/// tells plugins which sources changed since the previous build, so
+	/// plugins that know their outputs only depend on their own source can
+	/// skip work for the others.
+	pub fn incremental(&mut self, b: bool) {
+		self.ir.config.incremental = b;
+	}
+
+	/// Sets whether builds are dry runs. A dry run reads the sources, runs all
+	/// plugins and checkers and returns the IR as usual, but doesn't touch the
+	/// destination directories, the manifest or the incremental build state.
+	/// Instead,
+	/// [`BuildReport::dry_run`](struct.BuildReport.html#structfield.dry_run)
+	/// lists the files that would have been written to and deleted from the
+	/// destination directory. Default is `false`.
+	pub fn dry_run(&mut self, b: bool) {
+		self.ir.config.dry_run = b;
+	}
+
+	/// Sets how many threads parallel work uses, like reading and writing
+	/// files, [`IR::par_update_files`](struct.IR.html#method.par_update_files)
+	/// and file-local transforms. The threads are started once and shared
+	/// by all builds of this instance. Default is one thread per CPU.
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.threads(2);
+	/// ```
+	pub fn threads(&mut self, n: usize) {
+		self.ir.config.threads = Some(n);
+	}
+
+	/// Sets how many files may be read or written at once. Containers on CI
+	/// and network file systems often behave badly when a build opens
+	/// thousands of files at once. Default is the number of threads, see
+	/// [`Shtola::threads`](#method.threads).
+	pub fn io_limit(&mut self, n: usize) {
+		self.ir.config.io_limit = Some(n);
+	}
+
+	/// Sets whether source files are checked for changes made during the
+	/// build, like an editor saving a file while a long build runs. Their
+	/// checksums are recorded when reading and verified right before writing.
+	/// Default is [`SourceVerification::Off`](enum.SourceVerification.html).
+	pub fn verify_sources(&mut self, mode: SourceVerification) {
+		self.ir.config.verify_sources = mode;
+	}
+
+	/// Sets whether drafts are built. Drafts are files with `draft: true` or
+	/// `published: false` in their frontmatter, read leniently like
+	/// [`frontmatter::get_bool`](frontmatter/fn.get_bool.html) does. If
+	/// turned off, drafts are left out right after reading, before any plugin
+	/// runs, and counted in
+	/// [`BuildReport::drafts`](struct.BuildReport.html#structfield.drafts).
+	/// Default is `true`, so a production build can turn them off:
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.drafts(std::env::var("PRODUCTION").is_err());
+	/// ```
+	pub fn drafts(&mut self, b: bool) {
+		self.ir.config.drafts = b;
+	}
+
+	/// Sets whether the build should fail if any file fails to be processed.
+	/// In non-strict mode, failed files are left out of the build and listed in
+	/// the [`BuildReport`](struct.BuildReport.html) instead. Default is `true`.
+	pub fn strict(&mut self, b: bool) {
+		self.ir.config.strict = b;
+	}
+
+	/// Sets how files that open a frontmatter block without ever closing it are
+	/// handled. Default is
+	/// [`UnclosedFrontmatter::Error`](enum.UnclosedFrontmatter.html).
+	pub fn unclosed_frontmatter(&mut self, mode: UnclosedFrontmatter) {
+		self.ir.config.unclosed_frontmatter = mode;
+	}
+
+	/// Sets how files whose frontmatter can't be parsed, like YAML with a bad
+	/// indent, are handled. Default is
+	/// [`InvalidFrontmatter::Error`](enum.InvalidFrontmatter.html).
+	pub fn invalid_frontmatter(&mut self, mode: InvalidFrontmatter) {
+		self.ir.config.invalid_frontmatter = mode;
+	}
+
+	/// Sets whether frontmatter may consist of multiple YAML documents, each
+	/// closed by a `---` line. The documents are merged into one frontmatter
+	/// object. Default is `false`, see
+	/// [`frontmatter::multi_document_lexer`](frontmatter/fn.multi_document_lexer.html)
+	/// for why. If turned off, files whose frontmatter is directly followed by
+	/// another YAML document fail instead of getting it at the start of their
+	/// content.
+	pub fn multi_document_frontmatter(&mut self, b: bool) {
+		self.ir.config.multi_document_frontmatter = b;
+	}
+
+	/// Sets the name of the files holding frontmatter defaults, or turns them
+	/// off with `None`. A defaults file is a YAML or JSON mapping whose keys
+	/// apply to all files with frontmatter in its directory and below. Defaults
+	/// of deeper directories override those of the directories above them, and
+	/// a file's own frontmatter overrides them all. Defaults files aren't part
+	/// of the build themselves. Default is `_defaults.yml`.
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.defaults_file(Some("_meta.yml"));
+	/// ```
+	pub fn defaults_file(&mut self, name: Option<&str>) {
+		self.ir.config.defaults_file = name.map(String::from);
+	}
+
+	/// Sets which permissions of source files are carried over to the files
+	/// written from them. Default is
+	/// [`PreservePermissions::Executable`](enum.PreservePermissions.html).
+	pub fn preserve_permissions(&mut self, mode: PreservePermissions) {
+		self.ir.config.preserve_permissions = mode;
+	}
+
+	/// Sets whether files written unchanged from their source file, like
+	/// images, get the modification time and extended attributes of the source
+	/// file. This keeps deploys with `rsync --times` and `Last-Modified` headers
+	/// meaningful. Extended attributes are only copied on Unix, and only where
+	/// the file system allows it. Default is `false`.
+	pub fn preserve_metadata(&mut self, b: bool) {
+		self.ir.config.preserve_metadata = b;
+	}
+
+	/// Sets which line endings text files are written with, whatever the line
+	/// endings of their source files. Builds from checkouts on Windows and on
+	/// Linux then produce the same outputs. Binary files are left alone.
+	/// Default is [`LineEndings::Keep`](enum.LineEndings.html).
+	pub fn line_endings(&mut self, mode: LineEndings) {
+		self.ir.config.line_endings = mode;
+	}
+
+	/// Sets whether frontmatter is written back in front of the content of
+	/// the files that have it, for pipelines where Shtola prepares files for
+	/// another tool. Files are written as
+	/// [`ShFile::serialize_with_frontmatter`](struct.ShFile.html#method.serialize_with_frontmatter)
+	/// returns them, and the files of the build result contain what was
+	/// written. Default is `false`, which strips frontmatter.
+	pub fn keep_frontmatter(&mut self, b: bool) {
+		self.ir.config.keep_frontmatter = b;
+	}
+
+	/// Sets the date of the build, which plugins use for things that change
+	/// over time, like the featured post of the week, see
+	/// [`IR::build_date`](struct.IR.html#method.build_date). Default is the
+	/// date in the `SOURCE_DATE_EPOCH` environment variable, or the current
+	/// date if it isn't set.
+	///
+	/// ```
+	/// use shtola::frontmatter::Date;
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.build_date(Date::parse("2020-01-31").unwrap());
+	/// ```
+	pub fn build_date(&mut self, date: frontmatter::Date) {
+		self.ir.config.build_date = Some(date);
+	}
+
+	/// Sets whether builds are atomic. Atomic builds write their outputs to a
+	/// hidden sibling of each destination directory, like
+	/// `.dest.shtola-staging`, which replaces the destination once all outputs
+	/// are written. A failed or interrupted build leaves the destination as
+	/// it was, so a site being served is never half-written. The staging
+	/// directory starts out as a copy of the destination unless it's
+	/// [cleaned](#method.clean), so atomic builds need space for a second copy
+	/// of the site. Default is `false`.
+	///
+	/// With [additional destinations](#method.add_destination), all
+	/// destinations are moved aside before any staging directory takes their
+	/// place, and all are restored if one of them can't be replaced. If the
+	/// build is killed while replacing them, the next build restores the
+	/// destinations that are missing. Staging directories are never read as
+	/// sources or watched, even inside a source directory.
+	///
+	/// ```
+	/// use shtola::Shtola;
+	///
+	/// let mut m = Shtola::new();
+	/// m.atomic(true);
+	/// ```
+	pub fn atomic(&mut self, b: bool) {
+		self.ir.config.atomic = b;
+	}
+
+	/// Sets whether frontmatter should be parsed. Default is `true`. See
+	/// [`frontmatter::lexer`](frontmatter/fn.lexer.html) for the formats
+	/// frontmatter can be written in. Only fenced frontmatter is split off
+	/// [data files](frontmatter/constant.DATA_EXTENSIONS.html) like
+	/// `.json` and `.jsonl` files.
 	pub fn frontmatter(&mut self, b: bool) {
 		self.ir.config.frontmatter = b;
 	}
@@ -115,100 +515,1772 @@ impl Shtola {
 	/// m.register(plugin);
 	/// ```
 	pub fn register(&mut self, func: Box<dyn Fn(IR) -> IR>) {
-		self.ware.wrap(func);
+		self.register_with(PluginInfo::default(), func);
+	}
+
+	/// Registers a new plugin function along with a description of the plugin.
+	/// The ordering constraints of all described plugins are validated before
+	/// the build starts.
+	///
+	/// ```
+	/// use shtola::{PluginInfo, Shtola, IR};
+	///
+	/// let mut m = Shtola::new();
+	/// m.register_with(PluginInfo::new("markdown"), Box::new(|ir: IR| ir));
+	/// m.register_with(
+	///   PluginInfo::new("templates").after("markdown"),
+	///   Box::new(|ir: IR| ir),
+	/// );
+	/// ```
+	pub fn register_with(&mut self, info: PluginInfo, func: Box<dyn Fn(IR) -> IR>) {
+		self.register_fallible_with(info, Box::new(move |ir: IR| Ok(func(ir))));
+	}
+
+	/// Registers a plugin function that can fail. The first plugin returning an
+	/// error stops the build, and [`Shtola::build`](#method.build) returns the
+	/// error along with the position and name of the plugin.
+	///
+	/// ```
+	/// use shtola::{PluginError, Shtola, IR};
+	///
+	/// let mut m = Shtola::new();
+	/// m.register_fallible(Box::new(|ir: IR| {
+	///   if ir.metadata.contains_key("title") {
+	///     Ok(ir)
+	///   } else {
+	///     Err(PluginError::new("The site needs a title"))
+	///   }
+	/// }));
+	/// ```
+	pub fn register_fallible(&mut self, func: TryPlugin) {
+		self.register_fallible_with(PluginInfo::default(), func);
+	}
+
+	/// Registers a plugin function that can fail along with a description of
+	/// the plugin, whose name is included in the error.
+	pub fn register_fallible_with(&mut self, info: PluginInfo, func: TryPlugin) {
+		let position = self.plugins.len();
+		let timings = Rc::clone(&self.plugin_timings);
+		timings.borrow_mut().push(Duration::default());
+		self.plugins.push(info);
+		self.ware.wrap(Box::new(move |ir: IR| {
+			let start = Instant::now();
+			let result = func(ir);
+			timings.borrow_mut()[position] = start.elapsed();
+			result
+		}));
+	}
+
+	/// Creates a nested plugin chain that only runs on files matching the given
+	/// glob. Once built, the chain is a regular plugin: its results are merged
+	/// back into the IR, while all other files pass through untouched.
+	///
+	/// ```
+	/// use shtola::{Shtola, IR};
+	///
+	/// let mut m = Shtola::new();
+	/// let blog = Shtola::branch("blog/**")
+	///   .unwrap()
+	///   .register(Box::new(|ir: IR| ir))
+	///   .register(Box::new(|ir: IR| ir))
+	///   .build();
+	/// m.register(blog);
+	/// ```
+	pub fn branch(glob: &str) -> Result<PipelineBuilder, ShtolaError> {
+		Ok(PipelineBuilder {
+			set: glob_set(&[glob.to_string()])?,
+			ware: Ware::new(),
+		})
+	}
+
+	/// Turns a file-local transform into a plugin. Since the transform only
+	/// sees one file at a time, the files are split into chunks that are
+	/// processed on all available threads and merged back into the IR
+	/// afterwards. Plugins that need to look at more than one file, like ones
+	/// building indexes, keep running as regular plugins.
+	///
+	/// ```
+	/// use shtola::{ShFile, Shtola};
+	///
+	/// let mut m = Shtola::new();
+	/// m.register(Shtola::file_map(Box::new(|_path, file: ShFile| ShFile {
+	///   content: file.content.to_ascii_uppercase(),
+	///   ..file
+	/// })));
+	/// ```
+	pub fn file_map(func: FileMapPlugin) -> Plugin {
+		Box::new(move |ir: IR| {
+			ir.par_update_files(|_, _| true, |path, file| func(path, file.clone()))
+		})
+	}
+
+	/// Like [`Shtola::file_map`](#method.file_map), but for transforms that can
+	/// fail on single files. Files the transform fails on are reported with
+	/// [`IR::fail_file`](struct.IR.html#method.fail_file), so they fail the
+	/// build in strict mode and are left out otherwise.
+	///
+	/// ```
+	/// use shtola::{ShFile, Shtola};
+	///
+	/// let mut m = Shtola::new();
+	/// m.register(Shtola::try_file_map(Box::new(|_path, file: ShFile| {
+	///   match std::str::from_utf8(&file.content) {
+	///     Ok(_) => Ok(file),
+	///     Err(e) => Err(format!("Invalid UTF-8: {}", e)),
+	///   }
+	/// })));
+	/// ```
+	pub fn try_file_map(func: TryFileMapPlugin) -> Plugin {
+		Box::new(move |ir: IR| {
+			let files: Vec<(&PathBuf, &ShFile)> = ir.files.iter().map(|(p, f)| (p, f)).collect();
+			let results: Vec<(PathBuf, Result<ShFile, String>)> =
+				with_threads(ir.config.threads, || {
+					files
+						.into_par_iter()
+						.map(|(path, file)| (path.clone(), func(path, file.clone())))
+						.collect()
+				});
+			let mut ir = ir;
+			for (path, result) in results {
+				ir = match result {
+					Ok(file) => IR {
+						files: ir.files.update(path, file),
+						..ir
+					},
+					Err(message) => ir.fail_file(path, &message),
+				};
+			}
+			ir
+		})
+	}
+
+	/// Registers a file-local transform, running it in parallel as described
+	/// in [`Shtola::file_map`](#method.file_map). In
+	/// [incremental mode](#method.incremental), the transform only runs on
+	/// files it didn't get in the same form in the previous build. For the
+	/// others, its previous results are used.
+	pub fn register_file(&mut self, func: FileMapPlugin) {
+		let position = self.plugins.len();
+		let transforms = Rc::clone(&self.transforms);
+		self.register(Box::new(move |ir: IR| {
+			let mut transforms = transforms.borrow_mut();
+			if transforms.enabled {
+				transforms.apply(position, ir, &func)
+			} else {
+				ir.par_update_files(|_, _| true, |path, file| func(path, file.clone()))
+			}
+		}));
+	}
+
+	/// Registers a new checker for the verification phase. Checkers run after
+	/// all plugins and before anything is written. If any checker reports an
+	/// error, the build fails with all reported errors and the destination
+	/// directory is left untouched. Builds also fail there if
+	/// [required outputs](#method.required) are missing, or if outputs would
+	/// be written to the same file, like paths that only differ in case.
+	///
+	/// ```
+	/// use shtola::{Shtola, IR};
+	///
+	/// let mut m = Shtola::new();
+	/// m.check(Box::new(|ir: &IR| {
+	///   if ir.files.is_empty() {
+	///     Err(vec!["No files were produced".into()])
+	///   } else {
+	///     Ok(())
+	///   }
+	/// }));
+	/// ```
+	pub fn check(&mut self, checker: Checker) {
+		self.checkers.push(checker);
 	}
 
 	/// Performs the build process. This does a couple of things:
-	/// - If [`Shtola::clean`](#method.clean) is set, removes and recreates the
-	///   destination directory
+	/// - Validates the ordering constraints of the registered plugins
 	/// - Reads from the source file and ignores files as it's been configured
 	/// - Parses front matter for the remaining files
 	/// - Runs the middleware chain, executing all plugins
-	/// - Writes the result back to the destination directory
-	pub fn build(&mut self) -> Result<IR, std::io::Error> {
+	/// - Runs all registered checkers, aborting the build if any of them fail
+	/// - Runs the plugins of additional destinations
+	/// - If [`Shtola::clean`](#method.clean) is set, removes and recreates the
+	///   destination directories
+	/// - Writes the results to the destination directories, only writing
+	///   changed files in [incremental mode](#method.incremental), or to
+	///   staging directories that replace them in
+	///   [atomic builds](#method.atomic)
+	pub fn build(&mut self) -> Result<IR, ShtolaError> {
+		let retry = self.ir.config.verify_sources == SourceVerification::Retry;
+		let mut attempt = 1;
+		loop {
+			if let Some(ir) = self.build_attempt(None, retry && attempt < BUILD_ATTEMPTS)? {
+				return Ok(ir);
+			}
+			attempt += 1;
+		}
+	}
+
+	/// Performs the build process like [`Shtola::build`](#method.build), but
+	/// starts from the given IR instead of reading the source directories,
+	/// site config and data files. Together with
+	/// [`IR::to_json_file`](struct.IR.html#method.to_json_file), this splits
+	/// a build across processes, like generating content in one CI stage and
+	/// rendering it in a cheaper one.
+	///
+	/// The files, metadata and site settings of the IR are used, along with
+	/// the files that failed before. Its configuration is replaced by the one
+	/// of this instance, which decides where and how the files are written.
+	/// Sources aren't [verified](#method.verify_sources), as none are read.
+	///
+	/// ```
+	/// use shtola::{Shtola, IR};
+	///
+	/// let mut generate = Shtola::new();
+	/// generate.source("../fixtures/simple");
+	/// generate.destination("../fixtures/dest_build_from_doc");
+	/// generate.dry_run(true);
+	/// let ir = generate.build().unwrap();
+	///
+	/// let mut render = Shtola::new();
+	/// render.destination("../fixtures/dest_build_from_doc");
+	/// render.clean(true);
+	/// let r = render.build_from(ir).unwrap();
+	/// assert_eq!(r.report.written, 1);
+	/// ```
+	pub fn build_from(&mut self, ir: IR) -> Result<IR, ShtolaError> {
+		let built = self.build_attempt(Some(ir), false)?;
+		Ok(built.expect("builds from an IR aren't retried"))
+	}
+
+	// Builds once, from the source directories or the given IR. Returns `None`
+	// without writing anything if sources changed during the build and it may
+	// be retried.
+	fn build_attempt(
+		&mut self,
+		input: Option<IR>,
+		may_retry: bool,
+	) -> Result<Option<IR>, ShtolaError> {
 		let now = Instant::now();
 		info!("Starting Shtola");
 		trace!("Starting IR config: {:?}", self.ir.config);
-		if self.ir.config.clean {
-			info!("Cleaning before build...");
-			debug!("Removing {:?}", &self.ir.config.destination);
-			fs::remove_dir_all(&self.ir.config.destination)?;
-			debug!("Recreating {:?}", &self.ir.config.destination);
-			fs::create_dir_all(&self.ir.config.destination)
-				.expect("Unable to recreate destination directory!");
-		}
-
-		let mut builder = GlobSetBuilder::new();
-		for item in &self.ir.config.ignores {
-			builder.add(Glob::new(item).unwrap());
-		}
-		trace!("Globs: {:?}", &builder);
-		let set = builder.build().unwrap();
-		trace!("Globset: {:?}", &set);
-		info!("Reading files...");
-		let files = read_dir(&self.ir.config.source, self.ir.config.frontmatter, set)?;
-		trace!("Files: {:?}", &files);
+		validate_order(&self.plugins)?;
+		let _pools = PoolScope::enter(self.pools());
+		// Sources needn't exist if nothing is read from them.
+		let reading = input.is_none();
+		let source = |path: &Path| {
+			if reading {
+				resolve(path, false)
+			} else {
+				absolute(path)
+			}
+		};
+		self.ir.config.source = source(&self.ir.config.source)?;
+		for path in self.ir.config.sources.iter_mut() {
+			*path = source(path)?;
+		}
+		// Dry runs mustn't create the destination directories.
+		let dry_run = self.ir.config.dry_run;
+		let output = |path: &Path| {
+			if dry_run {
+				absolute(path)
+			} else {
+				resolve(path, true)
+			}
+		};
+		// A destination an interrupted atomic build moved aside is restored
+		// before it would be recreated empty.
+		if self.ir.config.atomic && !dry_run {
+			recover(&self.ir.config.destination)?;
+			for target in &self.targets {
+				recover(&target.path)?;
+			}
+		}
+		let destination = output(&self.ir.config.destination)?;
+		for target in self.targets.iter_mut() {
+			target.path = output(&target.path)?;
+		}
+		self.ir.config.destination = destination;
+		let previous_manifest = match &self.ir.config.manifest {
+			Some(path) => Some(Manifest::read(path)?),
+			None => None,
+		};
+		let state_path = self.ir.config.destination.join(incremental::STATE_FILE);
+		let mut previous_state = if self.ir.config.incremental {
+			Some(State::read(&state_path)?)
+		} else {
+			None
+		};
+		// Cached files and plugin results are only used by the program and
+		// configuration that created them.
+		let fingerprint = incremental::fingerprint(&self.ir.config);
+		let cache = previous_state
+			.as_mut()
+			.filter(|state| state.fingerprint == fingerprint && !self.ir.config.clean)
+			.map(|state| {
+				(
+					std::mem::take(&mut state.reads),
+					std::mem::take(&mut state.transforms),
+				)
+			});
+		let (previous_reads, previous_transforms) = match cache {
+			Some((reads, transforms)) => (Some(reads), transforms),
+			None => (
+				previous_state.as_ref().map(|_| BTreeMap::new()),
+				BTreeMap::new(),
+			),
+		};
+		*self.transforms.borrow_mut() = Transforms {
+			enabled: previous_state.is_some(),
+			previous: previous_transforms,
+			..Default::default()
+		};
+		let mut timings = Timings::default();
+		let (mut ir, checksums, mut reads) = match input {
+			Some(input) => {
+				info!("Building from the given IR, skipping the read phase...");
+				let report = BuildReport {
+					read: input.report.read,
+					ignored: input.report.ignored,
+					drafts: input.report.drafts,
+					errors: input.report.errors,
+					tools: input.report.tools,
+					..Default::default()
+				};
+				let ir = IR {
+					config: self.ir.config.clone(),
+					report,
+					..input
+				};
+				(ir, Checksums::new(), BTreeMap::new())
+			}
+			None => {
+				let (ir, checksums, reads) = self.read(previous_reads.as_ref())?;
+				timings.read = ir.report.timings.read;
+				(ir, checksums, reads)
+			}
+		};
+		trace!("Files: {:?}", &ir.files);
 
-		self.ir.files = files;
+		let sources = previous_state
+			.as_ref()
+			.map(|_| incremental::source_manifest(&ir.files));
+		let watched_changes = self.pending_changes.take();
+		let source_changes = match (&sources, &previous_state) {
+			(Some(sources), Some(previous)) => {
+				let changes = sources.diff(&previous.sources);
+				info!("Source changes since previous build: {}", changes);
+				Some(changes)
+			}
+			_ => watched_changes,
+		};
+		ir.report.source_changes = source_changes;
+		for timing in self.plugin_timings.borrow_mut().iter_mut() {
+			*timing = Duration::default();
+		}
 		info!("Running plugins...");
-		let result_ir = self.ware.run(self.ir.clone());
+		let plugins_start = Instant::now();
+		let mut result_ir = self.ware.try_run(ir).map_err(|(position, e)| {
+			let e = PluginError {
+				position: Some(position),
+				plugin: self.plugins[position].name.clone(),
+				..e
+			};
+			error!("{}", e);
+			ShtolaError::Plugin(e)
+		})?;
+		timings.plugins = plugins_start.elapsed();
+		let Transforms {
+			current: mut transforms,
+			reused,
+			..
+		} = std::mem::take(&mut *self.transforms.borrow_mut());
+		result_ir.report.cached_transforms = reused;
+		result_ir.report.plugin_timings = self
+			.plugins
+			.iter()
+			.zip(self.plugin_timings.borrow().iter())
+			.map(|(info, duration)| PluginTiming {
+				name: info.name.clone(),
+				duration: *duration,
+			})
+			.collect();
 		trace!("Result IR: {:?}", &result_ir);
+		info!("Verifying...");
+		let verify_start = Instant::now();
+		verify(&result_ir, &self.checkers)?;
+		timings.verify = verify_start.elapsed();
+		if self.ir.config.verify_sources != SourceVerification::Off {
+			let modified = modified_sources(&checksums, &self.ir.config);
+			if !modified.is_empty() && may_retry {
+				warn!(
+					"Sources changed during the build, building again: {:?}",
+					modified
+				);
+				return Ok(None);
+			}
+			if !modified.is_empty() {
+				warn!("Sources changed during the build: {:?}", modified);
+			}
+			result_ir.report.modified_sources = modified;
+		}
+		// Targets run their plugins on the files before they are prepared for
+		// writing.
+		let plugin_ir = result_ir.clone();
+		result_ir.files = prepare_outputs(result_ir.files, &self.ir.config)?;
+		if self.ir.config.dry_run {
+			let pending =
+				pending_changes(&result_ir.files, &self.ir.config, previous_state.as_ref())?;
+			info!("Dry run, would have written: {}", pending);
+			result_ir.report.dry_run = Some(pending);
+			if let (Some(_), Some(previous)) = (&self.ir.config.manifest, previous_manifest) {
+				result_ir.report.summary =
+					Some(Manifest::from_files(&result_ir.files).diff(&previous));
+			}
+			timings.total = now.elapsed();
+			result_ir.report.timings = timings;
+			info!("Dry run done in {}s", now.elapsed().as_secs());
+			return Ok(Some(result_ir));
+		}
+		let write_start = Instant::now();
+		let mut outputs = vec![(&self.ir.config.destination, result_ir.files.clone())];
+		for target in &self.targets {
+			debug!("Running plugins for {:?}", &target.path);
+			let target_ir = target.ware.run(plugin_ir.clone());
+			let files = prepare_outputs(target_ir.files, &self.ir.config)?;
+			outputs.push((&target.path, files));
+		}
+		// Atomic builds write to staging directories, which start out as
+		// copies of the destinations unless they are cleaned.
+		let dirs: Vec<PathBuf> = if self.ir.config.atomic {
+			info!("Preparing staging directories...");
+			outputs
+				.iter()
+				.map(|(dest, _)| stage(dest, self.ir.config.clean))
+				.collect::<Result<_, _>>()?
+		} else {
+			if self.ir.config.clean {
+				info!("Cleaning before build...");
+				for (dest, _) in &outputs {
+					debug!("Removing {:?}", dest);
+					fs::remove_dir_all(dest)?;
+					debug!("Recreating {:?}", dest);
+					fs::create_dir_all(dest)?;
+				}
+			}
+			outputs.iter().map(|(dest, _)| dest.to_path_buf()).collect()
+		};
 		info!("Writing to disk...");
-		write_dir(result_ir.clone(), &self.ir.config.destination)?;
+		let mut write = || -> Result<(), std::io::Error> {
+			for (i, ((_, files), dir)) in outputs.iter().zip(&dirs).enumerate() {
+				// Without incremental state, metadata is copied to all outputs,
+				// so permissions changed since the previous build are carried
+				// over to unchanged outputs as well.
+				let (written, copied) = match (i, &previous_state, &sources) {
+					(0, Some(previous), Some(sources)) => {
+						let previous_outputs = if self.ir.config.clean {
+							Manifest::default()
+						} else {
+							previous.outputs.clone()
+						};
+						let written = with_threads(self.ir.config.io_threads(), || {
+							write_changed(files, dir, &previous_outputs)
+						})?;
+						let state = State {
+							sources: sources.clone(),
+							outputs: Manifest::from_files(files),
+							fingerprint: fingerprint.clone(),
+							reads: std::mem::take(&mut reads),
+							transforms: std::mem::take(&mut transforms),
+						};
+						let state_path = dir.join(incremental::STATE_FILE);
+						debug!("Writing incremental build state to {:?}", &state_path);
+						state.write(&state_path)?;
+						(written.clone(), written)
+					}
+					_ => {
+						let written =
+							with_threads(self.ir.config.io_threads(), || write_files(files, dir))?;
+						(written, files.clone())
+					}
+				};
+				copy_metadata(&copied, &self.ir.config, dir)?;
+				result_ir.report.written += written.len();
+				result_ir.report.skipped += files.len() - written.len();
+			}
+			Ok(())
+		};
+		if let Err(e) = write() {
+			if self.ir.config.atomic {
+				for dir in &dirs {
+					let _ = fs::remove_dir_all(dir);
+				}
+			}
+			return Err(e.into());
+		}
+		if self.ir.config.atomic {
+			let dests: Vec<&Path> = outputs.iter().map(|(dest, _)| dest.as_path()).collect();
+			swap(&dirs, &dests)?;
+		}
+		if result_ir.report.skipped > 0 {
+			info!("Skipped {} unchanged files", result_ir.report.skipped);
+		}
+		if let (Some(path), Some(previous)) = (&self.ir.config.manifest, previous_manifest) {
+			let mut manifest = Manifest::from_files(&result_ir.files);
+			manifest.versions = Some(self.versions(&result_ir.report));
+			if previous.versions.is_some() && previous.versions != manifest.versions {
+				info!("Versions of Shtola, plugins or tools changed since previous build");
+			}
+			let summary = manifest.diff(&previous);
+			info!("Changes since previous build: {}", summary);
+			debug!("Writing manifest to {:?}", path);
+			manifest.write(path)?;
+			result_ir.report.summary = Some(summary);
+		}
+		timings.write = write_start.elapsed();
+		timings.total = now.elapsed();
+		result_ir.report.timings = timings;
 		info!("Build done in {}s", now.elapsed().as_secs());
-		Ok(result_ir)
+		Ok(Some(result_ir))
+	}
+}
+
+impl Shtola {
+	// Reads the site config, data files and source files into the IR the
+	// plugins start with.
+	// Sources the given cache holds with their current size and modification
+	// time are taken from it instead of being read again.
+	fn read(
+		&mut self,
+		cache: Option<&BTreeMap<PathBuf, CachedSource>>,
+	) -> Result<(IR, Checksums, BTreeMap<PathBuf, CachedSource>), ShtolaError> {
+		if let Some(path) = &self.ir.config.site_config {
+			debug!("Reading site config from {:?}", path);
+			self.ir.site = SiteConfig::read(path)?;
+		}
+		let mut data = HashMap::new();
+		if let Some(dir) = &self.ir.config.data_dir {
+			let sources: Vec<&PathBuf> = self.ir.config.all_sources().collect();
+			for source in sources.into_iter().rev() {
+				debug!("Reading data files from {:?}", source.join(dir));
+				data.extend(data::read(source.join(dir))?);
+			}
+		}
+		info!("Reading files...");
+		let read_start = Instant::now();
+		let ReadFiles {
+			mut files,
+			errors,
+			ignored,
+			checksums,
+			reads,
+			cached,
+		} = read_files(&self.ir.config, &self.target_paths(), cache)?;
+		let drafts = if self.ir.config.drafts {
+			0
+		} else {
+			remove_drafts(&mut files)
+		};
+		self.ir.report = BuildReport {
+			read: files.len() + drafts + errors.len(),
+			ignored,
+			drafts,
+			errors,
+			cached_reads: cached,
+			timings: Timings {
+				read: read_start.elapsed(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+		self.ir.files = files;
+		let ir = IR {
+			metadata: data.union(self.ir.metadata.clone()),
+			..self.ir.clone()
+		};
+		Ok((ir, checksums, reads))
+	}
+
+	// Returns the thread pools for the configured numbers of threads, building
+	// those that don't exist yet.
+	fn pools(&mut self) -> Vec<Arc<ThreadPool>> {
+		let config = &self.ir.config;
+		let sizes: Vec<usize> = config.threads.into_iter().chain(config.io_limit).collect();
+		self.pools
+			.retain(|pool| sizes.contains(&pool.current_num_threads()));
+		for n in sizes {
+			if !self
+				.pools
+				.iter()
+				.any(|pool| pool.current_num_threads() == n)
+			{
+				if let Some(pool) = build_pool(n) {
+					self.pools.push(Arc::new(pool));
+				}
+			}
+		}
+		self.pools.clone()
+	}
+
+	// The paths of the additional destinations.
+	fn target_paths(&self) -> Vec<&Path> {
+		self.targets.iter().map(|t| t.path.as_path()).collect()
+	}
+
+	// The versions of the software used in a build, for the manifest.
+	fn versions(&self, report: &BuildReport) -> Versions {
+		let plugins = self
+			.plugins
+			.iter()
+			.enumerate()
+			.filter_map(|(i, info)| {
+				let name = info.name.clone().unwrap_or_else(|| format!("#{}", i));
+				Some((name, info.version.clone()?))
+			})
+			.collect();
+		Versions {
+			shtola: env!("CARGO_PKG_VERSION").to_string(),
+			plugins,
+			tools: report.tools.clone(),
+		}
+	}
+}
+
+/// A nested plugin chain for a subset of files, created with
+/// [`Shtola::branch`](struct.Shtola.html#method.branch).
+pub struct PipelineBuilder {
+	set: GlobSet,
+	ware: Ware<IR>,
+}
+
+impl PipelineBuilder {
+	/// Registers a new plugin function in the nested chain.
+	pub fn register(mut self, func: Plugin) -> PipelineBuilder {
+		self.ware.wrap(func);
+		self
+	}
+
+	/// Turns the nested chain into a plugin. The plugin runs the chain on the
+	/// matching files only and merges the result back into the full file set.
+	/// Metadata changes made by the nested chain are kept.
+	pub fn build(self) -> Plugin {
+		let PipelineBuilder { set, ware } = self;
+		Box::new(move |ir: IR| {
+			let mut matching = HashMap::new();
+			let mut rest = HashMap::new();
+			for (path, file) in ir.files.iter() {
+				if set.is_match(path) {
+					matching.insert(path.clone(), file.clone());
+				} else {
+					rest.insert(path.clone(), file.clone());
+				}
+			}
+			debug!("Running branch on {} files", matching.len());
+			let branch_ir = ware.run(IR {
+				files: matching,
+				..ir
+			});
+			IR {
+				files: branch_ir.files.clone().union(rest),
+				..branch_ir
+			}
+		})
+	}
+}
+
+/// Convenience type to return from plugin functions.
+pub type Plugin = Box<dyn Fn(IR) -> IR>;
+
+/// A plugin function that can fail. See
+/// [`Shtola::register_fallible`](struct.Shtola.html#method.register_fallible).
+pub type TryPlugin = Box<dyn Fn(IR) -> Result<IR, PluginError>>;
+
+/// An error returned by a plugin, stopping the build. See
+/// [`ShtolaError::Plugin`](enum.ShtolaError.html#variant.Plugin).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginError {
+	/// What went wrong.
+	pub message: String,
+	/// The position of the failed plugin in the chain, starting at 0. Set by
+	/// the build.
+	pub position: Option<usize>,
+	/// The name of the failed plugin, if it was registered with one. Set by
+	/// the build.
+	pub plugin: Option<String>,
+}
+
+impl PluginError {
+	/// Creates a new error with the given message.
+	pub fn new(message: &str) -> PluginError {
+		PluginError {
+			message: message.to_string(),
+			position: None,
+			plugin: None,
+		}
+	}
+}
+
+impl fmt::Display for PluginError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match (&self.plugin, self.position) {
+			(Some(name), _) => write!(f, "Plugin {:?} failed: {}", name, self.message),
+			(None, Some(position)) => write!(f, "Plugin #{} failed: {}", position, self.message),
+			(None, None) => write!(f, "{}", self.message),
+		}
+	}
+}
+
+impl std::error::Error for PluginError {}
+
+/// An error that stopped a build.
+#[derive(Debug)]
+pub enum ShtolaError {
+	/// Reading or writing a file failed.
+	Io(std::io::Error),
+	/// A path, like the source directory, couldn't be resolved or created.
+	Path(PathBuf, std::io::Error),
+	/// A glob, like one of the ignored paths, is invalid.
+	Glob(globset::Error),
+	/// The configuration is invalid, see
+	/// [`ShtolaBuilder::build_pipeline`](struct.ShtolaBuilder.html#method.build_pipeline).
+	Config(String),
+	/// The ordering constraints of the registered plugins aren't met. Holds a
+	/// description of every violated constraint.
+	PluginOrder(Vec<String>),
+	/// A plugin failed.
+	Plugin(PluginError),
+	/// Watching the source directory failed.
+	#[cfg(feature = "watch")]
+	Watch(notify::Error),
+	/// The verification phase failed, see
+	/// [`Shtola::check`](struct.Shtola.html#method.check).
+	Verification {
+		/// Every problem found, including the failed files, rendered for
+		/// humans.
+		errors: Vec<String>,
+		/// The files that failed to be processed, for example because their
+		/// frontmatter is invalid. Only set in strict mode.
+		files: Vec<FileError>,
+	},
+}
+
+impl fmt::Display for ShtolaError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ShtolaError::Io(e) => write!(f, "{}", e),
+			ShtolaError::Path(path, e) => write!(f, "{:?}: {}", path, e),
+			ShtolaError::Glob(e) => write!(f, "{}", e),
+			ShtolaError::Config(e) => write!(f, "Invalid configuration: {}", e),
+			ShtolaError::PluginOrder(errors) => {
+				write!(f, "Invalid plugin order:\n- {}", errors.join("\n- "))
+			}
+			ShtolaError::Plugin(e) => write!(f, "{}", e),
+			#[cfg(feature = "watch")]
+			ShtolaError::Watch(e) => write!(f, "Unable to watch for changes: {}", e),
+			ShtolaError::Verification { errors, .. } => {
+				write!(f, "Verification failed:\n- {}", errors.join("\n- "))
+			}
+		}
+	}
+}
+
+impl std::error::Error for ShtolaError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			ShtolaError::Io(e) | ShtolaError::Path(_, e) => Some(e),
+			ShtolaError::Glob(e) => Some(e),
+			ShtolaError::Plugin(e) => Some(e),
+			#[cfg(feature = "watch")]
+			ShtolaError::Watch(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<std::io::Error> for ShtolaError {
+	fn from(e: std::io::Error) -> Self {
+		ShtolaError::Io(e)
+	}
+}
+
+impl From<globset::Error> for ShtolaError {
+	fn from(e: globset::Error) -> Self {
+		ShtolaError::Glob(e)
+	}
+}
+
+impl From<PluginError> for ShtolaError {
+	fn from(e: PluginError) -> Self {
+		ShtolaError::Plugin(e)
+	}
+}
+
+/// A pure transform of a single file, which Shtola can run in parallel. See
+/// [`Shtola::file_map`](struct.Shtola.html#method.file_map).
+pub type FileMapPlugin = Box<dyn Fn(&Path, ShFile) -> ShFile + Send + Sync>;
+
+/// A file-local transform that can fail, see
+/// [`Shtola::try_file_map`](struct.Shtola.html#method.try_file_map).
+pub type TryFileMapPlugin = Box<dyn Fn(&Path, ShFile) -> Result<ShFile, String> + Send + Sync>;
+
+/// Describes a registered plugin. Plugins can be given a name and declare
+/// which other plugins or build phases they have to run before or after.
+/// Constraints naming plugins that aren't registered are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct PluginInfo {
+	/// The name of the plugin.
+	pub name: Option<String>,
+	/// Names of the plugins this plugin has to run after.
+	pub after: Vec<String>,
+	/// Names of the plugins this plugin has to run before.
+	pub before: Vec<String>,
+	/// The build phases this plugin has to run after.
+	pub after_phases: Vec<Phase>,
+	/// The build phases this plugin has to run before.
+	pub before_phases: Vec<Phase>,
+	/// The version of the plugin, recorded in the
+	/// [build manifest](manifest/struct.Versions.html).
+	pub version: Option<String>,
+}
+
+/// A phase of [`Shtola::build`](struct.Shtola.html#method.build), in the
+/// order they run. Plugins can declare which phases they have to run before
+/// or after, see
+/// [`PluginInfo::before_phase`](struct.PluginInfo.html#method.before_phase).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+	/// Reading the source directories.
+	Read,
+	/// Running the registered plugins.
+	Plugins,
+	/// Running the checkers.
+	Verify,
+	/// Running the plugins of additional destinations and writing the
+	/// outputs.
+	Write,
+}
+
+impl fmt::Display for Phase {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Phase::Read => "read",
+			Phase::Plugins => "plugins",
+			Phase::Verify => "verify",
+			Phase::Write => "write",
+		};
+		write!(f, "the {} phase", name)
+	}
+}
+
+impl PluginInfo {
+	/// Creates a new plugin description with the given name.
+	pub fn new(name: &str) -> PluginInfo {
+		PluginInfo {
+			name: Some(name.to_string()),
+			..Default::default()
+		}
+	}
+
+	/// Adds a plugin that this plugin has to run after.
+	pub fn after(mut self, name: &str) -> PluginInfo {
+		self.after.push(name.to_string());
+		self
+	}
+
+	/// Adds a plugin that this plugin has to run before.
+	pub fn before(mut self, name: &str) -> PluginInfo {
+		self.before.push(name.to_string());
+		self
+	}
+
+	/// Adds a build phase that this plugin has to run after. Registered
+	/// plugins run after the read phase only.
+	pub fn after_phase(mut self, phase: Phase) -> PluginInfo {
+		self.after_phases.push(phase);
+		self
+	}
+
+	/// Adds a build phase that this plugin has to run before, like a plugin
+	/// whose results have to be checked:
+	///
+	/// ```
+	/// use shtola::{Phase, PluginInfo};
+	///
+	/// let info = PluginInfo::new("links").before_phase(Phase::Verify);
+	/// ```
+	///
+	/// Registered plugins run before the verify and write phases only.
+	pub fn before_phase(mut self, phase: Phase) -> PluginInfo {
+		self.before_phases.push(phase);
+		self
+	}
+
+	/// Sets the version of the plugin. Plugin crates usually pass their own
+	/// version:
+	///
+	/// ```
+	/// use shtola::PluginInfo;
+	///
+	/// let info = PluginInfo::new("my-plugin").version(env!("CARGO_PKG_VERSION"));
+	/// ```
+	pub fn version(mut self, version: &str) -> PluginInfo {
+		self.version = Some(version.to_string());
+		self
+	}
+}
+
+/// Convenience type to return from checker functions. A checker inspects the
+/// final IR and returns every problem it found, or `Ok(())` if there were none.
+pub type Checker = Box<dyn Fn(&IR) -> Result<(), Vec<String>>>;
+
+/// The intermediate representation that's passed to plugins. Includes global
+/// metadata, the files with frontmatter and the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IR {
+	/// The filestate, contained in an `im::HashMap`.
+	#[serde(with = "snapshot::sorted")]
+	pub files: HashMap<PathBuf, ShFile>,
+	/// The configuration.
+	pub config: Config,
+	/// Global metadata managed as a `HashMap` that keep JSON values as values.
+	#[serde(with = "snapshot::sorted")]
+	pub metadata: HashMap<String, json::Value>,
+	/// The settings of the site.
+	pub site: SiteConfig,
+	/// The report about the build. Plugins may add errors to it using
+	/// [`IR::fail_file`](#method.fail_file), the rest is filled in by Shtola
+	/// once the build has finished.
+	pub report: BuildReport,
+}
+
+impl IR {
+	/// Returns the pages built from the given source file according to the
+	/// `dependencies` metadata, which maps pages to the source files they were
+	/// built from, like the layouts they were rendered with. Sorted by path.
+	pub fn dependents(&self, source: &Path) -> Vec<PathBuf> {
+		let source = source.to_string_lossy().replace('\\', "/");
+		let dependencies = match self.metadata.get("dependencies") {
+			Some(serde_json::Value::Object(map)) => map,
+			_ => return Vec::new(),
+		};
+		let mut pages: Vec<PathBuf> = dependencies
+			.iter()
+			.filter(|(_, used)| match used {
+				serde_json::Value::Array(used) => used.iter().any(|u| u.as_str() == Some(&source)),
+				_ => false,
+			})
+			.map(|(page, _)| PathBuf::from(page))
+			.collect();
+		pages.sort();
+		pages
+	}
+
+	/// Starts a [`Query`](query/struct.Query.html) over the files.
+	pub fn query(&self) -> query::Query<'_> {
+		query::Query::new(self)
+	}
+
+	/// The date of the build. This is the date set with
+	/// [`Shtola::build_date`](struct.Shtola.html#method.build_date), or the
+	/// date in the `SOURCE_DATE_EPOCH` environment variable, which
+	/// reproducible builds set to the time of the last commit, or the current
+	/// date, in UTC.
+	pub fn build_date(&self) -> frontmatter::Date {
+		if let Some(date) = self.config.build_date {
+			return date;
+		}
+		let epoch = std::env::var("SOURCE_DATE_EPOCH")
+			.ok()
+			.and_then(|secs| secs.trim().parse::<i64>().ok());
+		match epoch {
+			Some(secs) => frontmatter::Date::from_days_since_epoch(secs.div_euclid(86400)),
+			None => frontmatter::Date::from_system_time(SystemTime::now()),
+		}
+	}
+
+	/// Iterates over the files with the given extension, ignoring case. Files
+	/// without an extension are skipped. Like the files themselves, the
+	/// iterator is in no particular order.
+	///
+	/// ```
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| {
+	///     for (path, file) in ir.files_with_extension("md") {
+	///       // ...
+	///     }
+	///     ir
+	///   })
+	/// }
+	/// ```
+	pub fn files_with_extension<'a>(
+		&'a self,
+		extension: &'a str,
+	) -> impl Iterator<Item = (&'a PathBuf, &'a ShFile)> + 'a {
+		self.files
+			.iter()
+			.filter(move |(p, _)| has_extension(p, &[extension]))
+			.map(|(p, f)| (p, f))
+	}
+
+	/// Like [`IR::files_with_extension`](#method.files_with_extension), but
+	/// for files with any of the given extensions, like the
+	/// [`HTML_EXTENSIONS`](constant.HTML_EXTENSIONS.html).
+	///
+	/// ```
+	/// use shtola::{Plugin, HTML_EXTENSIONS, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| {
+	///     let pages = ir.files_with_extensions(HTML_EXTENSIONS).count();
+	///     // ...
+	///     ir
+	///   })
+	/// }
+	/// ```
+	pub fn files_with_extensions<'a, S: AsRef<str>>(
+		&'a self,
+		extensions: &'a [S],
+	) -> impl Iterator<Item = (&'a PathBuf, &'a ShFile)> + 'a {
+		self.files
+			.iter()
+			.filter(move |(p, _)| has_extension(p, extensions))
+			.map(|(p, f)| (p, f))
+	}
+
+	/// Iterates over the files whose path matches the glob, like `posts/**` or
+	/// `**/*.{png,jpg}`. As with [`Shtola::branch`](struct.Shtola.html#method.branch),
+	/// `*` also matches `/`. Fails if the glob is invalid. Like the files
+	/// themselves, the iterator is in no particular order.
+	///
+	/// ```
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| {
+	///     let posts = ir.files_matching("posts/**").unwrap().count();
+	///     // ...
+	///     ir
+	///   })
+	/// }
+	/// ```
+	pub fn files_matching(
+		&self,
+		glob: &str,
+	) -> Result<impl Iterator<Item = (&PathBuf, &ShFile)> + '_, ShtolaError> {
+		let set = glob_set(&[glob.to_string()])?;
+		Ok(self
+			.files
+			.iter()
+			.filter(move |(p, _)| set.is_match(p))
+			.map(|(p, f)| (p, f)))
+	}
+
+	/// Replaces every file matching the predicate with the result of `f`,
+	/// processing the files in parallel. All other files are left untouched.
+	///
+	/// ```
+	/// use shtola::{Plugin, ShFile, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| {
+	///     ir.par_update_files(
+	///       |path, _| path.extension().map_or(false, |e| e == "txt"),
+	///       |_, file| ShFile { content: file.content.trim_ascii().to_vec(), ..file.clone() },
+	///     )
+	///   })
+	/// }
+	/// ```
+	pub fn par_update_files<P, F>(self, pred: P, f: F) -> IR
+	where
+		P: Fn(&Path, &ShFile) -> bool + Sync,
+		F: Fn(&Path, &ShFile) -> ShFile + Sync,
+	{
+		let matching: Vec<(&PathBuf, &ShFile)> = self
+			.files
+			.iter()
+			.filter(|(p, f)| pred(p, f))
+			.map(|(p, f)| (p, f))
+			.collect();
+		trace!("Updating {} files in parallel", matching.len());
+		let updated: Vec<(PathBuf, ShFile)> = with_threads(self.config.threads, || {
+			matching
+				.into_par_iter()
+				.map(|(path, file)| (path.clone(), f(path, file)))
+				.collect()
+		});
+		let update_hash: HashMap<PathBuf, ShFile> = updated.into_iter().collect();
+		IR {
+			files: update_hash.union(self.files),
+			..self
+		}
+	}
+
+	/// Adds a file generated by a plugin, like a feed or a tag page, replacing
+	/// any file at the same path. The file is marked as
+	/// [`Origin::Generated`](enum.Origin.html#variant.Generated) and its kind
+	/// and MIME type are detected from the path and content. Pass
+	/// `Value::Null` for a file without frontmatter.
+	///
+	/// ```
+	/// use shtola::json::json;
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| {
+	///     ir.create_file("tags/rust.md", "Posts about Rust", json!({ "layout": "tag" }))
+	///   })
+	/// }
+	/// ```
+	pub fn create_file<P, C>(mut self, path: P, content: C, frontmatter: json::Value) -> IR
+	where
+		P: Into<PathBuf>,
+		C: Into<Vec<u8>>,
+	{
+		let path = path.into();
+		trace!("Creating file {:?}", &path);
+		let file = ShFile {
+			frontmatter,
+			content: content.into(),
+			..ShFile::empty()
+		}
+		.detect_type(&path);
+		self.files.insert(path, file);
+		self
+	}
+
+	/// Moves a file to a new path, keeping its frontmatter, metadata and
+	/// everything else. Its type is detected again for the new path, unless a
+	/// plugin created it without one. A file already at the new path is
+	/// replaced. Does nothing if there is no file at the old path.
+	///
+	/// ```
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| ir.rename("about.html", "about/index.html"))
+	/// }
+	/// ```
+	pub fn rename<P: AsRef<Path>, Q: Into<PathBuf>>(mut self, old: P, new: Q) -> IR {
+		let old = old.as_ref();
+		let new = new.into();
+		if let Some(file) = self.files.remove(old) {
+			trace!("Renaming {:?} to {:?}", old, &new);
+			let file = match file.mime {
+				Some(_) => file.detect_type(&new),
+				None => file,
+			};
+			self.files.insert(new, file);
+		}
+		self
+	}
+
+	/// Changes the extension of every file matching the glob, like
+	/// [`IR::rename`](#method.rename) does for single files. Fails if the glob
+	/// is invalid.
+	///
+	/// ```
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| ir.rename_extension("*.htm", "html").unwrap())
+	/// }
+	/// ```
+	pub fn rename_extension(self, glob: &str, extension: &str) -> Result<IR, ShtolaError> {
+		let paths: Vec<PathBuf> = self.files_matching(glob)?.map(|(p, _)| p.clone()).collect();
+		Ok(paths.into_iter().fold(self, |ir, path| {
+			let new = path.with_extension(extension);
+			ir.rename(path, new)
+		}))
+	}
+
+	/// Compares the files to the ones of a previous IR, like the result of the
+	/// previous build in watch mode, so only the files that changed need to be
+	/// acted upon. Files count as changed if their content differs. The lists
+	/// are sorted by path.
+	///
+	/// ```
+	/// use shtola::{ShFile, Shtola};
+	/// use std::path::PathBuf;
+	///
+	/// let mut s = Shtola::new();
+	/// s.source("../fixtures/simple");
+	/// s.destination("../fixtures/dest_diff_doc");
+	/// s.dry_run(true);
+	/// let previous = s.build().unwrap();
+	/// let mut current = previous.clone();
+	/// current.files.insert("new.txt".into(), ShFile::empty());
+	/// let diff = current.diff(&previous);
+	/// assert_eq!(diff.added, vec![PathBuf::from("new.txt")]);
+	/// assert!(diff.changed.is_empty() && diff.deleted.is_empty());
+	/// ```
+	pub fn diff(&self, previous: &IR) -> Summary {
+		let mut summary = Summary::default();
+		for (path, file) in self.files.iter() {
+			match previous.files.get(path) {
+				None => summary.added.push(path.clone()),
+				Some(old) if old.content != file.content => summary.changed.push(path.clone()),
+				Some(_) => (),
+			}
+		}
+		for path in previous.files.keys() {
+			if !self.files.contains_key(path) {
+				summary.deleted.push(path.clone());
+			}
+		}
+		summary.added.sort();
+		summary.changed.sort();
+		summary.deleted.sort();
+		let size = |ir: &IR| {
+			ir.files
+				.values()
+				.map(|f| f.content.len() as i64)
+				.sum::<i64>()
+		};
+		summary.size_delta = size(self) - size(previous);
+		summary
+	}
+
+	/// Writes a snapshot of the IR as JSON to the given path, so the state of
+	/// the pipeline can be inspected with tools like `jq` or handed to another
+	/// process, which continues the build with
+	/// [`Shtola::build_from`](struct.Shtola.html#method.build_from). Files and
+	/// metadata are sorted by key, and file contents are encoded as base64:
+	///
+	/// ```sh
+	/// jq -r '.files["index.html"].content | @base64d' ir.json
+	/// ```
+	pub fn to_json_file<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+		let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+		fs::write(path, json)
+	}
+
+	/// Reads a snapshot written by
+	/// [`IR::to_json_file`](#method.to_json_file).
+	///
+	/// ```
+	/// use shtola::{Shtola, IR};
+	///
+	/// let mut s = Shtola::new();
+	/// s.source("../fixtures/simple");
+	/// s.destination("../fixtures/dest_snapshot_doc");
+	/// let ir = s.build().unwrap();
+	/// ir.to_json_file("../fixtures/dest_snapshot_doc/ir.json").unwrap();
+	/// let read = IR::from_json_file("../fixtures/dest_snapshot_doc/ir.json").unwrap();
+	/// assert!(read.diff(&ir).changed.is_empty());
+	/// ```
+	pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<IR, std::io::Error> {
+		let json = fs::read(path)?;
+		serde_json::from_slice(&json).map_err(std::io::Error::other)
+	}
+
+	/// Removes a file from the IR and records the reason in the report. Use
+	/// this in plugins to signal that a single file couldn't be processed. In
+	/// strict mode, the build fails before writing; otherwise, all other files
+	/// are written as usual.
+	///
+	/// ```
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| ir.fail_file("broken.md", "Unable to render"))
+	/// }
+	/// ```
+	pub fn fail_file<P: Into<PathBuf>>(mut self, path: P, message: &str) -> IR {
+		let path = path.into();
+		self.files.remove(&path);
+		self.report.errors.push(FileError {
+			path,
+			message: message.to_string(),
+			location: None,
+		});
+		self
+	}
+
+	/// Records the version of an external tool a plugin invoked, so it ends up
+	/// in the [build manifest](manifest/struct.Versions.html).
+	///
+	/// ```
+	/// use shtola::manifest::tool_version;
+	/// use shtola::{Plugin, IR};
+	///
+	/// fn plugin() -> Plugin {
+	///   Box::new(|ir: IR| match tool_version("sass") {
+	///     Some(version) => ir.record_tool("sass", &version),
+	///     None => ir,
+	///   })
+	/// }
+	/// ```
+	pub fn record_tool(mut self, name: &str, version: &str) -> IR {
+		self.report
+			.tools
+			.insert(name.to_string(), version.to_string());
+		self
+	}
+
+	/// Like [`IR::fail_file`](#method.fail_file), but also records where in
+	/// which source file the problem is, so the error can be shown with the
+	/// offending line. The location may point to another file than the failed
+	/// one, like the layout a page failed to render with.
+	pub fn fail_file_at<P: Into<PathBuf>>(
+		mut self,
+		path: P,
+		message: &str,
+		location: Location,
+	) -> IR {
+		self = self.fail_file(path, message);
+		if let Some(error) = self.report.errors.last_mut() {
+			error.location = Some(location);
+		}
+		self
+	}
+}
+
+/// A report about a finished build, attached to the IR returned by
+/// [`Shtola::build`](struct.Shtola.html#method.build).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildReport {
+	/// The changes compared to the previous build, if a manifest is configured.
+	pub summary: Option<Summary>,
+	/// The changes of the source files compared to the previous build, in
+	/// [incremental mode](struct.Shtola.html#method.incremental), or the
+	/// changes that caused a rebuild while [watching](struct.Shtola.html#method.watch).
+	/// Set before the plugins run, which still get all files, so they can
+	/// limit their work to the changed ones where that's safe.
+	pub source_changes: Option<Summary>,
+	/// Files that failed to be processed and were left out of the build.
+	pub errors: Vec<FileError>,
+	/// In a [dry run](struct.Shtola.html#method.dry_run), the files that would
+	/// have been written to and deleted from the destination directory,
+	/// compared to its current contents.
+	pub dry_run: Option<Summary>,
+	/// The number of files read from the source directory, including those
+	/// that failed to be read.
+	pub read: usize,
+	/// The number of files and directories skipped because they match the
+	/// ignore globs, or files skipped because they match none of the include
+	/// globs. An ignored directory counts once.
+	pub ignored: usize,
+	/// The number of drafts left out, see
+	/// [`Shtola::drafts`](struct.Shtola.html#method.drafts).
+	pub drafts: usize,
+	/// The number of files written, to the destination directory and all
+	/// targets. Unchanged files aren't counted.
+	pub written: usize,
+	/// The number of files not written because they're unchanged. Outside of
+	/// incremental mode, files count as unchanged if the destination already
+	/// holds them with the same content.
+	pub skipped: usize,
+	/// In [incremental mode](struct.Shtola.html#method.incremental), the
+	/// number of sources taken from the previous build instead of being read
+	/// again.
+	pub cached_reads: usize,
+	/// In incremental mode, the number of files
+	/// [file-local plugins](struct.Shtola.html#method.register_file) didn't
+	/// run on, because they got the same files in the previous build.
+	pub cached_transforms: usize,
+	/// How long each phase of the build took.
+	pub timings: Timings,
+	/// How long each registered plugin took, in the order they run. Plugins
+	/// that didn't run because an earlier one failed took no time.
+	pub plugin_timings: Vec<PluginTiming>,
+	/// The source files that changed or disappeared while building, if
+	/// [`Shtola::verify_sources`](struct.Shtola.html#method.verify_sources) is
+	/// on. Their outputs may be based on the old or the new content.
+	pub modified_sources: Vec<PathBuf>,
+	/// The versions of the external tools plugins invoked, see
+	/// [`IR::record_tool`](struct.IR.html#method.record_tool).
+	pub tools: BTreeMap<String, String>,
+}
+
+/// How long the phases of a build took, see
+/// [`BuildReport::timings`](struct.BuildReport.html#structfield.timings).
+/// Phases that didn't run took no time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+	/// Reading the source directory.
+	pub read: Duration,
+	/// Running the registered plugins.
+	pub plugins: Duration,
+	/// Running the checkers.
+	pub verify: Duration,
+	/// Running the targets' plugins and writing all outputs.
+	pub write: Duration,
+	/// The whole build.
+	pub total: Duration,
+}
+
+/// How long a plugin took.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginTiming {
+	/// The name of the plugin, if it was registered with one.
+	pub name: Option<String>,
+	/// The time it took.
+	pub duration: Duration,
+}
+
+/// An error that occurred while processing a single file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileError {
+	/// The path of the file, relative to the source directory.
+	pub path: PathBuf,
+	/// What went wrong.
+	pub message: String,
+	/// Where in the sources the problem is, if known.
+	pub location: Option<Location>,
+}
+
+impl FileError {
+	/// Renders the error for humans. If the location is known, the offending
+	/// line is shown along with the lines around it, read from the given
+	/// source directory:
+	///
+	/// ```text
+	/// "broken.md": Invalid frontmatter: ...
+	///  --> broken.md:2:8
+	///   |
+	/// 1 | ---
+	/// 2 | title: [unclosed
+	///   |        ^
+	/// 3 | ---
+	/// ```
+	pub fn render(&self, source: &Path) -> String {
+		let location = match &self.location {
+			Some(location) => location,
+			None => return self.to_string(),
+		};
+		let mut result = format!(
+			"{}\n --> {}:{}",
+			self,
+			location.path.to_string_lossy(),
+			location.line
+		);
+		if let Some(column) = location.column {
+			result.push_str(&format!(":{}", column));
+		}
+		let text = match fs::read(source.join(&location.path)) {
+			Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+			Err(_) => return result,
+		};
+		let lines: Vec<&str> = text.lines().collect();
+		if location.line == 0 || location.line > lines.len() {
+			return result;
+		}
+		let first = (location.line - 1).max(1);
+		let last = (location.line + 1).min(lines.len());
+		let width = last.to_string().len();
+		result.push_str(&format!("\n{:width$} |", "", width = width));
+		for n in first..=last {
+			result.push_str(&format!(
+				"\n{:>width$} | {}",
+				n,
+				lines[n - 1],
+				width = width
+			));
+			if let (true, Some(column)) = (n == location.line, location.column) {
+				let indent = " ".repeat(column.saturating_sub(1));
+				result.push_str(&format!("\n{:width$} | {}^", "", indent, width = width));
+			}
+		}
+		result
+	}
+}
+
+impl fmt::Display for FileError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?}: {}", self.path, self.message)
 	}
 }
 
-/// Convenience type to return from plugin functions.
-pub type Plugin = Box<dyn Fn(IR) -> IR>;
-
-/// The intermediate representation that's passed to plugins. Includes global
-/// metadata, the files with frontmatter and the global config.
-#[derive(Debug, Clone)]
-pub struct IR {
-	/// The filestate, contained in an `im::HashMap`.
-	pub files: HashMap<PathBuf, ShFile>,
-	/// The configuration.
-	pub config: Config,
-	/// Global metadata managed as a `HashMap` that keep JSON values as values.
-	pub metadata: HashMap<String, json::Value>,
+/// A position in a source file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+	/// The path of the file, relative to the source directory.
+	pub path: PathBuf,
+	/// The line, starting at 1.
+	pub line: usize,
+	/// The column, starting at 1, if known.
+	pub column: Option<usize>,
 }
 
 /// Configuration struct.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
 	/// Files that are to be ignored.
 	pub ignores: Vec<String>,
+	/// Files that are to be read, if not empty.
+	pub includes: Vec<String>,
+	/// Files that have to exist after all plugins have run.
+	pub required: Vec<String>,
 	/// Source to read from.
 	pub source: PathBuf,
+	/// Additional sources to read from, in order of decreasing precedence.
+	/// Files in `source` take precedence over all of them.
+	pub sources: Vec<PathBuf>,
 	/// Destination to write to.
 	pub destination: PathBuf,
 	/// Whether to clean the destination directory.
 	pub clean: bool,
+	/// Whether to fail the build if any single file fails.
+	pub strict: bool,
 	/// Whether to parse frontmatter.
 	pub frontmatter: bool,
+	/// Whether frontmatter may consist of multiple YAML documents.
+	pub multi_document_frontmatter: bool,
+	/// How to handle frontmatter that is never closed.
+	pub unclosed_frontmatter: UnclosedFrontmatter,
+	/// How to handle frontmatter that can't be parsed.
+	pub invalid_frontmatter: InvalidFrontmatter,
+	/// The name of the files holding frontmatter defaults for their directory.
+	pub defaults_file: Option<String>,
+	/// Where to read and write the build manifest.
+	pub manifest: Option<PathBuf>,
+	/// Where to read the settings of the site from.
+	pub site_config: Option<PathBuf>,
+	/// The directory within the sources to read data files from.
+	pub data_dir: Option<PathBuf>,
+	/// Whether to only write changed files and report changed sources.
+	pub incremental: bool,
+	/// Whether to skip writing anything.
+	pub dry_run: bool,
+	/// Whether to build drafts.
+	pub drafts: bool,
+	/// How many threads parallel work uses, if not one per CPU.
+	pub threads: Option<usize>,
+	/// How many files may be read or written at once, if not one per thread.
+	pub io_limit: Option<usize>,
+	/// Whether to check sources for changes made during the build.
+	pub verify_sources: SourceVerification,
+	/// Which permissions of source files to carry over to written files.
+	pub preserve_permissions: PreservePermissions,
+	/// Whether to carry over modification times and extended attributes of
+	/// source files to unchanged outputs.
+	pub preserve_metadata: bool,
+	/// Which line endings to write text files with.
+	pub line_endings: LineEndings,
+	/// Whether to write frontmatter back in front of the content.
+	pub keep_frontmatter: bool,
+	/// The date of the build, if not the current date.
+	pub build_date: Option<frontmatter::Date>,
+	/// Whether to write to staging directories that replace the destination
+	/// directories once everything is written.
+	pub atomic: bool,
+}
+
+impl Config {
+	/// All source directories, in order of decreasing precedence.
+	pub fn all_sources(&self) -> impl Iterator<Item = &PathBuf> {
+		std::iter::once(&self.source).chain(self.sources.iter())
+	}
+
+	/// How many threads reading and writing files uses, if limited.
+	pub fn io_threads(&self) -> Option<usize> {
+		self.io_limit.or(self.threads)
+	}
+
+	/// The source directory a file at the given path, relative to the source
+	/// directories, is read from. Falls back to the main source directory for
+	/// files that exist in none of them, like generated files.
+	pub fn source_of(&self, path: &Path) -> &Path {
+		self.all_sources()
+			.find(|source| source.join(path).exists())
+			.unwrap_or(&self.source)
+	}
 }
 
 impl Default for Config {
 	fn default() -> Self {
 		Config {
 			ignores: Vec::new(),
+			includes: Vec::new(),
+			required: Vec::new(),
 			source: PathBuf::from("."),
+			sources: Vec::new(),
 			destination: PathBuf::from("./dest"),
 			clean: false,
+			strict: true,
 			frontmatter: true,
+			multi_document_frontmatter: false,
+			unclosed_frontmatter: UnclosedFrontmatter::Error,
+			invalid_frontmatter: InvalidFrontmatter::Error,
+			defaults_file: Some("_defaults.yml".into()),
+			manifest: None,
+			site_config: None,
+			data_dir: None,
+			incremental: false,
+			dry_run: false,
+			drafts: true,
+			threads: None,
+			io_limit: None,
+			verify_sources: SourceVerification::Off,
+			preserve_permissions: PreservePermissions::Executable,
+			preserve_metadata: false,
+			line_endings: LineEndings::Keep,
+			keep_frontmatter: false,
+			build_date: None,
+			atomic: false,
 		}
 	}
 }
 
+/// How to handle a file starting with a `---` line that isn't followed by a
+/// closing `---` line.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnclosedFrontmatter {
+	/// Treat the file as having no frontmatter. Useful if the `---` is meant
+	/// as a horizontal rule, like in Markdown.
+	Content,
+	/// Treat the file as failed and report it with its path.
+	Error,
+}
+
+/// How to handle a file whose frontmatter can't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidFrontmatter {
+	/// Treat the whole file, frontmatter block included, as content and log a
+	/// warning naming the file and line.
+	Content,
+	/// Treat the file as failed and report it with its path and the line of
+	/// the error.
+	Error,
+}
+
+/// Which permissions of a source file are carried over to the file written
+/// at the same path. Files without a source file at the same path, like
+/// generated or renamed files, keep the default permissions. Only has an effect
+/// on Unix.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreservePermissions {
+	/// Use the default permissions for all files.
+	None,
+	/// Make files executable if their source file is executable, so downloads
+	/// like shell scripts keep working.
+	Executable,
+	/// Copy all permission bits of the source file.
+	All,
+}
+
+/// How to handle source files that changed between reading and writing, see
+/// [`Shtola::verify_sources`](struct.Shtola.html#method.verify_sources).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceVerification {
+	/// Don't check sources.
+	Off,
+	/// Log a warning and list the files in
+	/// [`BuildReport::modified_sources`](struct.BuildReport.html#structfield.modified_sources).
+	Warn,
+	/// Build again, up to [`BUILD_ATTEMPTS`](constant.BUILD_ATTEMPTS.html)
+	/// times in total, then warn like `Warn` does.
+	Retry,
+}
+
+/// How many times a build is attempted in total if sources keep changing
+/// while building, see
+/// [`SourceVerification::Retry`](enum.SourceVerification.html#variant.Retry).
+pub const BUILD_ATTEMPTS: usize = 3;
+
+/// Which line endings text files are written with. Files count as text if
+/// they are valid UTF-8 and don't have one of the
+/// [`BINARY_EXTENSIONS`](frontmatter/constant.BINARY_EXTENSIONS.html).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+	/// Write files with the line endings they have after the plugins ran.
+	Keep,
+	/// Write all line endings as `\n`.
+	Lf,
+	/// Write all line endings as `\r\n`.
+	Crlf,
+}
+
 /// Shtola's file representation, with frontmatter included.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShFile {
 	/// The frontmatter.
 	pub frontmatter: json::Value,
-	/// The file contents (without frontmatter).
+	/// The frontmatter exactly as it was written in the source file, including
+	/// key order and comments. Plugins that re-emit frontmatter can use this
+	/// to avoid needless changes if `frontmatter` wasn't modified. Empty if the
+	/// file has no frontmatter.
+	pub raw_frontmatter: String,
+	/// How the frontmatter was written in the source file, which
+	/// [`ShFile::serialize_with_frontmatter`](#method.serialize_with_frontmatter)
+	/// writes it back as. YAML for files created by plugins.
+	#[serde(default)]
+	pub frontmatter_format: frontmatter::Format,
+	/// The file contents (without frontmatter). Serialized as base64.
+	#[serde(with = "snapshot::base64")]
 	pub content: Vec<u8>,
+	/// Data computed by plugins, like a word count or an excerpt. Unlike the
+	/// frontmatter, which is written by users, this is where plugins attach
+	/// their results for later plugins and templates, see
+	/// [`context`](context/index.html).
+	#[serde(with = "snapshot::sorted")]
+	pub metadata: HashMap<String, json::Value>,
+	/// File system information about the source file, as it was when the file
+	/// was read. `None` for files created by plugins.
+	pub stat: Option<FileStat>,
+	/// Whether the file holds text or binary data, detected when the file was
+	/// read. Files created by plugins are text unless the plugin says otherwise.
+	pub kind: mime::Kind,
+	/// The MIME type of the file, detected from its extension and content when
+	/// the file was read, see [`mime::detect`](mime/fn.detect.html). `None`
+	/// for files created by plugins, unless they use
+	/// [`ShFile::detect_type`](#method.detect_type).
+	pub mime: Option<String>,
+	/// Whether the file was read from a source directory or created by a
+	/// plugin.
+	pub origin: Origin,
+}
+
+/// Where a file comes from, see [`ShFile::origin`](struct.ShFile.html#structfield.origin).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+	/// The file was read from a source directory. Files derived from a source
+	/// file, like HTML rendered from Markdown, keep this origin.
+	Source,
+	/// The file was created by a plugin, like a feed or a tag page. This is the
+	/// origin of [`ShFile::empty`](struct.ShFile.html#method.empty).
+	Generated,
+}
+
+/// File system information about a source file, see
+/// [`ShFile::stat`](struct.ShFile.html#structfield.stat).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStat {
+	/// When the file was last modified, if the platform records it.
+	pub modified: Option<SystemTime>,
+	/// When the file was created, if the platform and file system record it.
+	pub created: Option<SystemTime>,
+	/// The size of the file in bytes, frontmatter included.
+	pub size: u64,
+	/// Whether the file is read-only.
+	pub readonly: bool,
+	/// The permission bits of the file. Only set on Unix.
+	pub mode: Option<u32>,
+}
+
+impl From<&fs::Metadata> for FileStat {
+	fn from(metadata: &fs::Metadata) -> Self {
+		#[cfg(unix)]
+		let mode = {
+			use std::os::unix::fs::PermissionsExt;
+			Some(metadata.permissions().mode() & 0o7777)
+		};
+		#[cfg(not(unix))]
+		let mode = None;
+		FileStat {
+			modified: metadata.modified().ok(),
+			created: metadata.created().ok(),
+			size: metadata.len(),
+			readonly: metadata.permissions().readonly(),
+			mode,
+		}
+	}
 }
 
 impl ShFile {
@@ -230,62 +2302,1171 @@ impl ShFile {
 	pub fn empty() -> ShFile {
 		ShFile {
 			frontmatter: json!(null),
+			raw_frontmatter: String::new(),
+			frontmatter_format: frontmatter::Format::Yaml,
+			metadata: HashMap::new(),
 			content: Vec::new(),
+			stat: None,
+			kind: mime::Kind::Text,
+			mime: None,
+			origin: Origin::Generated,
+		}
+	}
+
+	/// Detects the kind and MIME type of the file as if it was read from
+	/// `path`. Plugins that create files or change their extension use this to
+	/// keep [`ShFile::mime`](#structfield.mime) accurate:
+	///
+	/// ```
+	/// use shtola::mime::Kind;
+	/// use shtola::ShFile;
+	/// use std::path::Path;
+	///
+	/// let file = ShFile {
+	///   content: b"<h1>Hi</h1>".to_vec(),
+	///   ..ShFile::empty()
+	/// }
+	/// .detect_type(Path::new("index.html"));
+	/// assert_eq!(file.kind, Kind::Text);
+	/// assert_eq!(file.mime.as_deref(), Some("text/html"));
+	/// ```
+	pub fn detect_type(self, path: &Path) -> ShFile {
+		let (kind, mime) = mime::detect(path, &self.content);
+		ShFile {
+			kind,
+			mime: Some(mime.to_string()),
+			..self
+		}
+	}
+
+	/// Returns the content with the frontmatter in front of it, the way it
+	/// would be written in a source file. If the frontmatter is unchanged since
+	/// it was read, it is written as it was, comments included. Otherwise
+	/// JSON frontmatter is written back as JSON and anything else as YAML.
+	/// Files without frontmatter are returned as they are.
+	///
+	/// ```
+	/// use shtola::json::json;
+	/// use shtola::ShFile;
+	///
+	/// let file = ShFile {
+	///   frontmatter: json!({ "title": "Hello" }),
+	///   content: b"Hi!".to_vec(),
+	///   ..ShFile::empty()
+	/// };
+	/// assert_eq!(file.serialize_with_frontmatter().unwrap(), b"---\ntitle: Hello\n---\nHi!");
+	/// ```
+	pub fn serialize_with_frontmatter(&self) -> Result<Vec<u8>, ShtolaError> {
+		if self.frontmatter.is_null() {
+			return Ok(self.content.clone());
+		}
+		let format = self.frontmatter_format;
+		let unchanged = frontmatter::to_json(&self.raw_frontmatter)
+			.is_ok_and(|matter| matter == self.frontmatter);
+		let mut result = match (unchanged, format) {
+			(true, frontmatter::Format::Yaml) => format!("---\n{}\n---\n", self.raw_frontmatter),
+			(true, frontmatter::Format::Json) => self.raw_frontmatter.clone(),
+			(true, frontmatter::Format::FencedJson) => {
+				format!("---json\n{}\n---\n", self.raw_frontmatter)
+			}
+			(false, format) => migrate::render(&self.frontmatter, format, "")?,
+		};
+		// JSON frontmatter isn't closed by a line of its own.
+		if format == frontmatter::Format::Json {
+			result.push('\n');
 		}
+		let mut result = result.into_bytes();
+		result.extend_from_slice(&self.content);
+		Ok(result)
+	}
+
+	/// Deserializes the frontmatter into a type of the plugin's own, so it
+	/// doesn't need to walk the JSON value by hand. Files without frontmatter
+	/// are treated as having an empty one, so optional fields work for them.
+	/// Errors name the offending key:
+	///
+	/// ```
+	/// use serde::Deserialize;
+	/// use shtola::json::json;
+	/// use shtola::ShFile;
+	///
+	/// #[derive(Deserialize)]
+	/// struct Post {
+	///   title: String,
+	///   tags: Option<Vec<String>>,
+	/// }
+	///
+	/// let file = ShFile {
+	///   frontmatter: json!({ "title": "Hello", "tags": ["rust", 5] }),
+	///   ..ShFile::empty()
+	/// };
+	/// let error = file.frontmatter_as::<Post>().err().unwrap();
+	/// assert_eq!(
+	///   error.to_string(),
+	///   "Frontmatter key \"tags[1]\" is invalid: invalid type: integer `5`, expected a string"
+	/// );
+	/// ```
+	pub fn frontmatter_as<T: DeserializeOwned>(&self) -> Result<T, frontmatter::FieldError> {
+		let empty = json!({});
+		let frontmatter = if self.frontmatter.is_null() {
+			&empty
+		} else {
+			&self.frontmatter
+		};
+		serde_path_to_error::deserialize(frontmatter).map_err(|e| {
+			let path = e.path().to_string();
+			let path = if path == "." { String::new() } else { path };
+			let inner = e.into_inner().to_string();
+			// Missing fields are reported at their parent.
+			let missing = inner
+				.strip_prefix("missing field `")
+				.and_then(|rest| rest.strip_suffix('`'));
+			match missing {
+				Some(field) if path.is_empty() => frontmatter::FieldError {
+					key: field.to_string(),
+					message: "is missing".into(),
+				},
+				Some(field) => frontmatter::FieldError {
+					key: format!("{}.{}", path, field),
+					message: "is missing".into(),
+				},
+				None => frontmatter::FieldError {
+					key: path,
+					message: format!("is invalid: {}", inner),
+				},
+			}
+		})
 	}
 }
 
-fn read_dir(
-	source: &PathBuf,
-	frontmatter: bool,
-	set: GlobSet,
-) -> Result<HashMap<PathBuf, ShFile>, std::io::Error> {
+/// The extensions of HTML pages, for use with
+/// [`has_extension`](fn.has_extension.html) and
+/// [`IR::files_with_extensions`](struct.IR.html#method.files_with_extensions).
+pub const HTML_EXTENSIONS: &[&str] = &["html", "htm"];
+
+/// Whether a path has one of the given extensions, ignoring case.
+///
+/// ```
+/// use shtola::{has_extension, HTML_EXTENSIONS};
+/// use std::path::Path;
+///
+/// assert!(has_extension(Path::new("blog/index.HTM"), HTML_EXTENSIONS));
+/// assert!(!has_extension(Path::new("style.css"), HTML_EXTENSIONS));
+/// assert!(!has_extension(Path::new("html"), HTML_EXTENSIONS));
+/// ```
+pub fn has_extension<S: AsRef<str>>(path: &Path, extensions: &[S]) -> bool {
+	match path.extension().and_then(|e| e.to_str()) {
+		Some(ext) => extensions
+			.iter()
+			.any(|e| e.as_ref().eq_ignore_ascii_case(ext)),
+		None => false,
+	}
+}
+
+/// Reads all files from the source directories of the given config, leaving
+/// out ignored files and parsing frontmatter as configured. If several source
+/// directories contain the same path, the file is read from the one with the
+/// highest precedence, see [`Config::sources`](struct.Config.html#structfield.sources). Files that couldn't be
+/// processed are returned as errors next to the files that could. Binary
+/// files, recognized by their extension or content, are passed through
+/// untouched without looking for frontmatter. Files are read in parallel, as
+/// many at once as [`Config::io_threads`](struct.Config.html#method.io_threads)
+/// allows. This is the read phase of [`Shtola::build`](struct.Shtola.html#method.build), exposed
+/// for custom build loops.
+///
+/// ```
+/// use shtola::{read_dir, write_dir, Config};
+/// use std::path::Path;
+///
+/// let config = Config { source: "../fixtures/simple".into(), ..Default::default() };
+/// let (files, errors) = read_dir(&config).unwrap();
+/// assert!(errors.is_empty());
+/// write_dir(&files, Path::new("../fixtures/dest_doc")).unwrap();
+/// ```
+pub fn read_dir(
+	config: &Config,
+) -> Result<(HashMap<PathBuf, ShFile>, Vec<FileError>), ShtolaError> {
+	read_files(config, &[], None).map(|read| (read.files, read.errors))
+}
+
+// The path, path relative to the source directory and checksum of every file
+// read.
+type Checksums = Vec<(PathBuf, PathBuf, String)>;
+
+// What `read_files` found.
+pub(crate) struct ReadFiles {
+	pub(crate) files: HashMap<PathBuf, ShFile>,
+	pub(crate) errors: Vec<FileError>,
+	pub(crate) ignored: usize,
+	pub(crate) checksums: Checksums,
+	// The files read, for the cache of the next incremental build, and how
+	// many of them were taken from the given cache.
+	pub(crate) reads: BTreeMap<PathBuf, CachedSource>,
+	pub(crate) cached: usize,
+}
+
+// Like `read_dir`, but also counts the ignored paths and records checksums of
+// the files if sources are verified. Additional destinations inside a source
+// directory are left out like the destination. With a cache, sources it
+// holds with their current size and modification time aren't read again, and
+// all files read are returned for the next cache.
+fn read_files(
+	config: &Config,
+	targets: &[&Path],
+	cache: Option<&BTreeMap<PathBuf, CachedSource>>,
+) -> Result<ReadFiles, ShtolaError> {
+	let set = glob_set(&config.ignores)?;
+	let includes = glob_set(&config.includes)?;
+	let mut ignored = 0;
+	trace!("Globset: {:?}", &set);
+	// The sources are walked first, so the files can be read in parallel.
+	let mut seen = std::collections::HashSet::new();
+	let mut entries: Vec<(&PathBuf, PathBuf, PathBuf)> = Vec::new();
+	for source in config.all_sources() {
+		let iters = WalkDir::new(source)
+			.into_iter()
+			.filter_entry(|e| {
+				let path = diff_paths(e.path(), source).unwrap();
+				trace!("Read Filter: {:?} matches? {}", &path, set.is_match(&path));
+				if config.data_dir.as_ref() == Some(&path) {
+					return false;
+				}
+				// Outputs inside a source directory aren't sources.
+				if e.file_type().is_dir()
+					&& (e.path() == config.destination
+						|| targets.contains(&e.path())
+						|| is_sibling_name(e.file_name()))
+				{
+					return false;
+				}
+				let excluded = !config.includes.is_empty()
+					&& !e.file_type().is_dir()
+					&& !includes.is_match(&path);
+				if set.is_match(&path) || excluded {
+					ignored += 1;
+					return false;
+				}
+				true
+			})
+			.filter(|e| e.as_ref().map_or(true, |e| !e.file_type().is_dir()));
+		for entry in iters {
+			let entry = entry.map_err(std::io::Error::from)?;
+			let path = entry.path();
+			let rel_path = diff_paths(path, source).unwrap();
+			if !seen.insert(rel_path.clone()) {
+				debug!(
+					"Skipping {:?}, a source with higher precedence has it",
+					&path
+				);
+				continue;
+			}
+			entries.push((source, path.to_path_buf(), rel_path));
+		}
+	}
+	// Checksums are needed to verify the sources and to cache them.
+	let summing = config.verify_sources != SourceVerification::Off || cache.is_some();
+	let contents: Vec<std::io::Result<Source>> = with_threads(config.io_threads(), || {
+		entries
+			.par_iter()
+			.map(|(_, path, rel_path)| {
+				let metadata = fs::metadata(path)?;
+				let cached = cache
+					.and_then(|cache| cache.get(rel_path))
+					.filter(|cached| {
+						cached.size == metadata.len()
+							&& Some(cached.modified) == metadata.modified().ok()
+					});
+				if let Some(cached) = cached {
+					trace!("Using the cached read of {:?}", path);
+					return Ok(Source::Cached(cached));
+				}
+				debug!("Reading file at {:?}", path);
+				let mut file = fs::File::open(path)?;
+				let metadata = file.metadata()?;
+				let mut bytes = Vec::new();
+				file.read_to_end(&mut bytes)?;
+				let sum = if summing {
+					checksum(&bytes)
+				} else {
+					String::new()
+				};
+				Ok(Source::Read(bytes, Box::new(metadata), sum))
+			})
+			.collect()
+	});
+	let mut checksums = Vec::new();
+	if config.verify_sources != SourceVerification::Off {
+		for ((_, path, rel_path), source) in entries.iter().zip(&contents) {
+			match source {
+				Ok(Source::Read(_, _, sum)) => {
+					checksums.push((path.clone(), rel_path.clone(), sum.clone()))
+				}
+				Ok(Source::Cached(cached)) => {
+					checksums.push((path.clone(), rel_path.clone(), cached.checksum.clone()))
+				}
+				Err(_) => {}
+			}
+		}
+	}
 	let mut result = HashMap::new();
-	let iters = WalkDir::new(source)
-		.into_iter()
-		.filter_entry(|e| {
-			let path = diff_paths(e.path(), source).unwrap();
-			trace!("Read Filter: {:?} matches? {}", &path, set.is_match(&path));
-			!set.is_match(path)
-		})
-		.filter(|e| !e.as_ref().ok().unwrap().file_type().is_dir());
-	for entry in iters {
-		let entry = entry?;
-		let path = entry.path();
-		let file: ShFile;
-		let mut content = String::new();
-		debug!("Reading file at {:?}", &path);
-		fs::File::open(path)?.read_to_string(&mut content)?;
-		if frontmatter {
-			let (matter, content) = frontmatter::lexer(&content);
-			if matter.len() > 0 {
+	let mut reads = BTreeMap::new();
+	let mut cached = 0;
+	let mut errors: Vec<FileError> = Vec::new();
+	let mut defaults: HashMap<PathBuf, serde_json::Map<String, serde_json::Value>> = HashMap::new();
+	for ((source, path, rel_path), contents) in entries.into_iter().zip(contents) {
+		let path = path.as_path();
+		let (bytes, metadata, sum) = match contents? {
+			Source::Read(bytes, metadata, sum) => (bytes, *metadata, sum),
+			Source::Cached(read) => {
+				cached += 1;
+				result.insert(rel_path.clone(), read.file.clone());
+				reads.insert(rel_path, read.clone());
+				continue;
+			}
+		};
+		let stat = FileStat::from(&metadata);
+		let (kind, mime) = mime::detect(&rel_path, &bytes);
+		let mime = Some(mime.to_string());
+		if config.frontmatter && is_defaults_file(&rel_path, config) {
+			let dir = rel_path.parent().unwrap_or(Path::new("")).to_path_buf();
+			debug!("Reading frontmatter defaults at {:?}", &path);
+			match read_defaults(bytes, &rel_path)? {
+				Ok(map) => {
+					defaults.insert(dir, map);
+				}
+				Err(e) => errors.push(e),
+			}
+			continue;
+		}
+		let file = if config.frontmatter
+			&& !frontmatter::has_binary_extension(&rel_path)
+			&& frontmatter::is_text(&bytes)
+		{
+			let text = String::from_utf8(bytes).unwrap();
+			// The object a data file starts with is data, not frontmatter.
+			let lexed = if frontmatter::has_data_extension(&rel_path) && text.starts_with('{') {
+				Some((String::new(), text.clone()))
+			} else if config.multi_document_frontmatter {
+				frontmatter::multi_document_lexer(&text)
+			} else if frontmatter::is_multi_document(&text) {
+				let (_, offset) = frontmatter::locate_frontmatter(&text, false).unwrap_or_default();
+				errors.push(FileError {
+					location: Some(Location {
+						path: rel_path.clone(),
+						line: text[..offset].matches('\n').count() + 1,
+						column: None,
+					}),
+					path: rel_path,
+					message: "Frontmatter is followed by another YAML document, which would \
+					 end up in the content. Turn on multi-document frontmatter to \
+					 merge them, or separate the content with a blank line"
+						.into(),
+				});
+				continue;
+			} else {
+				frontmatter::lexer(&text)
+			};
+			let (matter, content) = match lexed {
+				Some(lexed) => lexed,
+				None if config.unclosed_frontmatter == UnclosedFrontmatter::Content => {
+					(String::new(), text.clone())
+				}
+				None => {
+					errors.push(FileError {
+						location: Some(Location {
+							path: rel_path.clone(),
+							line: 1,
+							column: None,
+						}),
+						path: rel_path,
+						message: "Frontmatter is never closed".into(),
+					});
+					continue;
+				}
+			};
+			if !matter.is_empty() {
 				debug!("Lexing frontmatter for {:?}", &path);
 				trace!("Frontmatter: {:?}", &matter);
 			}
-			let json = frontmatter::to_json(&matter);
-			file = ShFile {
+			let json = match frontmatter::to_json(&matter) {
+				Ok(json) => json,
+				Err(e) => {
+					// Fenced frontmatter starts below the opening line.
+					let offset = if text.starts_with('{') { 0 } else { 1 };
+					let error = FileError {
+						location: e.line.map(|line| Location {
+							path: rel_path.clone(),
+							line: line + offset,
+							column: e.column,
+						}),
+						path: rel_path,
+						message: format!("Invalid frontmatter: {}", e),
+					};
+					if config.invalid_frontmatter == InvalidFrontmatter::Content {
+						warn!("Treating frontmatter as content: {}", error.render(source));
+						result.insert(
+							error.path,
+							ShFile {
+								content: text.into(),
+								stat: Some(stat),
+								kind,
+								mime,
+								origin: Origin::Source,
+								..ShFile::empty()
+							},
+						);
+					} else {
+						errors.push(error);
+					}
+					continue;
+				}
+			};
+			ShFile {
 				frontmatter: json,
+				frontmatter_format: frontmatter::Format::of(&text, &matter),
+				raw_frontmatter: matter,
+				metadata: HashMap::new(),
 				content: content.into(),
-			};
+				stat: Some(stat),
+				kind,
+				mime,
+				origin: Origin::Source,
+			}
 		} else {
-			file = ShFile {
-				frontmatter: json!(null),
-				content: content.into(),
+			trace!("Not lexing frontmatter for {:?}", &path);
+			ShFile {
+				content: bytes,
+				stat: Some(stat),
+				kind,
+				mime,
+				origin: Origin::Source,
+				..ShFile::empty()
+			}
+		};
+		if let (Some(_), Ok(modified)) = (cache, metadata.modified()) {
+			let read = CachedSource {
+				size: metadata.len(),
+				modified,
+				checksum: sum,
+				file: file.clone(),
 			};
+			reads.insert(rel_path.clone(), read);
 		}
-		let rel_path = diff_paths(path, source).unwrap();
 		result.insert(rel_path, file);
 	}
-	Ok(result)
+	if !defaults.is_empty() {
+		apply_defaults(&mut result, &defaults);
+	}
+	Ok(ReadFiles {
+		files: result,
+		errors,
+		ignored,
+		checksums,
+		reads,
+		cached,
+	})
+}
+
+// A source file, either read or taken from the cache of the previous build.
+enum Source<'a> {
+	Read(Vec<u8>, Box<fs::Metadata>, String),
+	Cached(&'a CachedSource),
+}
+
+fn checksum(bytes: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(bytes))
+}
+
+// Reads the sources again, returning those that changed or disappeared since
+// their checksums were recorded.
+fn modified_sources(checksums: &Checksums, config: &Config) -> Vec<PathBuf> {
+	let mut modified: Vec<PathBuf> = with_threads(config.io_threads(), || {
+		checksums
+			.par_iter()
+			.filter(|(path, _, sum)| fs::read(path).map_or(true, |bytes| checksum(&bytes) != *sum))
+			.map(|(_, rel_path, _)| rel_path.clone())
+			.collect()
+	});
+	modified.sort();
+	modified
+}
+
+fn is_defaults_file(path: &Path, config: &Config) -> bool {
+	match (&config.defaults_file, path.file_name()) {
+		(Some(name), Some(file_name)) => file_name == name.as_str(),
+		_ => false,
+	}
+}
+
+// Parses a defaults file, which has to be a mapping. Errors of the file itself
+// are returned as the inner error, like the frontmatter errors of other files.
+fn read_defaults(
+	bytes: Vec<u8>,
+	rel_path: &Path,
+) -> Result<Result<serde_json::Map<String, serde_json::Value>, FileError>, ShtolaError> {
+	let text = String::from_utf8(bytes)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+	let error = |message: String, line: Option<usize>, column: Option<usize>| FileError {
+		location: line.map(|line| Location {
+			path: rel_path.to_path_buf(),
+			line,
+			column,
+		}),
+		path: rel_path.to_path_buf(),
+		message,
+	};
+	Ok(match frontmatter::to_json(text.trim()) {
+		Ok(serde_json::Value::Object(map)) => Ok(map),
+		Ok(serde_json::Value::Null) => Ok(serde_json::Map::new()),
+		Ok(_) => Err(error("Defaults are not a mapping".into(), Some(1), None)),
+		Err(e) => Err(error(format!("Invalid defaults: {}", e), e.line, e.column)),
+	})
+}
+
+// Merges the defaults of a file's directory and the directories above it
+// under its own frontmatter. Files without frontmatter are left alone, so
+// defaults like `layout` don't reach stylesheets or images.
+fn apply_defaults(
+	files: &mut HashMap<PathBuf, ShFile>,
+	defaults: &HashMap<PathBuf, serde_json::Map<String, serde_json::Value>>,
+) {
+	let paths: Vec<PathBuf> = files.keys().cloned().collect();
+	for path in paths {
+		let file = files.get_mut(&path).unwrap();
+		let own = match &file.frontmatter {
+			serde_json::Value::Object(own) => own.clone(),
+			_ => continue,
+		};
+		let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+		dirs.reverse();
+		let mut merged = serde_json::Map::new();
+		for dir in dirs {
+			if let Some(map) = defaults.get(dir) {
+				merged.extend(map.clone());
+			}
+		}
+		if merged.is_empty() {
+			continue;
+		}
+		trace!("Applying frontmatter defaults to {:?}", &path);
+		merged.extend(own);
+		file.frontmatter = serde_json::Value::Object(merged);
+	}
+}
+
+// Removes files marked as drafts, returning how many there were.
+fn remove_drafts(files: &mut HashMap<PathBuf, ShFile>) -> usize {
+	let drafts: Vec<PathBuf> = files
+		.iter()
+		.filter(|(_, file)| {
+			frontmatter::get_bool(&file.frontmatter, "draft") == Ok(Some(true))
+				|| frontmatter::get_bool(&file.frontmatter, "published") == Ok(Some(false))
+		})
+		.map(|(path, _)| path.clone())
+		.collect();
+	for path in &drafts {
+		debug!("Leaving out draft {:?}", path);
+		files.remove(path);
+	}
+	if !drafts.is_empty() {
+		info!("Left out {} drafts", drafts.len());
+	}
+	drafts.len()
+}
+
+fn validate_order(plugins: &[PluginInfo]) -> Result<(), ShtolaError> {
+	let positions = |name: &String| -> Vec<usize> {
+		plugins
+			.iter()
+			.enumerate()
+			.filter(|(_, p)| p.name.as_ref() == Some(name))
+			.map(|(i, _)| i)
+			.collect()
+	};
+	let describe = |i: usize| match &plugins[i].name {
+		Some(name) => format!("{:?}", name),
+		None => format!("#{}", i),
+	};
+	let mut errors = Vec::new();
+	for (i, plugin) in plugins.iter().enumerate() {
+		for name in &plugin.after {
+			for j in positions(name).into_iter().filter(|&j| j > i) {
+				errors.push(format!(
+					"Plugin {} must run after {}, but is registered before it",
+					describe(i),
+					describe(j)
+				));
+			}
+		}
+		for name in &plugin.before {
+			for j in positions(name).into_iter().filter(|&j| j < i) {
+				errors.push(format!(
+					"Plugin {} must run before {}, but is registered after it",
+					describe(i),
+					describe(j)
+				));
+			}
+		}
+		// Registered plugins run in the plugins phase.
+		for phase in plugin.after_phases.iter().filter(|&&p| p >= Phase::Plugins) {
+			errors.push(format!(
+				"Plugin {} must run after {}, but plugins run {}",
+				describe(i),
+				phase,
+				if *phase == Phase::Plugins {
+					"in it"
+				} else {
+					"before it"
+				}
+			));
+		}
+		for phase in plugin
+			.before_phases
+			.iter()
+			.filter(|&&p| p <= Phase::Plugins)
+		{
+			errors.push(format!(
+				"Plugin {} must run before {}, but plugins run {}",
+				describe(i),
+				phase,
+				if *phase == Phase::Plugins {
+					"in it"
+				} else {
+					"after it"
+				}
+			));
+		}
+	}
+	if errors.is_empty() {
+		return Ok(());
+	}
+	Err(ShtolaError::PluginOrder(errors))
+}
+
+fn check_required(ir: &IR) -> Result<(), Vec<String>> {
+	let missing: Vec<String> = ir
+		.config
+		.required
+		.iter()
+		.filter(|path| !ir.files.contains_key(&PathBuf::from(path)))
+		.map(|path| {
+			format!(
+				"Required output {:?} is missing after running plugins",
+				path
+			)
+		})
+		.collect();
+	if missing.is_empty() {
+		Ok(())
+	} else {
+		Err(missing)
+	}
+}
+
+// Finds outputs that would be written to the same place: paths that only
+// differ in case, which case-insensitive file systems store as one file, or
+// in how they're written, like `a/./b.html` and `a/b.html`, and files other
+// outputs need as their directory.
+fn check_duplicates(ir: &IR) -> Result<(), Vec<String>> {
+	let mut paths: Vec<&PathBuf> = ir.files.keys().collect();
+	paths.sort();
+	let mut seen: BTreeMap<String, &PathBuf> = BTreeMap::new();
+	let mut errors = Vec::new();
+	for path in &paths {
+		let key: Vec<String> = path
+			.components()
+			.filter(|c| *c != std::path::Component::CurDir)
+			.map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+			.collect();
+		if let Some(other) = seen.insert(key.join("/"), path) {
+			errors.push(format!(
+				"Outputs {:?} and {:?} would be written to the same file",
+				other, path
+			));
+		}
+		if let Some(dir) = path.ancestors().skip(1).find(|a| ir.files.contains_key(*a)) {
+			errors.push(format!(
+				"Output {:?} needs {:?} as its directory, which is an output file",
+				path, dir
+			));
+		}
+	}
+	if errors.is_empty() {
+		Ok(())
+	} else {
+		Err(errors)
+	}
+}
+
+fn check_file_errors(ir: &IR) -> Result<(), Vec<String>> {
+	if ir.config.strict {
+		let errors: Vec<String> = ir
+			.report
+			.errors
+			.iter()
+			.map(|e| e.render(ir.config.source_of(&e.path)))
+			.collect();
+		if !errors.is_empty() {
+			return Err(errors);
+		}
+	} else {
+		for e in &ir.report.errors {
+			warn!("Skipping file {}", e.render(ir.config.source_of(&e.path)));
+		}
+	}
+	Ok(())
+}
+
+fn verify(ir: &IR, checkers: &[Checker]) -> Result<(), ShtolaError> {
+	let errors: Vec<String> = std::iter::once(check_file_errors(ir))
+		.chain(std::iter::once(check_required(ir)))
+		.chain(std::iter::once(check_duplicates(ir)))
+		.chain(checkers.iter().map(|checker| checker(ir)))
+		.filter_map(Result::err)
+		.flatten()
+		.collect();
+	if errors.is_empty() {
+		return Ok(());
+	}
+	for e in &errors {
+		error!("Verification error: {}", e);
+	}
+	let files = if ir.config.strict {
+		ir.report.errors.clone()
+	} else {
+		Vec::new()
+	};
+	Err(ShtolaError::Verification { errors, files })
+}
+
+// Writes the files whose content differs from the previous outputs or that
+// are missing from the destination, and removes previous outputs that aren't
+// part of the build anymore. Returns the written files.
+fn write_changed(
+	files: &HashMap<PathBuf, ShFile>,
+	dest: &Path,
+	previous: &Manifest,
+) -> Result<HashMap<PathBuf, ShFile>, std::io::Error> {
+	let manifest = Manifest::from_files(files);
+	let changed: HashMap<PathBuf, ShFile> = files
+		.iter()
+		.filter(|(path, _)| {
+			previous.files.get(path) != manifest.files.get(path) || !dest.join(path).exists()
+		})
+		.map(|(path, file)| (path.clone(), file.clone()))
+		.collect();
+	debug!(
+		"Writing {} of {} files to {:?}",
+		changed.len(),
+		files.len(),
+		dest
+	);
+	let written = write_files(&changed, dest)?;
+	for path in previous.files.keys().filter(|p| !files.contains_key(*p)) {
+		let dest_path = dest.join(path);
+		if dest_path.exists() {
+			debug!("Removing {:?}", &dest_path);
+			fs::remove_file(dest_path)?;
+		}
+	}
+	Ok(written)
+}
+
+// Turns the files the plugins produced into the files to write, putting
+// frontmatter back in front of the content and normalizing line endings as
+// configured.
+fn prepare_outputs(
+	files: HashMap<PathBuf, ShFile>,
+	config: &Config,
+) -> Result<HashMap<PathBuf, ShFile>, ShtolaError> {
+	let files = if config.keep_frontmatter {
+		let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+		for (path, file) in files.iter().filter(|(_, f)| !f.frontmatter.is_null()) {
+			trace!("Writing frontmatter of {:?}", path);
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					content: file.serialize_with_frontmatter()?,
+					..file.clone()
+				},
+			);
+		}
+		update_hash.union(files)
+	} else {
+		files
+	};
+	Ok(normalize_line_endings(files, config.line_endings))
+}
+
+// Rewrites the line endings of all text files, leaving files that already
+// have the right ones untouched.
+fn normalize_line_endings(
+	files: HashMap<PathBuf, ShFile>,
+	mode: LineEndings,
+) -> HashMap<PathBuf, ShFile> {
+	if mode == LineEndings::Keep {
+		return files;
+	}
+	let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+	for (path, file) in files.iter() {
+		if frontmatter::has_binary_extension(path) || !frontmatter::is_text(&file.content) {
+			continue;
+		}
+		let text = std::str::from_utf8(&file.content).unwrap();
+		let lf = text.replace("\r\n", "\n");
+		let normalized = match mode {
+			LineEndings::Crlf => lf.replace('\n', "\r\n"),
+			_ => lf,
+		};
+		if normalized != text {
+			trace!("Normalizing line endings of {:?}", path);
+			update_hash.insert(
+				path.clone(),
+				ShFile {
+					content: normalized.into_bytes(),
+					..file.clone()
+				},
+			);
+		}
+	}
+	update_hash.union(files)
+}
+
+// Compares what a build would write to what's in the destination directory.
+fn pending_changes(
+	files: &HashMap<PathBuf, ShFile>,
+	config: &Config,
+	state: Option<&State>,
+) -> Result<Summary, std::io::Error> {
+	let mut current = Manifest::from_dir(&config.destination)?;
+	current.files.remove(Path::new(incremental::STATE_FILE));
+	let mut pending = Manifest::from_files(files).diff(&current);
+	// Without cleaning, only stale outputs of incremental builds are deleted.
+	if !config.clean {
+		pending
+			.deleted
+			.retain(|path| state.is_some_and(|s| s.outputs.files.contains_key(path)));
+	}
+	Ok(pending)
+}
+
+// The hidden sibling directory an atomic build uses for the given
+// destination, like `.dest.shtola-staging`.
+fn sibling(dest: &Path, suffix: &str) -> PathBuf {
+	let name = dest.file_name().unwrap_or_default().to_string_lossy();
+	dest.with_file_name(format!(".{}.shtola-{}", name, suffix))
+}
+
+// Creates the staging directory of a destination for an atomic build. A
+// staging directory left over by an interrupted build is removed first.
+fn stage(dest: &Path, clean: bool) -> Result<PathBuf, std::io::Error> {
+	let staging = sibling(dest, "staging");
+	if staging.exists() {
+		debug!("Removing leftover {:?}", &staging);
+		fs::remove_dir_all(&staging)?;
+	}
+	if clean || !dest.exists() {
+		fs::create_dir_all(&staging)?;
+		return Ok(staging);
+	}
+	debug!("Copying {:?} to {:?}", dest, &staging);
+	for entry in WalkDir::new(dest) {
+		let entry = entry?;
+		let target = staging.join(entry.path().strip_prefix(dest).unwrap());
+		if entry.file_type().is_dir() {
+			fs::create_dir_all(&target)?;
+			continue;
+		}
+		// Unchanged files keep their modification time, like they would
+		// without staging.
+		fs::copy(entry.path(), &target)?;
+		let modified = entry.metadata()?.modified()?;
+		fs::File::options()
+			.write(true)
+			.open(&target)?
+			.set_modified(modified)?;
+	}
+	Ok(staging)
+}
+
+// Replaces the destinations with their staging directories. All
+// destinations are moved aside first, since directories can't be replaced by
+// renaming over them, so a failure leaves every destination as it was. The
+// old destinations are removed once all staging directories took their place.
+fn swap(stagings: &[PathBuf], dests: &[&Path]) -> Result<(), std::io::Error> {
+	let mut moved = Vec::new();
+	let mut placed = Vec::new();
+	let mut replace = || -> Result<(), std::io::Error> {
+		for dest in dests {
+			let old = sibling(dest, "old");
+			if old.exists() {
+				fs::remove_dir_all(&old)?;
+			}
+			if dest.exists() {
+				fs::rename(dest, &old)?;
+				moved.push(*dest);
+			}
+		}
+		for (staging, dest) in stagings.iter().zip(dests) {
+			debug!("Replacing {:?} with {:?}", dest, staging);
+			fs::rename(staging, dest)?;
+			placed.push((staging, *dest));
+		}
+		Ok(())
+	};
+	if let Err(e) = replace() {
+		warn!("Unable to replace the destinations, restoring them: {}", e);
+		for (staging, dest) in placed {
+			let _ = fs::rename(dest, staging);
+		}
+		for dest in moved {
+			let _ = fs::rename(sibling(dest, "old"), dest);
+		}
+		for staging in stagings {
+			let _ = fs::remove_dir_all(staging);
+		}
+		return Err(e);
+	}
+	for dest in moved {
+		fs::remove_dir_all(sibling(dest, "old"))?;
+	}
+	Ok(())
+}
+
+// Restores a destination that an atomic build moved aside but was killed
+// before replacing.
+fn recover(dest: &Path) -> Result<(), std::io::Error> {
+	let old = sibling(dest, "old");
+	if !dest.exists() && old.is_dir() {
+		warn!("Restoring {:?} from an interrupted build", dest);
+		fs::rename(&old, dest)?;
+	}
+	Ok(())
+}
+
+// Whether a directory name is one of the siblings atomic builds use, which
+// are never sources.
+fn is_sibling_name(name: &std::ffi::OsStr) -> bool {
+	let name = name.to_string_lossy();
+	name.starts_with('.') && (name.ends_with(".shtola-staging") || name.ends_with(".shtola-old"))
+}
+
+/// Writes files to the given destination directory, creating subdirectories
+/// as needed. Files are written in parallel. Files the destination already
+/// holds with the same content aren't written again, so their modification
+/// times stay the same for tools like rsync. This is the write phase of
+/// [`Shtola::build`](struct.Shtola.html#method.build), exposed for custom
+/// build loops.
+pub fn write_dir(files: &HashMap<PathBuf, ShFile>, dest: &Path) -> Result<(), std::io::Error> {
+	write_files(files, dest).map(|_| ())
+}
+
+// Like `write_dir`, but returns the files that were written.
+fn write_files(
+	files: &HashMap<PathBuf, ShFile>,
+	dest: &Path,
+) -> Result<HashMap<PathBuf, ShFile>, std::io::Error> {
+	let files: Vec<(&PathBuf, &ShFile)> = files.iter().map(|(p, f)| (p, f)).collect();
+	let written: Vec<Option<(PathBuf, ShFile)>> = files
+		.into_par_iter()
+		.map(|(path, file)| {
+			let dest_path = dest.join(path);
+			if is_written(&dest_path, &file.content) {
+				trace!("Skipping unchanged {:?}", &dest_path);
+				return Ok(None);
+			}
+			debug!("Writing {:?} to {:?}", &path, &dest_path);
+			if let Some(parent) = dest_path.parent() {
+				fs::create_dir_all(parent)?;
+			}
+			fs::File::create(dest_path)?.write_all(&file.content)?;
+			Ok(Some((path.clone(), file.clone())))
+		})
+		.collect::<Result<_, std::io::Error>>()?;
+	Ok(written.into_iter().flatten().collect())
+}
+
+// Whether the file at the path already has the given content. The sizes are
+// compared first, so most changed files aren't read.
+fn is_written(path: &Path, content: &[u8]) -> bool {
+	match fs::metadata(path) {
+		Ok(metadata) if metadata.is_file() && metadata.len() == content.len() as u64 => {
+			fs::read(path).is_ok_and(|existing| existing == content)
+		}
+		_ => false,
+	}
+}
+
+thread_local! {
+	// The thread pools of the build running on this thread.
+	static POOLS: RefCell<Vec<Arc<ThreadPool>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Makes the given thread pools the ones `with_threads` uses on this thread,
+// until it's dropped.
+struct PoolScope(Vec<Arc<ThreadPool>>);
+
+impl PoolScope {
+	fn enter(pools: Vec<Arc<ThreadPool>>) -> PoolScope {
+		PoolScope(POOLS.with(|current| current.replace(pools)))
+	}
+}
+
+impl Drop for PoolScope {
+	fn drop(&mut self) {
+		let previous = std::mem::take(&mut self.0);
+		POOLS.with(|current| current.replace(previous));
+	}
 }
 
-fn write_dir(ir: IR, dest: &PathBuf) -> Result<(), std::io::Error> {
-	for (path, file) in ir.files {
-		let dest_path = dest.join(&path);
-		debug!("Writing {:?} to {:?}", &path, &dest_path);
-		fs::create_dir_all(dest_path.parent().unwrap())
-			.expect("Unable to create destination subdirectory!");
-		fs::File::create(dest_path)?.write_all(&file.content)?;
+fn build_pool(n: usize) -> Option<ThreadPool> {
+	match rayon::ThreadPoolBuilder::new().num_threads(n).build() {
+		Ok(pool) => Some(pool),
+		Err(e) => {
+			warn!("Unable to start {} threads, using the default: {}", n, e);
+			None
+		}
+	}
+}
+
+// Runs `op` on a thread pool with the given number of threads, so parallel
+// iterators inside it use no more than that. Builds use the pools of their
+// Shtola instance, work outside of builds starts a pool of its own. Without a
+// limit, the global pool is used.
+fn with_threads<R: Send>(threads: Option<usize>, op: impl FnOnce() -> R + Send) -> R {
+	let n = match threads {
+		Some(n) => n,
+		None => return op(),
+	};
+	let pool = POOLS.with(|pools| {
+		pools
+			.borrow()
+			.iter()
+			.find(|pool| pool.current_num_threads() == n)
+			.cloned()
+	});
+	match pool {
+		Some(pool) => pool.install(op),
+		None => match build_pool(n) {
+			Some(pool) => pool.install(op),
+			None => op(),
+		},
+	}
+}
+
+fn copy_metadata(
+	files: &HashMap<PathBuf, ShFile>,
+	config: &Config,
+	dest: &Path,
+) -> Result<(), std::io::Error> {
+	if config.preserve_permissions == PreservePermissions::None && !config.preserve_metadata {
+		return Ok(());
+	}
+	for (path, file) in files {
+		let source_path = config.source_of(path).join(path);
+		let metadata = match fs::metadata(&source_path) {
+			Ok(metadata) => metadata,
+			Err(_) => continue,
+		};
+		let dest_path = dest.join(path);
+		if config.preserve_metadata
+			&& metadata.len() == file.content.len() as u64
+			&& fs::read(&source_path)? == file.content
+		{
+			trace!("Copying metadata of {:?} to {:?}", &source_path, &dest_path);
+			fs::File::options()
+				.write(true)
+				.open(&dest_path)?
+				.set_modified(metadata.modified()?)?;
+			copy_xattrs(&source_path, &dest_path);
+		}
+		copy_permissions(&metadata, &dest_path, config.preserve_permissions)?;
+	}
+	Ok(())
+}
+
+#[cfg(unix)]
+fn copy_permissions(
+	metadata: &fs::Metadata,
+	dest_path: &Path,
+	preserve: PreservePermissions,
+) -> Result<(), std::io::Error> {
+	use std::os::unix::fs::PermissionsExt;
+
+	let source_mode = metadata.permissions().mode();
+	let mut permissions = fs::metadata(dest_path)?.permissions();
+	let mode = match preserve {
+		PreservePermissions::None => return Ok(()),
+		PreservePermissions::All => source_mode & 0o7777,
+		PreservePermissions::Executable => (permissions.mode() & 0o7666) | (source_mode & 0o111),
+	};
+	if mode != permissions.mode() & 0o7777 {
+		trace!("Setting mode of {:?} to {:o}", dest_path, mode);
+		permissions.set_mode(mode);
+		fs::set_permissions(dest_path, permissions)?;
 	}
 	Ok(())
 }
+
+#[cfg(not(unix))]
+fn copy_permissions(
+	_metadata: &fs::Metadata,
+	_dest_path: &Path,
+	_preserve: PreservePermissions,
+) -> Result<(), std::io::Error> {
+	Ok(())
+}
+
+// Extended attributes are copied on a best effort basis, since file systems
+// and permissions differ in which attributes they allow.
+#[cfg(unix)]
+fn copy_xattrs(source_path: &Path, dest_path: &Path) {
+	let names = match xattr::list(source_path) {
+		Ok(names) => names,
+		Err(e) => {
+			debug!(
+				"Unable to list extended attributes of {:?}: {}",
+				source_path, e
+			);
+			return;
+		}
+	};
+	for name in names {
+		if let Ok(Some(value)) = xattr::get(source_path, &name) {
+			if let Err(e) = xattr::set(dest_path, &name, &value) {
+				warn!(
+					"Unable to copy extended attribute {:?} to {:?}: {}",
+					name, dest_path, e
+				);
+			}
+		}
+	}
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_source_path: &Path, _dest_path: &Path) {}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, ShtolaError> {
+	let mut builder = GlobSetBuilder::new();
+	for item in globs {
+		builder.add(Glob::new(item)?);
+	}
+	Ok(builder.build()?)
+}
+
+// Makes a path absolute without requiring it to exist.
+fn absolute(path: &Path) -> Result<PathBuf, ShtolaError> {
+	fs::canonicalize(path)
+		.or_else(|_| std::path::absolute(path))
+		.map_err(|e| ShtolaError::Path(path.to_path_buf(), e))
+}
+
+// Makes a configured path absolute, creating it first if asked to.
+fn resolve(path: &Path, create: bool) -> Result<PathBuf, ShtolaError> {
+	let result = if create {
+		fs::create_dir_all(path).and_then(|_| fs::canonicalize(path))
+	} else {
+		fs::canonicalize(path)
+	};
+	result.map_err(|e| ShtolaError::Path(path.to_path_buf(), e))
+}
@@ -0,0 +1,214 @@
+//! Build manifests record what a build wrote to its destination, so that the
+//! next build can summarize what changed compared to the previous deployment.
+//! Manifests written by [`Shtola::build`](../struct.Shtola.html#method.build)
+//! also record the [`Versions`](struct.Versions.html) of the software that
+//! produced the build, so production builds can be audited.
+
+use crate::{HashMap, ShFile};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+/// A record of every file in a build, keyed by its path relative to the
+/// destination directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+	/// The recorded files.
+	pub files: BTreeMap<PathBuf, Entry>,
+	/// The versions of the software that produced the files, if recorded.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub versions: Option<Versions>,
+}
+
+/// The versions of Shtola, the plugins and the external tools used in a build.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Versions {
+	/// The version of Shtola.
+	pub shtola: String,
+	/// The versions of the registered plugins that declare one with
+	/// [`PluginInfo::version`](../struct.PluginInfo.html#method.version), keyed
+	/// by plugin name.
+	#[serde(default)]
+	pub plugins: BTreeMap<String, String>,
+	/// The versions of the external tools plugins invoked, recorded with
+	/// [`IR::record_tool`](../struct.IR.html#method.record_tool), keyed by
+	/// tool name.
+	#[serde(default)]
+	pub tools: BTreeMap<String, String>,
+}
+
+/// A single file in a [`Manifest`](struct.Manifest.html).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+	/// The SHA-256 hash of the file contents, hex-encoded.
+	pub hash: String,
+	/// The file size in bytes.
+	pub size: u64,
+}
+
+impl Manifest {
+	/// Creates a manifest from a set of files.
+	pub fn from_files(files: &HashMap<PathBuf, ShFile>) -> Manifest {
+		let files = files
+			.iter()
+			.map(|(path, file)| {
+				let entry = Entry {
+					hash: hash(&file.content),
+					size: file.content.len() as u64,
+				};
+				(path.clone(), entry)
+			})
+			.collect();
+		Manifest {
+			files,
+			versions: None,
+		}
+	}
+
+	/// Creates a manifest of the files in a directory. A missing directory
+	/// results in an empty manifest.
+	pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Manifest, std::io::Error> {
+		let dir = dir.as_ref();
+		let mut files = BTreeMap::new();
+		if !dir.exists() {
+			return Ok(Manifest {
+				files,
+				versions: None,
+			});
+		}
+		for entry in WalkDir::new(dir) {
+			let entry = entry.map_err(std::io::Error::from)?;
+			if entry.file_type().is_dir() {
+				continue;
+			}
+			let content = fs::read(entry.path())?;
+			let path = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+			let entry = Entry {
+				hash: hash(&content),
+				size: content.len() as u64,
+			};
+			files.insert(path, entry);
+		}
+		Ok(Manifest {
+			files,
+			versions: None,
+		})
+	}
+
+	/// Reads a manifest from a JSON file. A missing file results in an empty
+	/// manifest, as is the case for the very first build.
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<Manifest, std::io::Error> {
+		let path = path.as_ref();
+		if !path.exists() {
+			return Ok(Manifest::default());
+		}
+		let json = fs::read(path)?;
+		serde_json::from_slice(&json).map_err(std::io::Error::other)
+	}
+
+	/// Writes the manifest as JSON to the given path.
+	pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+		let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+		fs::write(path, json)
+	}
+
+	/// Compares this manifest to a previous one.
+	pub fn diff(&self, previous: &Manifest) -> Summary {
+		let mut summary = Summary::default();
+		for (path, entry) in &self.files {
+			match previous.files.get(path) {
+				None => summary.added.push(path.clone()),
+				Some(old) if old.hash != entry.hash => summary.changed.push(path.clone()),
+				Some(_) => (),
+			}
+		}
+		for path in previous.files.keys() {
+			if !self.files.contains_key(path) {
+				summary.deleted.push(path.clone());
+			}
+		}
+		summary.size_delta = self.size() as i64 - previous.size() as i64;
+		summary
+	}
+
+	/// The total size of all files in bytes.
+	pub fn size(&self) -> u64 {
+		self.files.values().map(|e| e.size).sum()
+	}
+}
+
+/// The difference between two manifests, or between two IRs as returned by
+/// [`IR::diff`](../struct.IR.html#method.diff). Its `Display` implementation
+/// gives a human-readable one-line summary.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+	/// Files that didn't exist in the previous build.
+	pub added: Vec<PathBuf>,
+	/// Files whose contents changed since the previous build.
+	pub changed: Vec<PathBuf>,
+	/// Files that existed in the previous build, but not anymore.
+	pub deleted: Vec<PathBuf>,
+	/// The change of the total size in bytes.
+	pub size_delta: i64,
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let sign = if self.size_delta < 0 { "-" } else { "+" };
+		write!(
+			f,
+			"{} added, {} changed, {} deleted, {}{} total",
+			self.added.len(),
+			self.changed.len(),
+			self.deleted.len(),
+			sign,
+			human_size(self.size_delta.unsigned_abs())
+		)
+	}
+}
+
+/// Asks an external tool for its version by running it with `--version`.
+/// Returns the first line of its output, or `None` if it couldn't be run.
+///
+/// ```no_run
+/// use shtola::manifest::tool_version;
+///
+/// assert!(tool_version("esbuild").is_some());
+/// ```
+pub fn tool_version(program: &str) -> Option<String> {
+	let output = Command::new(program).arg("--version").output().ok()?;
+	if !output.status.success() {
+		return None;
+	}
+	let text = String::from_utf8_lossy(&output.stdout);
+	let line = text.lines().next()?.trim();
+	if line.is_empty() {
+		None
+	} else {
+		Some(line.to_string())
+	}
+}
+
+fn hash(content: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(content))
+}
+
+fn human_size(bytes: u64) -> String {
+	let units = ["B", "KiB", "MiB", "GiB"];
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < units.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	if unit == 0 {
+		format!("{} {}", bytes, units[0])
+	} else {
+		format!("{:.1} {}", size, units[unit])
+	}
+}
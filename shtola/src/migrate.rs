@@ -0,0 +1,127 @@
+//! Frontmatter migrations, see [`Shtola::migrate`](../struct.Shtola.html#method.migrate).
+
+use crate::frontmatter::{
+	has_binary_extension, has_data_extension, is_text, locate_frontmatter, Format,
+};
+use crate::{read_files, resolve, ReadFiles, Shtola, ShtolaError};
+use log::{debug, info};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+impl Shtola {
+	/// Rewrites the frontmatter of the files in the source directories in
+	/// place, for changes like renaming a key across all posts. The callback
+	/// gets the path of every text file, relative to its source directory,
+	/// along with its parsed frontmatter, and returns the new frontmatter:
+	///
+	/// ```no_run
+	/// use shtola::Shtola;
+	///
+	/// let mut s = Shtola::new();
+	/// s.source("site");
+	/// let changed = s
+	///   .migrate(|_path, mut matter| {
+	///     if let Some(tag) = matter.as_object_mut().and_then(|m| m.remove("tag")) {
+	///       matter["tags"] = vec![tag].into();
+	///     }
+	///     matter
+	///   })
+	///   .unwrap();
+	/// println!("Migrated {} files", changed.len());
+	/// ```
+	///
+	/// Files without frontmatter get `null`. Frontmatter is read even if
+	/// [`Shtola::frontmatter`](struct.Shtola.html#method.frontmatter) is
+	/// turned off. Only files whose frontmatter changed are written, and their
+	/// content is kept byte for byte. YAML frontmatter is written back as YAML
+	/// and JSON frontmatter as JSON, which loses comments and formatting.
+	/// Returning `null` removes the frontmatter. Ignored files are left alone,
+	/// and nothing is written if any file's frontmatter can't be parsed or in
+	/// a [dry run](struct.Shtola.html#method.dry_run).
+	///
+	/// Returns the paths of the changed files, sorted.
+	pub fn migrate<F>(&self, mut func: F) -> Result<Vec<PathBuf>, ShtolaError>
+	where
+		F: FnMut(&Path, Value) -> Value,
+	{
+		let mut config = self.ir.config.clone();
+		config.source = resolve(&config.source, false)?;
+		for source in config.sources.iter_mut() {
+			*source = resolve(source, false)?;
+		}
+		config.frontmatter = true;
+		let ReadFiles { files, errors, .. } = read_files(&config, &[], None)?;
+		if !errors.is_empty() {
+			return Err(ShtolaError::Verification {
+				errors: errors
+					.iter()
+					.map(|e| e.render(config.source_of(&e.path)))
+					.collect(),
+				files: errors,
+			});
+		}
+		let mut changes = Vec::new();
+		for (path, file) in files.iter() {
+			if has_binary_extension(path) {
+				continue;
+			}
+			// The content was trimmed when reading, so it's read again.
+			let bytes = fs::read(config.source_of(path).join(path))?;
+			if !is_text(&bytes) {
+				continue;
+			}
+			let text = String::from_utf8(bytes).unwrap();
+			// Frontmatter put in front of a data file would break it.
+			if has_data_extension(path) && text.starts_with('{') {
+				continue;
+			}
+			let migrated = func(path, file.frontmatter.clone());
+			if migrated == file.frontmatter {
+				continue;
+			}
+			let (matter, offset) =
+				match locate_frontmatter(&text, config.multi_document_frontmatter) {
+					Some(located) => located,
+					None => continue,
+				};
+			let body = &text[offset..];
+			let format = Format::of(&text, &matter);
+			changes.push((path.clone(), render(&migrated, format, body)?));
+		}
+		changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+		let paths: Vec<PathBuf> = changes.iter().map(|(p, _)| p.clone()).collect();
+		if config.dry_run {
+			info!("Dry run, would have migrated {} files", paths.len());
+			return Ok(paths);
+		}
+		for (path, content) in changes {
+			debug!("Migrating frontmatter of {:?}", &path);
+			fs::write(config.source_of(&path).join(&path), content)?;
+		}
+		info!("Migrated {} files", paths.len());
+		Ok(paths)
+	}
+}
+
+// Puts a document back together from its new frontmatter and its content.
+pub(crate) fn render(matter: &Value, format: Format, body: &str) -> Result<String, ShtolaError> {
+	if matter.is_null() {
+		return Ok(body.trim_start_matches(['\r', '\n']).to_string());
+	}
+	let invalid = |e: String| {
+		ShtolaError::Io(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!("Unable to write frontmatter: {}", e),
+		))
+	};
+	let json = || serde_json::to_string_pretty(matter).map_err(|e| invalid(e.to_string()));
+	match format {
+		Format::Json => return Ok(format!("{}{}", json()?, body)),
+		Format::FencedJson => return Ok(format!("---json\n{}\n---\n{}", json()?, body)),
+		Format::Yaml => {}
+	}
+	let yaml = serde_yaml::to_string(matter).map_err(|e| invalid(e.to_string()))?;
+	let yaml = yaml.strip_prefix("---\n").unwrap_or(&yaml).trim_end();
+	Ok(format!("---\n{}\n---\n{}", yaml, body))
+}
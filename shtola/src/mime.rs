@@ -0,0 +1,163 @@
+//! Content type detection for source files. Every file read from a source
+//! directory gets a [`Kind`](enum.Kind.html) and a MIME type, see
+//! [`ShFile::kind`](../struct.ShFile.html#structfield.kind) and
+//! [`ShFile::mime`](../struct.ShFile.html#structfield.mime), so plugins can
+//! cheaply select the files they care about:
+//!
+//! ```
+//! use shtola::{Plugin, IR};
+//!
+//! fn plugin() -> Plugin {
+//!   Box::new(|ir: IR| {
+//!     let images = ir
+//!       .files
+//!       .iter()
+//!       .filter(|(_, f)| f.mime.as_ref().is_some_and(|m| m.starts_with("image/")));
+//!     // ...
+//!     ir
+//!   })
+//! }
+//! ```
+
+use crate::frontmatter;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Whether a file holds text or binary data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+	/// UTF-8 text.
+	Text,
+	/// Anything else, like images and fonts.
+	Binary,
+}
+
+/// The MIME type of text files without a known extension.
+pub const TEXT: &str = "text/plain";
+
+/// The MIME type of binary files without a known extension or signature.
+pub const BINARY: &str = "application/octet-stream";
+
+const EXTENSIONS: &[(&str, &str)] = &[
+	("html", "text/html"),
+	("htm", "text/html"),
+	("css", "text/css"),
+	("js", "text/javascript"),
+	("mjs", "text/javascript"),
+	("cjs", "text/javascript"),
+	("json", "application/json"),
+	("webmanifest", "application/manifest+json"),
+	("xml", "application/xml"),
+	("rss", "application/rss+xml"),
+	("atom", "application/atom+xml"),
+	("svg", "image/svg+xml"),
+	("md", "text/markdown"),
+	("markdown", "text/markdown"),
+	("txt", "text/plain"),
+	("csv", "text/csv"),
+	("yml", "application/yaml"),
+	("yaml", "application/yaml"),
+	("toml", "application/toml"),
+	("png", "image/png"),
+	("jpg", "image/jpeg"),
+	("jpeg", "image/jpeg"),
+	("gif", "image/gif"),
+	("webp", "image/webp"),
+	("avif", "image/avif"),
+	("ico", "image/x-icon"),
+	("bmp", "image/bmp"),
+	("tif", "image/tiff"),
+	("tiff", "image/tiff"),
+	("woff", "font/woff"),
+	("woff2", "font/woff2"),
+	("ttf", "font/ttf"),
+	("otf", "font/otf"),
+	("eot", "application/vnd.ms-fontobject"),
+	("pdf", "application/pdf"),
+	("epub", "application/epub+zip"),
+	("zip", "application/zip"),
+	("gz", "application/gzip"),
+	("mp3", "audio/mpeg"),
+	("ogg", "audio/ogg"),
+	("wav", "audio/wav"),
+	("mp4", "video/mp4"),
+	("webm", "video/webm"),
+	("wasm", "application/wasm"),
+];
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+	(b"\x89PNG\r\n\x1a\n", "image/png"),
+	(b"\xff\xd8\xff", "image/jpeg"),
+	(b"GIF87a", "image/gif"),
+	(b"GIF89a", "image/gif"),
+	(b"%PDF-", "application/pdf"),
+	(b"PK\x03\x04", "application/zip"),
+	(b"\x1f\x8b", "application/gzip"),
+	(b"wOFF", "font/woff"),
+	(b"wOF2", "font/woff2"),
+	(b"\0asm", "application/wasm"),
+];
+
+/// Looks up the MIME type of a path by its extension, ignoring case.
+///
+/// ```
+/// use shtola::mime::from_extension;
+/// use std::path::Path;
+///
+/// assert_eq!(from_extension(Path::new("img/Logo.PNG")), Some("image/png"));
+/// assert_eq!(from_extension(Path::new("Makefile")), None);
+/// ```
+pub fn from_extension(path: &Path) -> Option<&'static str> {
+	let ext = path.extension()?.to_str()?;
+	EXTENSIONS
+		.iter()
+		.find(|(known, _)| known.eq_ignore_ascii_case(ext))
+		.map(|(_, mime)| *mime)
+}
+
+/// Looks up the MIME type of binary content by its leading bytes.
+///
+/// ```
+/// use shtola::mime::from_content;
+///
+/// assert_eq!(from_content(b"%PDF-1.7\n"), Some("application/pdf"));
+/// assert_eq!(from_content(b"Hello"), None);
+/// ```
+pub fn from_content(content: &[u8]) -> Option<&'static str> {
+	if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+		return Some("image/webp");
+	}
+	SIGNATURES
+		.iter()
+		.find(|(signature, _)| content.starts_with(signature))
+		.map(|(_, mime)| *mime)
+}
+
+/// Detects the kind and MIME type of a file. Files with one of the
+/// [`BINARY_EXTENSIONS`](../frontmatter/constant.BINARY_EXTENSIONS.html) or
+/// content that isn't UTF-8 are binary. The MIME type comes from the
+/// extension, falling back to the content's signature for binary files and
+/// to [`TEXT`](constant.TEXT.html) or [`BINARY`](constant.BINARY.html).
+///
+/// ```
+/// use shtola::mime::{detect, Kind};
+/// use std::path::Path;
+///
+/// assert_eq!(detect(Path::new("style.css"), b"a {}"), (Kind::Text, "text/css"));
+/// assert_eq!(detect(Path::new("logo"), b"\x89PNG\r\n\x1a\n\0"), (Kind::Binary, "image/png"));
+/// assert_eq!(detect(Path::new("LICENSE"), b"MIT"), (Kind::Text, "text/plain"));
+/// ```
+pub fn detect(path: &Path, content: &[u8]) -> (Kind, &'static str) {
+	let kind = if frontmatter::has_binary_extension(path) || !frontmatter::is_text(content) {
+		Kind::Binary
+	} else {
+		Kind::Text
+	};
+	let mime = match (from_extension(path), kind) {
+		(Some(mime), _) => mime,
+		(None, Kind::Text) => TEXT,
+		(None, Kind::Binary) => from_content(content).unwrap_or(BINARY),
+	};
+	(kind, mime)
+}
@@ -0,0 +1,159 @@
+//! Queries over the files of an IR, filtering and sorting them by their
+//! frontmatter. Queries can be built in Rust:
+//!
+//! ```
+//! use shtola::{IR, Plugin};
+//!
+//! fn plugin() -> Plugin {
+//!   Box::new(|ir: IR| {
+//!     let latest = ir.query().filter("tags", "rust").sort_by_desc("date").limit(5).run();
+//!     // ...
+//!     ir
+//!   })
+//! }
+//! ```
+//!
+//! or from a JSON description, which is how template engines and other
+//! plugins taking user configuration can expose them:
+//!
+//! ```json
+//! { "where": { "tags": "rust" }, "sort": "-date", "limit": 5 }
+//! ```
+
+use crate::frontmatter::get;
+use crate::json::Value;
+use crate::{ShFile, IR};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+/// A query over the files of an IR, created with
+/// [`IR::query`](../struct.IR.html#method.query). Files start out sorted by
+/// path.
+pub struct Query<'a> {
+	files: Vec<(&'a PathBuf, &'a ShFile)>,
+}
+
+impl<'a> Query<'a> {
+	pub(crate) fn new(ir: &'a IR) -> Query<'a> {
+		let mut files: Vec<(&PathBuf, &ShFile)> = ir.files.iter().map(|(p, f)| (p, f)).collect();
+		files.sort_by_key(|(p, _)| *p);
+		Query { files }
+	}
+
+	/// Keeps files whose frontmatter field equals the value or, if the field is
+	/// an array, contains it. Fields of nested objects can be accessed with
+	/// dots, as in `author.name`.
+	pub fn filter<V: Into<Value>>(self, field: &str, value: V) -> Query<'a> {
+		let value = value.into();
+		self.filter_by(|_, file| match get(&file.frontmatter, field) {
+			Some(Value::Array(items)) => items.contains(&value),
+			Some(v) => v == &value,
+			None => false,
+		})
+	}
+
+	/// Keeps files that have the given frontmatter field.
+	pub fn has(self, field: &str) -> Query<'a> {
+		self.filter_by(|_, file| get(&file.frontmatter, field).is_some())
+	}
+
+	/// Keeps files for which the predicate returns `true`.
+	pub fn filter_by<F: Fn(&Path, &ShFile) -> bool>(mut self, predicate: F) -> Query<'a> {
+		self.files.retain(|(p, f)| predicate(p, f));
+		self
+	}
+
+	/// Sorts files by a frontmatter field in ascending order. Numbers are
+	/// compared numerically, everything else as text, which works for ISO 8601
+	/// dates. Files without the field come last.
+	pub fn sort_by(mut self, field: &str) -> Query<'a> {
+		self.files.sort_by(|(_, a), (_, b)| {
+			compare(get(&a.frontmatter, field), get(&b.frontmatter, field))
+		});
+		self
+	}
+
+	/// Sorts files by a frontmatter field in descending order. Files without
+	/// the field come last.
+	pub fn sort_by_desc(mut self, field: &str) -> Query<'a> {
+		self.files.sort_by(|(_, a), (_, b)| {
+			match (get(&a.frontmatter, field), get(&b.frontmatter, field)) {
+				(Some(a), Some(b)) => compare(Some(b), Some(a)),
+				(a, b) => compare(a, b),
+			}
+		});
+		self
+	}
+
+	/// Keeps at most `n` files.
+	pub fn limit(mut self, n: usize) -> Query<'a> {
+		self.files.truncate(n);
+		self
+	}
+
+	/// Applies a query described as JSON. Supported keys are `where` (an
+	/// object of fields and values for [`filter`](#method.filter)), `has` (a
+	/// field name or a list of them), `sort` (a field name, prefixed with `-`
+	/// for descending order) and `limit`. Whatever order the keys are in, the
+	/// files are filtered first, then sorted, then limited.
+	pub fn apply(self, spec: &Value) -> Result<Query<'a>, String> {
+		let spec = spec.as_object().ok_or("Query must be an object")?;
+		let mut query = self;
+		let mut sort = None;
+		let mut limit = None;
+		for (key, value) in spec {
+			match (key.as_str(), value) {
+				("where", Value::Object(conditions)) => {
+					query = conditions
+						.iter()
+						.fold(query, |q, (field, value)| q.filter(field, value.clone()))
+				}
+				("has", Value::String(field)) => query = query.has(field),
+				("has", Value::Array(fields)) => {
+					query = fields
+						.iter()
+						.filter_map(Value::as_str)
+						.fold(query, |q, field| q.has(field))
+				}
+				("sort", Value::String(field)) => sort = Some(field),
+				("limit", Value::Number(n)) if n.is_u64() => limit = n.as_u64(),
+				_ => return Err(format!("Invalid query key {:?}", key)),
+			}
+		}
+		if let Some(field) = sort {
+			query = match field.strip_prefix('-') {
+				Some(field) => query.sort_by_desc(field),
+				None => query.sort_by(field),
+			};
+		}
+		if let Some(n) = limit {
+			query = query.limit(n as usize);
+		}
+		Ok(query)
+	}
+
+	/// Returns the matching files.
+	pub fn run(self) -> Vec<(&'a PathBuf, &'a ShFile)> {
+		self.files
+	}
+}
+
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+	match (a, b) {
+		(Some(Value::Number(a)), Some(Value::Number(b))) => {
+			let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+			a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+		}
+		(Some(a), Some(b)) => text(a).cmp(&text(b)),
+		(Some(_), None) => Ordering::Less,
+		(None, Some(_)) => Ordering::Greater,
+		(None, None) => Ordering::Equal,
+	}
+}
+
+fn text(value: &Value) -> String {
+	match value {
+		Value::String(s) => s.clone(),
+		v => v.to_string(),
+	}
+}
@@ -0,0 +1,61 @@
+//! Per-page controls for search engines, set in the frontmatter:
+//!
+//! ```yaml
+//! canonical: /guides/setup.html
+//! noindex: true
+//! ```
+//!
+//! `canonical` is the preferred URL of a page whose content is also published
+//! elsewhere. URLs starting with `/` are relative to the
+//! [base URL](../site/struct.SiteConfig.html#structfield.base_url) of the
+//! site. `noindex` asks search engines to leave the page out, and is read
+//! leniently like [`get_bool`](../frontmatter/fn.get_bool.html) does.
+//!
+//! Plugins that publish pages to search engines or visitors, like sitemaps and
+//! search indexes, read both with the functions of this module, so a page
+//! left out of one is left out of all of them.
+
+use crate::frontmatter::{get_bool, get_str, FieldError};
+use crate::ShFile;
+
+/// Whether the page asks to be left out of search engines.
+///
+/// ```
+/// use shtola::json::json;
+/// use shtola::robots::noindex;
+/// use shtola::ShFile;
+///
+/// let file = ShFile { frontmatter: json!({ "noindex": "yes" }), ..ShFile::empty() };
+/// assert!(noindex(&file).unwrap());
+/// assert!(!noindex(&ShFile::empty()).unwrap());
+/// ```
+pub fn noindex(file: &ShFile) -> Result<bool, FieldError> {
+	Ok(get_bool(&file.frontmatter, "noindex")? == Some(true))
+}
+
+/// The canonical URL of the page, if it sets one, resolved against the base
+/// URL of the site.
+///
+/// ```
+/// use shtola::json::json;
+/// use shtola::robots::canonical;
+/// use shtola::ShFile;
+///
+/// let file = ShFile { frontmatter: json!({ "canonical": "/a.html" }), ..ShFile::empty() };
+/// assert_eq!(
+///   canonical(&file, "https://example.com").unwrap().unwrap(),
+///   "https://example.com/a.html"
+/// );
+/// ```
+pub fn canonical(file: &ShFile, base_url: &str) -> Result<Option<String>, FieldError> {
+	let url = match get_str(&file.frontmatter, "canonical")? {
+		Some(url) => url,
+		None => return Ok(None),
+	};
+	match url.strip_prefix('/') {
+		Some(path) if !path.starts_with('/') => {
+			Ok(Some(format!("{}/{}", base_url.trim_end_matches('/'), path)))
+		}
+		_ => Ok(Some(url)),
+	}
+}
@@ -0,0 +1,148 @@
+//! Deterministic selection of content, like a featured post that changes
+//! every week or a shuffled list of testimonials. Choices are derived from a
+//! seed and, for rotating content, the
+//! [build date](../struct.IR.html#method.build_date), never from chance, so
+//! building the same sources on the same date gives the same site:
+//!
+//! ```
+//! use shtola::rotate::{Period, Selector};
+//! use shtola::{Plugin, IR};
+//!
+//! fn plugin() -> Plugin {
+//!   Box::new(|ir: IR| {
+//!     let mut posts: Vec<_> = ir.files_with_extension("md").map(|(p, _)| p).collect();
+//!     posts.sort();
+//!     let selector = Selector::for_period("featured", ir.build_date(), Period::Week);
+//!     let featured = selector.pick(&posts);
+//!     // ...
+//!     ir
+//!   })
+//! }
+//! ```
+//!
+//! Selections depend on the order of the items, so sort them first if they
+//! come from a map like [`IR::files`](../struct.IR.html#structfield.files).
+
+use crate::frontmatter::Date;
+use sha2::{Digest, Sha256};
+
+/// How often rotating content changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+	/// Every day.
+	Day,
+	/// Every week, starting on Mondays.
+	Week,
+	/// Every calendar month.
+	Month,
+	/// Every calendar year.
+	Year,
+}
+
+impl Period {
+	/// Parses the name of a period, like `week`, as used in templates.
+	pub fn from_name(name: &str) -> Option<Period> {
+		match name {
+			"day" => Some(Period::Day),
+			"week" => Some(Period::Week),
+			"month" => Some(Period::Month),
+			"year" => Some(Period::Year),
+			_ => None,
+		}
+	}
+
+	/// Numbers the periods, so that dates in the same period get the same
+	/// number and the next period gets the next one.
+	///
+	/// ```
+	/// use shtola::frontmatter::Date;
+	/// use shtola::rotate::Period;
+	///
+	/// let monday = Date::parse("2020-02-03").unwrap();
+	/// let sunday = Date::parse("2020-02-09").unwrap();
+	/// assert_eq!(Period::Week.index(monday), Period::Week.index(sunday));
+	/// assert_eq!(Period::Day.index(monday) + 6, Period::Day.index(sunday));
+	/// ```
+	pub fn index(self, date: Date) -> i64 {
+		match self {
+			Period::Day => date.days_since_epoch(),
+			// The epoch was a Thursday.
+			Period::Week => (date.days_since_epoch() + 3).div_euclid(7),
+			Period::Month => date.year as i64 * 12 + date.month as i64 - 1,
+			Period::Year => date.year as i64,
+		}
+	}
+}
+
+/// Picks and shuffles items based on a seed. The same seed always makes the
+/// same choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selector {
+	seed: u64,
+}
+
+impl Selector {
+	/// Creates a selector whose choices only depend on the given seed.
+	pub fn new(seed: &str) -> Selector {
+		let hash = Sha256::digest(seed.as_bytes());
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(&hash[..8]);
+		Selector {
+			seed: u64::from_le_bytes(bytes),
+		}
+	}
+
+	/// Creates a selector whose choices change once per period, counted from
+	/// the given date, usually the
+	/// [build date](../struct.IR.html#method.build_date).
+	pub fn for_period(seed: &str, date: Date, period: Period) -> Selector {
+		Selector::new(&format!("{}\0{:?}\0{}", seed, period, period.index(date)))
+	}
+
+	/// Picks an item. `None` if there are no items.
+	///
+	/// ```
+	/// use shtola::rotate::Selector;
+	///
+	/// let quotes = ["Hello", "Hi", "Hey"];
+	/// let selector = Selector::new("quote");
+	/// assert_eq!(selector.pick(&quotes), selector.pick(&quotes));
+	/// assert_eq!(selector.pick::<&str>(&[]), None);
+	/// ```
+	pub fn pick<'a, T>(&self, items: &'a [T]) -> Option<&'a T> {
+		if items.is_empty() {
+			return None;
+		}
+		let mut state = self.seed;
+		Some(&items[(next(&mut state) % items.len() as u64) as usize])
+	}
+
+	/// Shuffles items in place. Take the first few to pick several different
+	/// items.
+	///
+	/// ```
+	/// use shtola::rotate::Selector;
+	///
+	/// let mut numbers: Vec<u32> = (0..10).collect();
+	/// Selector::new("numbers").shuffle(&mut numbers);
+	/// numbers.sort();
+	/// assert_eq!(numbers, (0..10).collect::<Vec<u32>>());
+	/// ```
+	pub fn shuffle<T>(&self, items: &mut [T]) {
+		let mut state = self.seed;
+		for i in (1..items.len()).rev() {
+			let j = (next(&mut state) % (i as u64 + 1)) as usize;
+			items.swap(i, j);
+		}
+	}
+}
+
+// SplitMix64, which is plenty for picking content and stable across
+// platforms and releases.
+fn next(state: &mut u64) -> u64 {
+	*state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+	let mut z = *state;
+	z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	z ^ (z >> 31)
+}
@@ -0,0 +1,167 @@
+//! A development server, see
+//! [`Shtola::serve`](../struct.Shtola.html#method.serve). It serves the
+//! destination directory like most static hosts do: directories are served by
+//! their `index.html`, `/about` falls back to `about.html`, and missing files
+//! are answered with `404.html` if there is one. It's meant for previewing a
+//! site locally, not for production.
+//!
+//! Only available with the `serve` feature.
+
+use crate::decode;
+use crate::mime::{self, Kind};
+use crate::{resolve, Shtola, ShtolaError};
+use log::{debug, error, info, warn};
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+
+/// Options for the development server.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// The address to listen on. Default is `127.0.0.1`, which only accepts
+	/// connections from the same machine.
+	pub host: String,
+	/// The port to listen on, or `0` for any free port. Default is `8000`.
+	pub port: u16,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			host: "127.0.0.1".into(),
+			port: 8000,
+		}
+	}
+}
+
+/// A running server, which stops when it's dropped.
+pub struct Server {
+	http: Arc<tiny_http::Server>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+	/// The URL the server is reachable at, like `http://127.0.0.1:8000/`.
+	pub fn url(&self) -> String {
+		format!("http://{}/", self.http.server_addr())
+	}
+}
+
+impl Drop for Server {
+	fn drop(&mut self) {
+		self.http.unblock();
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Starts serving a directory in the background.
+pub fn serve_dir<P: Into<PathBuf>>(dir: P, options: &Options) -> Result<Server, ShtolaError> {
+	let dir = dir.into();
+	let address = format!("{}:{}", options.host, options.port);
+	let http = tiny_http::Server::http(&address).map_err(std::io::Error::other)?;
+	let http = Arc::new(http);
+	let requests = Arc::clone(&http);
+	let thread = thread::spawn(move || {
+		for request in requests.incoming_requests() {
+			respond(&dir, request);
+		}
+	});
+	Ok(Server {
+		http,
+		thread: Some(thread),
+	})
+}
+
+impl Shtola {
+	/// Serves the destination directory over HTTP and rebuilds whenever the
+	/// source directory changes, see [`serve`](serve/index.html) and
+	/// [`Shtola::watch`](#method.watch). Failed builds are logged and the
+	/// previous output keeps being served. Runs until the process is stopped.
+	///
+	/// ```no_run
+	/// use shtola::Shtola;
+	///
+	/// let mut s = Shtola::new();
+	/// s.source("site");
+	/// s.destination("_site");
+	/// s.serve(Default::default()).unwrap();
+	/// ```
+	///
+	/// Only available with the `serve` feature.
+	pub fn serve(&mut self, options: Options) -> Result<(), ShtolaError> {
+		let dir = resolve(&self.ir.config.destination, true)?;
+		let server = serve_dir(&dir, &options)?;
+		info!("Serving {:?} at {}", &dir, server.url());
+		self.watch(|result| {
+			match result {
+				Ok(ir) => info!("Built {} files", ir.files.len()),
+				Err(e) => error!("{}", e),
+			}
+			ControlFlow::Continue(())
+		})
+	}
+}
+
+fn respond(dir: &Path, request: Request) {
+	debug!("{} {}", request.method(), request.url());
+	let response = match request.method() {
+		Method::Get | Method::Head => match find(dir, request.url()) {
+			Some(path) => file_response(&path, 200),
+			None if dir.join("404.html").is_file() => file_response(&dir.join("404.html"), 404),
+			None => Response::from_string("Not found").with_status_code(404),
+		},
+		_ => Response::from_string("Method not allowed").with_status_code(405),
+	};
+	if let Err(e) = request.respond(response) {
+		warn!("Unable to respond: {}", e);
+	}
+}
+
+fn file_response(path: &Path, status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+	match fs::read(path) {
+		Ok(body) => {
+			let header = Header::from_bytes("Content-Type", content_type(path, &body)).unwrap();
+			Response::from_data(body)
+				.with_status_code(StatusCode(status))
+				.with_header(header)
+		}
+		Err(e) => Response::from_string(e.to_string()).with_status_code(500),
+	}
+}
+
+// Maps a request URL to a file in the served directory.
+fn find(dir: &Path, url: &str) -> Option<PathBuf> {
+	let path = url.split(['?', '#']).next().unwrap_or_default();
+	let relative = PathBuf::from(decode::percent(path).trim_start_matches('/'));
+	if relative
+		.components()
+		.any(|c| !matches!(c, Component::Normal(_)))
+	{
+		return None;
+	}
+	let path = dir.join(&relative);
+	if path.is_dir() {
+		Some(path.join("index.html")).filter(|p| p.is_file())
+	} else if path.is_file() {
+		Some(path)
+	} else {
+		// Appending instead of replacing the extension, so `/v1.2` is served
+		// by `v1.2.html`.
+		let mut html = path.into_os_string();
+		html.push(".html");
+		Some(PathBuf::from(html)).filter(|p| p.is_file())
+	}
+}
+
+// The MIME type from the same table the build uses, marking text as UTF-8.
+fn content_type(path: &Path, body: &[u8]) -> String {
+	match mime::detect(path, body) {
+		(Kind::Text, mime) => format!("{}; charset=utf-8", mime),
+		(Kind::Binary, mime) => mime.to_string(),
+	}
+}
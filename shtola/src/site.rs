@@ -0,0 +1,78 @@
+//! Settings describing the site as a whole, like its title and URL. Plugins
+//! read them from [`IR::site`](../struct.IR.html#structfield.site) instead of
+//! each expecting their own metadata keys. The settings are usually read from
+//! a YAML or JSON file set with
+//! [`Shtola::site_config`](../struct.Shtola.html#method.site_config):
+//!
+//! ```yaml
+//! title: My site
+//! base_url: https://example.com
+//! language: en
+//! author: Marisa
+//! params:
+//!   twitter: "@example"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// The settings of a site. All settings are optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+	/// The title of the site.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub title: Option<String>,
+	/// The absolute URL the site is served from, like `https://example.com`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub base_url: Option<String>,
+	/// The language of the site, like `en`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub language: Option<String>,
+	/// The author of the site.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub author: Option<String>,
+	/// Any further settings, for themes and plugins.
+	#[serde(skip_serializing_if = "Map::is_empty")]
+	pub params: Map<String, Value>,
+}
+
+impl SiteConfig {
+	/// Reads the settings from a YAML or JSON file.
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<SiteConfig, std::io::Error> {
+		let text = fs::read_to_string(path)?;
+		serde_yaml::from_str(&text).map_err(std::io::Error::other)
+	}
+
+	/// The base URL without a trailing slash, or an empty string if there is
+	/// none, so it can be put in front of site-absolute URLs.
+	pub fn base_url(&self) -> &str {
+		self.base_url
+			.as_deref()
+			.unwrap_or_default()
+			.trim_end_matches('/')
+	}
+
+	/// Turns a site-absolute URL like `/blog/` into an absolute one using the
+	/// base URL.
+	///
+	/// ```
+	/// use shtola::site::SiteConfig;
+	///
+	/// let site = SiteConfig {
+	///   base_url: Some("https://example.com/".into()),
+	///   ..Default::default()
+	/// };
+	/// assert_eq!(site.absolute_url("/blog/"), "https://example.com/blog/");
+	/// ```
+	pub fn absolute_url(&self, url: &str) -> String {
+		format!("{}/{}", self.base_url(), url.trim_start_matches('/'))
+	}
+
+	/// Gets a value from the free-form parameters.
+	pub fn param(&self, key: &str) -> Option<&Value> {
+		self.params.get(key)
+	}
+}
@@ -0,0 +1,50 @@
+//! Serde helpers for IR snapshots, see
+//! [`IR::to_json_file`](../struct.IR.html#method.to_json_file).
+
+/// Serializes an `im::HashMap` with its keys sorted, so snapshots of the same
+/// IR are identical and diff cleanly.
+pub(crate) mod sorted {
+	use crate::HashMap;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use std::collections::BTreeMap;
+	use std::hash::Hash;
+
+	pub fn serialize<S, K, V>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+		K: Serialize + Ord + Hash + Clone,
+		V: Serialize + Clone,
+	{
+		map.iter()
+			.map(|(k, v)| (k, v))
+			.collect::<BTreeMap<_, _>>()
+			.serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+	where
+		D: Deserializer<'de>,
+		K: Deserialize<'de> + Ord + Hash + Clone,
+		V: Deserialize<'de> + Clone,
+	{
+		let map = BTreeMap::<K, V>::deserialize(deserializer)?;
+		Ok(map.into_iter().collect())
+	}
+}
+
+/// Serializes bytes as a base64 string.
+pub(crate) mod base64 {
+	use base64::engine::general_purpose::STANDARD;
+	use base64::Engine;
+	use serde::de::Error;
+	use serde::{Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&STANDARD.encode(bytes))
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+		let text = String::deserialize(deserializer)?;
+		STANDARD.decode(text).map_err(D::Error::custom)
+	}
+}
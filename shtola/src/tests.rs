@@ -1,7 +1,15 @@
-use crate::json::json;
-use crate::{HashMap, ShFile, Shtola, IR};
+use crate::frontmatter::Date;
+use crate::json::{self, json};
+use crate::manifest::Manifest;
+use crate::mime::Kind;
+use crate::rotate::{Period, Selector};
+use crate::{
+	read_dir, write_dir, Config, HashMap, InvalidFrontmatter, LineEndings, Origin, PluginError,
+	PluginInfo, PreservePermissions, ShFile, Shtola, ShtolaError, SourceVerification,
+	UnclosedFrontmatter, IR,
+};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn read_works() {
@@ -15,6 +23,7 @@ fn read_works() {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn clean_works() {
 	let mut s = Shtola::new();
 	s.source("../fixtures/simple");
@@ -39,8 +48,8 @@ fn write_works() {
 			update_hash.insert(
 				k.into(),
 				ShFile {
-					frontmatter: v.frontmatter.clone(),
 					content: "hello".into(),
+					..v.clone()
 				},
 			);
 		}
@@ -96,6 +105,160 @@ fn ignore_works() {
 	assert_eq!(path.to_str().unwrap(), "not_ignored.md");
 }
 
+#[test]
+fn include_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/include");
+	s.destination("../fixtures/dest_include");
+	s.includes(&mut vec!["**/*.md".into(), "*.toml".into()]);
+	s.ignores(&mut vec!["drafts".into(), "sub/d.md".into()]);
+	s.clean(true);
+	let r = s.build().unwrap();
+	let mut paths: Vec<&PathBuf> = r.files.keys().collect();
+	paths.sort();
+	assert_eq!(paths, vec![Path::new("a.md"), Path::new("config.toml")]);
+	assert_eq!(r.report.ignored, 4);
+}
+
+#[test]
+fn drafts_work() {
+	let build = |drafts: bool| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/drafts");
+		s.destination("../fixtures/dest_drafts");
+		s.clean(true);
+		s.drafts(drafts);
+		s.build().unwrap()
+	};
+	let r = build(true);
+	assert_eq!(r.files.len(), 4);
+	assert_eq!(r.report.drafts, 0);
+	let r = build(false);
+	let mut paths: Vec<&PathBuf> = r.files.keys().collect();
+	paths.sort();
+	assert_eq!(paths, vec![Path::new("done.md"), Path::new("live.md")]);
+	assert_eq!(r.report.drafts, 2);
+	assert_eq!(r.report.read, 4);
+}
+
+#[test]
+fn unchanged_outputs_are_skipped() {
+	let dest = Path::new("../fixtures/dest_skip_unchanged");
+	let _ = fs::remove_dir_all(dest);
+	let build = |content: &'static str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/defaults");
+		s.destination(dest);
+		s.register(Box::new(move |ir: IR| {
+			ir.create_file("generated.txt", content, json!(null))
+		}));
+		s.build().unwrap()
+	};
+	let r = build("First");
+	assert_eq!((r.report.written, r.report.skipped), (5, 0));
+	let modified = || {
+		fs::metadata(dest.join("about.md"))
+			.unwrap()
+			.modified()
+			.unwrap()
+	};
+	let before = modified();
+	std::thread::sleep(std::time::Duration::from_millis(20));
+	let r = build("First");
+	assert_eq!((r.report.written, r.report.skipped), (0, 5));
+	assert_eq!(modified(), before);
+	let r = build("Second");
+	assert_eq!((r.report.written, r.report.skipped), (1, 4));
+	assert_eq!(
+		fs::read_to_string(dest.join("generated.txt")).unwrap(),
+		"Second"
+	);
+}
+
+#[test]
+fn atomic_works() {
+	let dest = Path::new("../fixtures/dest_atomic");
+	let staging = Path::new("../fixtures/.dest_atomic.shtola-staging");
+	let old = Path::new("../fixtures/.dest_atomic.shtola-old");
+	let _ = fs::remove_dir_all(dest);
+	let build = |broken: bool| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/simple");
+		s.destination(dest);
+		s.atomic(true);
+		if broken {
+			// A file can't be written where a directory has to be created.
+			s.register(Box::new(|ir: IR| {
+				ir.create_file("hello.txt/nested.txt", "", json!(null))
+			}));
+		}
+		s.build()
+	};
+	let r = build(false).unwrap();
+	assert_eq!(r.report.written, 1);
+	assert!(dest.join("hello.txt").exists());
+	assert!(!staging.exists() && !old.exists());
+
+	fs::write(dest.join("extra.txt"), "Extra").unwrap();
+	fs::create_dir_all(staging.join("leftover")).unwrap();
+	let r = build(false).unwrap();
+	assert_eq!((r.report.written, r.report.skipped), (0, 1));
+	assert!(dest.join("extra.txt").exists());
+	assert!(!dest.join("leftover").exists());
+	assert!(!staging.exists() && !old.exists());
+
+	assert!(build(true).is_err());
+	assert!(dest.join("hello.txt").is_file());
+	assert!(dest.join("extra.txt").exists());
+	assert!(!staging.exists() && !old.exists());
+}
+
+#[test]
+fn atomic_recovers_and_skips_siblings() {
+	let source = Path::new("../fixtures/dest_atomic_source");
+	let dest = source.join("dest");
+	let old = source.join(".dest.shtola-old");
+	let _ = fs::remove_dir_all(source);
+	fs::create_dir_all(source.join(".dest.shtola-staging")).unwrap();
+	fs::write(source.join("hello.txt"), "Hello").unwrap();
+	fs::write(source.join(".dest.shtola-staging/leftover.txt"), "").unwrap();
+	let build = || {
+		let mut s = Shtola::new();
+		s.source(source);
+		s.destination(&dest);
+		s.atomic(true);
+		s.build().unwrap()
+	};
+	let r = build();
+	let paths: Vec<&PathBuf> = r.files.keys().collect();
+	assert_eq!(paths, vec![Path::new("hello.txt")]);
+
+	// A build killed between moving the destination aside and replacing it.
+	fs::rename(&dest, &old).unwrap();
+	let r = build();
+	assert_eq!(r.files.len(), 1);
+	assert_eq!((r.report.written, r.report.skipped), (0, 1));
+	assert!(dest.join("hello.txt").is_file() && !old.exists());
+}
+
+#[test]
+fn build_report_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/ignore");
+	s.destination("../fixtures/dest_report");
+	s.ignores(&mut vec!["ignored.md".to_string()]);
+	s.clean(true);
+	s.register_with(PluginInfo::new("noop"), Box::new(|ir: IR| ir));
+	s.register(Box::new(|ir: IR| ir));
+	let r = s.build().unwrap();
+	assert_eq!(r.report.read, 1);
+	assert_eq!(r.report.ignored, 1);
+	assert_eq!(r.report.written, 1);
+	assert!(r.report.timings.total >= r.report.timings.read);
+	let names: Vec<_> = r.report.plugin_timings.iter().map(|t| &t.name).collect();
+	assert_eq!(names, vec![&Some("noop".to_string()), &None]);
+}
+
 #[test]
 fn metadata_works() {
 	let mut s = Shtola::new();
@@ -121,3 +284,1804 @@ fn metadata_works() {
 	assert_eq!(r.metadata.get("test").unwrap(), &json!(["a", "b", "c"]));
 	assert_eq!(r.metadata.get("test2").unwrap(), &json!({"bar": "baz"}));
 }
+
+#[test]
+fn checkers_work() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_check");
+	s.clean(true);
+	fs::write("../fixtures/dest_check/previous.txt", "").unwrap();
+	s.check(Box::new(|_: &IR| {
+		Err(vec!["first".into(), "second".into()])
+	}));
+	s.check(Box::new(|_: &IR| Ok(())));
+	s.check(Box::new(|_: &IR| Err(vec!["third".into()])));
+	let err = s.build().unwrap_err().to_string();
+	assert!(err.contains("first") && err.contains("second") && err.contains("third"));
+	assert!(PathBuf::from("../fixtures/dest_check/previous.txt").exists());
+	assert!(!PathBuf::from("../fixtures/dest_check/hello.txt").exists());
+}
+
+#[test]
+fn required_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_required");
+	s.required(&mut vec!["hello.txt".into(), "feed.xml".into()]);
+	let err = s.build().unwrap_err().to_string();
+	assert!(err.contains("\"feed.xml\""));
+	assert!(!err.contains("\"hello.txt\""));
+}
+
+#[test]
+fn duplicate_outputs_fail() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_duplicates");
+	s.clean(true);
+	s.register(Box::new(|ir: IR| {
+		let files = ir
+			.files
+			.update("HELLO.txt".into(), ShFile::empty())
+			.update("hello.txt/inner.txt".into(), ShFile::empty());
+		IR { files, ..ir }
+	}));
+	let err = s.build().unwrap_err().to_string();
+	assert!(err.contains("\"HELLO.txt\" and \"hello.txt\" would be written to the same file"));
+	assert!(err.contains("\"hello.txt/inner.txt\" needs \"hello.txt\" as its directory"));
+	assert!(!PathBuf::from("../fixtures/dest_duplicates/hello.txt").exists());
+}
+
+#[test]
+fn manifest_works() {
+	let manifest = PathBuf::from("../fixtures/dest_manifest.json");
+	let _ = fs::remove_file(&manifest);
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_manifest");
+	s.manifest(&manifest);
+	let r = s.build().unwrap();
+	let summary = r.report.summary.unwrap();
+	assert_eq!(summary.added, vec![PathBuf::from("hello.txt")]);
+	assert_eq!(summary.size_delta, 5);
+
+	s.register(Box::new(|ir: IR| {
+		let files = ir.files.update(
+			"hello.txt".into(),
+			ShFile {
+				content: "hi".into(),
+				..ShFile::empty()
+			},
+		);
+		IR { files, ..ir }
+	}));
+	let r = s.build().unwrap();
+	let summary = r.report.summary.unwrap();
+	assert_eq!(summary.changed, vec![PathBuf::from("hello.txt")]);
+	assert_eq!(
+		summary.to_string(),
+		"0 added, 1 changed, 0 deleted, -3 B total"
+	);
+	fs::remove_file(&manifest).unwrap();
+}
+
+#[test]
+fn manifest_versions_work() {
+	let manifest = PathBuf::from("../fixtures/dest_manifest_versions.json");
+	let _ = fs::remove_file(&manifest);
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_manifest_versions");
+	s.manifest(&manifest);
+	s.register_with(
+		PluginInfo::new("versioned").version("1.2.3"),
+		Box::new(|ir: IR| ir.record_tool("sass", "1.69.5")),
+	);
+	s.register_with(PluginInfo::new("unversioned"), Box::new(|ir: IR| ir));
+	let r = s.build().unwrap();
+	assert_eq!(r.report.tools["sass"], "1.69.5");
+	let versions = Manifest::read(&manifest).unwrap().versions.unwrap();
+	assert_eq!(versions.shtola, env!("CARGO_PKG_VERSION"));
+	assert_eq!(versions.plugins.len(), 1);
+	assert_eq!(versions.plugins["versioned"], "1.2.3");
+	assert_eq!(versions.tools["sass"], "1.69.5");
+	fs::remove_file(&manifest).unwrap();
+}
+
+#[test]
+fn plugin_order_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_order");
+	s.register_with(
+		PluginInfo::new("templates").after("markdown"),
+		Box::new(|ir: IR| ir),
+	);
+	s.register(Box::new(|ir: IR| ir));
+	s.register_with(
+		PluginInfo::new("markdown").before("feed"),
+		Box::new(|ir: IR| ir),
+	);
+	let err = s.build().unwrap_err().to_string();
+	assert!(err.contains("Plugin \"templates\" must run after \"markdown\""));
+	assert!(!err.contains("feed"));
+}
+
+#[test]
+fn plugin_phases_work() {
+	use crate::Phase;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_phases");
+	s.register_with(
+		PluginInfo::new("links")
+			.after_phase(Phase::Read)
+			.before_phase(Phase::Write),
+		Box::new(|ir: IR| ir),
+	);
+	s.build().unwrap();
+	s.register_with(
+		PluginInfo::new("deploy").after_phase(Phase::Write),
+		Box::new(|ir: IR| ir),
+	);
+	s.register_with(
+		PluginInfo::new("sources").before_phase(Phase::Read),
+		Box::new(|ir: IR| ir),
+	);
+	let err = s.build().unwrap_err().to_string();
+	assert!(
+		err.contains("Plugin \"deploy\" must run after the write phase, but plugins run before it")
+	);
+	assert!(
+		err.contains("Plugin \"sources\" must run before the read phase, but plugins run after it")
+	);
+	assert!(!err.contains("links"));
+}
+
+#[test]
+fn fallible_plugins_work() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_fallible");
+	s.register(Box::new(|ir: IR| ir));
+	s.register_fallible_with(
+		PluginInfo::new("feed"),
+		Box::new(|_| Err(PluginError::new("No posts found"))),
+	);
+	s.register(Box::new(|_| panic!("the build should have stopped")));
+	let err = s.build().unwrap_err();
+	assert_eq!(err.to_string(), "Plugin \"feed\" failed: No posts found");
+	match err {
+		ShtolaError::Plugin(e) => assert_eq!(e.position, Some(1)),
+		e => panic!("unexpected error: {:?}", e),
+	}
+	assert!(!Path::new("../fixtures/dest_fallible/hello.txt").exists());
+}
+
+#[test]
+fn branch_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/ignore");
+	s.destination("../fixtures/dest_branch");
+	let branch = Shtola::branch("not_*")
+		.unwrap()
+		.register(Box::new(|ir: IR| {
+			assert_eq!(ir.files.len(), 1);
+			let mut update_hash: HashMap<PathBuf, ShFile> = HashMap::new();
+			for (k, v) in &ir.files {
+				update_hash.insert(
+					k.into(),
+					ShFile {
+						content: "branched".into(),
+						..v.clone()
+					},
+				);
+			}
+			IR {
+				files: update_hash.union(ir.files),
+				metadata: ir.metadata.update("branch".into(), json!(true)),
+				..ir
+			}
+		}))
+		.build();
+	s.register(branch);
+	let r = s.build().unwrap();
+	assert_eq!(r.files.len(), 2);
+	let branched = r.files.get(&PathBuf::from("not_ignored.md")).unwrap();
+	assert_eq!(branched.content, b"branched");
+	let untouched = r.files.get(&PathBuf::from("ignored.md")).unwrap();
+	assert_eq!(untouched.content, b"test\n");
+	assert_eq!(r.metadata.get("branch").unwrap(), &json!(true));
+}
+
+#[test]
+fn strict_fails_on_file_errors() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/broken");
+	s.destination("../fixtures/dest_strict");
+	let err = s.build().unwrap_err();
+	assert!(err.to_string().contains("broken.md"));
+	match err {
+		ShtolaError::Verification { files, .. } => {
+			assert!(files.iter().any(|f| f.path == Path::new("broken.md")))
+		}
+		e => panic!("unexpected error: {:?}", e),
+	}
+}
+
+#[test]
+fn configuration_errors_are_returned() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/does_not_exist");
+	match s.build().unwrap_err() {
+		ShtolaError::Path(path, _) => assert_eq!(path, Path::new("../fixtures/does_not_exist")),
+		e => panic!("unexpected error: {:?}", e),
+	}
+	assert!(matches!(
+		Shtola::branch("blog/[").map(|_| ()),
+		Err(ShtolaError::Glob(_))
+	));
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_glob");
+	s.ignores(&mut vec!["{unclosed".into()]);
+	assert!(matches!(s.build(), Err(ShtolaError::Glob(_))));
+}
+
+#[test]
+fn builder_works() {
+	let mut s = Shtola::builder()
+		.source("../fixtures/simple")
+		.destination("../fixtures/dest_builder")
+		.clean(true)
+		.plugin(Box::new(|ir: IR| IR {
+			files: ir.files.update("new.txt".into(), ShFile::empty()),
+			..ir
+		}))
+		.build_pipeline()
+		.unwrap();
+	let r = s.build().unwrap();
+	assert!(r.files.contains_key(Path::new("new.txt")));
+
+	let missing = Shtola::builder()
+		.source("../fixtures/does_not_exist")
+		.build_pipeline();
+	assert!(matches!(missing, Err(ShtolaError::Path(..))));
+	let nested = Shtola::builder()
+		.source("../fixtures/simple")
+		.destination("../fixtures/simple/../simple/out")
+		.build_pipeline();
+	assert!(matches!(nested, Err(ShtolaError::Config(_))));
+	let target = Shtola::builder()
+		.source("../fixtures/simple")
+		.destination("../fixtures/dest_builder")
+		.add_destination("../fixtures/simple", Vec::new())
+		.build_pipeline();
+	assert!(matches!(target, Err(ShtolaError::Config(_))));
+	let ignored = Shtola::builder()
+		.source("../fixtures/simple")
+		.destination("../fixtures/simple/out")
+		.ignores(vec!["out".into()])
+		.build_pipeline();
+	assert!(ignored.is_ok());
+}
+
+#[test]
+fn non_strict_collects_file_errors() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/broken");
+	s.destination("../fixtures/dest_non_strict");
+	s.strict(false);
+	s.register(Box::new(|ir: IR| ir.fail_file("fine.md", "Render error")));
+	let r = s.build().unwrap();
+	assert!(r.files.is_empty());
+	let paths: Vec<&PathBuf> = r.report.errors.iter().map(|e| &e.path).collect();
+	assert_eq!(
+		paths,
+		vec![&PathBuf::from("broken.md"), &PathBuf::from("fine.md")]
+	);
+	assert_eq!(r.report.errors[1].message, "Render error");
+}
+
+#[test]
+fn invalid_frontmatter_can_be_content() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/broken");
+	s.destination("../fixtures/dest_invalid_frontmatter");
+	s.invalid_frontmatter(InvalidFrontmatter::Content);
+	let r = s.build().unwrap();
+	assert!(r.report.errors.is_empty());
+	let broken = r.files.get(Path::new("broken.md")).unwrap();
+	assert!(broken.frontmatter.is_null());
+	assert_eq!(broken.content, b"---\ntitle: [unclosed\n---\nbody\n");
+	let fine = r.files.get(Path::new("fine.md")).unwrap();
+	assert_eq!(fine.frontmatter, json!({"title": "fine"}));
+}
+
+#[test]
+fn line_endings_work() {
+	let build = |mode: LineEndings, dest: &str| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/line_endings");
+		s.destination(dest);
+		s.line_endings(mode);
+		s.build().unwrap()
+	};
+	let r = build(LineEndings::Lf, "../fixtures/dest_line_endings_lf");
+	for path in &["windows.txt", "unix.txt"] {
+		let written = fs::read(Path::new("../fixtures/dest_line_endings_lf").join(path)).unwrap();
+		assert_eq!(written, b"line one\nline two\n");
+	}
+	let png = fs::read("../fixtures/line_endings/logo.png").unwrap();
+	assert_eq!(r.files.get(Path::new("logo.png")).unwrap().content, png);
+
+	let r = build(LineEndings::Crlf, "../fixtures/dest_line_endings_crlf");
+	for path in &["windows.txt", "unix.txt"] {
+		let file = r.files.get(Path::new(path)).unwrap();
+		assert_eq!(file.content, b"line one\r\nline two\r\n");
+	}
+	assert_eq!(r.files.get(Path::new("logo.png")).unwrap().content, png);
+
+	let r = build(LineEndings::Keep, "../fixtures/dest_line_endings_keep");
+	let windows = r.files.get(Path::new("windows.txt")).unwrap();
+	assert_eq!(windows.content, b"line one\r\nline two\r\n");
+}
+
+#[test]
+fn keep_frontmatter_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/keep_frontmatter");
+	s.destination("../fixtures/dest_keep_frontmatter");
+	s.keep_frontmatter(true);
+	s.register(Box::new(|ir: IR| {
+		let path = PathBuf::from("changed.md");
+		let mut file = ir.files.get(&path).unwrap().clone();
+		file.frontmatter["draft"] = json!(false);
+		IR {
+			files: ir.files.update(path, file),
+			..ir
+		}
+	}));
+	s.build().unwrap();
+	let written = |path: &str| {
+		fs::read_to_string(Path::new("../fixtures/dest_keep_frontmatter").join(path)).unwrap()
+	};
+	assert_eq!(
+		written("kept.md"),
+		"---\ntitle: Kept # as written\n---\n# Kept"
+	);
+	assert_eq!(
+		written("changed.md"),
+		"---\ntitle: Changed\ndraft: false\n---\n# Changed"
+	);
+	assert_eq!(written("json.md"), "{\n  \"title\": \"JSON\"\n}\n# JSON");
+	assert_eq!(written("plain.txt"), "No frontmatter\n");
+}
+
+#[test]
+fn defaults_work() {
+	let build = |name: Option<&str>| {
+		let mut s = Shtola::new();
+		s.source("../fixtures/defaults");
+		s.destination("../fixtures/dest_defaults");
+		s.clean(true);
+		s.defaults_file(name);
+		s.build().unwrap()
+	};
+	let r = build(Some("_defaults.yml"));
+	let frontmatter = |path: &str| r.files.get(Path::new(path)).unwrap().frontmatter.clone();
+	assert_eq!(r.files.len(), 4);
+	assert_eq!(
+		frontmatter("about.md"),
+		json!({ "layout": "page", "author": "Fiona", "title": "About" })
+	);
+	assert_eq!(
+		frontmatter("blog/first.md"),
+		json!({ "layout": "post", "author": "Fiona", "comments": true, "title": "First" })
+	);
+	assert_eq!(
+		frontmatter("blog/2020/old.md"),
+		json!({ "layout": "post", "author": "Guest", "comments": false, "title": "Old" })
+	);
+	assert_eq!(frontmatter("style.css"), json!(null));
+	let r = build(None);
+	assert_eq!(r.files.len(), 7);
+	assert_eq!(
+		r.files.get(Path::new("about.md")).unwrap().frontmatter,
+		json!({ "title": "About" })
+	);
+}
+
+#[test]
+fn data_dir_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/data");
+	s.destination("../fixtures/dest_data");
+	s.clean(true);
+	s.data_dir("_data");
+	s.register(Box::new(|ir: IR| {
+		assert_eq!(ir.metadata.get("menu").unwrap()[1]["url"], "/blog/");
+		ir
+	}));
+	let r = s.build().unwrap();
+	assert_eq!(r.files.len(), 1);
+	assert!(r.files.contains_key(Path::new("index.md")));
+	assert_eq!(
+		r.metadata.get("authors").unwrap(),
+		&json!({ "marisa": { "name": "Marisa" } })
+	);
+	assert_eq!(
+		r.metadata.get("site").unwrap(),
+		&json!({ "title": "My site", "social": { "mastodon": "@marisa" } })
+	);
+	assert!(r.metadata.get("README").is_none());
+}
+
+#[test]
+fn lexer_works() {
+	use crate::frontmatter::lexer;
+
+	let (matter, content) = lexer("---\nhello: bro\n---\ncontent\n").unwrap();
+	assert_eq!(matter, "hello: bro");
+	assert_eq!(content, "content");
+	let (matter, content) = lexer("---\nhello: bro\n---").unwrap();
+	assert_eq!(matter, "hello: bro");
+	assert_eq!(content, "");
+	let (matter, content) = lexer("no frontmatter\n---\n").unwrap();
+	assert_eq!(matter, "");
+	assert_eq!(content, "no frontmatter\n---\n");
+	assert!(lexer("---\n\nA horizontal rule.\n").is_none());
+	let (matter, content) = lexer("---json\n{\"a\": 1}\n---\n{body}").unwrap();
+	assert_eq!(matter, "{\"a\": 1}");
+	assert_eq!(content, "{body}");
+	assert!(lexer("---json\n{\"a\": 1}\n").is_none());
+}
+
+#[test]
+fn crlf_frontmatter_works() {
+	use crate::frontmatter::{lexer, to_json, Format};
+
+	let text = "---\r\ntitle: Hello\r\ntags: [a]\r\n---\r\ncontent\r\n";
+	let (matter, content) = lexer(text).unwrap();
+	assert_eq!(matter, "title: Hello\r\ntags: [a]");
+	assert_eq!(content, "content");
+	assert_eq!(
+		to_json(&matter).unwrap(),
+		json!({ "title": "Hello", "tags": ["a"] })
+	);
+	let text = "---json\r\n{\"a\": 1}\r\n---\r\n{body}";
+	let (matter, content) = lexer(text).unwrap();
+	assert_eq!(matter, "{\"a\": 1}");
+	assert_eq!(content, "{body}");
+	assert_eq!(Format::of(text, &matter), Format::FencedJson);
+}
+
+#[test]
+fn unclosed_frontmatter_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/rule");
+	s.destination("../fixtures/dest_rule");
+	let err = s.build().unwrap_err().to_string();
+	assert!(err.contains("rule.md"));
+
+	s.unclosed_frontmatter(UnclosedFrontmatter::Content);
+	let r = s.build().unwrap();
+	let file = r.files.get(&PathBuf::from("rule.md")).unwrap();
+	assert!(file.frontmatter.is_null());
+	assert!(file.content.starts_with(b"---\n"));
+}
+
+#[test]
+fn json_frontmatter_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/json");
+	s.destination("../fixtures/dest_json");
+	let r = s.build().unwrap();
+	let post = r.files.get(&PathBuf::from("post.md")).unwrap();
+	assert_eq!(
+		post.frontmatter,
+		json!({"title": "Hello", "tags": ["a", "b"]})
+	);
+	assert_eq!(post.content, b"# Hello");
+	let fenced = r.files.get(&PathBuf::from("fenced.md")).unwrap();
+	assert_eq!(
+		fenced.frontmatter,
+		json!({"title": "Fenced", "weight": 1.0, "nested": {"on": "yes"}})
+	);
+	assert_eq!(fenced.content, b"{{ content }}");
+	assert_eq!(
+		String::from_utf8(fenced.serialize_with_frontmatter().unwrap()).unwrap(),
+		"---json\n{\"title\": \"Fenced\", \"weight\": 1.0, \"nested\": {\"on\": \"yes\"}}\n---\n{{ content }}"
+	);
+	let changed = ShFile {
+		frontmatter: json!({"title": "Changed"}),
+		..fenced.clone()
+	};
+	assert_eq!(
+		String::from_utf8(changed.serialize_with_frontmatter().unwrap()).unwrap(),
+		"---json\n{\n  \"title\": \"Changed\"\n}\n---\n{{ content }}"
+	);
+	let data = r.files.get(&PathBuf::from("data.json")).unwrap();
+	assert!(data.frontmatter.is_null());
+	assert_eq!(data.content, b"{\"data\": true}\n");
+	// The first record of a JSON Lines file isn't frontmatter.
+	let events = r.files.get(&PathBuf::from("events.jsonl")).unwrap();
+	assert!(events.frontmatter.is_null());
+	assert_eq!(
+		events.content,
+		b"{\"event\": \"start\"}\n{\"event\": \"stop\"}\n"
+	);
+}
+
+#[test]
+fn split_works() {
+	use crate::frontmatter::{split, Separators};
+
+	let separators = Separators {
+		frontmatter: "+++".into(),
+		excerpt: "<!--cut-->".into(),
+	};
+	let doc = split("+++\nhello: bro\n+++\nIntro<!--cut--> rest", &separators).unwrap();
+	assert_eq!(doc.frontmatter, "hello: bro");
+	assert_eq!(doc.excerpt.unwrap(), "Intro");
+	assert_eq!(doc.body, "Intro rest");
+	let doc = split("No excerpt here", &Separators::default()).unwrap();
+	assert_eq!(doc.frontmatter, "");
+	assert_eq!(doc.excerpt, None);
+	assert_eq!(doc.body, "No excerpt here");
+	assert!(split("+++\nunclosed", &separators).is_none());
+}
+
+#[test]
+fn frontmatter_getters_work() {
+	use crate::frontmatter::{get_bool, get_date, get_str, get_vec, Date};
+
+	let matter = json!({
+		"draft": "Yes",
+		"sitemap": 0,
+		"date": "2020-02-29T10:00:00Z",
+		"bad_date": "2019-02-29",
+		"tags": "rust",
+		"nested": { "flags": ["a", "b"], "title": "Nested" },
+		"empty": null,
+	});
+	assert_eq!(get_bool(&matter, "draft"), Ok(Some(true)));
+	assert_eq!(get_bool(&matter, "sitemap"), Ok(Some(false)));
+	assert_eq!(get_bool(&matter, "missing"), Ok(None));
+	assert_eq!(
+		get_bool(&matter, "tags").unwrap_err().to_string(),
+		"Frontmatter key \"tags\" should be a boolean, but is a string"
+	);
+	assert_eq!(get_str(&matter, "nested.title"), Ok(Some("Nested".into())));
+	assert_eq!(get_str(&matter, "empty"), Ok(None));
+	assert_eq!(
+		get_date(&matter, "date"),
+		Ok(Some(Date {
+			year: 2020,
+			month: 2,
+			day: 29
+		}))
+	);
+	assert!(get_date(&matter, "bad_date").is_err());
+	assert_eq!(get_vec(&matter, "tags"), Ok(vec![json!("rust")]));
+	assert_eq!(
+		get_vec(&matter, "nested.flags"),
+		Ok(vec![json!("a"), json!("b")])
+	);
+	assert_eq!(get_vec(&matter, "missing"), Ok(vec![]));
+	assert_eq!(
+		get_vec(&matter, "nested").unwrap_err().to_string(),
+		"Frontmatter key \"nested\" should be a list, but is an object"
+	);
+}
+
+#[test]
+fn frontmatter_as_works() {
+	use serde::Deserialize;
+
+	#[derive(Debug, Deserialize, PartialEq)]
+	struct Author {
+		name: String,
+	}
+
+	#[derive(Debug, Deserialize, PartialEq)]
+	struct Post {
+		title: String,
+		#[serde(default)]
+		weight: u32,
+		author: Option<Author>,
+	}
+
+	let file = |frontmatter| ShFile {
+		frontmatter,
+		..ShFile::empty()
+	};
+	assert_eq!(
+		file(json!({"title": "Hi", "author": {"name": "marisa"}, "extra": true}))
+			.frontmatter_as::<Post>()
+			.unwrap(),
+		Post {
+			title: "Hi".into(),
+			weight: 0,
+			author: Some(Author {
+				name: "marisa".into()
+			}),
+		}
+	);
+	let error = |frontmatter| file(frontmatter).frontmatter_as::<Post>().unwrap_err();
+	assert_eq!(
+		error(json!(null)).to_string(),
+		"Frontmatter key \"title\" is missing"
+	);
+	assert_eq!(
+		error(json!({"title": "Hi", "author": {}})).key,
+		"author.name"
+	);
+	let wrong = error(json!({"title": "Hi", "weight": "heavy"}));
+	assert_eq!(wrong.key, "weight");
+	assert_eq!(
+		wrong.message,
+		"is invalid: invalid type: string \"heavy\", expected u32"
+	);
+}
+
+#[test]
+fn multi_document_frontmatter_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/multi");
+	s.destination("../fixtures/dest_multi");
+	s.multi_document_frontmatter(true);
+	let r = s.build().unwrap();
+	let file = r.files.get(&PathBuf::from("legacy.md")).unwrap();
+	assert_eq!(
+		file.frontmatter,
+		json!({"title": "Legacy", "author": "me", "tags": ["b"]})
+	);
+	assert_eq!(file.content, b"Content\n\n---\n\nMore content");
+
+	assert!(crate::frontmatter::to_json("a: 1\n---\n- b").is_err());
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/multi");
+	s.destination("../fixtures/dest_multi_off");
+	s.strict(false);
+	let r = s.build().unwrap();
+	assert!(r.files.get(&PathBuf::from("legacy.md")).is_none());
+	let error = &r.report.errors[0];
+	assert_eq!(error.path, PathBuf::from("legacy.md"));
+	assert_eq!(error.location.as_ref().unwrap().line, 5);
+	assert!(error.message.contains("followed by another YAML document"));
+	let separated = "---\ntitle: A\n---\n\nNote: this\n---\n";
+	assert!(!crate::frontmatter::is_multi_document(separated));
+}
+
+#[test]
+fn frontmatter_order_is_preserved() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/ordered");
+	s.destination("../fixtures/dest_ordered");
+	let r = s.build().unwrap();
+	let file = r.files.get(&PathBuf::from("ordered.md")).unwrap();
+	let keys: Vec<&String> = file.frontmatter.as_object().unwrap().keys().collect();
+	assert_eq!(keys, vec!["zebra", "apple", "mango"]);
+	assert_eq!(
+		file.raw_frontmatter,
+		"# Written by hand\nzebra: 1\napple: 2\nmango: 3"
+	);
+}
+
+#[test]
+fn composable_read_write_works() {
+	let config = Config {
+		source: "../fixtures/simple".into(),
+		..Default::default()
+	};
+	let (files, errors) = read_dir(&config).unwrap();
+	assert!(errors.is_empty());
+	for dest in &["../fixtures/dest_compose1", "../fixtures/dest_compose2"] {
+		write_dir(&files, Path::new(dest)).unwrap();
+		assert!(Path::new(dest).join("hello.txt").exists());
+	}
+}
+
+#[test]
+fn multiple_destinations_work() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_public");
+	s.add_destination(
+		"../fixtures/dest_preview",
+		vec![Box::new(|ir: IR| {
+			let files = ir.files.update("preview.txt".into(), ShFile::empty());
+			IR { files, ..ir }
+		})],
+	);
+	s.clean(true);
+	let r = s.build().unwrap();
+	assert!(!r.files.contains_key(&PathBuf::from("preview.txt")));
+	assert!(Path::new("../fixtures/dest_public/hello.txt").exists());
+	assert!(!Path::new("../fixtures/dest_public/preview.txt").exists());
+	assert!(Path::new("../fixtures/dest_preview/hello.txt").exists());
+	assert!(Path::new("../fixtures/dest_preview/preview.txt").exists());
+}
+
+#[test]
+fn destinations_inside_the_source_are_not_read() {
+	let source = Path::new("../fixtures/dest_nested_source");
+	let _ = fs::remove_dir_all(source);
+	fs::create_dir_all(source).unwrap();
+	fs::write(source.join("a.txt"), "A").unwrap();
+	let mut s = Shtola::new();
+	s.source(source);
+	s.destination(source.join("public"));
+	s.add_destination(source.join("preview"), Vec::new());
+	s.build().unwrap();
+	let r = s.build().unwrap();
+	let paths: Vec<&PathBuf> = r.files.keys().collect();
+	assert_eq!(paths, vec![&PathBuf::from("a.txt")]);
+	assert!(source.join("preview/a.txt").exists());
+}
+
+#[test]
+fn query_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/posts");
+	s.destination("../fixtures/dest_query");
+	s.register(Box::new(|ir: IR| {
+		let mut d = ir.files.get(Path::new("d.md")).unwrap().clone();
+		d.frontmatter = json!({ "og.title": "D", "date": null });
+		IR {
+			files: ir.files.update("d.md".into(), d),
+			..ir
+		}
+	}));
+	let r = s.build().unwrap();
+	let titles = |files: Vec<(&PathBuf, &ShFile)>| -> Vec<String> {
+		files
+			.iter()
+			.map(|(p, _)| p.to_string_lossy().into_owned())
+			.collect()
+	};
+	let latest = r
+		.query()
+		.filter("tags", "rust")
+		.sort_by_desc("date")
+		.limit(1);
+	assert_eq!(titles(latest.run()), vec!["b.md"]);
+	assert_eq!(
+		titles(r.query().sort_by("date").run()),
+		vec!["a.md", "c.md", "b.md", "d.md"]
+	);
+	assert_eq!(
+		titles(r.query().filter("author.name", "marisa").run()),
+		vec!["a.md"]
+	);
+	assert_eq!(
+		titles(r.query().filter("og.title", "D").run()),
+		vec!["d.md"]
+	);
+	assert_eq!(
+		titles(r.query().has("date").run()),
+		vec!["a.md", "b.md", "c.md"]
+	);
+	let spec = json!({ "where": { "tags": "rust" }, "sort": "-title", "limit": 5 });
+	assert_eq!(
+		titles(r.query().apply(&spec).unwrap().run()),
+		vec!["b.md", "a.md"]
+	);
+	let spec = json!({ "limit": 1, "sort": "-date", "where": { "tags": "rust" } });
+	assert_eq!(titles(r.query().apply(&spec).unwrap().run()), vec!["b.md"]);
+	assert!(r.query().apply(&json!({ "order": "title" })).is_err());
+}
+
+#[test]
+fn binary_files_are_not_lexed() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/binary");
+	s.destination("../fixtures/dest_binary");
+	let r = s.build().unwrap();
+	let pdf = r.files.get(Path::new("doc.pdf")).unwrap();
+	assert_eq!(pdf.frontmatter, json!(null));
+	assert_eq!(pdf.content, fs::read("../fixtures/binary/doc.pdf").unwrap());
+	assert_eq!(
+		fs::read("../fixtures/dest_binary/doc.pdf").unwrap(),
+		pdf.content
+	);
+	let page = r.files.get(Path::new("page.md")).unwrap();
+	assert_eq!(page.frontmatter, json!({ "title": "Text" }));
+	for image in &["logo.png", "sprite.png"] {
+		let file = r.files.get(Path::new(image)).unwrap();
+		assert_eq!(file.frontmatter, json!(null));
+		let source = fs::read(Path::new("../fixtures/binary").join(image)).unwrap();
+		assert_eq!(file.content, source);
+		let written = fs::read(Path::new("../fixtures/dest_binary").join(image)).unwrap();
+		assert_eq!(written, source);
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn executable_bit_is_preserved() {
+	use std::os::unix::fs::PermissionsExt;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/permissions");
+	s.destination("../fixtures/dest_permissions");
+	s.clean(true);
+	s.build().unwrap();
+	let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode();
+	assert_eq!(mode("../fixtures/dest_permissions/run.sh") & 0o111, 0o111);
+	assert_eq!(mode("../fixtures/dest_permissions/private.txt") & 0o111, 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn all_permissions_are_preserved() {
+	use std::os::unix::fs::PermissionsExt;
+
+	let private = "../fixtures/permissions/private.txt";
+	fs::set_permissions(private, fs::Permissions::from_mode(0o600)).unwrap();
+	let mut s = Shtola::new();
+	s.source("../fixtures/permissions");
+	s.destination("../fixtures/dest_permissions_all");
+	s.clean(true);
+	s.preserve_permissions(PreservePermissions::All);
+	s.build().unwrap();
+	fs::set_permissions(private, fs::Permissions::from_mode(0o644)).unwrap();
+	let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+	assert_eq!(mode("../fixtures/dest_permissions_all/private.txt"), 0o600);
+	assert_eq!(mode("../fixtures/dest_permissions_all/run.sh"), 0o755);
+}
+
+#[test]
+fn metadata_is_preserved() {
+	use std::time::{Duration, SystemTime};
+
+	let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+	for name in &["plain.txt", "page.md"] {
+		let file = fs::File::options()
+			.write(true)
+			.open(Path::new("../fixtures/times").join(name))
+			.unwrap();
+		file.set_modified(time).unwrap();
+	}
+	let mut s = Shtola::new();
+	s.source("../fixtures/times");
+	s.destination("../fixtures/dest_times");
+	s.clean(true);
+	s.preserve_metadata(true);
+	s.build().unwrap();
+	let modified = |path: &str| fs::metadata(path).unwrap().modified().unwrap();
+	assert_eq!(modified("../fixtures/dest_times/plain.txt"), time);
+	assert_ne!(modified("../fixtures/dest_times/page.md"), time);
+}
+
+#[test]
+fn file_map_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_file_map");
+	s.register(Shtola::file_map(Box::new(|path, file: ShFile| ShFile {
+		frontmatter: json!({ "path": path }),
+		content: file.content.to_ascii_uppercase(),
+		..file
+	})));
+	let r = s.build().unwrap();
+	let file = r.files.get(Path::new("hello.txt")).unwrap();
+	assert_eq!(file.content, b"TEST\n");
+	assert_eq!(file.frontmatter, json!({ "path": "hello.txt" }));
+}
+
+#[test]
+fn try_file_map_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/posts");
+	s.destination("../fixtures/dest_try_file_map");
+	s.strict(false);
+	s.register_file(Box::new(|_, file: ShFile| ShFile {
+		content: file.content.to_ascii_uppercase(),
+		..file
+	}));
+	s.register(Shtola::try_file_map(Box::new(|path, file: ShFile| {
+		if file.frontmatter["tags"] == json!(["rust"]) {
+			Err(format!("{:?} is about Rust", path))
+		} else {
+			Ok(file)
+		}
+	})));
+	let r = s.build().unwrap();
+	assert!(!r.report.errors.is_empty());
+	for e in &r.report.errors {
+		assert!(r.files.get(&e.path).is_none());
+		assert!(e.message.ends_with("is about Rust"));
+	}
+	assert!(r
+		.files
+		.values()
+		.all(|f| f.content == f.content.to_ascii_uppercase()));
+}
+
+#[test]
+fn mime_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/binary");
+	s.destination("../fixtures/dest_mime");
+	s.dry_run(true);
+	s.register(Box::new(|ir: IR| IR {
+		files: ir.files.update("generated.txt".into(), ShFile::empty()),
+		..ir
+	}));
+	let r = s.build().unwrap();
+	let file = |path: &str| r.files.get(Path::new(path)).unwrap();
+	let types = |path: &str| (file(path).kind, file(path).mime.as_deref());
+	assert_eq!(types("logo.png"), (Kind::Binary, Some("image/png")));
+	assert_eq!(types("doc.pdf"), (Kind::Binary, Some("application/pdf")));
+	assert_eq!(types("page.md"), (Kind::Text, Some("text/markdown")));
+	assert_eq!(types("generated.txt"), (Kind::Text, None));
+}
+
+#[test]
+fn file_helpers_work() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/defaults");
+	s.destination("../fixtures/dest_file_helpers");
+	s.dry_run(true);
+	let r = s.build().unwrap();
+	let mut markdown: Vec<&PathBuf> = r.files_with_extension("MD").map(|(p, _)| p).collect();
+	markdown.sort();
+	assert_eq!(
+		markdown,
+		vec![
+			Path::new("about.md"),
+			Path::new("blog/2020/old.md"),
+			Path::new("blog/first.md"),
+		]
+	);
+	let css: Vec<&PathBuf> = r.files_matching("*.css").unwrap().map(|(p, _)| p).collect();
+	assert_eq!(css, vec![Path::new("style.css")]);
+	assert_eq!(r.files_matching("blog/**").unwrap().count(), 2);
+	assert!(r.files_matching("blog/[").is_err());
+}
+
+#[test]
+fn rename_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/defaults");
+	s.destination("../fixtures/dest_rename");
+	s.dry_run(true);
+	s.register(Box::new(|ir: IR| {
+		ir.rename("about.md", "about/index.md")
+			.rename("missing.md", "found.md")
+			.rename_extension("blog/**", "html")
+			.unwrap()
+	}));
+	let r = s.build().unwrap();
+	let mut paths: Vec<&PathBuf> = r.files.keys().collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		vec![
+			Path::new("about/index.md"),
+			Path::new("blog/2020/old.html"),
+			Path::new("blog/first.html"),
+			Path::new("style.css"),
+		]
+	);
+	let about = r.files.get(Path::new("about/index.md")).unwrap();
+	assert_eq!(about.frontmatter["layout"], "page");
+	assert_eq!(about.origin, Origin::Source);
+	let first = r.files.get(Path::new("blog/first.html")).unwrap();
+	assert_eq!(first.mime.as_deref(), Some("text/html"));
+}
+
+#[test]
+fn ir_diff_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/defaults");
+	s.destination("../fixtures/dest_ir_diff");
+	s.dry_run(true);
+	let previous = s.build().unwrap();
+	let current = previous
+		.clone()
+		.create_file("new.md", "New", json!(null))
+		.rename("style.css", "main.css");
+	let mut files = current.files.clone();
+	files.insert(
+		"about.md".into(),
+		ShFile {
+			content: "Changed".into(),
+			..current.files[Path::new("about.md")].clone()
+		},
+	);
+	files.insert(
+		"blog/first.md".into(),
+		ShFile {
+			frontmatter: json!({ "title": "Only the frontmatter changed" }),
+			..current.files[Path::new("blog/first.md")].clone()
+		},
+	);
+	let current = IR { files, ..current };
+	let diff = current.diff(&previous);
+	assert_eq!(
+		diff.added,
+		vec![PathBuf::from("main.css"), PathBuf::from("new.md")]
+	);
+	assert_eq!(diff.changed, vec![PathBuf::from("about.md")]);
+	assert_eq!(diff.deleted, vec![PathBuf::from("style.css")]);
+	assert_eq!(diff.size_delta, 3 - 1);
+	assert_eq!(previous.diff(&previous), Default::default());
+}
+
+#[test]
+fn snapshot_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/defaults");
+	s.destination("../fixtures/dest_snapshot");
+	s.strict(false);
+	let ir = s
+		.build()
+		.unwrap()
+		.create_file("logo.png", "", json!(null))
+		.fail_file("style.css", "Broken");
+	let mut files = ir.files.clone();
+	files[Path::new("logo.png")].content = vec![0x89, b'P', b'N', b'G', 0xff];
+	files[Path::new("about.md")].metadata = files[Path::new("about.md")]
+		.metadata
+		.update("words".into(), json!(2));
+	let ir = IR { files, ..ir };
+	let path = "../fixtures/dest_snapshot/ir.json";
+	ir.to_json_file(path).unwrap();
+
+	let json: json::Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+	let keys: Vec<&String> = json["files"].as_object().unwrap().keys().collect();
+	assert_eq!(
+		keys,
+		vec!["about.md", "blog/2020/old.md", "blog/first.md", "logo.png"]
+	);
+	assert_eq!(json["files"]["logo.png"]["content"], "iVBOR/8=");
+	assert_eq!(json["files"]["logo.png"]["origin"], "generated");
+	assert_eq!(json["config"]["invalid_frontmatter"], "error");
+	assert_eq!(json["report"]["errors"][0]["message"], "Broken");
+
+	let read = IR::from_json_file(path).unwrap();
+	assert_eq!(read.diff(&ir), Default::default());
+	for (path, file) in ir.files.iter() {
+		let other = &read.files[path];
+		assert_eq!(other.frontmatter, file.frontmatter);
+		assert_eq!(other.raw_frontmatter, file.raw_frontmatter);
+		assert_eq!(other.metadata, file.metadata);
+		assert_eq!(other.stat, file.stat);
+		assert_eq!((other.kind, &other.mime), (file.kind, &file.mime));
+		assert_eq!(other.origin, file.origin);
+	}
+	assert_eq!(read.metadata, ir.metadata);
+	assert_eq!(read.config.source, ir.config.source);
+	assert!(!read.config.strict);
+	assert_eq!(read.report.errors, ir.report.errors);
+	assert_eq!(read.report.read, ir.report.read);
+	assert!(IR::from_json_file("../fixtures/dest_snapshot/missing.json").is_err());
+}
+
+#[test]
+fn rotate_works() {
+	let date = |text: &str| Date::parse(text).unwrap();
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_rotate");
+	s.dry_run(true);
+	s.build_date(date("2020-02-05"));
+	let r = s.build().unwrap();
+	assert_eq!(r.build_date(), date("2020-02-05"));
+
+	let items: Vec<u32> = (0..100).collect();
+	let pick = |day: &str, period: Period| {
+		*Selector::for_period("featured", date(day), period)
+			.pick(&items)
+			.unwrap()
+	};
+	assert_eq!(
+		pick("2020-02-03", Period::Week),
+		pick("2020-02-09", Period::Week)
+	);
+	let weeks: Vec<u32> = ["2020-02-03", "2020-02-10", "2020-02-17", "2020-02-24"]
+		.iter()
+		.map(|day| pick(day, Period::Week))
+		.collect();
+	assert!(weeks.windows(2).any(|w| w[0] != w[1]));
+	assert_eq!(
+		pick("2020-02-01", Period::Month),
+		pick("2020-02-29", Period::Month)
+	);
+	assert_eq!(Period::Week.index(date("1970-01-05")), 1);
+	assert_eq!(
+		Period::Month.index(date("2020-01-31")) + 1,
+		Period::Month.index(date("2020-02-01"))
+	);
+
+	let mut shuffled = items.clone();
+	Selector::new("testimonials").shuffle(&mut shuffled);
+	assert_ne!(shuffled, items);
+	let mut again = items.clone();
+	Selector::new("testimonials").shuffle(&mut again);
+	assert_eq!(again, shuffled);
+	let mut other = items.clone();
+	Selector::new("quotes").shuffle(&mut other);
+	assert_ne!(other, shuffled);
+}
+
+#[test]
+fn build_from_works() {
+	let mut generate = Shtola::new();
+	generate.source("../fixtures/defaults");
+	generate.destination("../fixtures/dest_build_from_generate");
+	generate.strict(false);
+	generate.register(Box::new(|ir: IR| {
+		ir.create_file("generated.txt", "Generated", json!(null))
+			.fail_file("style.css", "Broken")
+	}));
+	let ir = generate.build().unwrap();
+	ir.to_json_file("../fixtures/dest_build_from_generate/ir.json")
+		.unwrap();
+
+	let ir = IR::from_json_file("../fixtures/dest_build_from_generate/ir.json").unwrap();
+	let mut render = Shtola::new();
+	render.source("../fixtures/does_not_exist");
+	render.destination("../fixtures/dest_build_from");
+	render.clean(true);
+	render.strict(false);
+	render.register(Box::new(|ir: IR| {
+		let mut files = ir.files.clone();
+		for file in files.iter_mut() {
+			file.content = file.content.to_ascii_uppercase();
+		}
+		IR { files, ..ir }
+	}));
+	let r = render.build_from(ir).unwrap();
+	assert_eq!(r.report.read, 4);
+	assert_eq!(r.report.written, 4);
+	assert_eq!(r.report.errors.len(), 1);
+	assert_eq!(r.report.errors[0].path, Path::new("style.css"));
+	assert!(r.config.destination.ends_with("dest_build_from"));
+	assert_eq!(
+		fs::read_to_string("../fixtures/dest_build_from/generated.txt").unwrap(),
+		"GENERATED"
+	);
+	assert!(Path::new("../fixtures/dest_build_from/about.md").exists());
+	assert!(!Path::new("../fixtures/dest_build_from/style.css").exists());
+
+	let mut strict = Shtola::new();
+	strict.destination("../fixtures/dest_build_from_strict");
+	assert!(matches!(
+		strict.build_from(r),
+		Err(ShtolaError::Verification { .. })
+	));
+}
+
+#[test]
+fn create_file_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_create_file");
+	s.clean(true);
+	s.register(Box::new(|ir: IR| {
+		ir.create_file("feed.xml", "<feed/>", json!(null))
+			.create_file("tags/rust.md", "Rust", json!({ "tag": "rust" }))
+	}));
+	let r = s.build().unwrap();
+	let hello = r.files.get(Path::new("hello.txt")).unwrap();
+	assert_eq!(hello.origin, Origin::Source);
+	let feed = r.files.get(Path::new("feed.xml")).unwrap();
+	assert_eq!(feed.origin, Origin::Generated);
+	assert_eq!(feed.mime.as_deref(), Some("application/xml"));
+	let tag = r.files.get(Path::new("tags/rust.md")).unwrap();
+	assert_eq!(tag.frontmatter, json!({ "tag": "rust" }));
+	assert_eq!(
+		fs::read_to_string("../fixtures/dest_create_file/tags/rust.md").unwrap(),
+		"Rust"
+	);
+}
+
+#[test]
+fn file_stat_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_stat");
+	s.register(Box::new(|ir: IR| IR {
+		files: ir.files.update("generated.txt".into(), ShFile::empty()),
+		..ir
+	}));
+	let r = s.build().unwrap();
+	let source = fs::metadata("../fixtures/simple/hello.txt").unwrap();
+	let stat = r
+		.files
+		.get(Path::new("hello.txt"))
+		.unwrap()
+		.stat
+		.clone()
+		.unwrap();
+	assert_eq!(stat.size, source.len());
+	assert_eq!(stat.modified, Some(source.modified().unwrap()));
+	assert!(!stat.readonly);
+	#[cfg(unix)]
+	assert!(stat.mode.is_some());
+	assert!(r
+		.files
+		.get(Path::new("generated.txt"))
+		.unwrap()
+		.stat
+		.is_none());
+}
+
+#[test]
+fn verify_sources_works() {
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	let source = Path::new("../fixtures/dest_verify_source");
+	let build = |mode: SourceVerification| {
+		let _ = fs::remove_dir_all(source);
+		fs::create_dir_all(source).unwrap();
+		fs::write(source.join("a.txt"), "old").unwrap();
+		fs::write(source.join("b.txt"), "unchanged").unwrap();
+		let runs = Rc::new(Cell::new(0));
+		let mut s = Shtola::new();
+		s.source(source);
+		s.destination("../fixtures/dest_verify");
+		s.clean(true);
+		s.verify_sources(mode);
+		let counter = runs.clone();
+		s.register(Box::new(move |ir: IR| {
+			// Stands in for an editor saving a file during the first build.
+			if counter.get() == 0 {
+				fs::write(source.join("a.txt"), "new").unwrap();
+			}
+			counter.set(counter.get() + 1);
+			ir
+		}));
+		(s.build().unwrap(), runs.get())
+	};
+	let (r, runs) = build(SourceVerification::Warn);
+	assert_eq!(runs, 1);
+	assert_eq!(r.report.modified_sources, vec![PathBuf::from("a.txt")]);
+	assert_eq!(r.files.get(Path::new("a.txt")).unwrap().content, b"old");
+	let (r, runs) = build(SourceVerification::Retry);
+	assert_eq!(runs, 2);
+	assert!(r.report.modified_sources.is_empty());
+	assert_eq!(r.files.get(Path::new("a.txt")).unwrap().content, b"new");
+	let (r, _) = build(SourceVerification::Off);
+	assert!(r.report.modified_sources.is_empty());
+}
+
+#[test]
+fn threads_work() {
+	use std::collections::HashSet;
+	use std::sync::{Arc, Mutex};
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_threads");
+	s.clean(true);
+	s.threads(2);
+	s.io_limit(1);
+	let workers = Arc::new(Mutex::new(HashSet::new()));
+	for _ in 0..2 {
+		let workers = Arc::clone(&workers);
+		s.register(Box::new(move |ir: IR| {
+			ir.par_update_files(
+				|_, _| true,
+				|_, file| {
+					assert_eq!(rayon::current_num_threads(), 2);
+					workers.lock().unwrap().insert(std::thread::current().id());
+					file.clone()
+				},
+			)
+		}));
+	}
+	s.build().unwrap();
+	let r = s.build().unwrap();
+	// All builds share one pool of two threads.
+	assert!(workers.lock().unwrap().len() <= 2);
+	let (files, _) = read_dir(&Config {
+		source: "../fixtures/dest_threads".into(),
+		frontmatter: false,
+		..Default::default()
+	})
+	.unwrap();
+	assert_eq!(files.len(), r.files.len());
+}
+
+#[test]
+fn par_update_files_works() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/posts");
+	s.destination("../fixtures/dest_par_update");
+	s.register(Box::new(|ir: IR| {
+		ir.par_update_files(
+			|_, file| file.frontmatter["tags"] == json!(["rust"]),
+			|path, file| ShFile {
+				content: path.to_string_lossy().into_owned().into_bytes(),
+				..file.clone()
+			},
+		)
+	}));
+	let r = s.build().unwrap();
+	assert_eq!(r.files.get(Path::new("b.md")).unwrap().content, b"b.md");
+	assert_eq!(r.files.get(Path::new("a.md")).unwrap().content, b"A");
+	assert_eq!(r.files.len(), 4);
+}
+
+#[test]
+fn page_context_works() {
+	use crate::context::PageContext;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/context");
+	s.destination("../fixtures/dest_context");
+	s.register(Box::new(|ir: IR| IR {
+		metadata: ir.metadata.update("title".into(), json!("My site")),
+		..ir
+	}));
+	let r = s.build().unwrap();
+	let contexts = PageContext::all(&r);
+	let hello = &contexts[Path::new("blog/hello.html")];
+	assert_eq!(
+		hello.to_json(),
+		json!({
+			"page": {
+				"title": "Hello",
+				"collection": "blog",
+				"path": "blog/hello.html",
+				"url": "/blog/hello.html",
+				"root": "../",
+				"content": "<p>Hello!</p>",
+			},
+			"site": { "title": "My site" },
+			"collections": {
+				"blog": [
+					{
+						"title": "Hello",
+						"collection": "blog",
+						"path": "blog/hello.html",
+						"url": "/blog/hello.html",
+						"root": "../",
+					},
+					{
+						"title": "Other",
+						"collection": ["blog", "featured"],
+						"path": "blog/other.html",
+						"url": "/blog/other.html",
+						"root": "../",
+					},
+				],
+				"featured": [
+					{
+						"title": "Other",
+						"collection": ["blog", "featured"],
+						"path": "blog/other.html",
+						"url": "/blog/other.html",
+						"root": "../",
+					},
+				],
+			},
+		})
+	);
+	assert_eq!(json::to_value(hello).unwrap(), hello.to_json());
+	assert!(!contexts.contains_key(Path::new("logo.png")));
+	assert_eq!(hello.relative_url("/css/style.css"), "../css/style.css");
+	assert_eq!(
+		hello.relative_url("https://example.com"),
+		"https://example.com"
+	);
+	let index = PageContext::new(&r, Path::new("index.html")).unwrap();
+	assert_eq!(index.url(), "/");
+	assert_eq!(index.relative_url("/css/style.css"), "css/style.css");
+}
+
+#[test]
+fn context_url_works() {
+	use crate::context::url;
+
+	assert_eq!(url(Path::new("index.html")), "/");
+	assert_eq!(url(Path::new("blog/index.html")), "/blog/");
+	assert_eq!(url(Path::new("blog/hello.html")), "/blog/hello.html");
+	assert_eq!(url(Path::new("reindex.html")), "/reindex.html");
+	assert_eq!(url(Path::new("a/myindex.html")), "/a/myindex.html");
+}
+
+#[test]
+fn decode_works() {
+	use crate::decode;
+
+	assert_eq!(decode::percent("caf%C3%A9%20menu"), "café menu");
+	assert_eq!(decode::percent("100%"), "100%");
+	assert_eq!(decode::percent("%zz%2"), "%zz%2");
+	assert_eq!(decode::entities("a &amp; b&nbsp;&#x2f;"), "a & b\u{a0}/");
+}
+
+#[test]
+fn file_metadata_works() {
+	use crate::context::PageContext;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/context");
+	s.destination("../fixtures/dest_file_metadata");
+	s.register(Box::new(|ir: IR| {
+		ir.par_update_files(
+			|path, _| path.starts_with("blog"),
+			|_, file| {
+				let words = String::from_utf8_lossy(&file.content)
+					.split_whitespace()
+					.count();
+				ShFile {
+					metadata: file.metadata.update("words".into(), json!(words)),
+					..file.clone()
+				}
+			},
+		)
+	}));
+	let r = s.build().unwrap();
+	let hello = r.files.get(Path::new("blog/hello.html")).unwrap();
+	assert_eq!(hello.metadata.get("words"), Some(&json!(1)));
+	assert!(hello.frontmatter.get("words").is_none());
+	let context = PageContext::new(&r, Path::new("blog/hello.html")).unwrap();
+	assert_eq!(context.page["metadata"], json!({ "words": 1 }));
+	assert_eq!(
+		context.collections["blog"][1]["metadata"],
+		json!({ "words": 1 })
+	);
+	let index = PageContext::new(&r, Path::new("index.html")).unwrap();
+	assert!(index.page.get("metadata").is_none());
+}
+
+#[test]
+fn incremental_builds_work() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use std::time::{Duration, SystemTime};
+
+	let source = Path::new("../fixtures/dest_incremental_source");
+	let dest = Path::new("../fixtures/dest_incremental");
+	let _ = fs::remove_dir_all(source);
+	let _ = fs::remove_dir_all(dest);
+	fs::create_dir_all(source).unwrap();
+	fs::write(source.join("a.md"), "---\ntitle: A\n---\nA").unwrap();
+	fs::write(source.join("b.md"), "B").unwrap();
+	let runs = Arc::new(AtomicUsize::new(0));
+	let build = || {
+		let mut s = Shtola::new();
+		s.source(source);
+		s.destination(dest);
+		s.incremental(true);
+		let runs = Arc::clone(&runs);
+		s.register_file(Box::new(move |_, file: ShFile| {
+			runs.fetch_add(1, Ordering::SeqCst);
+			file
+		}));
+		let report = s.build().unwrap().report;
+		(report.source_changes.clone().unwrap(), report)
+	};
+	let (changes, report) = build();
+	assert_eq!(
+		changes.added,
+		vec![PathBuf::from("a.md"), PathBuf::from("b.md")]
+	);
+	assert_eq!(report.cached_reads, 0);
+	assert_eq!(runs.load(Ordering::SeqCst), 2);
+	let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+	for name in &["a.md", "b.md"] {
+		fs::File::options()
+			.write(true)
+			.open(dest.join(name))
+			.unwrap()
+			.set_modified(old)
+			.unwrap();
+	}
+
+	fs::write(source.join("a.md"), "---\ntitle: Changed\n---\nA").unwrap();
+	fs::write(source.join("c.md"), "C").unwrap();
+	let (changes, report) = build();
+	assert_eq!(changes.added, vec![PathBuf::from("c.md")]);
+	assert_eq!(changes.changed, vec![PathBuf::from("a.md")]);
+	// Only the unchanged b.md is taken from the previous build.
+	assert_eq!(report.cached_reads, 1);
+	assert_eq!(report.cached_transforms, 1);
+	assert_eq!(runs.load(Ordering::SeqCst), 4);
+	let modified = |name: &str| fs::metadata(dest.join(name)).unwrap().modified().unwrap();
+	// Only the frontmatter of a.md changed, so its output is the same.
+	assert_eq!(modified("a.md"), old);
+	assert_eq!(modified("b.md"), old);
+	assert_eq!(fs::read(dest.join("c.md")).unwrap(), b"C");
+
+	fs::remove_file(source.join("c.md")).unwrap();
+	let (changes, report) = build();
+	assert_eq!(changes.deleted, vec![PathBuf::from("c.md")]);
+	assert_eq!(report.cached_reads, 2);
+	assert_eq!(report.cached_transforms, 2);
+	assert_eq!(runs.load(Ordering::SeqCst), 4);
+	assert!(!dest.join("c.md").exists());
+	assert!(dest.join(".shtola-state.json").exists());
+}
+
+#[test]
+fn site_config_works() {
+	use crate::context::PageContext;
+
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination("../fixtures/dest_site");
+	s.site_config("../fixtures/site_config.yml");
+	s.register(Box::new(|ir: IR| IR {
+		metadata: ir.metadata.update("title".into(), json!("Metadata title")),
+		..ir
+	}));
+	let r = s.build().unwrap();
+	assert_eq!(r.site.title.as_deref(), Some("Config title"));
+	assert_eq!(r.site.author, None);
+	assert_eq!(r.site.base_url(), "https://example.com");
+	assert_eq!(r.site.absolute_url("/a.html"), "https://example.com/a.html");
+	assert_eq!(r.site.param("twitter"), Some(&json!("@example")));
+	let context = PageContext::new(&r, Path::new("hello.txt")).unwrap();
+	assert_eq!(
+		*context.site,
+		json!({
+			"title": "Metadata title",
+			"base_url": "https://example.com/",
+			"language": "en",
+			"params": { "twitter": "@example" },
+		})
+	);
+}
+
+#[test]
+fn file_errors_are_rendered_with_snippets() {
+	let config = Config {
+		source: "../fixtures/broken".into(),
+		..Default::default()
+	};
+	let (_, errors) = read_dir(&config).unwrap();
+	let rendered = errors[0].render(&config.source);
+	let mut lines = rendered.lines();
+	assert!(lines
+		.next()
+		.unwrap()
+		.starts_with("\"broken.md\": Invalid frontmatter: "));
+	assert_eq!(
+		lines.collect::<Vec<&str>>(),
+		vec![
+			" --> broken.md:3:1",
+			"  |",
+			"2 | title: [unclosed",
+			"3 | ---",
+			"  | ^",
+			"4 | body",
+		]
+	);
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn watch_rebuilds_on_changes() {
+	use std::ops::ControlFlow;
+	use std::sync::mpsc::channel;
+	use std::thread;
+	use std::time::Duration;
+
+	let source = Path::new("../fixtures/dest_watch_source");
+	let dest = Path::new("../fixtures/dest_watch");
+	let _ = fs::remove_dir_all(source);
+	fs::create_dir_all(source).unwrap();
+	fs::write(source.join("index.md"), "first").unwrap();
+	let (tx, rx) = channel();
+	thread::spawn(move || {
+		let mut s = Shtola::new();
+		s.source(source);
+		s.destination(dest);
+		let mut builds = 0;
+		s.watch(|result| {
+			let ir = result.unwrap();
+			let index = ir.files.get(Path::new("index.md")).unwrap();
+			tx.send((index.content.clone(), ir.report.source_changes))
+				.unwrap();
+			builds += 1;
+			if builds == 1 {
+				fs::write(source.join("index.md"), "second").unwrap();
+				ControlFlow::Continue(())
+			} else {
+				ControlFlow::Break(())
+			}
+		})
+		.unwrap();
+	});
+	let timeout = Duration::from_secs(10);
+	let (content, changes) = rx.recv_timeout(timeout).unwrap();
+	assert_eq!(content, b"first");
+	assert_eq!(changes, None);
+	let (content, changes) = rx.recv_timeout(timeout).unwrap();
+	assert_eq!(content, b"second");
+	assert_eq!(changes.unwrap().changed, vec![PathBuf::from("index.md")]);
+	assert_eq!(fs::read(dest.join("index.md")).unwrap(), b"second");
+}
+
+#[cfg(feature = "serve")]
+#[test]
+fn serve_works() {
+	use crate::serve::{serve_dir, Options};
+	use std::io::{Read, Write};
+	use std::net::TcpStream;
+
+	let server = serve_dir(
+		"../fixtures/serve",
+		&Options {
+			port: 0,
+			..Default::default()
+		},
+	)
+	.unwrap();
+	let address = server.url().replace("http://", "").replace('/', "");
+	let get = |path: &str| {
+		let mut stream = TcpStream::connect(&address).unwrap();
+		write!(
+			stream,
+			"GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+			path
+		)
+		.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		response
+	};
+	let docs = get("/docs/");
+	assert!(docs.starts_with("HTTP/1.1 200"));
+	assert!(docs.contains("text/html; charset=utf-8"));
+	assert!(docs.ends_with(&fs::read_to_string("../fixtures/serve/docs/index.html").unwrap()));
+	assert!(get("/docs?query=1").starts_with("HTTP/1.1 200"));
+	assert!(get("/releases/v1.2").ends_with("<h1>Version 1.2</h1>\n"));
+	assert!(get("/site.webmanifest").contains("application/manifest+json; charset=utf-8"));
+	assert!(get("/missing.html").starts_with("HTTP/1.1 404"));
+	assert!(get("/../simple/hello.txt").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn dry_run_works() {
+	let dest = Path::new("../fixtures/dest_dry_run");
+	let _ = fs::remove_dir_all(dest);
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination(dest);
+	s.dry_run(true);
+	let r = s.build().unwrap();
+	assert!(r.files.get(Path::new("hello.txt")).is_some());
+	assert!(!dest.exists());
+	let pending = r.report.dry_run.unwrap();
+	assert_eq!(pending.added, vec![PathBuf::from("hello.txt")]);
+
+	fs::create_dir_all(dest).unwrap();
+	fs::write(dest.join("hello.txt"), "outdated").unwrap();
+	fs::write(dest.join("stale.txt"), "stale").unwrap();
+	let mut s = Shtola::new();
+	s.source("../fixtures/simple");
+	s.destination(dest);
+	s.dry_run(true);
+	s.clean(true);
+	let pending = s.build().unwrap().report.dry_run.unwrap();
+	assert_eq!(pending.changed, vec![PathBuf::from("hello.txt")]);
+	assert_eq!(pending.deleted, vec![PathBuf::from("stale.txt")]);
+	assert_eq!(fs::read(dest.join("hello.txt")).unwrap(), b"outdated");
+	assert!(dest.join("stale.txt").exists());
+}
+
+#[test]
+fn migrate_works() {
+	let source = Path::new("../fixtures/dest_migrate");
+	let _ = fs::remove_dir_all(source);
+	fs::create_dir_all(source).unwrap();
+	fs::write(
+		source.join("tagged.md"),
+		"---\ntitle: Hello\ntag: rust\n---\n\n# Hello\n\nKept   as is.\n",
+	)
+	.unwrap();
+	fs::write(
+		source.join("untagged.md"),
+		"---\ntitle: Plain\n---\nNo tag.\n",
+	)
+	.unwrap();
+	fs::write(
+		source.join("data.html"),
+		"{\"tag\": \"json\"}\n<p>JSON</p>\n",
+	)
+	.unwrap();
+	fs::write(
+		source.join("fenced.md"),
+		"---json\n{\"tag\": \"fenced\"}\n---\n{{ body }}\n",
+	)
+	.unwrap();
+	fs::write(source.join("ignored.md"), "---\ntag: ignored\n---\n").unwrap();
+	let mut s = Shtola::new();
+	s.source(source);
+	s.ignores(&mut vec!["ignored.md".into()]);
+	let migrate = |_: &Path, mut matter: serde_json::Value| {
+		if let Some(tag) = matter.as_object_mut().and_then(|m| m.remove("tag")) {
+			matter["tags"] = json!([tag]);
+		}
+		matter
+	};
+
+	s.dry_run(true);
+	let changed = s.migrate(migrate).unwrap();
+	assert_eq!(
+		changed,
+		vec![
+			PathBuf::from("data.html"),
+			PathBuf::from("fenced.md"),
+			PathBuf::from("tagged.md")
+		]
+	);
+	let tagged = fs::read_to_string(source.join("tagged.md")).unwrap();
+	assert!(tagged.contains("tag: rust"));
+
+	s.dry_run(false);
+	assert_eq!(s.migrate(migrate).unwrap().len(), 3);
+	assert_eq!(
+		fs::read_to_string(source.join("tagged.md")).unwrap(),
+		"---\ntitle: Hello\ntags:\n  - rust\n---\n\n# Hello\n\nKept   as is.\n"
+	);
+	assert_eq!(
+		fs::read_to_string(source.join("data.html")).unwrap(),
+		"{\n  \"tags\": [\n    \"json\"\n  ]\n}\n<p>JSON</p>\n"
+	);
+	assert_eq!(
+		fs::read_to_string(source.join("fenced.md")).unwrap(),
+		"---json\n{\n  \"tags\": [\n    \"fenced\"\n  ]\n}\n---\n{{ body }}\n"
+	);
+	assert_eq!(
+		fs::read_to_string(source.join("ignored.md")).unwrap(),
+		"---\ntag: ignored\n---\n"
+	);
+	assert!(s.migrate(migrate).unwrap().is_empty());
+}
+
+#[test]
+fn multiple_sources_work() {
+	let mut s = Shtola::new();
+	s.source("../fixtures/sources/site");
+	s.add_source("../fixtures/sources/static");
+	s.add_source("../fixtures/sources/theme");
+	s.destination("../fixtures/dest_sources");
+	s.ignores(&mut vec!["notes.md".into()]);
+	s.clean(true);
+	let r = s.build().unwrap();
+	let content = |path: &str| {
+		String::from_utf8(r.files.get(Path::new(path)).unwrap().content.clone()).unwrap()
+	};
+	assert_eq!(r.files.len(), 4);
+	assert_eq!(content("index.html"), "site index\n");
+	assert_eq!(content("style.css"), "static style\n");
+	assert_eq!(content("robots.txt"), "static robots\n");
+	assert_eq!(content("css/base.css"), "theme base\n");
+	assert_eq!(r.report.ignored, 1);
+	assert_eq!(
+		r.config.source_of(Path::new("style.css")),
+		fs::canonicalize("../fixtures/sources/static").unwrap()
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn command_works() {
+	use crate::command::{run, temp_dir};
+
+	let dir = temp_dir("shtola-command-").unwrap();
+	let path = dir.path().to_path_buf();
+	let sh = |script: &str| vec!["sh".to_string(), "-c".into(), script.into()];
+	let out = run("Command", &sh("cat; touch made"), Some(&path), Some(b"hi")).unwrap();
+	assert_eq!(out, Ok(b"hi".to_vec()));
+	assert!(path.join("made").exists());
+	let out = run("Command", &sh("echo oops >&2; exit 1"), None, None).unwrap();
+	assert_eq!(out, Err("oops".into()));
+	let e = run("Renderer", &["shtola-missing".into()], None, None).unwrap_err();
+	assert_eq!(e.message, "Renderer \"shtola-missing\" isn't installed");
+	drop(dir);
+	assert!(!path.exists());
+}
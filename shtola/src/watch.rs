@@ -0,0 +1,166 @@
+//! Continuous rebuilds, see [`Shtola::watch`](../struct.Shtola.html#method.watch).
+
+use crate::manifest::Summary;
+use crate::{glob_set, is_sibling_name, resolve, Shtola, ShtolaError, IR};
+use log::{debug, info};
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+// How long the source directory has to be quiet before rebuilding, so saving
+// several files at once only causes one build.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+impl Shtola {
+	/// Builds, then watches the source directories and builds again whenever
+	/// files in it change. The result of every build is passed to `callback`,
+	/// which returns whether to keep watching:
+	///
+	/// ```no_run
+	/// use shtola::Shtola;
+	/// use std::ops::ControlFlow;
+	///
+	/// let mut s = Shtola::new();
+	/// s.source("site");
+	/// s.destination("_site");
+	/// s.watch(|result| {
+	///   match result {
+	///     Ok(ir) => println!("Built {} files", ir.files.len()),
+	///     Err(e) => eprintln!("{}", e),
+	///   }
+	///   ControlFlow::Continue(())
+	/// })
+	/// .unwrap();
+	/// ```
+	///
+	/// Failed builds don't stop watching. Changes to ignored files and to
+	/// destination directories inside the source directory, including the
+	/// staging directories of [atomic builds](#method.atomic), are
+	/// disregarded.
+	///
+	/// Rebuilds get the changed files in
+	/// [`BuildReport::source_changes`](struct.BuildReport.html#structfield.source_changes),
+	/// unless the build is [incremental](#method.incremental), where the
+	/// changes since the previous build are used. Plugins use them to limit
+	/// their work, like the templates plugin, which only renders the pages
+	/// again that depend on a changed layout, according to
+	/// [`IR::dependents`](struct.IR.html#method.dependents).
+	/// Only available with the `watch` feature.
+	pub fn watch<F>(&mut self, mut callback: F) -> Result<(), ShtolaError>
+	where
+		F: FnMut(Result<IR, ShtolaError>) -> ControlFlow<()>,
+	{
+		let sources = self
+			.ir
+			.config
+			.all_sources()
+			.map(|s| resolve(s, false))
+			.collect::<Result<Vec<PathBuf>, ShtolaError>>()?;
+		let (tx, rx) = channel();
+		let mut watcher = notify::recommended_watcher(tx).map_err(ShtolaError::Watch)?;
+		for source in &sources {
+			watcher
+				.watch(source, RecursiveMode::Recursive)
+				.map_err(ShtolaError::Watch)?;
+			info!("Watching {:?} for changes", source);
+		}
+		let result = self.build();
+		let mut previous = result.as_ref().ok().cloned();
+		if callback(result).is_break() {
+			return Ok(());
+		}
+		while let Ok(event) = rx.recv() {
+			// Changed files relative to their source, and whether they were
+			// created.
+			let mut changes = BTreeMap::new();
+			self.record(&sources, event, &mut changes)?;
+			if changes.is_empty() {
+				continue;
+			}
+			loop {
+				match rx.recv_timeout(DEBOUNCE) {
+					Ok(event) => self.record(&sources, event, &mut changes)?,
+					Err(RecvTimeoutError::Timeout) => break,
+					Err(RecvTimeoutError::Disconnected) => return Ok(()),
+				}
+			}
+			let summary = summarize(&sources, changes);
+			info!("Source changed, rebuilding: {}", summary);
+			if let Some(previous) = &previous {
+				for path in summary.changed.iter().chain(&summary.deleted) {
+					let pages = previous.dependents(path);
+					if !pages.is_empty() {
+						info!("{:?} is used by {} pages", path, pages.len());
+					}
+				}
+			}
+			self.pending_changes = Some(summary);
+			let result = self.build();
+			previous = result.as_ref().ok().cloned();
+			if callback(result).is_break() {
+				return Ok(());
+			}
+		}
+		Ok(())
+	}
+
+	// Adds the paths of an event that should cause a rebuild to the changes.
+	fn record(
+		&self,
+		sources: &[PathBuf],
+		event: notify::Result<Event>,
+		changes: &mut BTreeMap<PathBuf, bool>,
+	) -> Result<(), ShtolaError> {
+		let event = event.map_err(ShtolaError::Watch)?;
+		if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+			return Ok(());
+		}
+		let ignores = glob_set(&self.ir.config.ignores)?;
+		let mut outputs: Vec<&PathBuf> = vec![&self.ir.config.destination];
+		outputs.extend(self.targets.iter().map(|t| &t.path));
+		let mut relevant = false;
+		for path in &event.paths {
+			let relative = sources
+				.iter()
+				.find_map(|s| path.strip_prefix(s).ok())
+				.unwrap_or(path);
+			if !outputs.iter().any(|o| path.starts_with(o))
+				&& !path.components().any(|c| is_sibling_name(c.as_os_str()))
+				&& !relative.ancestors().any(|a| ignores.is_match(a))
+			{
+				relevant = true;
+				let created = changes.entry(path.clone()).or_insert(false);
+				*created |= event.kind.is_create();
+			}
+		}
+		if relevant {
+			debug!("Relevant change: {:?}", event);
+		}
+		Ok(())
+	}
+}
+
+// Sorts changed paths into added, changed and deleted files relative to their
+// source, by whether they still exist. Directories are left out, as the
+// changes of the files in them are reported on their own.
+fn summarize(sources: &[PathBuf], changes: BTreeMap<PathBuf, bool>) -> Summary {
+	let mut summary = Summary::default();
+	for (path, created) in changes {
+		let relative = match sources.iter().find_map(|s| path.strip_prefix(s).ok()) {
+			Some(relative) => relative.to_path_buf(),
+			None => continue,
+		};
+		if path.is_dir() {
+			continue;
+		}
+		match (path.exists(), created) {
+			(false, _) => summary.deleted.push(relative),
+			(true, true) => summary.added.push(relative),
+			(true, false) => summary.changed.push(relative),
+		}
+	}
+	summary
+}
@@ -0,0 +1,65 @@
+//! Adapters for chains over tuples. A `Ware<(A, B)>` can pass through several
+//! values at once, like `Ware2` does, and these adapters let middleware
+//! functions that only care about one of the values be used in such a chain.
+//!
+//! # Example
+//! ```
+//! use ware::adapters::{first, second};
+//! use ware::Ware;
+//!
+//! let mut chain: Ware<(i32, String)> = Ware::new();
+//! chain.wrap(first(Box::new(|num| num * 10)));
+//! chain.wrap(second(Box::new(|s: String| s + "!")));
+//! chain.wrap(Box::new(|(num, s)| (num + 1, s)));
+//! assert_eq!(chain.run((1, "hi".into())), (11, "hi!".to_string()));
+//! ```
+
+/// A middleware function for chains over pairs.
+pub type PairMiddleware<A, B> = Box<dyn Fn((A, B)) -> (A, B)>;
+
+/// Lets a middleware function operate on a part of a value. `split` takes the
+/// value apart into the part and the rest, and `join` puts it back together
+/// after `func` has run on the part.
+///
+/// # Example
+/// ```
+/// use ware::adapters::lens;
+/// use ware::Ware;
+///
+/// let mut chain: Ware<(i32, i32, i32)> = Ware::new();
+/// chain.wrap(lens(
+///     |(a, b, c)| (b, (a, c)),
+///     |b, (a, c)| (a, b, c),
+///     Box::new(|b| b * 2),
+/// ));
+/// assert_eq!(chain.run((1, 2, 3)), (1, 4, 3));
+/// ```
+pub fn lens<T, U, C, S, J>(split: S, join: J, func: Box<dyn Fn(U) -> U>) -> Box<dyn Fn(T) -> T>
+where
+	T: 'static,
+	U: 'static,
+	C: 'static,
+	S: Fn(T) -> (U, C) + 'static,
+	J: Fn(U, C) -> T + 'static,
+{
+	Box::new(move |value| {
+		let (part, rest) = split(value);
+		join(func(part), rest)
+	})
+}
+
+/// Lets a middleware function operate on the first value of a pair.
+pub fn first<A: 'static, B: 'static>(func: Box<dyn Fn(A) -> A>) -> PairMiddleware<A, B> {
+	lens(|(a, b)| (a, b), |a, b| (a, b), func)
+}
+
+/// Lets a middleware function operate on the second value of a pair.
+pub fn second<A: 'static, B: 'static>(func: Box<dyn Fn(B) -> B>) -> PairMiddleware<A, B> {
+	lens(|(a, b)| (b, a), |b, a| (a, b), func)
+}
+
+/// Turns a two-argument middleware function, as used by `Ware2`, into one
+/// that can be used in a `Ware` over pairs.
+pub fn tupled<A: 'static, B: 'static>(func: Box<dyn Fn(A, B) -> (A, B)>) -> PairMiddleware<A, B> {
+	Box::new(move |(a, b)| func(a, b))
+}
@@ -0,0 +1,53 @@
+//! Chains of asynchronous middleware functions.
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// A middleware function that returns a future.
+pub type AsyncMiddleware<R> = Box<dyn Fn(R) -> Pin<Box<dyn Future<Output = R>>>>;
+
+/// A middleware chain whose functions are asynchronous. Each function is
+/// awaited before the next one is called.
+///
+/// # Example
+/// ```
+/// use ware::AsyncWare;
+///
+/// let mut chain: AsyncWare<i32> = AsyncWare::new();
+/// chain.wrap(Box::new(|num| Box::pin(async move { num * 10 })));
+/// chain.wrap(Box::new(|num| Box::pin(async move { num - 2 })));
+/// let result = chain.run(5); // A future resolving to 48.
+/// ```
+pub struct AsyncWare<R> {
+	/// The internal list of middleware functions.
+	pub fns: Vec<AsyncMiddleware<R>>,
+}
+
+impl<R> Default for AsyncWare<R> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<R> AsyncWare<R> {
+	/// Create a new asynchronous middleware chain with the given type.
+	pub fn new() -> AsyncWare<R> {
+		AsyncWare { fns: Vec::new() }
+	}
+
+	/// Add a new middleware function to the internal function list.
+	pub fn wrap(&mut self, func: AsyncMiddleware<R>) {
+		self.fns.push(func);
+	}
+
+	/// Run the registered middleware functions with the given value to pass
+	/// through. Resolves to whatever the last registered middleware function
+	/// resolves to.
+	pub async fn run(&self, arg: R) -> R {
+		let mut acc = arg;
+		for func in &self.fns {
+			acc = func(acc).await;
+		}
+		acc
+	}
+}
@@ -0,0 +1,58 @@
+//! Building chains from iterators.
+
+use crate::Ware;
+
+/// Builds a [`Ware`](struct.Ware.html) chain, for example from an iterator of
+/// middleware functions.
+///
+/// # Example
+/// ```
+/// use ware::{Ware, WareBuilder};
+///
+/// let steps: Vec<Box<dyn Fn(i32) -> i32>> = vec![Box::new(|n| n + 1), Box::new(|n| n * 2)];
+/// let chain: Ware<i32> = WareBuilder::new()
+///     .wrap(Box::new(|n| n - 1))
+///     .extend(steps)
+///     .build();
+/// assert_eq!(chain.run(3), 6);
+/// ```
+pub struct WareBuilder<R> {
+	fns: Vec<Box<dyn Fn(R) -> R>>,
+}
+
+impl<R> Default for WareBuilder<R> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<R> WareBuilder<R> {
+	/// Create a new builder without any middleware functions.
+	pub fn new() -> WareBuilder<R> {
+		WareBuilder { fns: Vec::new() }
+	}
+
+	/// Add a single middleware function to the end of the chain.
+	pub fn wrap(mut self, func: Box<dyn Fn(R) -> R>) -> WareBuilder<R> {
+		self.fns.push(func);
+		self
+	}
+
+	/// Add all middleware functions of an iterator to the end of the chain,
+	/// in order.
+	pub fn extend<I: IntoIterator<Item = Box<dyn Fn(R) -> R>>>(
+		mut self,
+		funcs: I,
+	) -> WareBuilder<R> {
+		self.fns.extend(funcs);
+		self
+	}
+
+	/// Build the chain.
+	pub fn build(self) -> Ware<R> {
+		Ware {
+			fns: self.fns,
+			instrumentation: None,
+		}
+	}
+}
@@ -0,0 +1,162 @@
+//! The basic middleware chains.
+
+// The chains keep the signatures they were published with.
+#![allow(clippy::new_without_default, clippy::type_complexity)]
+
+use std::rc::Rc;
+
+/// A middleware chain that can pass through one argument.
+pub struct Ware<R> {
+	/// The internal list of middleware functions.
+	pub fns: Vec<Box<dyn Fn(R) -> R>>,
+	// Set by `instrument`, and called around every middleware function with
+	// its position in the chain.
+	pub(crate) instrumentation: Option<Rc<dyn Fn(usize, &dyn Fn(R) -> R, R) -> R>>,
+}
+
+impl<R> Ware<R> {
+	/// Create a new middleware chain with a given type.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware;
+	/// let mut chain: Ware<String> = Ware::new();
+	/// ```
+	pub fn new() -> Ware<R> {
+		let vec: Vec<Box<dyn Fn(R) -> R>> = Vec::new();
+		Ware {
+			fns: vec,
+			instrumentation: None,
+		}
+	}
+
+	/// Add a new middleware function to the internal function list. This function
+	/// must be of the `Fn` trait, take the specified type and return the same
+	/// specified type. It also has to be boxed for memory safety reasons.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware;
+	/// let mut chain: Ware<String> = Ware::new();
+	/// chain.wrap(Box::new(|st| {
+	///     let mut s = st.clone();
+	///     s.push('a');
+	///     s
+	/// }))
+	/// ```
+	pub fn wrap(&mut self, func: Box<dyn Fn(R) -> R>) {
+		self.fns.push(func);
+	}
+
+	/// Run the registered middleware functions with the given value to pass
+	/// through. Returns whatever the last registered middleware function
+	/// returns.
+	pub fn run(&self, arg: R) -> R {
+		match &self.instrumentation {
+			Some(instrumentation) => self.fns.iter().enumerate().fold(arg, |acc, (index, func)| {
+				instrumentation(index, &**func, acc)
+			}),
+			None => self.fns.iter().fold(arg, |acc, func| func(acc)),
+		}
+	}
+}
+
+impl<R: 'static> Ware<R> {
+	/// Add a middleware function that only runs when the predicate returns
+	/// `true` for the value passed through. Otherwise, the value is passed on
+	/// unchanged.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware;
+	/// let mut chain: Ware<i32> = Ware::new();
+	/// chain.wrap_if(|num| *num > 2, Box::new(|num| num * 10));
+	/// assert_eq!(chain.run(1), 1);
+	/// assert_eq!(chain.run(3), 30);
+	/// ```
+	pub fn wrap_if<P: Fn(&R) -> bool + 'static>(
+		&mut self,
+		predicate: P,
+		func: Box<dyn Fn(R) -> R>,
+	) {
+		self.wrap(Box::new(
+			move |arg| {
+				if predicate(&arg) {
+					func(arg)
+				} else {
+					arg
+				}
+			},
+		));
+	}
+}
+
+/// A middleware chain that can pass through two arguments. A `Ware<(R, S)>`
+/// together with the [`adapters`](adapters/index.html) does the same and
+/// scales to more arguments; use [`tupled`](adapters/fn.tupled.html) to move
+/// existing middleware over.
+pub struct Ware2<R, S> {
+	/// The internal list of middleware functions.
+	pub fns: Vec<Box<dyn Fn(R, S) -> (R, S)>>,
+}
+
+impl<R, S> Ware2<R, S> {
+	/// Create a new middleware chain with the two given types.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware2;
+	/// let mut chain: Ware2<String, i32> = Ware2::new();
+	/// ```
+	pub fn new() -> Ware2<R, S> {
+		let vec: Vec<Box<dyn Fn(R, S) -> (R, S)>> = Vec::new();
+		Ware2 { fns: vec }
+	}
+
+	/// Add a new middleware function to the internal function list. This function
+	/// must be of the `Fn` trait, take the specified types in order and return
+	/// a tuple of the same specified types. It also has to be boxed for memory
+	/// safety reasons.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware2;
+	/// let mut chain: Ware2<String, i32> = Ware2::new();
+	/// chain.wrap(Box::new(|st, num| {
+	///     let mut s = st.clone();
+	///     s.push('a');
+	///     (s, num + 1)
+	/// }))
+	/// ```
+	pub fn wrap(&mut self, func: Box<dyn Fn(R, S) -> (R, S)>) {
+		self.fns.push(func);
+	}
+
+	/// Run the registered middleware functions with the given value to pass
+	/// through. Returns whatever the last registered middleware function
+	/// returns.
+	pub fn run(&self, arg1: R, arg2: S) -> (R, S) {
+		self.fns
+			.iter()
+			.fold((arg1, arg2), |acc, func| func(acc.0, acc.1))
+	}
+}
+
+impl<R: 'static, S: 'static> Ware2<R, S> {
+	/// Add a middleware function that only runs when the predicate returns
+	/// `true` for the arguments passed through. Otherwise, the arguments are
+	/// passed on unchanged.
+	pub fn wrap_if<P: Fn(&R, &S) -> bool + 'static>(
+		&mut self,
+		predicate: P,
+		func: Box<dyn Fn(R, S) -> (R, S)>,
+	) {
+		self.wrap(Box::new(move |arg1, arg2| {
+			if predicate(&arg1, &arg2) {
+				func(arg1, arg2)
+			} else {
+				(arg1, arg2)
+			}
+		}));
+	}
+}
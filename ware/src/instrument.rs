@@ -0,0 +1,55 @@
+//! Logging and timing of middleware functions.
+
+use crate::Ware;
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::time::Instant;
+
+impl<R: 'static> Ware<R> {
+	/// Log every middleware function of the chain when it runs. Before a
+	/// function runs, the logger gets a line with its position in the chain
+	/// and the value it is called with, and afterwards one with the time it
+	/// took and the value it returned. This includes middleware functions
+	/// added after instrumenting, and replaces earlier instrumentation.
+	///
+	/// # Example
+	/// ```
+	/// use ware::Ware;
+	/// let mut chain: Ware<i32> = Ware::new();
+	/// chain.instrument(|line| eprintln!("{}", line));
+	/// chain.wrap(Box::new(|num| num + 1));
+	/// assert_eq!(chain.run(1), 2);
+	/// ```
+	pub fn instrument<L: Fn(&str) + 'static>(&mut self, logger: L)
+	where
+		R: Debug,
+	{
+		self.instrument_with(|value| format!("{:?}", value), logger);
+	}
+
+	/// Like [`instrument`](#method.instrument), but values are logged as
+	/// returned by `projection`, for values without a `Debug` implementation
+	/// or too large to log in full.
+	pub fn instrument_with<P, L>(&mut self, projection: P, logger: L)
+	where
+		P: Fn(&R) -> String + 'static,
+		L: Fn(&str) + 'static,
+	{
+		self.instrumentation = Some(Rc::new(move |index, func, arg| {
+			logger(&format!(
+				"middleware {}: called with {}",
+				index,
+				projection(&arg)
+			));
+			let start = Instant::now();
+			let result = func(arg);
+			logger(&format!(
+				"middleware {}: returned {} after {:?}",
+				index,
+				projection(&result),
+				start.elapsed()
+			));
+			result
+		}));
+	}
+}
@@ -33,99 +33,35 @@
 //!     assert_eq!(res2, 13);
 //! }
 //! ```
+//!
+//! A `Ware` over a tuple can do the same, and the [`adapters`](adapters/index.html)
+//! let middleware functions work on single values of the tuple, so chains
+//! passing through three or more values don't need their own type.
+//!
+//! Chains can also be built from iterators of functions with a
+//! [`WareBuilder`](struct.WareBuilder.html). Further chain types are available
+//! behind features:
+//!
+//! - `try`: [`TryWare`](struct.TryWare.html), whose middleware functions can
+//!   fail and stop the chain.
+//! - `async`: [`AsyncWare`](struct.AsyncWare.html), whose middleware functions
+//!   return futures.
 
-/// A middleware chain that can pass through one argument.
-pub struct Ware<R> {
-	/// The internal list of middleware functions.
-	pub fns: Vec<Box<dyn Fn(R) -> R>>,
-}
-
-impl<R> Ware<R> {
-	/// Create a new middleware chain with a given type.
-	///
-	/// # Example
-	/// ```
-	/// use ware::Ware;
-	/// let mut chain: Ware<String> = Ware::new();
-	/// ```
-	pub fn new() -> Ware<R> {
-		let vec: Vec<Box<dyn Fn(R) -> R>> = Vec::new();
-		Ware { fns: vec }
-	}
-
-	/// Add a new middleware function to the internal function list. This function
-	/// must be of the `Fn` trait, take the specified type and return the same
-	/// specified type. It also has to be boxed for memory safety reasons.
-	///
-	/// # Example
-	/// ```
-	/// use ware::Ware;
-	/// let mut chain: Ware<String> = Ware::new();
-	/// chain.wrap(Box::new(|st| {
-	///     let mut s = st.clone();
-	///     s.push('a');
-	///     s
-	/// }))
-	/// ```
-	pub fn wrap(&mut self, func: Box<dyn Fn(R) -> R>) {
-		self.fns.push(func);
-	}
-
-	/// Run the registered middleware functions with the given value to pass
-	/// through. Returns whatever the last registered middleware function
-	/// returns.
-	pub fn run(&self, arg: R) -> R {
-		self.fns.iter().fold(arg, |acc, func| func(acc))
-	}
-}
-
-/// A middleware chain that can pass through two arguments.
-pub struct Ware2<R, S> {
-	/// The internal list of middleware functions.
-	pub fns: Vec<Box<dyn Fn(R, S) -> (R, S)>>,
-}
-
-impl<R, S> Ware2<R, S> {
-	/// Create a new middleware chain with the two given types.
-	///
-	/// # Example
-	/// ```
-	/// use ware::Ware2;
-	/// let mut chain: Ware2<String, i32> = Ware2::new();
-	/// ```
-	pub fn new() -> Ware2<R, S> {
-		let vec: Vec<Box<dyn Fn(R, S) -> (R, S)>> = Vec::new();
-		Ware2 { fns: vec }
-	}
-
-	/// Add a new middleware function to the internal function list. This function
-	/// must be of the `Fn` trait, take the specified types in order and return
-	/// a tuple of the same specified types. It also has to be boxed for memory
-	/// safety reasons.
-	///
-	/// # Example
-	/// ```
-	/// use ware::Ware2;
-	/// let mut chain: Ware2<String, i32> = Ware2::new();
-	/// chain.wrap(Box::new(|st, num| {
-	///     let mut s = st.clone();
-	///     s.push('a');
-	///     (s, num + 1)
-	/// }))
-	/// ```
-	pub fn wrap(&mut self, func: Box<dyn Fn(R, S) -> (R, S)>) {
-		self.fns.push(func);
-	}
+pub mod adapters;
+#[cfg(feature = "async")]
+mod async_ware;
+mod builder;
+mod chain;
+mod instrument;
+#[cfg(feature = "try")]
+mod try_ware;
 
-	/// Run the registered middleware functions with the given value to pass
-	/// through. Returns whatever the last registered middleware function
-	/// returns.
-	pub fn run(&self, arg1: R, arg2: S) -> (R, S) {
-		self.fns
-			.iter()
-			.fold((arg1, arg2), |acc, func| func(acc.0, acc.1))
-	}
-}
+#[cfg(feature = "async")]
+pub use async_ware::{AsyncMiddleware, AsyncWare};
+pub use builder::WareBuilder;
+pub use chain::{Ware, Ware2};
+#[cfg(feature = "try")]
+pub use try_ware::{TryMiddleware, TryWare};
 
 #[cfg(test)]
 mod tests {
@@ -140,6 +76,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::let_and_return)]
 	fn it_is_immutable() {
 		let value = 1;
 		let closure = |num| {
@@ -167,4 +104,121 @@ mod tests {
 		w.wrap(Box::new(closure));
 		assert_eq!(w.run(val1, val2), (1, String::from("ab")));
 	}
+
+	#[test]
+	fn wrap_if_works() {
+		let mut w: Ware<i32> = Ware::new();
+		w.wrap_if(|num| num % 2 == 0, Box::new(|num| num / 2));
+		w.wrap(Box::new(|num| num + 1));
+		assert_eq!(w.run(4), 3);
+		assert_eq!(w.run(5), 6);
+
+		let mut w2: Ware2<i32, bool> = Ware2::new();
+		w2.wrap_if(
+			|_, enabled| *enabled,
+			Box::new(|num, enabled| (num * 2, enabled)),
+		);
+		assert_eq!(w2.run(3, true), (6, true));
+		assert_eq!(w2.run(3, false), (3, false));
+	}
+
+	#[test]
+	fn instrument_works() {
+		use std::cell::RefCell;
+		use std::rc::Rc;
+
+		let lines = Rc::new(RefCell::new(Vec::new()));
+		let log = lines.clone();
+		let mut w: Ware<i32> = Ware::new();
+		w.wrap(Box::new(|num| num + 1));
+		w.wrap(Box::new(|num| num * 2));
+		w.instrument_with(
+			|num| format!("<{}>", num),
+			move |line| log.borrow_mut().push(line.to_string()),
+		);
+		w.wrap(Box::new(|num| num - 1));
+		assert_eq!(w.run(1), 3);
+		let lines = lines.borrow();
+		assert_eq!(lines.len(), 6);
+		assert_eq!(lines[0], "middleware 0: called with <1>");
+		assert!(lines[1].starts_with("middleware 0: returned <2> after "));
+		assert_eq!(lines[2], "middleware 1: called with <2>");
+		assert!(lines[3].starts_with("middleware 1: returned <4> after "));
+		assert_eq!(lines[4], "middleware 2: called with <4>");
+		assert!(lines[5].starts_with("middleware 2: returned <3> after "));
+	}
+
+	#[test]
+	fn adapters_work() {
+		use crate::adapters::{first, lens, second, tupled};
+
+		let mut w: Ware<(i32, String, bool)> = Ware::new();
+		w.wrap(lens(
+			|(num, st, b)| ((num, st), b),
+			|(num, st), b| (num, st, b),
+			tupled(Box::new(|num, st: String| (num + 1, st + "b"))),
+		));
+		w.wrap(lens(
+			|(num, st, b)| ((st, b), num),
+			|(st, b), num| (num, st, b),
+			second(Box::new(|b: bool| !b)),
+		));
+		w.wrap(lens(
+			|(num, st, b)| ((num, b), st),
+			|(num, b), st| (num, st, b),
+			first(Box::new(|num| num * 10)),
+		));
+		assert_eq!(w.run((1, "a".into(), false)), (20, "ab".into(), true));
+	}
+
+	#[test]
+	fn builder_works() {
+		let funcs: Vec<Box<dyn Fn(i32) -> i32>> = vec![Box::new(|num| num * 2)];
+		let w = WareBuilder::new()
+			.wrap(Box::new(|num| num + 1))
+			.extend(funcs)
+			.build();
+		assert_eq!(w.run(1), 4);
+	}
+
+	#[cfg(feature = "try")]
+	#[test]
+	fn try_ware_works() {
+		let mut w: TryWare<i32, String> = TryWare::new();
+		w.wrap(Box::new(|num| Ok(num + 1)));
+		w.wrap(Box::new(|num| Err(format!("failed at {}", num))));
+		w.wrap(Box::new(|_| panic!("the chain should have stopped")));
+		assert_eq!(w.run(1), Err("failed at 2".into()));
+		assert_eq!(w.try_run(1), Err((1, "failed at 2".into())));
+	}
+
+	#[cfg(feature = "try")]
+	#[test]
+	fn on_error_works() {
+		let mut w: TryWare<i32, String> = TryWare::new();
+		w.wrap(Box::new(|num| Err(format!("failed at {}", num))));
+		w.on_error(|num, _| Ok(num));
+		w.wrap(Box::new(|num| Ok(num + 1)));
+		w.wrap(Box::new(|num| Err(format!("failed at {}", num))));
+		w.on_error(|_, e| Err(format!("{}!", e)));
+		assert_eq!(w.run(1), Err("failed at 2!".into()));
+
+		let mut empty: TryWare<i32, String> = TryWare::new();
+		empty.on_error(|_, _| Ok(0));
+		assert_eq!(empty.run(1), Ok(1));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_ware_works() {
+		use std::future::Future;
+		use std::task::{Context, Poll, Waker};
+
+		let mut w: AsyncWare<i32> = AsyncWare::new();
+		w.wrap(Box::new(|num| Box::pin(async move { num * 10 })));
+		w.wrap(Box::new(|num| Box::pin(async move { num - 2 })));
+		let mut future = Box::pin(w.run(5));
+		let mut context = Context::from_waker(Waker::noop());
+		assert_eq!(future.as_mut().poll(&mut context), Poll::Ready(48));
+	}
 }
@@ -0,0 +1,94 @@
+//! Chains of fallible middleware functions.
+
+/// A middleware function that may fail.
+pub type TryMiddleware<R, E> = Box<dyn Fn(R) -> Result<R, E>>;
+
+/// A middleware chain whose functions can fail. The first error stops the
+/// chain.
+///
+/// # Example
+/// ```
+/// use ware::TryWare;
+///
+/// let mut chain: TryWare<i32, String> = TryWare::new();
+/// chain.wrap(Box::new(|num| Ok(num + 1)));
+/// chain.wrap(Box::new(|num| if num > 5 { Err("too big".into()) } else { Ok(num) }));
+/// assert_eq!(chain.run(1), Ok(2));
+/// assert_eq!(chain.run(5), Err("too big".into()));
+/// ```
+pub struct TryWare<R, E> {
+	/// The internal list of middleware functions.
+	pub fns: Vec<TryMiddleware<R, E>>,
+}
+
+impl<R, E> Default for TryWare<R, E> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<R, E> TryWare<R, E> {
+	/// Create a new fallible middleware chain with the given types.
+	pub fn new() -> TryWare<R, E> {
+		TryWare { fns: Vec::new() }
+	}
+
+	/// Add a new middleware function to the internal function list.
+	pub fn wrap(&mut self, func: TryMiddleware<R, E>) {
+		self.fns.push(func);
+	}
+
+	/// Run the registered middleware functions with the given value to pass
+	/// through. Returns the first error, or whatever the last registered
+	/// middleware function returns.
+	pub fn run(&self, arg: R) -> Result<R, E> {
+		self.fns.iter().try_fold(arg, |acc, func| func(acc))
+	}
+
+	/// Like [`run`](#method.run), but the error comes with the position of the
+	/// middleware function that returned it, starting at 0, so callers can
+	/// tell which function failed.
+	///
+	/// # Example
+	/// ```
+	/// use ware::TryWare;
+	///
+	/// let mut chain: TryWare<i32, String> = TryWare::new();
+	/// chain.wrap(Box::new(|num| Ok(num + 1)));
+	/// chain.wrap(Box::new(|_| Err("failed".into())));
+	/// assert_eq!(chain.try_run(1), Err((1, "failed".into())));
+	/// ```
+	pub fn try_run(&self, arg: R) -> Result<R, (usize, E)> {
+		self.fns
+			.iter()
+			.enumerate()
+			.try_fold(arg, |acc, (i, func)| func(acc).map_err(|e| (i, e)))
+	}
+}
+
+impl<R: Clone + 'static, E: 'static> TryWare<R, E> {
+	/// Attach an error handler to the most recently added middleware function.
+	/// When that function fails, the handler gets the value the function was
+	/// called with along with the error. It can return a value to continue the
+	/// chain with, for example the unchanged value to skip the failed function,
+	/// or an error to stop the chain. Does nothing if the chain is empty.
+	///
+	/// # Example
+	/// ```
+	/// use ware::TryWare;
+	///
+	/// let mut chain: TryWare<i32, String> = TryWare::new();
+	/// chain.wrap(Box::new(|num| if num > 5 { Err("too big".into()) } else { Ok(num * 2) }));
+	/// chain.on_error(|num, _| Ok(num));
+	/// chain.wrap(Box::new(|num| Ok(num + 1)));
+	/// assert_eq!(chain.run(1), Ok(3));
+	/// assert_eq!(chain.run(10), Ok(11));
+	/// ```
+	pub fn on_error<H: Fn(R, E) -> Result<R, E> + 'static>(&mut self, handler: H) {
+		if let Some(func) = self.fns.pop() {
+			self.fns.push(Box::new(move |arg: R| {
+				func(arg.clone()).or_else(|e| handler(arg, e))
+			}));
+		}
+	}
+}